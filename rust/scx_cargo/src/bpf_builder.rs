@@ -11,8 +11,10 @@ use libbpf_cargo::SkeletonBuilder;
 use libbpf_rs::Linker;
 use std::collections::BTreeSet;
 use std::env;
+use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
+use std::process::Command;
 use tracing::Level;
 use tracing_subscriber::{filter, layer::SubscriberExt, Layer};
 
@@ -53,6 +55,10 @@ use tracing_subscriber::{filter, layer::SubscriberExt, Layer};
 /// If enabled with `.enable_skel()`, the input `.bpf.c` file is compiled
 /// and its skeleton and bindings are generated using `libbpf-cargo`.
 ///
+/// The git version of the source tree, the version of the kernel headers
+/// and a hash of the compiled BPF object are also exported to the crate so
+/// that they can be reported with `scx_utils::version_info!()`.
+///
 /// ## An Example
 ///
 /// This section shows how `BpfBuilder` can be used in an example project.
@@ -365,6 +371,7 @@ impl BpfBuilder {
         }
 
         linker.link()?;
+        self.gen_version_env(&linkobj)?;
 
         self.bindgen_bpf_intf()?;
 
@@ -406,12 +413,83 @@ impl BpfBuilder {
                 .rustfmt("disable_rustfmt")
                 .build_and_generate(&skel_path)
         })?;
+        self.gen_version_env(&obj)?;
 
         self.add_src_deps(deps, input)?;
 
         Ok(())
     }
 
+    /// Export the build metadata picked up by `scx_utils::version_info!()`
+    /// as compile-time environment variables of the scheduler crate.
+    ///
+    /// - `SCX_GIT_DESCRIBE`: `git describe` of the source tree.
+    /// - `SCX_VMLINUX_H_VERSION`: Version of the kernel headers the BPF
+    ///   code was compiled against, e.g. `6.16-gabcdef012345`.
+    /// - `SCX_BPF_OBJ_HASH`: Hash of the compiled BPF object `@obj`.
+    ///
+    /// The variables which can't be determined are left unset.
+    fn gen_version_env(&self, obj: &Path) -> Result<()> {
+        if let Some(desc) = Self::git_describe() {
+            println!("cargo:rustc-env=SCX_GIT_DESCRIBE={desc}");
+        }
+        if let Some(ver) = self.vmlinux_h_version() {
+            println!("cargo:rustc-env=SCX_VMLINUX_H_VERSION={ver}");
+        }
+
+        let buf = fs::read(obj).with_context(|| format!("Failed to read {obj:?}"))?;
+        println!("cargo:rustc-env=SCX_BPF_OBJ_HASH={:016x}", fnv1a64(&buf));
+        Ok(())
+    }
+
+    fn git(dir: &Path, args: &[&str]) -> Option<String> {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        let out = String::from_utf8(output.stdout).ok()?.trim().to_string();
+        (!out.is_empty()).then_some(out)
+    }
+
+    fn git_describe() -> Option<String> {
+        let dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").ok()?);
+        let desc = Self::git(&dir, &["describe", "--tags", "--always", "--dirty"])?;
+
+        // Rebuild when HEAD moves, the branch or tags change or the tree
+        // gets dirty so that the reported version doesn't go stale.
+        let head_ref = Self::git(&dir, &["symbolic-ref", "-q", "HEAD"]);
+        let names = ["HEAD", "index", "packed-refs", "refs/tags"];
+        for name in names.iter().copied().chain(head_ref.as_deref()) {
+            if let Some(path) = Self::git(&dir, &["rev-parse", "--git-path", name]) {
+                let path = dir.join(path);
+                if path.exists() {
+                    println!("cargo:rerun-if-changed={}", path.display());
+                }
+            }
+        }
+
+        Some(desc)
+    }
+
+    // The installed arch/$ARCH/vmlinux.h is a symlink to
+    // vmlinux-v$VER-g$SHA1.h. Not available if overridden by BPF_CFLAGS.
+    fn vmlinux_h_version(&self) -> Option<String> {
+        let link = self
+            .out_dir
+            .join("scx_utils-bpf_h/arch")
+            .join(self.clang.kernel_target().ok()?)
+            .join("vmlinux.h");
+        let target = fs::read_link(link).ok()?;
+        let name = target.file_name()?.to_str()?;
+        name.strip_prefix("vmlinux-v")?
+            .strip_suffix(".h")
+            .map(|ver| ver.to_string())
+    }
+
     fn add_src_deps(&self, deps: &mut BTreeSet<String>, input: &str) -> Result<()> {
         let c_path = PathBuf::from(input);
         let dir = c_path
@@ -463,6 +541,14 @@ impl BpfBuilder {
     }
 }
 
+// 64bit FNV-1a. Only used to tell BPF objects apart, so it doesn't need to
+// be cryptographically strong but must stay stable across toolchains.
+fn fnv1a64(buf: &[u8]) -> u64 {
+    buf.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
 // Helper function to set up tracing and output compiler warnings
 fn with_clang_warnings<F, R>(f: F) -> Result<R>
 where
@@ -499,6 +585,13 @@ mod tests {
         assert!(res.is_ok(), "Failed to create BpfBuilder ({res:?})");
     }
 
+    #[test]
+    fn test_fnv1a64() {
+        assert_eq!(super::fnv1a64(b""), 0xcbf29ce484222325);
+        assert_eq!(super::fnv1a64(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(super::fnv1a64(b"foobar"), 0x85944171f73967e8);
+    }
+
     #[test]
     fn test_vmlinux_h_ver_sha1() {
         let clang_info = ClangInfo::new().unwrap();
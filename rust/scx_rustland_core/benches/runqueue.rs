@@ -0,0 +1,88 @@
+// Copyright (c) Andrea Righi <andrea.righi@linux.dev>
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use scx_rustland_core::{BTreeRunQueue, PairingHeapRunQueue, RunQueue, TimingWheelRunQueue};
+use std::hint::black_box;
+
+const NR_TASKS: usize = 1024;
+
+// (deadline, pid) pairs, like a deadline-based policy would queue.
+fn keys(nr: usize) -> Vec<(u64, i32)> {
+    let mut x: u64 = 0x2545f4914f6cdd1d;
+    (0..nr)
+        .map(|pid| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            (x % 100_000_000, pid as i32)
+        })
+        .collect()
+}
+
+fn timing_wheel() -> TimingWheelRunQueue<(u64, i32)> {
+    // 1ms slots over a 100ms horizon.
+    TimingWheelRunQueue::new(100, 1_000_000, |t: &(u64, i32)| t.0)
+}
+
+// Fill the queue, then drain it.
+fn fill_drain<Q: RunQueue<(u64, i32)>>(q: &mut Q, keys: &[(u64, i32)]) {
+    for &k in keys {
+        q.insert(k);
+    }
+    while let Some(k) = q.pop() {
+        black_box(k);
+    }
+}
+
+// Keep the queue at a steady size, re-inserting each popped task with a
+// later deadline, like tasks cycling through the scheduler.
+fn steady_state<Q: RunQueue<(u64, i32)>>(q: &mut Q, keys: &[(u64, i32)]) {
+    for &k in keys {
+        q.insert(k);
+    }
+    for _ in 0..keys.len() * 4 {
+        let (deadline, pid) = q.pop().unwrap();
+        q.insert((deadline + 5_000_000, pid));
+    }
+    while let Some(k) = q.pop() {
+        black_box(k);
+    }
+}
+
+fn bench_fill_drain(c: &mut Criterion) {
+    let keys = keys(NR_TASKS);
+    let mut group = c.benchmark_group("Fill and Drain");
+
+    group.bench_with_input(BenchmarkId::new("BTree", NR_TASKS), &keys, |b, k| {
+        b.iter(|| fill_drain(&mut BTreeRunQueue::new(), k))
+    });
+    group.bench_with_input(BenchmarkId::new("PairingHeap", NR_TASKS), &keys, |b, k| {
+        b.iter(|| fill_drain(&mut PairingHeapRunQueue::new(), k))
+    });
+    group.bench_with_input(BenchmarkId::new("TimingWheel", NR_TASKS), &keys, |b, k| {
+        b.iter(|| fill_drain(&mut timing_wheel(), k))
+    });
+    group.finish();
+}
+
+fn bench_steady_state(c: &mut Criterion) {
+    let keys = keys(NR_TASKS);
+    let mut group = c.benchmark_group("Steady State");
+
+    group.bench_with_input(BenchmarkId::new("BTree", NR_TASKS), &keys, |b, k| {
+        b.iter(|| steady_state(&mut BTreeRunQueue::new(), k))
+    });
+    group.bench_with_input(BenchmarkId::new("PairingHeap", NR_TASKS), &keys, |b, k| {
+        b.iter(|| steady_state(&mut PairingHeapRunQueue::new(), k))
+    });
+    group.bench_with_input(BenchmarkId::new("TimingWheel", NR_TASKS), &keys, |b, k| {
+        b.iter(|| steady_state(&mut timing_wheel(), k))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_fill_drain, bench_steady_state);
+criterion_main!(benches);
@@ -3,5 +3,12 @@ pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 mod alloc;
 pub use alloc::ALLOCATOR;
 
+mod runqueue;
+pub use runqueue::BTreeRunQueue;
+pub use runqueue::PairingHeapRunQueue;
+pub use runqueue::RunQueue;
+pub use runqueue::RunQueueKind;
+pub use runqueue::TimingWheelRunQueue;
+
 mod rustland_builder;
 pub use rustland_builder::RustLandBuilder;
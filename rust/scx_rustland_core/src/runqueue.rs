@@ -0,0 +1,460 @@
+// Copyright (c) Andrea Righi <andrea.righi@linux.dev>
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! Task ordering data structures for user-space scheduling policies.
+//!
+//! Policies have very different insert/pop patterns (e.g., a deadline-based
+//! policy inserts tasks everywhere in the queue, while a FIFO-like policy
+//! mostly appends at the tail), so the run-queue is abstracted behind the
+//! [`RunQueue`] trait and a policy can pick the backend that suits it best:
+//!
+//! - [`BTreeRunQueue`]: balanced tree, O(log n) insert and pop, exact ordering.
+//! - [`PairingHeapRunQueue`]: O(1) insert and amortized O(log n) pop, exact
+//!   ordering, good for insert-heavy workloads.
+//! - [`TimingWheelRunQueue`]: O(1) insert and pop, ordering is approximated
+//!   to a configurable granularity of a u64 key (e.g., a deadline).
+//!
+//! Items are always popped from the smallest to the largest.
+//!
+//! [`RunQueueKind`] allows to choose the backend at run-time (e.g., from a
+//! command line option) and build it as a `Box<dyn RunQueue<T>>`.
+
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+use std::collections::BinaryHeap;
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+use anyhow::bail;
+use anyhow::Result;
+
+pub trait RunQueue<T: Ord> {
+    /// Queue a new item.
+    fn insert(&mut self, item: T);
+
+    /// Remove and return the smallest item.
+    fn pop(&mut self) -> Option<T>;
+
+    /// Return the number of queued items.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Number of slots of the timing wheel created by [`RunQueueKind::build`].
+const TIMING_WHEEL_SLOTS: usize = 1024;
+
+/// Run-queue backend.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RunQueueKind {
+    /// [`BTreeRunQueue`]
+    #[default]
+    BTree,
+    /// [`PairingHeapRunQueue`]
+    PairingHeap,
+    /// [`TimingWheelRunQueue`]
+    TimingWheel,
+}
+
+impl FromStr for RunQueueKind {
+    type Err = anyhow::Error;
+
+    /// Parse "btree", "pairing" or "wheel".
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "btree" => Ok(Self::BTree),
+            "pairing" => Ok(Self::PairingHeap),
+            "wheel" => Ok(Self::TimingWheel),
+            _ => bail!(
+                "unknown run-queue {:?}, expected btree, pairing or wheel",
+                s
+            ),
+        }
+    }
+}
+
+impl RunQueueKind {
+    /// Create an empty run-queue of this kind.
+    ///
+    /// @key and @granularity are only used by the timing wheel, see
+    /// [`TimingWheelRunQueue::new`].
+    pub fn build<T: Ord + 'static>(
+        self,
+        granularity: u64,
+        key: fn(&T) -> u64,
+    ) -> Box<dyn RunQueue<T>> {
+        match self {
+            Self::BTree => Box::new(BTreeRunQueue::new()),
+            Self::PairingHeap => Box::new(PairingHeapRunQueue::new()),
+            Self::TimingWheel => Box::new(TimingWheelRunQueue::new(
+                TIMING_WHEEL_SLOTS,
+                granularity,
+                key,
+            )),
+        }
+    }
+}
+
+/// Run-queue backed by a BTreeSet.
+///
+/// Like any set, items comparing equal are merged, so the ordering of the
+/// items should include a unique identifier (e.g., the pid) as tie-breaker.
+#[derive(Debug)]
+pub struct BTreeRunQueue<T: Ord> {
+    tree: BTreeSet<T>,
+}
+
+impl<T: Ord> Default for BTreeRunQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Ord> BTreeRunQueue<T> {
+    pub fn new() -> Self {
+        Self {
+            tree: BTreeSet::new(),
+        }
+    }
+}
+
+impl<T: Ord> RunQueue<T> for BTreeRunQueue<T> {
+    fn insert(&mut self, item: T) {
+        self.tree.insert(item);
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        self.tree.pop_first()
+    }
+
+    fn len(&self) -> usize {
+        self.tree.len()
+    }
+}
+
+#[derive(Debug)]
+struct PairingNode<T> {
+    item: T,
+    children: Vec<Box<PairingNode<T>>>,
+}
+
+/// Run-queue backed by a pairing heap (min-heap).
+#[derive(Debug)]
+pub struct PairingHeapRunQueue<T: Ord> {
+    root: Option<Box<PairingNode<T>>>,
+    len: usize,
+}
+
+impl<T: Ord> Default for PairingHeapRunQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Ord> PairingHeapRunQueue<T> {
+    pub fn new() -> Self {
+        Self { root: None, len: 0 }
+    }
+
+    fn meld(mut a: Box<PairingNode<T>>, mut b: Box<PairingNode<T>>) -> Box<PairingNode<T>> {
+        if a.item <= b.item {
+            a.children.push(b);
+            a
+        } else {
+            b.children.push(a);
+            b
+        }
+    }
+
+    // Standard two-pass pairing: meld the children in pairs from left to
+    // right, then meld the resulting heaps from right to left.
+    fn merge_pairs(children: Vec<Box<PairingNode<T>>>) -> Option<Box<PairingNode<T>>> {
+        let mut pairs = Vec::with_capacity(children.len() / 2 + 1);
+        let mut iter = children.into_iter();
+
+        while let Some(a) = iter.next() {
+            match iter.next() {
+                Some(b) => pairs.push(Self::meld(a, b)),
+                None => pairs.push(a),
+            }
+        }
+
+        let mut root = pairs.pop()?;
+        while let Some(node) = pairs.pop() {
+            root = Self::meld(node, root);
+        }
+        Some(root)
+    }
+}
+
+impl<T: Ord> RunQueue<T> for PairingHeapRunQueue<T> {
+    fn insert(&mut self, item: T) {
+        let node = Box::new(PairingNode {
+            item,
+            children: Vec::new(),
+        });
+        self.root = Some(match self.root.take() {
+            Some(root) => Self::meld(root, node),
+            None => node,
+        });
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        let root = self.root.take()?;
+        let PairingNode { item, children } = *root;
+        self.root = Self::merge_pairs(children);
+        self.len -= 1;
+        Some(item)
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl<T: Ord> Drop for PairingHeapRunQueue<T> {
+    fn drop(&mut self) {
+        // Tear down iteratively to avoid deep recursion on degenerate heaps.
+        let mut stack: Vec<_> = self.root.take().into_iter().collect();
+        while let Some(mut node) = stack.pop() {
+            stack.append(&mut node.children);
+        }
+    }
+}
+
+/// Run-queue backed by a timing wheel.
+///
+/// Items are hashed into `nr_slots` buckets of `granularity` width according
+/// to a u64 key (e.g., the task's deadline) and each bucket is served in FIFO
+/// order, so the ordering is only guaranteed across buckets. Items beyond the
+/// wheel horizon (`nr_slots * granularity` from the current position) are
+/// parked in an overflow heap and moved into the wheel as it advances.
+///
+/// The key must be consistent with the ordering of the items, i.e. `a < b`
+/// implies `key(a) <= key(b)`.
+pub struct TimingWheelRunQueue<T: Ord> {
+    slots: Vec<VecDeque<T>>,
+    granularity: u64,
+    base: u64,
+    cursor: usize,
+    overflow: BinaryHeap<Reverse<T>>,
+    key: fn(&T) -> u64,
+    len: usize,
+}
+
+impl<T: Ord> TimingWheelRunQueue<T> {
+    pub fn new(nr_slots: usize, granularity: u64, key: fn(&T) -> u64) -> Self {
+        let nr_slots = nr_slots.max(1);
+        Self {
+            slots: (0..nr_slots).map(|_| VecDeque::new()).collect(),
+            granularity: granularity.max(1),
+            base: 0,
+            cursor: 0,
+            overflow: BinaryHeap::new(),
+            key,
+            len: 0,
+        }
+    }
+
+    fn horizon(&self) -> u64 {
+        self.base
+            .saturating_add(self.slots.len() as u64 * self.granularity)
+    }
+
+    // Place @item in the wheel or in the overflow heap if it's beyond the
+    // horizon. Keys in the past are placed in the current slot.
+    fn place(&mut self, item: T) {
+        let key = (self.key)(&item);
+        if key >= self.horizon() {
+            self.overflow.push(Reverse(item));
+            return;
+        }
+        let offset = (key.saturating_sub(self.base) / self.granularity) as usize;
+        let idx = (self.cursor + offset) % self.slots.len();
+        self.slots[idx].push_back(item);
+    }
+
+    // Move the items which fell within the horizon out of the overflow heap.
+    fn refill(&mut self) {
+        while let Some(Reverse(item)) = self.overflow.peek() {
+            if (self.key)(item) >= self.horizon() {
+                break;
+            }
+            let Reverse(item) = self.overflow.pop().unwrap();
+            self.place(item);
+        }
+    }
+}
+
+impl<T: Ord> RunQueue<T> for TimingWheelRunQueue<T> {
+    fn insert(&mut self, item: T) {
+        self.place(item);
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let nr_slots = self.slots.len();
+        let found = (0..nr_slots).find(|i| !self.slots[(self.cursor + i) % nr_slots].is_empty());
+
+        match found {
+            Some(i) => {
+                // Advance the wheel to the first non-empty slot.
+                self.cursor = (self.cursor + i) % nr_slots;
+                self.base += i as u64 * self.granularity;
+            }
+            None => {
+                // The wheel is empty, jump to the earliest overflowed item.
+                let Reverse(item) = self.overflow.peek()?;
+                let key = (self.key)(item);
+                self.base = key - key % self.granularity;
+            }
+        }
+        self.refill();
+
+        let item = self.slots[self.cursor].pop_front();
+        if item.is_some() {
+            self.len -= 1;
+        }
+        item
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> Vec<u64> {
+        // Simple deterministic pseudo-random sequence.
+        let mut x: u64 = 0x2545f4914f6cdd1d;
+        (0..1000)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x % 1_000_000
+            })
+            .collect()
+    }
+
+    fn drain<Q: RunQueue<u64>>(q: &mut Q) -> Vec<u64> {
+        let mut out = vec![];
+        while let Some(v) = q.pop() {
+            out.push(v);
+        }
+        assert!(q.is_empty());
+        out
+    }
+
+    #[test]
+    fn test_pairing_heap_order() {
+        let mut q = PairingHeapRunQueue::new();
+        let mut expected = keys();
+        for &k in &expected {
+            q.insert(k);
+        }
+        assert_eq!(q.len(), expected.len());
+        expected.sort();
+        assert_eq!(drain(&mut q), expected);
+    }
+
+    #[test]
+    fn test_timing_wheel_order() {
+        // With a granularity of 1 the wheel keeps the exact ordering, also
+        // across the overflow heap.
+        let mut q = TimingWheelRunQueue::new(64, 1, |v: &u64| *v);
+        let mut expected = keys();
+        for &k in &expected {
+            q.insert(k);
+        }
+        expected.sort();
+        assert_eq!(drain(&mut q), expected);
+    }
+
+    // (deadline, timestamp, pid), ordered like the tasks of scx_rustland.
+    type Task = (u64, u64, i32);
+
+    // Run the dispatch path of a deadline-based policy: queue all the tasks,
+    // then dispatch them one at a time, re-queueing the task when the
+    // dispatch fails (every third attempt here).
+    fn dispatch_all(kind: RunQueueKind) -> Vec<Task> {
+        let mut q = kind.build(1000, |t: &Task| t.0);
+        for (pid, &k) in keys().iter().enumerate() {
+            q.insert((k, pid as u64 / 10, pid as i32));
+        }
+
+        let mut out = vec![];
+        let mut attempt = 0;
+        while let Some(task) = q.pop() {
+            attempt += 1;
+            if attempt % 3 == 0 {
+                q.insert(task);
+                continue;
+            }
+            out.push(task);
+        }
+        assert!(q.is_empty());
+        out
+    }
+
+    #[test]
+    fn test_runqueue_kinds() {
+        let mut expected: Vec<Task> = keys()
+            .iter()
+            .enumerate()
+            .map(|(pid, &k)| (k, pid as u64 / 10, pid as i32))
+            .collect();
+        expected.sort();
+
+        assert_eq!(
+            "btree".parse::<RunQueueKind>().unwrap(),
+            RunQueueKind::BTree
+        );
+        assert_eq!(
+            "pairing".parse::<RunQueueKind>().unwrap(),
+            RunQueueKind::PairingHeap
+        );
+        assert_eq!(
+            "wheel".parse::<RunQueueKind>().unwrap(),
+            RunQueueKind::TimingWheel
+        );
+        assert!("fifo".parse::<RunQueueKind>().is_err());
+
+        assert_eq!(dispatch_all(RunQueueKind::BTree), expected);
+        assert_eq!(dispatch_all(RunQueueKind::PairingHeap), expected);
+
+        // The timing wheel only orders the tasks by deadline bucket.
+        let out = dispatch_all(RunQueueKind::TimingWheel);
+        let mut sorted = out.clone();
+        sorted.sort();
+        assert_eq!(sorted, expected);
+        for w in out.windows(2) {
+            assert!(w[0].0 / 1000 <= w[1].0 / 1000);
+        }
+    }
+
+    #[test]
+    fn test_timing_wheel_granularity() {
+        let mut q = TimingWheelRunQueue::new(16, 1000, |v: &u64| *v);
+        for &k in &keys() {
+            q.insert(k);
+        }
+        let out = drain(&mut q);
+        assert_eq!(out.len(), 1000);
+        for w in out.windows(2) {
+            assert!(w[0] / 1000 <= w[1] / 1000);
+        }
+    }
+}
@@ -40,6 +40,14 @@ fn main() {
     let resp = client.request::<serde_json::Value>("stats", vec![("target".into(), "top".into())]);
     println!("{:#?}", resp);
 
+    println!("\n===== Requesting only \"name\" and the domain pressures:");
+    let resp = client.request_fields::<serde_json::Value>(
+        "stats",
+        vec![],
+        &["name", "doms_dict.*.pressure"],
+    );
+    println!("{:#?}", resp);
+
     println!("\n===== Requesting \"stats_meta\" but receiving with serde_json::Value:");
     let resp = client
         .request::<serde_json::Value>("stats_meta", vec![])
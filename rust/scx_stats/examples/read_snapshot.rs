@@ -0,0 +1,10 @@
+use scx_stats::prelude::*;
+use std::env::args;
+
+fn main() {
+    let args: Vec<String> = args().collect();
+    std::assert_eq!(args.len(), 2, "Usage: read_snapshot FILE");
+
+    let snap = StatsSnapshot::load(&args[1]).unwrap();
+    snap.format(&mut std::io::stdout()).unwrap();
+}
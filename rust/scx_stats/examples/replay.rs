@@ -0,0 +1,54 @@
+use scx_stats::prelude::*;
+use std::env::args;
+use std::fs::File;
+use std::io::BufWriter;
+use std::thread::sleep;
+use std::time::Duration;
+
+fn main() {
+    simple_logger::SimpleLogger::new()
+        .with_level(log::LevelFilter::Info)
+        .env()
+        .init()
+        .unwrap();
+
+    let usage = "Usage: replay record UNIX_SOCKET_PATH FILE INTERVAL_SECS\n       \
+                 replay replay FILE UNIX_SOCKET_PATH SPEED";
+    let args: Vec<String> = args().collect();
+    std::assert_eq!(args.len(), 5, "{}", usage);
+
+    match args[1].as_str() {
+        "record" => {
+            let mut client = StatsClient::new().set_path(&args[2]).connect(None).unwrap();
+            let f = BufWriter::new(File::create(&args[3]).unwrap());
+            let intv = Duration::from_secs_f64(args[4].parse().unwrap());
+
+            let mut recorder = StatsRecorder::new(f, &mut client).unwrap();
+            loop {
+                recorder.record(&mut client, "top").unwrap();
+                println!("recorded {} samples", recorder.nr_samples());
+                sleep(intv);
+            }
+        }
+        "replay" => {
+            let rec = StatsRecording::load(&args[2]).unwrap();
+            println!(
+                "replaying {} samples over {:?}",
+                rec.samples.len(),
+                rec.duration()
+            );
+            let data = StatsReplay::new(rec)
+                .set_speed(args[4].parse().unwrap())
+                .set_repeat(true)
+                .server_data();
+            let _server = StatsServer::<(), ()>::new(data)
+                .set_path(&args[3])
+                .launch()
+                .unwrap();
+            loop {
+                sleep(Duration::from_secs(1));
+            }
+        }
+        _ => panic!("{}", usage),
+    }
+}
@@ -25,17 +25,22 @@ def request(f, req, args={}):
         raise Exception(f"req: {req} args: {args} failed with {resp['errno']} ({resp['args']['resp']})")
     return resp['args']['resp']
 
+def field_om_label(field):
+    if 'user' in field and '_om_label' in field['user']:
+        return field['user']['_om_label']
+    return ''
+
 def make_om_metrics(sname, omid, field, labels, meta_db, registry):
     # @sname: The name of the current struct.
     #
     # @omid: The field path down from the top level struct. e.g. '.A.B'
-    # means that the top level's field 'A' is a dict and the current one is
-    # the field 'B' of the struct inside that dict.
+    # means that the top level's field 'A' is a dict or array and the
+    # current one is the field 'B' of the struct inside it.
     #
     # @field: The corresponding field part of the stats_meta.
     #
-    # @labels: The collected $om_labels as this function descends down
-    # nested dicts.
+    # @labels: The collected label names as this function descends down
+    # nested dicts and arrays.
 
     # om_skip tells us that the server wants this field to be
     # skipped for OM.
@@ -45,6 +50,7 @@ def make_om_metrics(sname, omid, field, labels, meta_db, registry):
 
     desc = field['desc'] if 'desc' in field else ''
     prefix = meta_db[sname]['_om_prefix']
+    gname = prefix + omid.rsplit('.', 1)[-1]
 
     if 'datum' in field:
         match field['datum']:
@@ -52,23 +58,36 @@ def make_om_metrics(sname, omid, field, labels, meta_db, registry):
             # $_om_prefix + the leaf level field name. The combination must
             # be unique.
             case 'i64' | 'u64' | 'float':
-                gname = prefix + omid.rsplit('.', 1)[-1]
                 dbg(f'creating OM metric {gname}@{omid} {labels} "{desc}"')
                 return { omid: Gauge(gname, desc, labels, registry=registry) }
-    elif 'dict' in field and 'datum' in field['dict'] and 'struct' in field['dict']['datum']:
-        # The only allowed nesting is struct inside dict.
-        sname = field['dict']['datum']['struct']
-        struct = meta_db[sname]
-        # $_om_label's will distinguish different members of the dict by
-        # pointing to the dict keys.
-        if not struct['_om_label']:
-            raise Exception(f'{omid} is nested inside but does not have _om_label')
-        # Recurse into the nested struct.
-        oms = {}
-        for fname, field in struct['fields'].items():
-            oms |= make_om_metrics(sname, f'{omid}.{fname}', field,
-                                   labels + [struct['_om_label']], meta_db, registry)
-        return oms
+    elif 'dict' in field or 'array' in field:
+        # Keyed maps and arrays, e.g. per-domain, per-layer or per-CPU
+        # stats. The keys, or the indices for arrays, become the values of
+        # an extra label so that each member is a separate dimension of the
+        # same metric. The label is named by the field's $_om_label or, for
+        # structs, by the struct's $_om_label.
+        elem = field['dict']['datum'] if 'dict' in field else field['array']
+        label = field_om_label(field)
+
+        if type(elem) == dict and 'struct' in elem:
+            esname = elem['struct']
+            struct = meta_db[esname]
+            label = label or struct['_om_label']
+            if not label:
+                raise Exception(f'{omid} is nested inside but does not have _om_label')
+            # Recurse into the nested struct.
+            oms = {}
+            for fname, efield in struct['fields'].items():
+                oms |= make_om_metrics(esname, f'{omid}.{fname}', efield,
+                                       labels + [label], meta_db, registry)
+            return oms
+
+        if elem in ['i64', 'u64', 'float']:
+            if not label:
+                info(f'field "{omid}" does not have _om_label, skipping')
+                return {}
+            dbg(f'creating OM metric {gname}@{omid} {labels + [label]} "{desc}"')
+            return { omid: Gauge(gname, desc, labels + [label], registry=registry) }
 
     info(f'field "{omid}" has unsupported type, skipping')
     return {}
@@ -76,10 +95,18 @@ def make_om_metrics(sname, omid, field, labels, meta_db, registry):
 def update_om_metrics(resp, omid, labels, meta_db, om_metrics):
     for k, v in resp.items():
         k_omid = f'{omid}.{k}'
-        if type(v) == dict:
-            # Descend into dict.
-            for dk, dv in v.items():
-                update_om_metrics(dv, k_omid, labels + [dk], meta_db, om_metrics);
+        if type(v) == dict or type(v) == list:
+            members = v.items() if type(v) == dict else enumerate(v)
+            for dk, dv in members:
+                if dv is None:
+                    continue
+                if type(dv) == dict:
+                    # Descend into the nested struct.
+                    update_om_metrics(dv, k_omid, labels + [str(dk)], meta_db, om_metrics)
+                elif k_omid in om_metrics:
+                    # Dict or array of values, the key is the last label.
+                    dbg(f'updating {k_omid} {labels + [str(dk)]} to {dv}')
+                    om_metrics[k_omid].labels(*labels, str(dk)).set(dv)
         elif k_omid in om_metrics:
             # Update known metrics.
             dbg(f'updating {k_omid} {labels} to {v}')
@@ -0,0 +1,291 @@
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::os::unix::net::UnixDatagram;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Number of the most recent alerts kept for clients to catch up on.
+pub const ALERT_LOG_LEN: usize = 256;
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertCmp {
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Eq,
+    Ne,
+}
+
+impl AlertCmp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Gt => ">",
+            Self::Ge => ">=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Eq => "==",
+            Self::Ne => "!=",
+        }
+    }
+
+    pub fn check(&self, val: f64, threshold: f64) -> bool {
+        match self {
+            Self::Gt => val > threshold,
+            Self::Ge => val >= threshold,
+            Self::Lt => val < threshold,
+            Self::Le => val <= threshold,
+            Self::Eq => val == threshold,
+            Self::Ne => val != threshold,
+        }
+    }
+}
+
+/// Threshold rule on a numeric stats field. The rule is violated while
+/// `field cmp threshold` holds and fires once it has been violated for
+/// `duration_ms` in a row. @field is a dot-separated path into the stats
+/// tree as in project(), without wildcards, e.g. "nodes.0.load".
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AlertRule {
+    pub name: String,
+    pub field: String,
+    pub cmp: AlertCmp,
+    pub threshold: f64,
+    pub duration_ms: u64,
+}
+
+impl AlertRule {
+    pub fn new(name: &str, field: &str, cmp: AlertCmp, threshold: f64) -> Self {
+        Self {
+            name: name.to_string(),
+            field: field.to_string(),
+            cmp,
+            threshold,
+            duration_ms: 0,
+        }
+    }
+
+    pub fn set_duration(mut self, duration: Duration) -> Self {
+        self.duration_ms = duration.as_millis() as u64;
+        self
+    }
+}
+
+impl FromStr for AlertRule {
+    type Err = anyhow::Error;
+
+    /// Parse "FIELD OP THRESHOLD[@SECS]", e.g. "cpu_busy>=90@5". OP is one
+    /// of >, >=, <, <=, == and !=. The rule is named after the string.
+    fn from_str(s: &str) -> Result<Self> {
+        let (expr, secs) = match s.split_once('@') {
+            Some((expr, secs)) => (expr, Some(secs)),
+            None => (s, None),
+        };
+
+        // Two-character operators first so that ">=" isn't taken as ">".
+        let (cmp, pos, len) = [
+            (AlertCmp::Ge, ">="),
+            (AlertCmp::Le, "<="),
+            (AlertCmp::Eq, "=="),
+            (AlertCmp::Ne, "!="),
+            (AlertCmp::Gt, ">"),
+            (AlertCmp::Lt, "<"),
+        ]
+        .iter()
+        .find_map(|(cmp, op)| expr.find(op).map(|pos| (*cmp, pos, op.len())))
+        .ok_or_else(|| anyhow!("no comparison operator in alert rule {:?}", s))?;
+
+        let field = expr[..pos].trim();
+        if field.is_empty() || field.split('.').any(|seg| seg.is_empty() || seg == "*") {
+            bail!("invalid field in alert rule {:?}", s);
+        }
+        let threshold: f64 = expr[pos + len..]
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid threshold in alert rule {:?}", s))?;
+
+        let mut rule = Self::new(s.trim(), field, cmp, threshold);
+        if let Some(secs) = secs {
+            let secs: f64 = secs
+                .trim()
+                .parse()
+                .with_context(|| format!("invalid duration in alert rule {:?}", s))?;
+            rule = rule.set_duration(Duration::from_secs_f64(secs));
+        }
+        Ok(rule)
+    }
+}
+
+/// A rule starting to fire or resolving. @seq increases monotonically
+/// within a server instance and @at_us is the wallclock time in usecs.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Alert {
+    pub seq: u64,
+    pub at_us: u64,
+    pub rule: String,
+    pub field: String,
+    pub value: f64,
+    pub firing: bool,
+}
+
+/// Response to the "alerts" request. @next is the "since" argument to use
+/// for the next request. If @missed is non-zero, the client fell behind by
+/// more than ALERT_LOG_LEN alerts and that many were dropped.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AlertBatch {
+    pub alerts: Vec<Alert>,
+    pub next: u64,
+    pub missed: u64,
+}
+
+#[derive(Default)]
+struct RuleState {
+    since: Option<Instant>,
+    firing: bool,
+}
+
+/// Evaluates alert rules against stats samples and keeps a log of the
+/// resulting alerts. Usually used through StatsServerData::add_alert_rule()
+/// and StatsServer::check_alerts().
+#[derive(Default)]
+pub struct AlertEngine {
+    rules: Vec<(AlertRule, RuleState)>,
+    log: VecDeque<Alert>,
+    next_seq: u64,
+    journal: bool,
+}
+
+impl AlertEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_rule(&mut self, rule: AlertRule) {
+        self.rules.push((rule, RuleState::default()));
+    }
+
+    pub fn rules(&self) -> impl Iterator<Item = &AlertRule> {
+        self.rules.iter().map(|(rule, _)| rule)
+    }
+
+    /// Also send the alerts to the systemd journal.
+    pub fn set_journal(&mut self, enable: bool) {
+        self.journal = enable;
+    }
+
+    fn lookup(sample: &Value, field: &str) -> Option<f64> {
+        field
+            .split('.')
+            .try_fold(sample, |val, seg| match val {
+                Value::Object(map) => map.get(seg),
+                Value::Array(arr) => seg.parse::<usize>().ok().and_then(|i| arr.get(i)),
+                _ => None,
+            })
+            .and_then(|val| val.as_f64())
+    }
+
+    /// Evaluate the rules against @sample taken now. See check_at().
+    pub fn check(&mut self, sample: &Value) -> Vec<Alert> {
+        self.check_at(sample, Instant::now())
+    }
+
+    /// Evaluate the rules against @sample taken at @now and return the
+    /// rules which started firing or resolved. A field missing from
+    /// @sample, e.g. a dict entry which went away, doesn't violate its
+    /// rule.
+    pub fn check_at(&mut self, sample: &Value, now: Instant) -> Vec<Alert> {
+        let at_us = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0);
+        let mut alerts = vec![];
+
+        for (rule, state) in self.rules.iter_mut() {
+            let value = Self::lookup(sample, &rule.field);
+            let violated = value.is_some_and(|v| rule.cmp.check(v, rule.threshold));
+
+            let firing = match (violated, state.firing) {
+                (true, false) => {
+                    let since = *state.since.get_or_insert(now);
+                    now.saturating_duration_since(since).as_millis() as u64 >= rule.duration_ms
+                }
+                (false, _) => {
+                    state.since = None;
+                    false
+                }
+                (true, true) => true,
+            };
+            if firing == state.firing {
+                continue;
+            }
+            state.firing = firing;
+
+            alerts.push(Alert {
+                seq: self.next_seq,
+                at_us,
+                rule: rule.name.clone(),
+                field: rule.field.clone(),
+                value: value.unwrap_or(f64::NAN),
+                firing,
+            });
+            self.next_seq += 1;
+        }
+
+        for alert in alerts.iter() {
+            if self.journal {
+                Self::send_journal(alert);
+            }
+            if self.log.len() >= ALERT_LOG_LEN {
+                self.log.pop_front();
+            }
+            self.log.push_back(alert.clone());
+        }
+        alerts
+    }
+
+    /// Return the alerts with seq >= @since.
+    pub fn since(&self, since: u64) -> AlertBatch {
+        let oldest = self.log.front().map_or(self.next_seq, |alert| alert.seq);
+        AlertBatch {
+            alerts: self
+                .log
+                .iter()
+                .filter(|alert| alert.seq >= since)
+                .cloned()
+                .collect(),
+            next: self.next_seq,
+            missed: oldest.saturating_sub(since),
+        }
+    }
+
+    fn send_journal(alert: &Alert) {
+        let ident = std::env::current_exe()
+            .ok()
+            .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "scx_stats".into());
+        let rule = alert.rule.replace('\n', " ");
+        let msg = format!(
+            "MESSAGE=alert {} {} ({}={})\nPRIORITY={}\nSYSLOG_IDENTIFIER={}\nSCX_ALERT_RULE={}\n",
+            rule,
+            if alert.firing { "firing" } else { "resolved" },
+            alert.field,
+            alert.value,
+            if alert.firing { 4 } else { 5 },
+            ident,
+            rule,
+        );
+
+        let res =
+            UnixDatagram::unbound().and_then(|sock| sock.send_to(msg.as_bytes(), JOURNAL_SOCKET));
+        match res {
+            Ok(_) => debug!("sent alert {} to the journal", alert.seq),
+            Err(e) => warn!("failed to send alert {} to the journal ({e})", alert.seq),
+        }
+    }
+}
@@ -1,9 +1,14 @@
+use crate::compress::read_frame;
+use crate::AlertBatch;
+use crate::ConnReport;
+use crate::StatsEncoding;
 use crate::StatsErrno;
 use crate::StatsRequest;
 use crate::StatsResponse;
 use anyhow::anyhow;
 use anyhow::bail;
 use anyhow::Result;
+use log::debug;
 use log::trace;
 use serde::Deserialize;
 use std::io::BufRead;
@@ -13,7 +18,9 @@ use std::io::{self};
 use std::os::unix::net::UnixStream;
 use std::path::Path;
 use std::path::PathBuf;
+use std::thread::sleep;
 use std::time::Duration;
+use std::time::Instant;
 
 pub struct StatsClient {
     base_path: PathBuf,
@@ -23,6 +30,14 @@ pub struct StatsClient {
 
     stream: Option<UnixStream>,
     reader: Option<BufReader<UnixStream>>,
+    timeout_ms: Option<u64>,
+    compress: bool,
+    encoding: Option<StatsEncoding>,
+
+    reconnect: Option<Duration>,
+    instance: Option<u64>,
+    last_seq: Option<u64>,
+    restarted: bool,
 }
 
 impl StatsClient {
@@ -35,6 +50,14 @@ impl StatsClient {
 
             stream: None,
             reader: None,
+            timeout_ms: None,
+            compress: false,
+            encoding: None,
+
+            reconnect: None,
+            instance: None,
+            last_seq: None,
+            restarted: false,
         }
     }
 
@@ -58,15 +81,44 @@ impl StatsClient {
         self
     }
 
+    /// If the connection breaks, e.g. because the scheduler was restarted,
+    /// keep trying to reconnect for up to @timeout and retry the request
+    /// instead of failing. Use take_restarted() to find out whether the
+    /// server restarted in the meantime.
+    pub fn set_reconnect(mut self, timeout: Duration) -> Self {
+        self.reconnect = Some(timeout);
+        self
+    }
+
+    /// Ask the server to compress large responses with one of the compiled-in
+    /// encodings, see StatsEncoding. Falls back to uncompressed responses if
+    /// the server doesn't support any of them.
+    pub fn set_compression(mut self, enable: bool) -> Self {
+        self.compress = enable;
+        self
+    }
+
+    /// Encoding negotiated with the server, None if responses aren't
+    /// compressed.
+    pub fn encoding(&self) -> Option<StatsEncoding> {
+        self.encoding
+    }
+
     pub fn connect(mut self, timeout_ms: Option<u64>) -> Result<Self> {
         if self.path.is_none() {
             self.path = Some(self.base_path.join(&self.sched_path).join(&self.stats_path));
         }
+        self.timeout_ms = timeout_ms;
+        self.open_stream()?;
+        Ok(self)
+    }
+
+    fn open_stream(&mut self) -> Result<()> {
         let path = self.path.as_ref().unwrap();
 
         let stream = UnixStream::connect(path)?;
         // Apply the same timeout to both writer and reader sides if provided
-        if let Some(ms) = timeout_ms {
+        if let Some(ms) = self.timeout_ms {
             let dur = Duration::from_millis(ms);
             stream.set_write_timeout(Some(dur))?;
             stream.set_read_timeout(Some(dur))?;
@@ -74,7 +126,66 @@ impl StatsClient {
 
         self.stream = Some(stream.try_clone()?);
         self.reader = Some(BufReader::new(stream));
-        Ok(self)
+        self.encoding = None;
+        if self.compress {
+            self.negotiate_compression()?;
+        }
+        Ok(())
+    }
+
+    fn negotiate_compression(&mut self) -> Result<()> {
+        let algos: Vec<&str> = StatsEncoding::supported()
+            .iter()
+            .map(|enc| enc.name())
+            .collect();
+        if algos.is_empty() {
+            debug!("no compression support compiled in");
+            return Ok(());
+        }
+
+        let req = StatsRequest::new("compress", vec![("algos".into(), algos.join(","))]);
+        let resp = self.exchange(&req)?;
+        match Self::parse_response::<Option<String>>(resp) {
+            Ok(name) => self.encoding = name.as_deref().and_then(StatsEncoding::from_name),
+            Err(e) => debug!("server doesn't support compression ({e})"),
+        }
+        debug!("negotiated encoding: {:?}", self.encoding);
+        Ok(())
+    }
+
+    fn reconnect(&mut self, timeout: Duration) -> Result<()> {
+        self.stream = None;
+        self.reader = None;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.open_stream() {
+                Ok(()) => return Ok(()),
+                Err(e) if Instant::now() >= deadline => {
+                    return Err(e.context("failed to reconnect"));
+                }
+                Err(e) => debug!("reconnect failed, retrying ({e})"),
+            }
+            sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// Token of the server instance which sent the last response, see
+    /// StatsResponse.
+    pub fn instance(&self) -> Option<u64> {
+        self.instance
+    }
+
+    /// Sequence number of the last response.
+    pub fn last_seq(&self) -> Option<u64> {
+        self.last_seq
+    }
+
+    /// Return whether a different server instance answered since the last
+    /// call, in which case the counters have likely been reset and e.g.
+    /// StatsRates should be reset too.
+    pub fn take_restarted(&mut self) -> bool {
+        std::mem::take(&mut self.restarted)
     }
 
     pub fn send_request<T>(&mut self, req: &StatsRequest) -> Result<T>
@@ -85,6 +196,25 @@ impl StatsClient {
             bail!("not connected");
         }
 
+        let mut req = req.clone();
+        if let Some(instance) = self.instance {
+            req.args.insert("resume".into(), instance.to_string());
+        }
+
+        let resp = match (self.exchange(&req), self.reconnect) {
+            (Ok(v), _) => v,
+            (Err(e), Some(timeout)) => {
+                debug!("request failed, reconnecting ({e})");
+                self.reconnect(timeout)?;
+                self.exchange(&req)?
+            }
+            (Err(e), None) => return Err(e),
+        };
+
+        Self::parse_response(resp)
+    }
+
+    fn exchange(&mut self, req: &StatsRequest) -> Result<StatsResponse> {
         let req = serde_json::to_string(&req)? + "\n";
         trace!("Sending: {}", req.trim());
         // Attempt write with timeout
@@ -96,22 +226,51 @@ impl StatsClient {
             }
         }
 
-        let mut line = String::new();
-        match self.reader.as_mut().unwrap().read_line(&mut line) {
-            Ok(0) => return Err(anyhow!("connection closed")),
-            Ok(_) => { /* proceed */ }
-            Err(e) => {
-                if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock {
-                    return Err(anyhow!("read timed out"));
-                } else {
-                    return Err(e.into());
+        let line = match self.encoding {
+            Some(_) => String::from_utf8(read_frame(self.reader.as_mut().unwrap())?)?,
+            None => {
+                let mut line = String::new();
+                match self.reader.as_mut().unwrap().read_line(&mut line) {
+                    Ok(0) => return Err(anyhow!("connection closed")),
+                    Ok(_) => { /* proceed */ }
+                    Err(e) => {
+                        if e.kind() == io::ErrorKind::TimedOut
+                            || e.kind() == io::ErrorKind::WouldBlock
+                        {
+                            return Err(anyhow!("read timed out"));
+                        } else {
+                            return Err(e.into());
+                        }
+                    }
                 }
+                line
             }
-        }
+        };
 
         trace!("Received: {}", line.trim());
-        let mut resp: StatsResponse = serde_json::from_str(&line)?;
+        let resp: StatsResponse = serde_json::from_str(&line)?;
 
+        if let Some(instance) = resp.instance() {
+            if self.instance.is_some_and(|v| v != instance) {
+                debug!(
+                    "server restarted, instance {:?} -> {}",
+                    self.instance, instance
+                );
+                self.restarted = true;
+            }
+            self.instance = Some(instance);
+        }
+        if let Some(seq) = resp.seq() {
+            self.last_seq = Some(seq);
+        }
+
+        Ok(resp)
+    }
+
+    fn parse_response<T>(mut resp: StatsResponse) -> Result<T>
+    where
+        T: for<'a> Deserialize<'a>,
+    {
         let (errno, resp) = (
             resp.errno,
             resp.args.remove("resp").unwrap_or(serde_json::Value::Null),
@@ -130,4 +289,34 @@ impl StatsClient {
     {
         self.send_request(&StatsRequest::new(req, args))
     }
+
+    /// Return the alerts the server emitted since the one with seq @since.
+    /// Pass the returned `next` as @since on the next call to follow the
+    /// alert stream.
+    pub fn poll_alerts(&mut self, since: u64) -> Result<AlertBatch> {
+        self.request("alerts", vec![("since".into(), since.to_string())])
+    }
+
+    /// Return the clients connected to the server including this one, e.g.
+    /// to find a monitoring agent polling the server too often.
+    pub fn clients(&mut self) -> Result<ConnReport> {
+        self.request("clients", vec![])
+    }
+
+    /// Like request() but ask the server to only send @fields of the
+    /// response, see project() for the syntax. Useful when only a few numbers
+    /// out of a large stats struct are needed, in which case @T can be a
+    /// struct with just those fields.
+    pub fn request_fields<T>(
+        &mut self,
+        req: &str,
+        mut args: Vec<(String, String)>,
+        fields: &[&str],
+    ) -> Result<T>
+    where
+        T: for<'a> Deserialize<'a>,
+    {
+        args.push(("fields".into(), fields.join(",")));
+        self.send_request(&StatsRequest::new(req, args))
+    }
 }
@@ -0,0 +1,144 @@
+use anyhow::{anyhow, bail, Result};
+use std::io::{Read, Write};
+
+/// Responses smaller than this are sent uncompressed even after compression
+/// has been negotiated as they wouldn't gain much.
+pub const COMPRESS_MIN_BYTES: usize = 1024;
+
+// Upper bound on the size of a frame to avoid allocating garbage lengths.
+const MAX_FRAME_BYTES: usize = 256 << 20;
+
+const TAG_RAW: u8 = 0;
+const TAG_GZIP: u8 = 1;
+const TAG_ZSTD: u8 = 2;
+
+/// Compression algorithm negotiated with the "compress" request. Which ones
+/// are available depends on the "gzip" and "zstd" features.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatsEncoding {
+    Gzip,
+    Zstd,
+}
+
+impl StatsEncoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim() {
+            "gzip" => Some(Self::Gzip),
+            "zstd" => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Compiled-in encodings, most preferred first.
+    pub fn supported() -> Vec<Self> {
+        let mut encs = vec![];
+        if cfg!(feature = "zstd") {
+            encs.push(Self::Zstd);
+        }
+        if cfg!(feature = "gzip") {
+            encs.push(Self::Gzip);
+        }
+        encs
+    }
+
+    /// Pick the most preferred supported encoding out of the comma-separated
+    /// list of names @offered by the peer.
+    pub fn negotiate(offered: &str) -> Option<Self> {
+        let offered: Vec<Self> = offered.split(',').filter_map(Self::from_name).collect();
+        Self::supported()
+            .into_iter()
+            .find(|enc| offered.contains(enc))
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            Self::Gzip => TAG_GZIP,
+            Self::Zstd => TAG_ZSTD,
+        }
+    }
+
+    fn compress(&self, buf: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Self::Gzip => {
+                let mut enc =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+                enc.write_all(buf)?;
+                Ok(enc.finish()?)
+            }
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Ok(zstd::encode_all(buf, 1)?),
+            #[allow(unreachable_patterns)]
+            _ => bail!(
+                "{} support not compiled in ({} bytes)",
+                self.name(),
+                buf.len()
+            ),
+        }
+    }
+
+    fn decompress(&self, buf: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Self::Gzip => {
+                let mut out = vec![];
+                flate2::read::GzDecoder::new(buf).read_to_end(&mut out)?;
+                Ok(out)
+            }
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Ok(zstd::decode_all(buf)?),
+            #[allow(unreachable_patterns)]
+            _ => bail!(
+                "{} support not compiled in ({} bytes)",
+                self.name(),
+                buf.len()
+            ),
+        }
+    }
+}
+
+/// Once compression is negotiated, each response is sent as a frame of a
+/// tag byte telling the encoding, the little-endian u32 length of the
+/// payload and the payload itself. Payloads shorter than COMPRESS_MIN_BYTES
+/// are sent as-is. Returns the number of bytes written.
+pub fn write_frame<W: Write>(w: &mut W, enc: StatsEncoding, payload: &[u8]) -> Result<usize> {
+    let (tag, body) = if payload.len() >= COMPRESS_MIN_BYTES {
+        (enc.tag(), enc.compress(payload)?)
+    } else {
+        (TAG_RAW, payload.to_vec())
+    };
+
+    let mut frame = Vec::with_capacity(5 + body.len());
+    frame.push(tag);
+    frame.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&body);
+    w.write_all(&frame)?;
+    Ok(frame.len())
+}
+
+/// Read a frame written by write_frame() and return the decoded payload.
+pub fn read_frame<R: Read>(r: &mut R) -> Result<Vec<u8>> {
+    let mut hdr = [0u8; 5];
+    r.read_exact(&mut hdr)?;
+
+    let len = u32::from_le_bytes(hdr[1..].try_into().unwrap()) as usize;
+    if len > MAX_FRAME_BYTES {
+        bail!("frame too large ({} bytes)", len);
+    }
+    let mut body = vec![0u8; len];
+    r.read_exact(&mut body)?;
+
+    match hdr[0] {
+        TAG_RAW => Ok(body),
+        TAG_GZIP => StatsEncoding::Gzip.decompress(&body),
+        TAG_ZSTD => StatsEncoding::Zstd.decompress(&body),
+        tag => Err(anyhow!("unknown frame tag {}", tag)),
+    }
+}
@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Weight of the latest request interval in ConnStats::interval_ms.
+const INTERVAL_EWMA_WEIGHT: f64 = 0.25;
+
+/// Server-side view of a connected client, reported by the reserved
+/// "clients" request.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ConnStats {
+    /// Connection ID, unique within the server instance.
+    pub id: u64,
+    /// Peer process as reported by SO_PEERCRED and its comm.
+    pub pid: Option<i32>,
+    pub comm: Option<String>,
+    /// UNIX time in seconds when the connection was accepted.
+    pub connected_at: u64,
+    pub nr_requests: u64,
+    /// Requests answered with a non-zero errno.
+    pub nr_errors: u64,
+    /// "stats" requests which failed to produce a sample.
+    pub nr_dropped: u64,
+    pub bytes_sent: u64,
+    /// Moving average of the interval between requests and the last one.
+    pub interval_ms: f64,
+    pub last_interval_ms: f64,
+    #[serde(skip)]
+    last_req_at: Option<Instant>,
+}
+
+impl ConnStats {
+    fn new(id: u64, stream: &UnixStream) -> Self {
+        let pid = peer_pid(stream);
+        let comm = pid.and_then(|pid| {
+            std::fs::read_to_string(format!("/proc/{}/comm", pid))
+                .ok()
+                .map(|comm| comm.trim_end().to_string())
+        });
+        let connected_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        Self {
+            id,
+            pid,
+            comm,
+            connected_at,
+            ..Default::default()
+        }
+    }
+
+    fn account_req(&mut self, now: Instant) {
+        if let Some(last) = self.last_req_at {
+            let intv = now.duration_since(last).as_secs_f64() * 1000.0;
+            self.interval_ms = match self.nr_requests {
+                1 => intv,
+                _ => self.interval_ms * (1.0 - INTERVAL_EWMA_WEIGHT) + intv * INTERVAL_EWMA_WEIGHT,
+            };
+            self.last_interval_ms = intv;
+        }
+        self.last_req_at = Some(now);
+        self.nr_requests += 1;
+    }
+}
+
+/// Response of the "clients" request.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ConnReport {
+    /// Number of connections accepted since the server was launched.
+    pub nr_accepted: u64,
+    pub clients: Vec<ConnStats>,
+}
+
+#[derive(Default)]
+pub(crate) struct ConnTable {
+    nr_accepted: u64,
+    conns: BTreeMap<u64, ConnStats>,
+}
+
+impl ConnTable {
+    pub(crate) fn add(&mut self, stream: &UnixStream) -> u64 {
+        let id = self.nr_accepted;
+        self.nr_accepted += 1;
+        self.conns.insert(id, ConnStats::new(id, stream));
+        id
+    }
+
+    pub(crate) fn remove(&mut self, id: u64) {
+        self.conns.remove(&id);
+    }
+
+    pub(crate) fn account(&mut self, id: u64, errno: i32, dropped: bool, bytes: usize) {
+        if let Some(conn) = self.conns.get_mut(&id) {
+            conn.account_req(Instant::now());
+            if errno != 0 {
+                conn.nr_errors += 1;
+            }
+            if dropped {
+                conn.nr_dropped += 1;
+            }
+            conn.bytes_sent += bytes as u64;
+        }
+    }
+
+    pub(crate) fn report(&self) -> ConnReport {
+        ConnReport {
+            nr_accepted: self.nr_accepted,
+            clients: self.conns.values().cloned().collect(),
+        }
+    }
+}
+
+fn peer_pid(stream: &UnixStream) -> Option<i32> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    match ret {
+        0 if cred.pid > 0 => Some(cred.pid),
+        _ => None,
+    }
+}
@@ -12,9 +12,32 @@ pub use server::{
     StatsRequest, StatsResponse, StatsServer, StatsServerData, ToJson,
 };
 
+mod conn;
+pub use conn::{ConnReport, ConnStats};
+
+mod compress;
+pub use compress::{StatsEncoding, COMPRESS_MIN_BYTES};
+
+mod project;
+pub use project::project;
+
 mod client;
 pub use client::StatsClient;
 
+mod rate;
+pub use rate::{counter_delta, counter_rate, CounterRate, StatsRates};
+
+mod replay;
+pub use replay::{StatsRecorder, StatsRecording, StatsReplay, StatsSample, RECORDING_VERSION};
+
+mod snapshot;
+pub use snapshot::{
+    SnapshotValue, StatsSnapshot, StatsSnapshotter, SNAPSHOT_MAGIC, SNAPSHOT_VERSION,
+};
+
+mod alert;
+pub use alert::{Alert, AlertBatch, AlertCmp, AlertEngine, AlertRule, ALERT_LOG_LEN};
+
 pub mod prelude {
     pub use crate::*;
 }
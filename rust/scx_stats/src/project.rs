@@ -0,0 +1,91 @@
+use anyhow::{bail, Result};
+use serde_json::{Map, Value};
+
+/// Prune @value down to the fields listed in @fields. Each field is a
+/// dot-separated path into the stats tree, e.g. "cpu_busy" or
+/// "nodes.0.load". A path may end at a struct or dict to select the whole
+/// subtree and "*" matches every entry of a dict or array, e.g.
+/// "layers.*.util".
+///
+/// The result keeps the shape of @value with everything which wasn't
+/// selected dropped. Array entries which weren't selected are kept as nulls
+/// so that the indices don't shift. It's an error for a path to not match
+/// anything, which catches typos in field names.
+pub fn project(value: &Value, fields: &[&str]) -> Result<Value> {
+    let mut out = Value::Null;
+    for field in fields.iter() {
+        let segs: Vec<&str> = field.split('.').collect();
+        if segs.iter().any(|seg| seg.is_empty()) {
+            bail!("invalid field {:?}", field);
+        }
+        if !project_path(value, &segs, &mut out) {
+            bail!("field {:?} doesn't exist", field);
+        }
+    }
+    Ok(out)
+}
+
+fn project_path(src: &Value, segs: &[&str], dst: &mut Value) -> bool {
+    let Some((seg, rest)) = segs.split_first() else {
+        *dst = src.clone();
+        return true;
+    };
+
+    match src {
+        Value::Object(map) => {
+            if dst.is_null() {
+                *dst = Value::Object(Map::new());
+            }
+            let Value::Object(dst_map) = dst else {
+                return false;
+            };
+
+            let keys: Vec<&String> = match *seg {
+                "*" => map.keys().collect(),
+                key => map.get_key_value(key).map(|(k, _)| k).into_iter().collect(),
+            };
+
+            let mut matched = false;
+            for key in keys {
+                let created = !dst_map.contains_key(key);
+                let child = dst_map.entry(key.clone()).or_insert(Value::Null);
+                if project_path(&map[key], rest, child) {
+                    matched = true;
+                } else if created {
+                    dst_map.remove(key);
+                }
+            }
+            matched
+        }
+        Value::Array(arr) => {
+            if dst.is_null() {
+                *dst = Value::Array(vec![Value::Null; arr.len()]);
+            }
+            let Value::Array(dst_arr) = dst else {
+                return false;
+            };
+
+            let idxs: Vec<usize> = match *seg {
+                "*" => (0..arr.len()).collect(),
+                idx => idx
+                    .parse()
+                    .ok()
+                    .filter(|i| *i < arr.len())
+                    .into_iter()
+                    .collect(),
+            };
+
+            let mut matched = false;
+            for idx in idxs {
+                let created = dst_arr[idx].is_null();
+                if project_path(&arr[idx], rest, &mut dst_arr[idx]) {
+                    matched = true;
+                } else if created {
+                    dst_arr[idx] = Value::Null;
+                }
+            }
+            matched
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,205 @@
+use crate::{StatsData, StatsKind, StatsMeta};
+use anyhow::{anyhow, bail, Result};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// Return how much a monotonically increasing counter advanced from @prev to
+/// @cur. A counter going backwards can't be told apart from a restarted
+/// source (e.g. the scheduler was reloaded), so it's treated as such and @cur
+/// is returned instead of a huge bogus value from the wrapping subtraction.
+pub fn counter_delta(prev: u64, cur: u64) -> u64 {
+    if cur >= prev {
+        cur - prev
+    } else {
+        cur
+    }
+}
+
+/// Per-second rate of a counter which advanced from @prev to @cur over @dur.
+pub fn counter_rate(prev: u64, cur: u64, dur: Duration) -> f64 {
+    let secs = dur.as_secs_f64();
+    if secs > 0.0 {
+        counter_delta(prev, cur) as f64 / secs
+    } else {
+        0.0
+    }
+}
+
+/// Server-side helper which turns a single counter into a per-second rate.
+/// Each update returns the rate since the previous update, 0.0 for the first
+/// one.
+#[derive(Clone, Debug, Default)]
+pub struct CounterRate {
+    last: Option<(u64, Instant)>,
+}
+
+impl CounterRate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, cur: u64) -> f64 {
+        self.update_at(cur, Instant::now())
+    }
+
+    pub fn update_at(&mut self, cur: u64, at: Instant) -> f64 {
+        let rate = match self.last {
+            Some((prev, prev_at)) => counter_rate(prev, cur, at.saturating_duration_since(prev_at)),
+            None => 0.0,
+        };
+        self.last = Some((cur, at));
+        rate
+    }
+}
+
+/// Client-side helper which converts the fields marked with the `counter`
+/// attribute in a stream of stats samples into per-second rates. All other
+/// fields are passed through as-is. The metadata is what the "stats_meta"
+/// request returns.
+pub struct StatsRates {
+    meta: BTreeMap<String, StatsMeta>,
+    top: String,
+    last: Option<(Value, Instant)>,
+}
+
+impl StatsRates {
+    /// Track samples of the stats struct marked `top` in @meta.
+    pub fn new(meta: BTreeMap<String, StatsMeta>) -> Result<Self> {
+        let top = meta
+            .values()
+            .find(|m| m.attrs.top.is_some())
+            .map(|m| m.name.clone())
+            .ok_or_else(|| anyhow!("top-level stats metadata missing"))?;
+        Self::with_top(meta, &top)
+    }
+
+    /// Track samples of the stats struct @top.
+    pub fn with_top(meta: BTreeMap<String, StatsMeta>, top: &str) -> Result<Self> {
+        if !meta.contains_key(top) {
+            bail!("unknown stats meta name {}", top);
+        }
+        Ok(Self {
+            meta,
+            top: top.to_string(),
+            last: None,
+        })
+    }
+
+    /// Forget the previous sample, e.g. because StatsClient::take_restarted()
+    /// reported that the server restarted. The next sample primes the
+    /// tracker again instead of being compared against the old instance.
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+
+    /// Feed a new sample taken now. See update_at().
+    pub fn update(&mut self, cur: Value) -> Result<Option<Value>> {
+        self.update_at(cur, Instant::now())
+    }
+
+    /// Feed a new sample taken at @at and return it with the counter fields
+    /// replaced by their per-second rates since the previous sample. Returns
+    /// None for the first sample as there's nothing to compare against.
+    /// Counters which are missing from the previous sample, e.g. a dict entry
+    /// which just appeared, report 0.0.
+    pub fn update_at(&mut self, cur: Value, at: Instant) -> Result<Option<Value>> {
+        let res = match &self.last {
+            Some((prev, prev_at)) => {
+                let secs = at.saturating_duration_since(*prev_at).as_secs_f64();
+                let mut out = cur.clone();
+                self.rates_inner(&self.top, prev, &mut out, secs, 0)?;
+                Some(out)
+            }
+            None => None,
+        };
+        self.last = Some((cur, at));
+        Ok(res)
+    }
+
+    fn rate(prev: Option<&Value>, cur: &mut Value, secs: f64) {
+        let Some(cur_v) = cur.as_u64() else {
+            return;
+        };
+        let rate = match prev.and_then(|v| v.as_u64()) {
+            Some(prev_v) if secs > 0.0 => counter_delta(prev_v, cur_v) as f64 / secs,
+            _ => 0.0,
+        };
+        *cur = Value::from(rate);
+    }
+
+    fn rates_inner(
+        &self,
+        name: &str,
+        prev: &Value,
+        cur: &mut Value,
+        secs: f64,
+        depth: usize,
+    ) -> Result<()> {
+        let m = match self.meta.get(name) {
+            Some(v) => v,
+            None => bail!("unknown stats meta name {}", name),
+        };
+        if depth > self.meta.len() {
+            bail!("loop in stats meta detected, {} already nested", name);
+        }
+
+        let Some(cur_obj) = cur.as_object_mut() else {
+            return Ok(());
+        };
+
+        for (fname, field) in m.fields.iter() {
+            let Some(cur_f) = cur_obj.get_mut(fname) else {
+                continue;
+            };
+            let prev_f = prev.get(fname);
+            let is_counter = field.attrs.counter.is_some();
+
+            match &field.data {
+                StatsData::Datum(StatsKind::Struct(inner)) => {
+                    if let Some(prev_f) = prev_f {
+                        self.rates_inner(inner, prev_f, cur_f, secs, depth + 1)?;
+                    }
+                }
+                StatsData::Datum(_) if is_counter => Self::rate(prev_f, cur_f, secs),
+                StatsData::Array(kind) => {
+                    let Some(arr) = cur_f.as_array_mut() else {
+                        continue;
+                    };
+                    for (i, elem) in arr.iter_mut().enumerate() {
+                        let prev_e = prev_f.and_then(|v| v.get(i));
+                        match kind {
+                            StatsKind::Struct(inner) => {
+                                if let Some(prev_e) = prev_e {
+                                    self.rates_inner(inner, prev_e, elem, secs, depth + 1)?;
+                                }
+                            }
+                            _ if is_counter => Self::rate(prev_e, elem, secs),
+                            _ => {}
+                        }
+                    }
+                }
+                StatsData::Dict { key: _, datum } => {
+                    let Some(dict) = cur_f.as_object_mut() else {
+                        continue;
+                    };
+                    for (key, elem) in dict.iter_mut() {
+                        let prev_e = prev_f.and_then(|v| v.get(key));
+                        match datum {
+                            StatsKind::Struct(inner) => {
+                                if let Some(prev_e) = prev_e {
+                                    self.rates_inner(inner, prev_e, elem, secs, depth + 1)?;
+                                }
+                            }
+                            _ if is_counter => Self::rate(prev_e, elem, secs),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,260 @@
+use crate::{StatsClient, StatsErrno, StatsMeta, StatsServerData};
+use anyhow::{anyhow, bail, Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Bumped whenever the recording format changes incompatibly.
+pub const RECORDING_VERSION: u32 = 1;
+
+/// First line of a recording.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct RecordingHeader {
+    version: u32,
+    /// UNIX time in seconds when the recording started.
+    started_at: u64,
+    meta: BTreeMap<String, StatsMeta>,
+}
+
+/// A recorded response to a "stats" request.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StatsSample {
+    /// Milliseconds since the recording started.
+    pub at_ms: u64,
+    pub target: String,
+    /// Non-zero if the server answered with an error, in which case @resp is
+    /// the error message.
+    pub errno: i32,
+    pub resp: Value,
+}
+
+/// Records the "stats" responses of a live server as JSON lines, one per
+/// sample after a header carrying the stats metadata. Every line is flushed
+/// as it's written so that a recorder which gets killed, e.g. together with
+/// a misbehaving scheduler, still leaves a usable recording behind.
+pub struct StatsRecorder<W: Write> {
+    w: W,
+    started_at: Instant,
+    nr_samples: u64,
+}
+
+impl<W: Write> StatsRecorder<W> {
+    /// Start a recording of the server @client is connected to.
+    pub fn new(mut w: W, client: &mut StatsClient) -> Result<Self> {
+        let header = RecordingHeader {
+            version: RECORDING_VERSION,
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            meta: client.request("stats_meta", vec![])?,
+        };
+        writeln!(w, "{}", serde_json::to_string(&header)?)?;
+        w.flush()?;
+
+        Ok(Self {
+            w,
+            started_at: Instant::now(),
+            nr_samples: 0,
+        })
+    }
+
+    /// Request a sample of @target and append it. Errors returned by the
+    /// server are recorded too so that they're reproduced on replay, only
+    /// transport errors fail the call.
+    pub fn record(&mut self, client: &mut StatsClient, target: &str) -> Result<()> {
+        let at_ms = self.started_at.elapsed().as_millis() as u64;
+        let (errno, resp) =
+            match client.request::<Value>("stats", vec![("target".into(), target.into())]) {
+                Ok(v) => (0, v),
+                Err(e) => match e.downcast_ref::<StatsErrno>() {
+                    Some(errno) => (errno.0, Value::String(e.root_cause().to_string())),
+                    None => return Err(e),
+                },
+            };
+
+        let sample = StatsSample {
+            at_ms,
+            target: target.into(),
+            errno,
+            resp,
+        };
+        writeln!(self.w, "{}", serde_json::to_string(&sample)?)?;
+        self.w.flush()?;
+        self.nr_samples += 1;
+        Ok(())
+    }
+
+    pub fn nr_samples(&self) -> u64 {
+        self.nr_samples
+    }
+}
+
+/// A recording made by StatsRecorder.
+#[derive(Clone, Debug)]
+pub struct StatsRecording {
+    /// UNIX time in seconds when the recording started.
+    pub started_at: u64,
+    pub meta: BTreeMap<String, StatsMeta>,
+    pub samples: Vec<StatsSample>,
+}
+
+impl StatsRecording {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let f = std::fs::File::open(path).with_context(|| format!("opening {path:?}"))?;
+        Self::read(BufReader::new(f)).with_context(|| format!("reading {path:?}"))
+    }
+
+    pub fn read<R: BufRead>(r: R) -> Result<Self> {
+        let mut lines = r.lines();
+
+        let header: RecordingHeader = match lines.next() {
+            Some(line) => serde_json::from_str(&line?)?,
+            None => bail!("empty recording"),
+        };
+        if header.version != RECORDING_VERSION {
+            bail!(
+                "unsupported recording version {} (expected {})",
+                header.version,
+                RECORDING_VERSION
+            );
+        }
+
+        let mut samples = vec![];
+        let mut lines = lines.peekable();
+        while let Some(line) = lines.next() {
+            match serde_json::from_str::<StatsSample>(&line?) {
+                Ok(v) => samples.push(v),
+                // The recorder was killed in the middle of writing a sample.
+                Err(e) if lines.peek().is_none() => {
+                    warn!("ignoring truncated last sample ({e})");
+                }
+                Err(e) => Err(e).with_context(|| format!("sample {}", samples.len()))?,
+            }
+        }
+
+        Ok(Self {
+            started_at: header.started_at,
+            meta: header.meta,
+            samples,
+        })
+    }
+
+    /// Time between the start of the recording and the last sample.
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.samples.last().map_or(0, |s| s.at_ms))
+    }
+
+    /// The targets which have samples.
+    pub fn targets(&self) -> BTreeSet<&str> {
+        self.samples.iter().map(|s| s.target.as_str()).collect()
+    }
+
+    /// The latest sample of @target recorded at or before @at, or the first
+    /// one if @at precedes all of them.
+    pub fn sample_at(&self, target: &str, at: Duration) -> Option<&StatsSample> {
+        let at_ms = at.as_millis() as u64;
+        let mut found = None;
+        for sample in self.samples.iter().filter(|s| s.target == target) {
+            if found.is_some() && sample.at_ms > at_ms {
+                break;
+            }
+            found = Some(sample);
+        }
+        found
+    }
+}
+
+/// Serves a StatsRecording back through a StatsServer so that clients can't
+/// tell it from the scheduler the recording was made of. The samples are
+/// handed out according to the time since the first request, scaled by the
+/// speed, rather than one per request, so that the recorded timeline is
+/// preserved however often the client polls. Once past the end, the last
+/// sample keeps being served unless repeating is enabled.
+///
+/// ```ignore
+/// let rec = StatsRecording::load("session.jsonl")?;
+/// let data = StatsReplay::new(rec).set_speed(4.0).server_data();
+/// let _server = StatsServer::<(), ()>::new(data).set_path(path).launch()?;
+/// ```
+pub struct StatsReplay {
+    rec: Arc<StatsRecording>,
+    speed: f64,
+    repeat: bool,
+}
+
+impl StatsReplay {
+    pub fn new(rec: StatsRecording) -> Self {
+        Self {
+            rec: Arc::new(rec),
+            speed: 1.0,
+            repeat: false,
+        }
+    }
+
+    /// Replay @speed times faster than recorded, e.g. 10.0 to go through a
+    /// ten minute recording in a minute.
+    pub fn set_speed(mut self, speed: f64) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Start over from the beginning after the last sample.
+    pub fn set_repeat(mut self, enable: bool) -> Self {
+        self.repeat = enable;
+        self
+    }
+
+    /// Position in the recording @elapsed after the replay started.
+    fn position(&self, elapsed: Duration) -> Duration {
+        let pos = elapsed.mul_f64(self.speed.max(0.0));
+        let dur = self.rec.duration();
+        match self.repeat && !dur.is_zero() {
+            true => Duration::from_nanos((pos.as_nanos() % dur.as_nanos()) as u64),
+            false => pos,
+        }
+    }
+
+    pub fn server_data<Req, Res>(self) -> StatsServerData<Req, Res>
+    where
+        Req: Send + 'static,
+        Res: Send + 'static,
+    {
+        let mut data = StatsServerData::new();
+        for meta in self.rec.meta.values() {
+            data = data.add_meta(meta.clone());
+        }
+
+        let targets: Vec<String> = self.rec.targets().into_iter().map(String::from).collect();
+        let replay = Arc::new(self);
+        // Shared by all targets and connections so that they stay in sync.
+        let started_at: Arc<Mutex<Option<Instant>>> = Arc::new(Mutex::new(None));
+
+        for target in targets {
+            let (replay, started_at) = (replay.clone(), started_at.clone());
+            let name = target.clone();
+            data = data.add_stats(
+                &name,
+                Box::new(move |_args, _chan| {
+                    let started_at = *started_at.lock().unwrap().get_or_insert_with(Instant::now);
+                    let pos = replay.position(started_at.elapsed());
+                    let sample = replay
+                        .rec
+                        .sample_at(&target, pos)
+                        .ok_or_else(|| anyhow!("no samples of {:?}", &target))?;
+                    match sample.errno {
+                        0 => Ok(sample.resp.clone()),
+                        errno => Err(anyhow!("{}", &sample.resp).context(StatsErrno(errno))),
+                    }
+                }),
+            );
+        }
+        data
+    }
+}
@@ -1,4 +1,9 @@
+use crate::compress::write_frame;
+use crate::conn::{ConnReport, ConnTable};
+use crate::project::project;
 use crate::StatsClient;
+use crate::StatsEncoding;
+use crate::{Alert, AlertEngine, AlertRule};
 use crate::{Meta, StatsData, StatsKind, StatsMeta};
 use anyhow::{anyhow, bail, Context, Result};
 use crossbeam::channel::{unbounded, Receiver, RecvError, Select, Sender};
@@ -9,9 +14,10 @@ use std::collections::{BTreeMap, BTreeSet};
 use std::io::{BufRead, BufReader, Write};
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread::spawn;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub trait StatsReader<Req, Res>:
     FnMut(&BTreeMap<String, String>, (&Sender<Req>, &Receiver<Res>)) -> Result<Value>
@@ -116,12 +122,32 @@ impl StatsRequest {
     }
 }
 
+/// Besides "resp", the server adds the following to the args of every
+/// response so that clients can tell when the server was restarted:
+///
+/// - "instance": Token identifying the server instance. A client seeing it
+///   change knows that the server was restarted and that counters may have
+///   been reset. Clients can pass it back as the "resume" arg of a request to
+///   have the server confirm it with "resumed".
+///
+/// - "seq": Sequence number of the response, monotonically increasing
+///   across all connections of the server instance.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct StatsResponse {
     pub errno: i32,
     pub args: BTreeMap<String, Value>,
 }
 
+impl StatsResponse {
+    pub fn instance(&self) -> Option<u64> {
+        self.args.get("instance").and_then(|v| v.as_u64())
+    }
+
+    pub fn seq(&self) -> Option<u64> {
+        self.args.get("seq").and_then(|v| v.as_u64())
+    }
+}
+
 pub struct StatsErrno(pub i32);
 
 impl std::fmt::Display for StatsErrno {
@@ -174,6 +200,7 @@ where
     top: Option<String>,
     meta: BTreeMap<String, StatsMeta>,
     ops: BTreeMap<String, Arc<Mutex<StatsOps<Req, Res>>>>,
+    alerts: AlertEngine,
 }
 
 impl<Req, Res> StatsServerData<Req, Res>
@@ -186,6 +213,7 @@ where
             top: None,
             meta: BTreeMap::new(),
             ops: BTreeMap::new(),
+            alerts: AlertEngine::new(),
         }
     }
 
@@ -218,6 +246,18 @@ where
         self.add_ops(name, ops)
     }
 
+    /// Register an alert rule evaluated by StatsServer::check_alerts().
+    pub fn add_alert_rule(mut self, rule: AlertRule) -> Self {
+        self.alerts.add_rule(rule);
+        self
+    }
+
+    /// Also send the alerts to the systemd journal.
+    pub fn set_alert_journal(mut self, enable: bool) -> Self {
+        self.alerts.set_journal(enable);
+        self
+    }
+
     fn visit_meta_inner(
         &self,
         name: &str,
@@ -351,6 +391,9 @@ where
     data: Arc<Mutex<StatsServerData<Req, Res>>>,
     inner_ch: ChannelPair<Req, Res>,
     exit: Arc<AtomicBool>,
+    instance: u64,
+    seq: Arc<AtomicU64>,
+    conns: Arc<Mutex<ConnTable>>,
 }
 
 impl<Req, Res> StatsServerInner<Req, Res>
@@ -363,12 +406,17 @@ where
         data: Arc<Mutex<StatsServerData<Req, Res>>>,
         inner_ch: ChannelPair<Req, Res>,
         exit: Arc<AtomicBool>,
+        instance: u64,
+        conns: Arc<Mutex<ConnTable>>,
     ) -> Self {
         Self {
             listener,
             data,
             inner_ch,
             exit,
+            instance,
+            seq: Arc::new(AtomicU64::new(0)),
+            conns,
         }
     }
 
@@ -385,12 +433,16 @@ where
     }
 
     fn handle_request(
-        line: String,
+        mut req: StatsRequest,
         data: &Arc<Mutex<StatsServerData<Req, Res>>>,
         ch: &ChannelPair<Req, Res>,
         open_ops: &mut StatsOpenOps<Req, Res>,
+        conns: &Arc<Mutex<ConnTable>>,
     ) -> Result<StatsResponse> {
-        let req: StatsRequest = serde_json::from_str(&line)?;
+        // Handled in serve(), don't leak it to the readers.
+        req.args.remove("resume");
+        // Applied to the output below, not for the readers either.
+        let fields = req.args.remove("fields");
 
         match req.req.as_str() {
             "stats" => {
@@ -415,11 +467,35 @@ where
 
                 let read = &mut open_ops.map.get_mut(target).unwrap().1;
 
-                let resp = read(&req.args, (&ch.req, &ch.res))?;
+                let mut resp = read(&req.args, (&ch.req, &ch.res))?;
+
+                if let Some(fields) = fields {
+                    let fields: Vec<&str> = fields.split(',').map(|f| f.trim()).collect();
+                    resp =
+                        project(&resp, &fields).map_err(|e| e.context(StatsErrno(libc::EINVAL)))?;
+                }
 
                 Self::build_resp(0, &resp)
             }
             "stats_meta" => Ok(Self::build_resp(0, &data.lock().unwrap().meta)?),
+            "alerts" => {
+                let since = match req.args.get("since") {
+                    Some(v) => v.parse::<u64>().map_err(|e| {
+                        anyhow!("invalid since {:?} ({})", v, e).context(StatsErrno(libc::EINVAL))
+                    })?,
+                    None => 0,
+                };
+                Ok(Self::build_resp(
+                    0,
+                    &data.lock().unwrap().alerts.since(since),
+                )?)
+            }
+            "alert_rules" => {
+                let data = data.lock().unwrap();
+                let rules: Vec<&AlertRule> = data.alerts.rules().collect();
+                Ok(Self::build_resp(0, &rules)?)
+            }
+            "clients" => Ok(Self::build_resp(0, &conns.lock().unwrap().report())?),
             req => Err(anyhow!("unknown command {:?}", req).context(StatsErrno(libc::EINVAL)))?,
         }
     }
@@ -429,9 +505,13 @@ where
         data: Arc<Mutex<StatsServerData<Req, Res>>>,
         inner_ch: ChannelPair<Req, Res>,
         exit: Arc<AtomicBool>,
+        instance: u64,
+        seq: Arc<AtomicU64>,
+        (conns, conn_id): (Arc<Mutex<ConnTable>>, u64),
     ) -> Result<()> {
         let mut stream_reader = BufReader::new(stream.try_clone()?);
         let mut open_ops = StatsOpenOps::new();
+        let mut encoding: Option<StatsEncoding> = None;
 
         loop {
             let mut line = String::new();
@@ -444,7 +524,27 @@ where
                 return Ok(());
             }
 
-            let resp = match Self::handle_request(line, &data, &inner_ch, &mut open_ops) {
+            let req = serde_json::from_str::<StatsRequest>(&line);
+            let resume = req
+                .as_ref()
+                .ok()
+                .and_then(|req| req.args.get("resume").and_then(|v| v.parse::<u64>().ok()));
+
+            // The "compress" handshake changes how the following responses
+            // on this connection are framed, so it's handled here.
+            let mut new_encoding = encoding;
+            let res = match req {
+                Ok(req) if req.req == "compress" => {
+                    new_encoding = req
+                        .args
+                        .get("algos")
+                        .and_then(|algos| StatsEncoding::negotiate(algos));
+                    Self::build_resp(0, &new_encoding.map(|enc| enc.name()))
+                }
+                Ok(req) => Self::handle_request(req, &data, &inner_ch, &mut open_ops, &conns),
+                Err(e) => Err(e.into()),
+            };
+            let mut resp = match res {
                 Ok(v) => v,
                 Err(e) => {
                     let errno = match e.downcast_ref::<StatsErrno>() {
@@ -455,8 +555,31 @@ where
                 }
             };
 
-            let output = serde_json::to_string(&resp)? + "\n";
-            stream.write_all(output.as_bytes())?;
+            resp.args.insert("instance".into(), instance.into());
+            resp.args
+                .insert("seq".into(), seq.fetch_add(1, Ordering::Relaxed).into());
+            if let Some(resume) = resume {
+                resp.args
+                    .insert("resumed".into(), (resume == instance).into());
+            }
+
+            let bytes = match encoding {
+                Some(enc) => {
+                    write_frame(&mut stream, enc, serde_json::to_string(&resp)?.as_bytes())?
+                }
+                None => {
+                    let output = serde_json::to_string(&resp)? + "\n";
+                    stream.write_all(output.as_bytes())?;
+                    output.len()
+                }
+            };
+            encoding = new_encoding;
+
+            let dropped = resp.errno != 0 && parsed.as_ref().is_some_and(|req| req.req == "stats");
+            conns
+                .lock()
+                .unwrap()
+                .account(conn_id, resp.errno, dropped, bytes);
         }
     }
 
@@ -556,6 +679,9 @@ where
                 Ok(stream) => {
                     let data = self.data.clone();
                     let exit = self.exit.clone();
+                    let (instance, seq) = (self.instance, self.seq.clone());
+                    let conns = self.conns.clone();
+                    let conn_id = conns.lock().unwrap().add(&stream);
 
                     let (req_pair, res_pair) = ChannelPair::<Req, Res>::bidi();
                     match add_req.send(res_pair) {
@@ -564,9 +690,18 @@ where
                     }
 
                     spawn(move || {
-                        if let Err(e) = Self::serve(stream, data, req_pair, exit) {
+                        if let Err(e) = Self::serve(
+                            stream,
+                            data,
+                            req_pair,
+                            exit,
+                            instance,
+                            seq,
+                            (conns.clone(), conn_id),
+                        ) {
                             warn!("stat communication errored ({e})");
                         }
+                        conns.lock().unwrap().remove(conn_id);
                     });
                 }
                 Err(e) => warn!("failed to accept stat connection ({e})"),
@@ -590,6 +725,8 @@ where
     outer_ch: ChannelPair<Res, Req>,
     inner_ch: Option<ChannelPair<Req, Res>>,
     exit: Arc<AtomicBool>,
+    instance: u64,
+    conns: Arc<Mutex<ConnTable>>,
 }
 
 impl<Req, Res> StatsServer<Req, Res>
@@ -609,9 +746,26 @@ where
             outer_ch: och,
             inner_ch: Some(ich),
             exit: Arc::new(AtomicBool::new(false)),
+            instance: Self::new_instance(),
+            conns: Arc::new(Mutex::new(ConnTable::default())),
         }
     }
 
+    // Only needs to differ between consecutive instances of the server, the
+    // launch time and pid are good enough.
+    fn new_instance() -> u64 {
+        let nsecs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        nsecs ^ ((std::process::id() as u64) << 32)
+    }
+
+    /// Token identifying this server instance, see StatsResponse.
+    pub fn instance(&self) -> u64 {
+        self.instance
+    }
+
     pub fn set_base_path<P: AsRef<Path>>(mut self, path: P) -> Self {
         self.base_path = PathBuf::from(path.as_ref());
         self
@@ -659,6 +813,8 @@ where
             self.data.clone(),
             self.inner_ch.take().unwrap(),
             self.exit.clone(),
+            self.instance,
+            self.conns.clone(),
         );
 
         spawn(move || inner.listen());
@@ -668,6 +824,21 @@ where
     pub fn channels(&self) -> (Sender<Res>, Receiver<Req>) {
         (self.outer_ch.req.clone(), self.outer_ch.res.clone())
     }
+
+    /// Evaluate the registered alert rules against @sample, usually the
+    /// JSON of the top-level stats struct, and queue the resulting alerts
+    /// for the clients polling with the "alerts" request. Should be called
+    /// periodically by the scheduler, e.g. from its main loop. Returns the
+    /// rules which started firing or resolved.
+    pub fn check_alerts(&self, sample: &Value) -> Vec<Alert> {
+        self.data.lock().unwrap().alerts.check(sample)
+    }
+
+    /// The currently connected clients, also available to the clients
+    /// through the reserved "clients" request.
+    pub fn clients(&self) -> ConnReport {
+        self.conns.lock().unwrap().report()
+    }
 }
 
 impl<Req, Res> std::ops::Drop for StatsServer<Req, Res>
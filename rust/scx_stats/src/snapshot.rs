@@ -0,0 +1,515 @@
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, TryLockError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const SNAPSHOT_MAGIC: [u8; 8] = *b"SCXSNAP\0";
+
+/// Bumped whenever the snapshot format changes incompatibly.
+pub const SNAPSHOT_VERSION: u16 = 1;
+
+// magic, version, reserved, payload length and payload crc32
+const HEADER_BYTES: usize = 8 + 2 + 2 + 4 + 4;
+
+// Upper bound on the payload size to avoid allocating garbage lengths.
+const MAX_PAYLOAD_BYTES: usize = 64 << 20;
+
+const VAL_U64: u8 = 0;
+const VAL_I64: u8 = 1;
+const VAL_F64: u8 = 2;
+const VAL_BOOL: u8 = 3;
+const VAL_STR: u8 = 4;
+
+/// A piece of scheduler internal state recorded in a snapshot.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SnapshotValue {
+    U64(u64),
+    I64(i64),
+    F64(f64),
+    Bool(bool),
+    Str(String),
+}
+
+impl fmt::Display for SnapshotValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::U64(v) => write!(f, "{}", v),
+            Self::I64(v) => write!(f, "{}", v),
+            Self::F64(v) => write!(f, "{}", v),
+            Self::Bool(v) => write!(f, "{}", v),
+            Self::Str(v) => write!(f, "{:?}", v),
+        }
+    }
+}
+
+macro_rules! snapshot_value_from {
+    ($variant:ident, $($ty:ty),+) => {
+        $(
+            impl From<$ty> for SnapshotValue {
+                fn from(v: $ty) -> Self {
+                    Self::$variant(v.into())
+                }
+            }
+        )+
+    };
+}
+
+snapshot_value_from!(U64, u64, u32, u16, u8);
+snapshot_value_from!(I64, i64, i32, i16, i8);
+snapshot_value_from!(F64, f64, f32);
+snapshot_value_from!(Bool, bool);
+snapshot_value_from!(Str, String, &str);
+
+/// The last stats samples and key internal state of a scheduler, written
+/// when it exits abnormally for postmortem analysis. The binary format is
+/// the same regardless of the architecture and endianness of the machine
+/// which wrote it, so snapshots can be inspected anywhere:
+///
+/// - Header: the 8 byte magic "SCXSNAP\0", the u16 format version, a
+///   reserved u16, the u32 payload length and the u32 CRC32 of the payload.
+/// - Payload: the scheduler name, the u64 UNIX time in seconds when the
+///   snapshot was taken, the reason, the u32 number of stats samples
+///   followed by a target name and a JSON string for each, and the u32
+///   number of state entries followed by a key, a u8 type tag and the value
+///   for each.
+///
+/// All integers are little-endian and f64's are stored as their IEEE 754
+/// bits. Strings are a u32 length followed by that many bytes of UTF-8.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StatsSnapshot {
+    pub sched: String,
+    /// UNIX time in seconds when the snapshot was taken.
+    pub taken_at: u64,
+    /// Why the snapshot was taken, e.g. the panic message or exit reason.
+    pub reason: String,
+    /// The last sample of each stats target.
+    pub stats: BTreeMap<String, Value>,
+    pub state: BTreeMap<String, SnapshotValue>,
+}
+
+struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn str(&mut self, v: &str) {
+        self.u32(v.len() as u32);
+        self.buf.extend_from_slice(v.as_bytes());
+    }
+}
+
+struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.buf.len() - self.pos < len {
+            bail!(
+                "truncated at byte {} reading {} bytes, {} left",
+                self.pos,
+                len,
+                self.buf.len() - self.pos
+            );
+        }
+        let v = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(v)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8(self.take(len)?.to_vec())?)
+    }
+}
+
+/// CRC32 (IEEE) of @buf. Snapshots are small and written once, so the
+/// bitwise version is good enough.
+fn crc32(buf: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in buf {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+impl StatsSnapshot {
+    pub fn new(sched: &str) -> Self {
+        Self {
+            sched: sched.into(),
+            ..Default::default()
+        }
+    }
+
+    fn encode_payload(&self) -> Result<Vec<u8>> {
+        let mut enc = Encoder { buf: vec![] };
+        enc.str(&self.sched);
+        enc.u64(self.taken_at);
+        enc.str(&self.reason);
+
+        enc.u32(self.stats.len() as u32);
+        for (target, val) in self.stats.iter() {
+            enc.str(target);
+            enc.str(&serde_json::to_string(val)?);
+        }
+
+        enc.u32(self.state.len() as u32);
+        for (key, val) in self.state.iter() {
+            enc.str(key);
+            match val {
+                SnapshotValue::U64(v) => {
+                    enc.u8(VAL_U64);
+                    enc.u64(*v);
+                }
+                SnapshotValue::I64(v) => {
+                    enc.u8(VAL_I64);
+                    enc.u64(*v as u64);
+                }
+                SnapshotValue::F64(v) => {
+                    enc.u8(VAL_F64);
+                    enc.u64(v.to_bits());
+                }
+                SnapshotValue::Bool(v) => {
+                    enc.u8(VAL_BOOL);
+                    enc.u8(*v as u8);
+                }
+                SnapshotValue::Str(v) => {
+                    enc.u8(VAL_STR);
+                    enc.str(v);
+                }
+            }
+        }
+        Ok(enc.buf)
+    }
+
+    fn decode_payload(buf: &[u8]) -> Result<Self> {
+        let mut dec = Decoder { buf, pos: 0 };
+        let mut snap = Self {
+            sched: dec.str()?,
+            taken_at: dec.u64()?,
+            reason: dec.str()?,
+            ..Default::default()
+        };
+
+        for _ in 0..dec.u32()? {
+            let target = dec.str()?;
+            let val = serde_json::from_str(&dec.str()?)
+                .with_context(|| format!("parsing stats of {:?}", &target))?;
+            snap.stats.insert(target, val);
+        }
+
+        for _ in 0..dec.u32()? {
+            let key = dec.str()?;
+            let val = match dec.u8()? {
+                VAL_U64 => SnapshotValue::U64(dec.u64()?),
+                VAL_I64 => SnapshotValue::I64(dec.u64()? as i64),
+                VAL_F64 => SnapshotValue::F64(f64::from_bits(dec.u64()?)),
+                VAL_BOOL => SnapshotValue::Bool(dec.u8()? != 0),
+                VAL_STR => SnapshotValue::Str(dec.str()?),
+                tag => bail!("unknown type {} of state {:?}", tag, &key),
+            };
+            snap.state.insert(key, val);
+        }
+
+        if dec.pos != buf.len() {
+            bail!("{} trailing bytes", buf.len() - dec.pos);
+        }
+        Ok(snap)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let payload = self.encode_payload()?;
+        let mut buf = Vec::with_capacity(HEADER_BYTES + payload.len());
+        buf.extend_from_slice(&SNAPSHOT_MAGIC);
+        buf.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes());
+        buf.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&crc32(&payload).to_le_bytes());
+        buf.extend_from_slice(&payload);
+        Ok(buf)
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> Result<Self> {
+        if buf.len() < HEADER_BYTES || buf[..8] != SNAPSHOT_MAGIC {
+            bail!("not a stats snapshot");
+        }
+
+        let version = u16::from_le_bytes(buf[8..10].try_into().unwrap());
+        if version != SNAPSHOT_VERSION {
+            bail!(
+                "unsupported snapshot version {} (expected {})",
+                version,
+                SNAPSHOT_VERSION
+            );
+        }
+
+        let len = u32::from_le_bytes(buf[12..16].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(buf[16..20].try_into().unwrap());
+        let payload = &buf[HEADER_BYTES..];
+        if payload.len() != len {
+            bail!("payload is {} bytes, expected {}", payload.len(), len);
+        }
+        if crc32(payload) != crc {
+            bail!("payload checksum mismatch");
+        }
+        Self::decode_payload(payload)
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(&self.to_bytes()?)?;
+        Ok(w.flush()?)
+    }
+
+    pub fn read<R: Read>(r: &mut R) -> Result<Self> {
+        let mut buf = vec![];
+        r.take((HEADER_BYTES + MAX_PAYLOAD_BYTES) as u64 + 1)
+            .read_to_end(&mut buf)?;
+        if buf.len() > HEADER_BYTES + MAX_PAYLOAD_BYTES {
+            bail!("snapshot too large");
+        }
+        Self::from_bytes(&buf)
+    }
+
+    /// Write the snapshot to @path atomically so that a crash while saving
+    /// doesn't leave a truncated snapshot behind.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, self.to_bytes()?).with_context(|| format!("writing {:?}", &tmp))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("renaming {:?} to {:?}", &tmp, path))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut f = std::fs::File::open(path).with_context(|| format!("opening {:?}", path))?;
+        Self::read(&mut f).with_context(|| format!("reading {:?}", path))
+    }
+
+    /// Pretty-print the snapshot for humans.
+    pub fn format<W: Write>(&self, w: &mut W) -> Result<()> {
+        writeln!(
+            w,
+            "{} snapshot taken at {} (UNIX time)",
+            &self.sched, self.taken_at
+        )?;
+        writeln!(w, "reason: {}", &self.reason)?;
+
+        if !self.state.is_empty() {
+            writeln!(w, "\nstate:")?;
+            let width = self.state.keys().map(|k| k.len()).max().unwrap_or(0);
+            for (key, val) in self.state.iter() {
+                writeln!(w, "  {:width$} = {}", key, val, width = width)?;
+            }
+        }
+
+        for (target, val) in self.stats.iter() {
+            writeln!(w, "\nstats[{}]:", target)?;
+            for line in serde_json::to_string_pretty(val)?.lines() {
+                writeln!(w, "  {}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Keeps the snapshot of a running scheduler up to date and writes it out
+/// when the scheduler panics or exits abnormally. Clones share the same
+/// snapshot.
+///
+/// ```ignore
+/// let snapshotter = StatsSnapshotter::new("scx_foo", "/var/lib/scx/scx_foo.snap");
+/// snapshotter.install_panic_hook();
+/// ...
+/// snapshotter.update_stats("top", &stats)?;
+/// snapshotter.set_state("nr_layers", layers.len() as u64);
+/// ...
+/// if uei_exited!(&skel, uei) {
+///     snapshotter.dump(&uei_reason)?;
+/// }
+/// ```
+#[derive(Clone)]
+pub struct StatsSnapshotter {
+    path: PathBuf,
+    snap: Arc<Mutex<StatsSnapshot>>,
+}
+
+impl StatsSnapshotter {
+    pub fn new<P: Into<PathBuf>>(sched: &str, path: P) -> Self {
+        Self {
+            path: path.into(),
+            snap: Arc::new(Mutex::new(StatsSnapshot::new(sched))),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Record @stats as the latest sample of @target.
+    pub fn update_stats<T: Serialize>(&self, target: &str, stats: &T) -> Result<()> {
+        let val = serde_json::to_value(stats)?;
+        self.snap
+            .lock()
+            .unwrap()
+            .stats
+            .insert(target.to_string(), val);
+        Ok(())
+    }
+
+    pub fn set_state<V: Into<SnapshotValue>>(&self, key: &str, val: V) {
+        self.snap
+            .lock()
+            .unwrap()
+            .state
+            .insert(key.to_string(), val.into());
+    }
+
+    fn dump_locked(snap: &mut StatsSnapshot, path: &Path, reason: &str) -> Result<()> {
+        snap.reason = reason.to_string();
+        snap.taken_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        snap.save(path)
+    }
+
+    /// Write the snapshot out, e.g. when the BPF scheduler exited with an
+    /// error. @reason is recorded in the snapshot.
+    pub fn dump(&self, reason: &str) -> Result<()> {
+        let mut snap = self.snap.lock().unwrap_or_else(|e| e.into_inner());
+        Self::dump_locked(&mut snap, &self.path, reason)
+    }
+
+    /// Write the snapshot out when any thread panics, then chain to the
+    /// previously installed hook. The panic may have happened while the
+    /// snapshot was being updated, in which case it's skipped rather than
+    /// risking a deadlock.
+    pub fn install_panic_hook(&self) {
+        let (snap, path) = (self.snap.clone(), self.path.clone());
+        let prev = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let guard = match snap.try_lock() {
+                Ok(v) => Some(v),
+                Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+                Err(TryLockError::WouldBlock) => None,
+            };
+            match guard {
+                Some(mut snap) => {
+                    match Self::dump_locked(&mut snap, &path, &format!("panic: {}", info)) {
+                        Ok(()) => eprintln!("Stats snapshot written to {:?}", &path),
+                        Err(e) => eprintln!("Failed to write stats snapshot ({:#})", &e),
+                    }
+                }
+                None => eprintln!("Stats snapshot busy, not written"),
+            }
+            prev(info);
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> StatsSnapshot {
+        let mut snap = StatsSnapshot::new("scx_foo");
+        snap.taken_at = 0x0102030405060708;
+        snap.reason = "panic: oops".into();
+        snap.stats.insert(
+            "top".into(),
+            serde_json::json!({"nr_running": 3, "load": 1.5}),
+        );
+        snap.state.insert("u".into(), u64::MAX.into());
+        snap.state.insert("i".into(), (-2i64).into());
+        snap.state.insert("f".into(), 0.25f64.into());
+        snap.state.insert("b".into(), true.into());
+        snap.state.insert("s".into(), "ccd0".into());
+        snap
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let snap = sample();
+        let buf = snap.to_bytes().unwrap();
+        assert_eq!(StatsSnapshot::from_bytes(&buf).unwrap(), snap);
+
+        let mut out = vec![];
+        snap.write(&mut out).unwrap();
+        assert_eq!(StatsSnapshot::read(&mut out.as_slice()).unwrap(), snap);
+    }
+
+    #[test]
+    fn test_byte_layout() {
+        // The layout must not depend on the host's endianness.
+        let mut snap = StatsSnapshot::new("s");
+        snap.taken_at = 0x0102030405060708;
+        snap.state.insert("k".into(), SnapshotValue::U64(0x1122));
+        let buf = snap.to_bytes().unwrap();
+
+        #[rustfmt::skip]
+        let payload: &[u8] = &[
+            1, 0, 0, 0, b's',
+            8, 7, 6, 5, 4, 3, 2, 1,
+            0, 0, 0, 0,
+            0, 0, 0, 0,
+            1, 0, 0, 0,
+            1, 0, 0, 0, b'k', VAL_U64,
+            0x22, 0x11, 0, 0, 0, 0, 0, 0,
+        ];
+        assert_eq!(&buf[..8], b"SCXSNAP\0");
+        assert_eq!(&buf[8..12], &[SNAPSHOT_VERSION as u8, 0, 0, 0]);
+        assert_eq!(&buf[12..16], &[payload.len() as u8, 0, 0, 0]);
+        assert_eq!(&buf[16..20], &crc32(payload).to_le_bytes());
+        assert_eq!(&buf[HEADER_BYTES..], payload);
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let buf = sample().to_bytes().unwrap();
+
+        let mut bad = buf.clone();
+        *bad.last_mut().unwrap() ^= 1;
+        assert!(StatsSnapshot::from_bytes(&bad).is_err());
+
+        assert!(StatsSnapshot::from_bytes(&buf[..buf.len() - 1]).is_err());
+        assert!(StatsSnapshot::from_bytes(&buf[1..]).is_err());
+    }
+}
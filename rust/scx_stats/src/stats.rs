@@ -191,6 +191,7 @@ impl std::fmt::Display for StatsData {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum StatsAttr {
     Top,
+    Counter,
     Desc(String),
     User(String, String),
 }
@@ -206,6 +207,7 @@ impl Parse for StatsAttrVec {
             let ident = input.parse::<Ident>()?;
             match ident.to_string().as_str() {
                 "top" => attrs.push(StatsAttr::Top),
+                "counter" => attrs.push(StatsAttr::Counter),
                 "desc" => {
                     input.parse::<Token!(=)>()?;
                     attrs.push(StatsAttr::Desc(input.parse::<LitStr>()?.value()))
@@ -237,6 +239,8 @@ impl Parse for StatsAttrVec {
 pub struct StatsFieldAttrs {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub desc: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counter: Option<String>,
     #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
     pub user: BTreeMap<String, String>,
 }
@@ -250,6 +254,7 @@ impl StatsFieldAttrs {
                 let vec = attr.parse_args::<StatsAttrVec>()?;
                 for elem in vec.attrs.into_iter() {
                     match elem {
+                        StatsAttr::Counter => fattrs.counter = Some("true".into()),
                         StatsAttr::Desc(v) => fattrs.desc = Some(v),
                         StatsAttr::User(k, v) => {
                             fattrs.user.insert(k, v);
@@ -311,6 +316,10 @@ impl StatsStructAttrs {
                         StatsAttr::User(k, v) => {
                             sattrs.user.insert(k, v);
                         }
+                        v => Err(Error::new(
+                            attr.span(),
+                            format!("Not a struct attribute: {v:?}"),
+                        ))?,
                     }
                 }
             }
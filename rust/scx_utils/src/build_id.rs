@@ -62,6 +62,79 @@ pub fn ops_version_suffix(semver: &str) -> String {
     ver
 }
 
+/// Build metadata of a scheduler binary. Use [`version_info!()`] to
+/// collect it for the calling crate. The git version, kernel headers version
+/// and BPF object hash are exported by `scx_cargo::BpfBuilder` and are `None`
+/// if they couldn't be determined at build time.
+///
+/// [`Display`](std::fmt::Display) prints the traditional one line version,
+/// e.g. `scx_rusty 1.0.26-gabcdef0 x86_64-unknown-linux-gnu`. The alternate
+/// form (`{:#}`) adds a line for each known field, which is what the
+/// schedulers print for `--version`.
+///
+/// [`version_info!()`]: crate::version_info
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct VersionInfo {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub full_version: String,
+    pub git_describe: Option<&'static str>,
+    pub kernel_headers: Option<&'static str>,
+    pub bpf_obj_hash: Option<&'static str>,
+}
+
+impl VersionInfo {
+    pub fn new(
+        name: &'static str,
+        version: &'static str,
+        git_describe: Option<&'static str>,
+        kernel_headers: Option<&'static str>,
+        bpf_obj_hash: Option<&'static str>,
+    ) -> Self {
+        Self {
+            name,
+            version,
+            full_version: full_version(version),
+            git_describe,
+            kernel_headers,
+            bpf_obj_hash,
+        }
+    }
+}
+
+impl std::fmt::Display for VersionInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.name, self.full_version)?;
+        if !f.alternate() {
+            return Ok(());
+        }
+        if let Some(v) = self.git_describe {
+            write!(f, "\n  git: {}", v)?;
+        }
+        if let Some(v) = self.kernel_headers {
+            write!(f, "\n  kernel headers: {}", v)?;
+        }
+        if let Some(v) = self.bpf_obj_hash {
+            write!(f, "\n  bpf object: {}", v)?;
+        }
+        Ok(())
+    }
+}
+
+/// Collect the [`VersionInfo`] of the calling crate.
+#[macro_export]
+macro_rules! version_info {
+    () => {
+        $crate::build_id::VersionInfo::new(
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            option_env!("SCX_GIT_DESCRIBE"),
+            option_env!("SCX_VMLINUX_H_VERSION"),
+            option_env!("SCX_BPF_OBJ_HASH"),
+        )
+    };
+}
+
 lazy_static::lazy_static! {
     pub static ref SCX_CARGO_VERSION: &'static str = env!("CARGO_PKG_VERSION");
     pub static ref SCX_FULL_VERSION: String = full_version(*SCX_CARGO_VERSION);
@@ -80,4 +153,28 @@ mod tests {
         //assert_eq!(super::*SCX_CARGO_VERSION, 1);
         println!("{}", *super::SCX_FULL_VERSION);
     }
+
+    #[test]
+    fn test_version_info() {
+        let info = super::VersionInfo::new(
+            "scx_test",
+            "1.2.3",
+            Some("v1.2.3-4-gabcdef0"),
+            None,
+            Some("0123456789abcdef"),
+        );
+        let line = format!("scx_test {}", super::full_version("1.2.3"));
+        assert_eq!(info.to_string(), line);
+
+        let out = format!("{:#}", info);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], line);
+        assert_eq!(lines[1], "  git: v1.2.3-4-gabcdef0");
+        assert_eq!(lines[2], "  bpf object: 0123456789abcdef");
+        assert_eq!(lines.len(), 3);
+
+        let info = crate::version_info!();
+        assert_eq!(info.name, "scx_utils");
+        assert_eq!(info.version, *super::SCX_CARGO_VERSION);
+    }
 }
@@ -0,0 +1,589 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Control Socket Protocol
+//!
+//! A small protocol to list, read, change and watch the tunables of a running
+//! scheduler over a UNIX socket, so that schedulers supporting live tuning
+//! share one protocol and one client instead of each growing its own.
+//!
+//! Every message is a little-endian `u32` length followed by that many bytes
+//! of JSON. A connection starts with the client sending
+//! [`ControlRequest::Hello`] with [`CONTROL_PROTO_VERSION`]. The server
+//! rejects mismatching versions and otherwise replies with the operations it
+//! supports, so that operations can be added without bumping the version.
+//! After [`ControlRequest::Subscribe`], the server pushes a
+//! [`ControlResponse::Changed`] whenever a watched tunable is changed, either
+//! by another client or by the scheduler through
+//! [`ControlServerHandle::notify()`].
+//!
+//! ```ignore
+//! let slice_us = Arc::new(AtomicU64::new(5000));
+//! let (get, set) = (slice_us.clone(), slice_us.clone());
+//! let handle = ControlServer::new("/var/run/scx/scx_foo/control")
+//!     .tunable(
+//!         "slice_us",
+//!         "Maximum time slice in usecs",
+//!         move || get.load(Ordering::Relaxed).into(),
+//!         move |v| {
+//!             let v = v.as_u64().context("slice_us must be an integer")?;
+//!             set.store(v, Ordering::Relaxed);
+//!             Ok(())
+//!         },
+//!     )
+//!     .launch()?;
+//!
+//! let mut client = ControlClient::connect("/var/run/scx/scx_foo/control")?;
+//! client.set("slice_us", 2000.into())?;
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread::spawn;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use log::debug;
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Version of the protocol, only bumped on incompatible changes.
+pub const CONTROL_PROTO_VERSION: u32 = 1;
+
+/// Maximum length of a message.
+pub const CONTROL_MAX_MSG_LEN: usize = 1 << 20;
+
+/// Operations supported by this implementation, reported on hello.
+pub const CONTROL_CAPABILITIES: &[&str] = &["list", "get", "set", "subscribe"];
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ControlRequest {
+    Hello {
+        version: u32,
+    },
+    List,
+    Get {
+        name: String,
+    },
+    Set {
+        name: String,
+        value: Value,
+    },
+    /// Watch the given tunables, all of them if empty.
+    Subscribe {
+        names: Vec<String>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControlResponse {
+    Hello {
+        version: u32,
+        capabilities: Vec<String>,
+    },
+    Tunables {
+        tunables: Vec<TunableInfo>,
+    },
+    Value {
+        name: String,
+        value: Value,
+    },
+    Ok,
+    Error {
+        message: String,
+    },
+    Changed {
+        name: String,
+        value: Value,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TunableInfo {
+    pub name: String,
+    pub desc: String,
+    pub value: Value,
+    pub read_only: bool,
+}
+
+/// Write @msg to @w as a length-prefixed JSON message.
+pub fn write_msg<W: Write, T: Serialize>(w: &mut W, msg: &T) -> Result<()> {
+    let buf = serde_json::to_vec(msg)?;
+    if buf.len() > CONTROL_MAX_MSG_LEN {
+        bail!("Control message too long ({} bytes)", buf.len());
+    }
+    w.write_all(&(buf.len() as u32).to_le_bytes())?;
+    w.write_all(&buf)?;
+    w.flush()?;
+    Ok(())
+}
+
+/// Read a length-prefixed JSON message from @r.
+pub fn read_msg<R: Read, T: DeserializeOwned>(r: &mut R) -> Result<T> {
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > CONTROL_MAX_MSG_LEN {
+        bail!("Control message too long ({} bytes)", len);
+    }
+    let mut buf = vec![0u8; len];
+    r.read_exact(&mut buf)?;
+    Ok(serde_json::from_slice(&buf)?)
+}
+
+type Getter = Box<dyn Fn() -> Value + Send + Sync>;
+type Setter = Box<dyn Fn(Value) -> Result<()> + Send + Sync>;
+
+struct Tunable {
+    desc: String,
+    get: Getter,
+    set: Option<Setter>,
+}
+
+struct Subscriber {
+    names: Vec<String>,
+    stream: Arc<Mutex<UnixStream>>,
+}
+
+struct ControlServerInner {
+    tunables: BTreeMap<String, Tunable>,
+    subscribers: Mutex<Vec<Subscriber>>,
+}
+
+impl ControlServerInner {
+    fn info(&self, name: &str, tunable: &Tunable) -> TunableInfo {
+        TunableInfo {
+            name: name.to_string(),
+            desc: tunable.desc.clone(),
+            value: (tunable.get)(),
+            read_only: tunable.set.is_none(),
+        }
+    }
+
+    fn notify(&self, name: &str) {
+        let Some(tunable) = self.tunables.get(name) else {
+            return;
+        };
+        let msg = ControlResponse::Changed {
+            name: name.to_string(),
+            value: (tunable.get)(),
+        };
+
+        // Drop the subscribers which went away.
+        self.subscribers.lock().unwrap().retain(|sub| {
+            if !sub.names.is_empty() && !sub.names.iter().any(|n| n == name) {
+                return true;
+            }
+            write_msg(&mut *sub.stream.lock().unwrap(), &msg).is_ok()
+        });
+    }
+
+    fn handle_request(
+        &self,
+        req: ControlRequest,
+        stream: &Arc<Mutex<UnixStream>>,
+    ) -> ControlResponse {
+        let lookup = |name: &str| {
+            self.tunables
+                .get(name)
+                .ok_or_else(|| anyhow!("Unknown tunable {:?}", name))
+        };
+
+        let res = match req {
+            ControlRequest::Hello { .. } => Err(anyhow!("Already said hello")),
+            ControlRequest::List => Ok(ControlResponse::Tunables {
+                tunables: self
+                    .tunables
+                    .iter()
+                    .map(|(name, tunable)| self.info(name, tunable))
+                    .collect(),
+            }),
+            ControlRequest::Get { name } => lookup(&name).map(|tunable| ControlResponse::Value {
+                value: (tunable.get)(),
+                name,
+            }),
+            ControlRequest::Set { name, value } => lookup(&name).and_then(|tunable| {
+                let set = tunable
+                    .set
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("Tunable {:?} is read-only", &name))?;
+                set(value)?;
+                self.notify(&name);
+                Ok(ControlResponse::Ok)
+            }),
+            ControlRequest::Subscribe { names } => names
+                .iter()
+                .try_for_each(|name| lookup(name).map(|_| ()))
+                .map(|_| {
+                    self.subscribers.lock().unwrap().push(Subscriber {
+                        names,
+                        stream: stream.clone(),
+                    });
+                    ControlResponse::Ok
+                }),
+        };
+
+        res.unwrap_or_else(|e| ControlResponse::Error {
+            message: format!("{:#}", e),
+        })
+    }
+
+    fn serve(&self, mut reader: UnixStream) -> Result<()> {
+        let stream = Arc::new(Mutex::new(reader.try_clone()?));
+
+        match read_msg(&mut reader)? {
+            ControlRequest::Hello { version } if version == CONTROL_PROTO_VERSION => {
+                write_msg(
+                    &mut *stream.lock().unwrap(),
+                    &ControlResponse::Hello {
+                        version: CONTROL_PROTO_VERSION,
+                        capabilities: CONTROL_CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+                    },
+                )?;
+            }
+            ControlRequest::Hello { version } => {
+                let message = format!(
+                    "Unsupported protocol version {}, expected {}",
+                    version, CONTROL_PROTO_VERSION
+                );
+                write_msg(
+                    &mut *stream.lock().unwrap(),
+                    &ControlResponse::Error { message },
+                )?;
+                return Ok(());
+            }
+            req => bail!("Expected hello, got {:?}", req),
+        }
+
+        loop {
+            let req = match read_msg(&mut reader) {
+                Ok(req) => req,
+                Err(e) => match e.downcast_ref::<std::io::Error>() {
+                    Some(ioe) if ioe.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+                    _ => return Err(e),
+                },
+            };
+            let res = self.handle_request(req, &stream);
+            write_msg(&mut *stream.lock().unwrap(), &res)?;
+        }
+    }
+}
+
+/// Server side of the control socket. Register the tunables and then
+/// [`launch()`](Self::launch) it.
+pub struct ControlServer {
+    path: PathBuf,
+    tunables: BTreeMap<String, Tunable>,
+}
+
+impl ControlServer {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            tunables: BTreeMap::new(),
+        }
+    }
+
+    /// Add a tunable which clients can change. @set should reject invalid
+    /// values with an error, which is relayed to the client.
+    pub fn tunable<G, S>(mut self, name: &str, desc: &str, get: G, set: S) -> Self
+    where
+        G: Fn() -> Value + Send + Sync + 'static,
+        S: Fn(Value) -> Result<()> + Send + Sync + 'static,
+    {
+        self.tunables.insert(
+            name.to_string(),
+            Tunable {
+                desc: desc.to_string(),
+                get: Box::new(get),
+                set: Some(Box::new(set)),
+            },
+        );
+        self
+    }
+
+    /// Add a tunable which clients can read and watch but not change.
+    pub fn read_only<G>(mut self, name: &str, desc: &str, get: G) -> Self
+    where
+        G: Fn() -> Value + Send + Sync + 'static,
+    {
+        self.tunables.insert(
+            name.to_string(),
+            Tunable {
+                desc: desc.to_string(),
+                get: Box::new(get),
+                set: None,
+            },
+        );
+        self
+    }
+
+    /// Bind the socket, replacing a stale one, and serve the clients from a
+    /// background thread.
+    pub fn launch(self) -> Result<ControlServerHandle> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let _ = fs::remove_file(&self.path);
+        let listener = UnixListener::bind(&self.path)
+            .with_context(|| format!("Failed to bind {}", self.path.display()))?;
+
+        let inner = Arc::new(ControlServerInner {
+            tunables: self.tunables,
+            subscribers: Mutex::new(vec![]),
+        });
+
+        let server = inner.clone();
+        spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("Failed to accept control connection ({})", &e);
+                        continue;
+                    }
+                };
+                let server = server.clone();
+                spawn(move || {
+                    if let Err(e) = server.serve(stream) {
+                        debug!("Control connection closed ({:#})", &e);
+                    }
+                });
+            }
+        });
+
+        Ok(ControlServerHandle {
+            path: self.path,
+            inner,
+        })
+    }
+}
+
+/// Handle of a launched [`ControlServer`]. The socket is removed on drop.
+pub struct ControlServerHandle {
+    path: PathBuf,
+    inner: Arc<ControlServerInner>,
+}
+
+impl ControlServerHandle {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Tell the subscribers of @name that the scheduler changed it.
+    pub fn notify(&self, name: &str) {
+        self.inner.notify(name);
+    }
+}
+
+impl Drop for ControlServerHandle {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Client side of the control socket.
+pub struct ControlClient {
+    stream: UnixStream,
+    capabilities: Vec<String>,
+}
+
+impl ControlClient {
+    /// Connect to the control socket at @path and negotiate the protocol.
+    pub fn connect<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut stream = UnixStream::connect(path)
+            .with_context(|| format!("Failed to connect to {}", path.display()))?;
+
+        write_msg(
+            &mut stream,
+            &ControlRequest::Hello {
+                version: CONTROL_PROTO_VERSION,
+            },
+        )?;
+        match read_msg(&mut stream)? {
+            ControlResponse::Hello { capabilities, .. } => Ok(Self {
+                stream,
+                capabilities,
+            }),
+            ControlResponse::Error { message } => bail!("{}", message),
+            res => bail!("Unexpected response to hello: {:?}", res),
+        }
+    }
+
+    /// Operations supported by the server.
+    pub fn capabilities(&self) -> &[String] {
+        &self.capabilities
+    }
+
+    pub fn has_capability(&self, op: &str) -> bool {
+        self.capabilities.iter().any(|c| c == op)
+    }
+
+    fn request(&mut self, req: ControlRequest) -> Result<ControlResponse> {
+        write_msg(&mut self.stream, &req)?;
+        match read_msg(&mut self.stream)? {
+            ControlResponse::Error { message } => bail!("{}", message),
+            res => Ok(res),
+        }
+    }
+
+    pub fn list(&mut self) -> Result<Vec<TunableInfo>> {
+        match self.request(ControlRequest::List)? {
+            ControlResponse::Tunables { tunables } => Ok(tunables),
+            res => bail!("Unexpected response to list: {:?}", res),
+        }
+    }
+
+    pub fn get(&mut self, name: &str) -> Result<Value> {
+        match self.request(ControlRequest::Get {
+            name: name.to_string(),
+        })? {
+            ControlResponse::Value { value, .. } => Ok(value),
+            res => bail!("Unexpected response to get: {:?}", res),
+        }
+    }
+
+    pub fn set(&mut self, name: &str, value: Value) -> Result<()> {
+        match self.request(ControlRequest::Set {
+            name: name.to_string(),
+            value,
+        })? {
+            ControlResponse::Ok => Ok(()),
+            res => bail!("Unexpected response to set: {:?}", res),
+        }
+    }
+
+    /// Watch @names, all tunables if empty. The connection is only used for
+    /// the change notifications afterwards.
+    pub fn subscribe(mut self, names: &[&str]) -> Result<ControlSubscription> {
+        match self.request(ControlRequest::Subscribe {
+            names: names.iter().map(|n| n.to_string()).collect(),
+        })? {
+            ControlResponse::Ok => Ok(ControlSubscription {
+                stream: self.stream,
+            }),
+            res => bail!("Unexpected response to subscribe: {:?}", res),
+        }
+    }
+}
+
+/// Stream of changes of the subscribed tunables.
+pub struct ControlSubscription {
+    stream: UnixStream,
+}
+
+impl ControlSubscription {
+    /// Wait for the next change and return the tunable's name and value.
+    pub fn next_change(&mut self) -> Result<(String, Value)> {
+        match read_msg(&mut self.stream)? {
+            ControlResponse::Changed { name, value } => Ok((name, value)),
+            res => bail!("Unexpected control message: {:?}", res),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU64;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_msg_roundtrip() {
+        let req = ControlRequest::Set {
+            name: "slice_us".into(),
+            value: 2000.into(),
+        };
+        let mut buf = vec![];
+        write_msg(&mut buf, &req).unwrap();
+
+        assert_eq!(
+            u32::from_le_bytes(buf[..4].try_into().unwrap()) as usize,
+            buf.len() - 4
+        );
+        assert_eq!(
+            std::str::from_utf8(&buf[4..]).unwrap(),
+            r#"{"op":"set","name":"slice_us","value":2000}"#
+        );
+        assert_eq!(read_msg::<_, ControlRequest>(&mut &buf[..]).unwrap(), req);
+
+        let mut long = vec![];
+        long.extend_from_slice(&(CONTROL_MAX_MSG_LEN as u32 + 1).to_le_bytes());
+        assert!(read_msg::<_, ControlRequest>(&mut &long[..]).is_err());
+    }
+
+    #[test]
+    fn test_server_client() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("scx_foo/control");
+
+        let slice_us = Arc::new(AtomicU64::new(5000));
+        let (get, set) = (slice_us.clone(), slice_us.clone());
+        let handle = ControlServer::new(&path)
+            .tunable(
+                "slice_us",
+                "Maximum time slice in usecs",
+                move || get.load(Ordering::Relaxed).into(),
+                move |v| {
+                    let v = v.as_u64().context("not an integer")?;
+                    set.store(v, Ordering::Relaxed);
+                    Ok(())
+                },
+            )
+            .read_only("nr_cpus", "Number of CPUs", || 8.into())
+            .launch()
+            .unwrap();
+
+        let mut client = ControlClient::connect(&path).unwrap();
+        assert!(client.has_capability("subscribe"));
+
+        let tunables = client.list().unwrap();
+        assert_eq!(tunables.len(), 2);
+        assert_eq!(tunables[0].name, "nr_cpus");
+        assert!(tunables[0].read_only);
+        assert_eq!(tunables[1].value, Value::from(5000));
+
+        let mut sub = ControlClient::connect(&path)
+            .unwrap()
+            .subscribe(&["slice_us"])
+            .unwrap();
+
+        client.set("slice_us", 2000.into()).unwrap();
+        assert_eq!(slice_us.load(Ordering::Relaxed), 2000);
+        assert_eq!(client.get("slice_us").unwrap(), Value::from(2000));
+        assert_eq!(
+            sub.next_change().unwrap(),
+            ("slice_us".to_string(), Value::from(2000))
+        );
+
+        assert!(client.set("slice_us", "fast".into()).is_err());
+        assert!(client.set("nr_cpus", 4.into()).is_err());
+        assert!(client.get("nope").is_err());
+
+        slice_us.store(3000, Ordering::Relaxed);
+        handle.notify("slice_us");
+        assert_eq!(sub.next_change().unwrap().1, Value::from(3000));
+
+        drop(handle);
+        assert!(!path.exists());
+    }
+}
@@ -55,12 +55,18 @@ pub mod compat;
 pub use compat::ksym_exists;
 pub use compat::ROOT_PREFIX;
 
+pub mod control;
+
 mod libbpf_logger;
 pub use libbpf_logger::init_libbpf_logging;
 pub mod libbpf_clap_opts;
 
 pub mod ravg;
 
+pub mod ratelimit;
+
+pub mod map_pin;
+
 mod topology;
 pub use topology::Core;
 pub use topology::CoreType;
@@ -71,6 +77,8 @@ pub use topology::Topology;
 pub use topology::NR_CPUS_POSSIBLE;
 pub use topology::NR_CPU_IDS;
 
+pub mod topology_fixtures;
+
 mod energy_model;
 pub use energy_model::EnergyModel;
 pub use energy_model::PerfDomain;
@@ -97,8 +105,17 @@ mod netdev;
 pub use netdev::read_netdevs;
 pub use netdev::NetDev;
 
+mod proc_cache;
+pub use proc_cache::ProcCache;
+pub use proc_cache::ProcInfo;
+
 pub mod pm;
 
+pub mod power;
+
+pub mod sched_thread;
+pub use sched_thread::SchedThreadArgs;
+
 pub mod enums;
 pub use enums::scx_enums;
 
@@ -109,3 +126,5 @@ pub use cli::TopologyArgs;
 pub mod autopower;
 
 pub mod perf;
+
+pub mod vtime;
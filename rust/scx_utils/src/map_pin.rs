@@ -0,0 +1,238 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # BPF Map Pinning
+//!
+//! Helpers to keep selected BPF maps, e.g. task classification data or
+//! vruntime state, alive across scheduler restarts by pinning them under
+//! `/sys/fs/bpf/scx/<sched>`. On the next start, libbpf reuses the pinned
+//! maps instead of creating empty ones, so the new instance starts with the
+//! state the previous one built up.
+//!
+//! ```ignore
+//! let mut pinner = MapPinner::new("scx_foo")?;
+//! pinner.pin(&mut open_skel.maps.task_ctx_stor)?;
+//! let mut skel = scx_ops_load!(open_skel, foo_ops, uei)?;
+//! ...
+//! if !uei_report!(&skel, uei)?.should_restart() {
+//!     pinner.unpin_all()?;
+//! }
+//! ```
+//!
+//! A pinned map is only reused if its key and value sizes match the ones in
+//! the BPF object being loaded, otherwise [`MapPinner::pin()`] drops it and
+//! the map starts empty. Layout changes which keep the sizes, e.g. reordered
+//! fields, aren't detected: [`MapPinner::clear()`] the stale maps instead.
+
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use libbpf_rs::libbpf_sys::bpf_map__key_size;
+use libbpf_rs::libbpf_sys::bpf_map__value_size;
+use libbpf_rs::AsRawLibbpf;
+use libbpf_rs::MapCore;
+use libbpf_rs::MapHandle;
+use libbpf_rs::OpenMapMut;
+use log::debug;
+use log::info;
+
+/// Root of the pinned maps of all schedulers.
+pub const PIN_ROOT: &str = "/sys/fs/bpf/scx";
+
+const BPF_FS_MAGIC: u32 = 0xcafe4a11;
+
+fn is_bpffs(path: &Path) -> bool {
+    let Ok(cpath) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    let mut st: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(cpath.as_ptr(), &mut st) } != 0 {
+        return false;
+    }
+    st.f_type as u32 == BPF_FS_MAGIC
+}
+
+/// Whether the map pinned at @path has the key and value sizes of @map.
+fn is_compatible(map: &OpenMapMut, path: &Path) -> bool {
+    let Ok(pinned) = MapHandle::from_pinned_path(path) else {
+        return false;
+    };
+    let ptr = map.as_libbpf_object().as_ptr();
+    let (key_size, value_size) = unsafe { (bpf_map__key_size(ptr), bpf_map__value_size(ptr)) };
+    pinned.key_size() == key_size && pinned.value_size() == value_size
+}
+
+/// Pins the maps of one scheduler under a directory of its own.
+pub struct MapPinner {
+    dir: PathBuf,
+    names: Vec<String>,
+}
+
+impl MapPinner {
+    /// Pin under `/sys/fs/bpf/scx/@sched`, which must be on a bpffs.
+    pub fn new(sched: &str) -> Result<Self> {
+        let root = Path::new(PIN_ROOT);
+        let bpffs = root.parent().unwrap();
+        if !is_bpffs(bpffs) {
+            bail!("{:?} is not a mounted bpffs", bpffs);
+        }
+        Self::with_dir(root.join(sched))
+    }
+
+    /// Pin under @dir. The directory is created if it doesn't exist.
+    pub fn with_dir<P: Into<PathBuf>>(dir: P) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).with_context(|| format!("creating {:?}", &dir))?;
+        Ok(Self { dir, names: vec![] })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Path @name is pinned at.
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    /// Whether a previous instance left map @name pinned.
+    pub fn is_pinned(&self, name: &str) -> bool {
+        self.path(name).exists()
+    }
+
+    /// Names of the maps pinned through this pinner.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().map(|n| n.as_str())
+    }
+
+    /// Pin @map, which must not be loaded yet. If a previous instance left
+    /// a compatible map pinned, libbpf reuses it on load. Otherwise, it's
+    /// created empty and pinned on load. Returns whether the map will be
+    /// rehydrated.
+    pub fn pin(&mut self, map: &mut OpenMapMut) -> Result<bool> {
+        let name = map.name().to_string_lossy().into_owned();
+        let path = self.path(&name);
+        let mut rehydrate = path.exists();
+
+        if rehydrate && !is_compatible(map, &path) {
+            info!("Dropping incompatible pinned map {:?}", &path);
+            fs::remove_file(&path).with_context(|| format!("unpinning {:?}", &path))?;
+            rehydrate = false;
+        }
+
+        map.set_pin_path(&path)
+            .with_context(|| format!("setting pin path of {:?} to {:?}", &name, &path))?;
+        debug!(
+            "{} map {:?} at {:?}",
+            if rehydrate { "Reusing" } else { "Pinning" },
+            &name,
+            &path
+        );
+
+        if !self.names.contains(&name) {
+            self.names.push(name);
+        }
+        Ok(rehydrate)
+    }
+
+    /// Remove the maps pinned through this pinner and the directory if it
+    /// ends up empty. To be called on a clean exit so that the next start
+    /// doesn't pick up state which is no longer current. Skip it when the
+    /// scheduler is about to be restarted.
+    pub fn unpin_all(&self) -> Result<()> {
+        for name in self.names.iter() {
+            let path = self.path(name);
+            match fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => Err(e).with_context(|| format!("unpinning {:?}", &path))?,
+            }
+        }
+        let _ = fs::remove_dir(&self.dir);
+        Ok(())
+    }
+
+    /// Remove everything pinned under the directory, including maps left
+    /// behind by other versions of the scheduler. Use when the pinned maps
+    /// can't be reused, e.g. because their layout changed.
+    pub fn clear(&self) -> Result<()> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(v) => v,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => Err(e).with_context(|| format!("reading {:?}", &self.dir))?,
+        };
+
+        let mut nr_cleared = 0;
+        for entry in entries {
+            let path = entry?.path();
+            fs::remove_file(&path).with_context(|| format!("unpinning {:?}", &path))?;
+            nr_cleared += 1;
+        }
+        if nr_cleared > 0 {
+            info!(
+                "Cleared {} stale pinned maps in {:?}",
+                nr_cleared, &self.dir
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths() {
+        let tmp = tempfile::tempdir().unwrap();
+        let pinner = MapPinner::with_dir(tmp.path().join("scx_foo")).unwrap();
+
+        assert!(pinner.dir().is_dir());
+        assert_eq!(pinner.path("task_ctx"), tmp.path().join("scx_foo/task_ctx"));
+        assert!(!pinner.is_pinned("task_ctx"));
+
+        fs::write(pinner.path("task_ctx"), "").unwrap();
+        assert!(pinner.is_pinned("task_ctx"));
+    }
+
+    #[test]
+    fn test_unpin_all() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut pinner = MapPinner::with_dir(tmp.path().join("scx_foo")).unwrap();
+        pinner.names = vec!["a".into(), "b".into()];
+
+        // "b" was never pinned, e.g. the scheduler exited before loading.
+        fs::write(pinner.path("a"), "").unwrap();
+        pinner.unpin_all().unwrap();
+        assert!(!pinner.dir().exists());
+
+        // Maps pinned by others keep the directory around.
+        let pinner = MapPinner::with_dir(tmp.path().join("scx_bar")).unwrap();
+        fs::write(pinner.path("other"), "").unwrap();
+        pinner.unpin_all().unwrap();
+        assert!(pinner.is_pinned("other"));
+    }
+
+    #[test]
+    fn test_clear() {
+        let tmp = tempfile::tempdir().unwrap();
+        let pinner = MapPinner::with_dir(tmp.path().join("scx_foo")).unwrap();
+        fs::write(pinner.path("a"), "").unwrap();
+        fs::write(pinner.path("b"), "").unwrap();
+
+        pinner.clear().unwrap();
+        assert!(pinner.dir().is_dir());
+        assert_eq!(fs::read_dir(pinner.dir()).unwrap().count(), 0);
+
+        fs::remove_dir(pinner.dir()).unwrap();
+        pinner.clear().unwrap();
+    }
+}
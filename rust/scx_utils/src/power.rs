@@ -0,0 +1,430 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Power Telemetry
+//!
+//! Reads the energy and power counters which the kernel exposes so that
+//! power-aware schedulers can correlate their decisions with the measured
+//! power draw. The following sources are probed:
+//!
+//! - RAPL zones under `/sys/class/powercap`. These cover Intel CPUs and, on
+//!   recent kernels, AMD ones.
+//! - hwmon devices under `/sys/class/hwmon` which report energy counters,
+//!   e.g. `amd_energy`, or power readings.
+//!
+//! [`PowerSampler::sample()`] returns the joules consumed by each domain
+//! since the previous call:
+//!
+//! ```ignore
+//! let mut sampler = PowerSampler::new()?;
+//! loop {
+//!     sleep(Duration::from_secs(1));
+//!     let sample = sampler.sample()?;
+//!     info!("package power: {:.1}W", sample.package_watts());
+//! }
+//! ```
+//!
+//! The energy counters are usually only readable by root.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::bail;
+use anyhow::Result;
+
+use crate::compat::ROOT_PREFIX;
+use crate::misc::read_from_file;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerSource {
+    Rapl,
+    Hwmon,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Reading {
+    /// Cumulative energy counter in microjoules which wraps around after
+    /// reaching @max_uj. 0 if the range is unknown.
+    Energy { max_uj: u64 },
+    /// Instantaneous power in microwatts.
+    Power,
+}
+
+/// A power domain, e.g. a CPU package or its cores, and where to read it.
+#[derive(Clone, Debug)]
+pub struct PowerDomain {
+    pub name: String,
+    pub source: PowerSource,
+    /// Whether the domain covers a whole CPU package. Package domains don't
+    /// overlap and can be summed up. Others, e.g. the cores of a package or
+    /// the whole platform, may overlap with each other.
+    pub package: bool,
+    path: PathBuf,
+    reading: Reading,
+}
+
+impl PowerDomain {
+    fn read(&self) -> Result<u64> {
+        read_from_file(&self.path)
+    }
+}
+
+/// Energy consumed by one domain over a sampling interval.
+#[derive(Clone, Debug)]
+pub struct DomainEnergy {
+    pub name: String,
+    pub package: bool,
+    pub joules: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct PowerSample {
+    pub interval: Duration,
+    pub domains: Vec<DomainEnergy>,
+}
+
+impl PowerSample {
+    pub fn joules(&self, name: &str) -> Option<f64> {
+        self.domains
+            .iter()
+            .find(|dom| dom.name == name)
+            .map(|dom| dom.joules)
+    }
+
+    /// Average power of domain @name over the interval.
+    pub fn watts(&self, name: &str) -> Option<f64> {
+        self.joules(name).map(|j| self.to_watts(j))
+    }
+
+    /// Energy consumed by all CPU packages.
+    pub fn package_joules(&self) -> f64 {
+        self.domains
+            .iter()
+            .filter(|dom| dom.package)
+            .map(|dom| dom.joules)
+            .sum()
+    }
+
+    pub fn package_watts(&self) -> f64 {
+        self.to_watts(self.package_joules())
+    }
+
+    fn to_watts(&self, joules: f64) -> f64 {
+        match self.interval.as_secs_f64() {
+            secs if secs > 0.0 => joules / secs,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Microjoules consumed between two readings of a counter which wraps
+/// around after @max_uj.
+fn energy_delta(prev: u64, cur: u64, max_uj: u64) -> u64 {
+    if cur >= prev {
+        cur - prev
+    } else if max_uj > prev {
+        max_uj - prev + cur
+    } else {
+        // The range is unknown or the counter was reset.
+        cur
+    }
+}
+
+fn read_name(path: &Path) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+/// RAPL zones are named intel-rapl:PKG for packages and platforms and
+/// intel-rapl:PKG:SUB for their subzones. intel-rapl-mmio zones duplicate
+/// the package ones and are skipped.
+fn probe_rapl(dir: &Path) -> Vec<PowerDomain> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+
+    let mut zone_names = BTreeMap::new();
+    for entry in entries.flatten() {
+        let zone = entry.file_name().to_string_lossy().into_owned();
+        if !zone.starts_with("intel-rapl:") {
+            continue;
+        }
+        if let Some(name) = read_name(&entry.path().join("name")) {
+            zone_names.insert(zone, name);
+        }
+    }
+
+    let mut domains = vec![];
+    for (zone, name) in zone_names.iter() {
+        let path = dir.join(zone).join("energy_uj");
+        if read_from_file::<u64>(&path).is_err() {
+            continue;
+        }
+
+        let (parent, top) = match zone.rsplit_once(':') {
+            Some((parent, _)) if parent.contains(':') => (zone_names.get(parent), false),
+            _ => (None, true),
+        };
+        domains.push(PowerDomain {
+            name: match parent {
+                Some(parent) => format!("{parent}/{name}"),
+                None => name.clone(),
+            },
+            source: PowerSource::Rapl,
+            package: top && name.starts_with("package-"),
+            path,
+            reading: Reading::Energy {
+                max_uj: read_from_file(&dir.join(zone).join("max_energy_range_uj")).unwrap_or(0),
+            },
+        });
+    }
+    domains
+}
+
+/// hwmon devices report energy in energyN_input and power in powerN_input
+/// or powerN_average, optionally labeled by energyN_label and powerN_label.
+fn probe_hwmon(dir: &Path) -> Vec<PowerDomain> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+
+    let mut domains = vec![];
+    for entry in entries.flatten() {
+        let hwmon = entry.path();
+        let Some(chip) = read_name(&hwmon.join("name")) else {
+            continue;
+        };
+        let Ok(files) = fs::read_dir(&hwmon) else {
+            continue;
+        };
+
+        for file in files.flatten() {
+            let file = file.file_name().to_string_lossy().into_owned();
+            let (sensor, reading) = if let Some(sensor) = file.strip_suffix("_input") {
+                match sensor.starts_with("energy") {
+                    true => (sensor, Reading::Energy { max_uj: 0 }),
+                    false => (sensor, Reading::Power),
+                }
+            } else if let Some(sensor) = file.strip_suffix("_average") {
+                // Only used if there's no instantaneous reading.
+                if hwmon.join(format!("{sensor}_input")).exists() {
+                    continue;
+                }
+                (sensor, Reading::Power)
+            } else {
+                continue;
+            };
+
+            if !sensor.starts_with("energy") && !sensor.starts_with("power") {
+                continue;
+            }
+            let path = hwmon.join(&file);
+            if read_from_file::<u64>(&path).is_err() {
+                continue;
+            }
+
+            let label = read_name(&hwmon.join(format!("{sensor}_label")))
+                .unwrap_or_else(|| sensor.to_string());
+            domains.push(PowerDomain {
+                name: format!("{chip}/{label}"),
+                source: PowerSource::Hwmon,
+                package: false,
+                path,
+                reading,
+            });
+        }
+    }
+    domains
+}
+
+/// Polls the power domains of the system. See the module documentation.
+pub struct PowerSampler {
+    domains: Vec<PowerDomain>,
+    last: Vec<Option<u64>>,
+    last_at: Instant,
+}
+
+impl PowerSampler {
+    pub fn new() -> Result<Self> {
+        Self::with_root(Path::new(&format!("{}/sys/class", *ROOT_PREFIX)))
+    }
+
+    /// Probe the powercap and hwmon classes under @root instead of
+    /// /sys/class.
+    pub fn with_root(root: &Path) -> Result<Self> {
+        let mut domains = probe_rapl(&root.join("powercap"));
+        domains.append(&mut probe_hwmon(&root.join("hwmon")));
+        if domains.is_empty() {
+            bail!("No readable power domains under {:?}", root);
+        }
+        domains.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let last = domains.iter().map(|dom| dom.read().ok()).collect();
+        Ok(Self {
+            domains,
+            last,
+            last_at: Instant::now(),
+        })
+    }
+
+    pub fn domains(&self) -> &[PowerDomain] {
+        &self.domains
+    }
+
+    /// Energy consumed by each domain since the previous call, or since the
+    /// sampler was created for the first call. Domains which can't be read
+    /// at the moment are left out.
+    pub fn sample(&mut self) -> Result<PowerSample> {
+        self.sample_at(Instant::now())
+    }
+
+    fn sample_at(&mut self, now: Instant) -> Result<PowerSample> {
+        let interval = now.saturating_duration_since(self.last_at);
+        self.last_at = now;
+
+        let mut domains = vec![];
+        for (dom, last) in self.domains.iter().zip(self.last.iter_mut()) {
+            let Ok(cur) = dom.read() else {
+                *last = None;
+                continue;
+            };
+
+            let uj = match dom.reading {
+                Reading::Energy { max_uj } => match last.replace(cur) {
+                    Some(prev) => energy_delta(prev, cur, max_uj) as f64,
+                    None => continue,
+                },
+                Reading::Power => cur as f64 * interval.as_secs_f64(),
+            };
+            domains.push(DomainEnergy {
+                name: dom.name.clone(),
+                package: dom.package,
+                joules: uj / 1_000_000.0,
+            });
+        }
+
+        Ok(PowerSample { interval, domains })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(root: &Path, path: &str, val: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, val).unwrap();
+    }
+
+    fn fake_sysfs() -> tempfile::TempDir {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        write(root, "powercap/intel-rapl/enabled", "1");
+        write(root, "powercap/intel-rapl:0/name", "package-0");
+        write(root, "powercap/intel-rapl:0/energy_uj", "1000000");
+        write(
+            root,
+            "powercap/intel-rapl:0/max_energy_range_uj",
+            "262143328850",
+        );
+        write(root, "powercap/intel-rapl:0:0/name", "core");
+        write(root, "powercap/intel-rapl:0:0/energy_uj", "500000");
+        write(root, "powercap/intel-rapl:1/name", "psys");
+        write(root, "powercap/intel-rapl:1/energy_uj", "0");
+        write(root, "powercap/intel-rapl-mmio:0/name", "package-0");
+        write(root, "powercap/intel-rapl-mmio:0/energy_uj", "0");
+
+        write(root, "hwmon/hwmon0/name", "amd_energy");
+        write(root, "hwmon/hwmon0/energy1_input", "2000000");
+        write(root, "hwmon/hwmon0/energy1_label", "Esocket0");
+        write(root, "hwmon/hwmon1/name", "acpi_power");
+        write(root, "hwmon/hwmon1/power1_average", "5000000");
+        write(root, "hwmon/hwmon2/name", "k10temp");
+        write(root, "hwmon/hwmon2/temp1_input", "45000");
+        tmp
+    }
+
+    #[test]
+    fn test_probe() {
+        let tmp = fake_sysfs();
+        let sampler = PowerSampler::with_root(tmp.path()).unwrap();
+
+        let names: Vec<&str> = sampler.domains().iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "acpi_power/power1",
+                "amd_energy/Esocket0",
+                "package-0",
+                "package-0/core",
+                "psys"
+            ]
+        );
+
+        let packages: Vec<&str> = sampler
+            .domains()
+            .iter()
+            .filter(|d| d.package)
+            .map(|d| d.name.as_str())
+            .collect();
+        assert_eq!(packages, vec!["package-0"]);
+        assert_eq!(sampler.domains()[0].source, PowerSource::Hwmon);
+        assert_eq!(sampler.domains()[2].source, PowerSource::Rapl);
+
+        let empty = tempfile::tempdir().unwrap();
+        assert!(PowerSampler::with_root(empty.path()).is_err());
+    }
+
+    #[test]
+    fn test_sample() {
+        let tmp = fake_sysfs();
+        let root = tmp.path();
+        let mut sampler = PowerSampler::with_root(root).unwrap();
+        let at = sampler.last_at + Duration::from_secs(2);
+
+        write(root, "powercap/intel-rapl:0/energy_uj", "21000000");
+        write(root, "powercap/intel-rapl:0:0/energy_uj", "3500000");
+        write(root, "hwmon/hwmon0/energy1_input", "6000000");
+        let sample = sampler.sample_at(at).unwrap();
+
+        assert_eq!(sample.interval, Duration::from_secs(2));
+        assert_eq!(sample.joules("package-0"), Some(20.0));
+        assert_eq!(sample.watts("package-0"), Some(10.0));
+        assert_eq!(sample.joules("package-0/core"), Some(3.0));
+        assert_eq!(sample.joules("psys"), Some(0.0));
+        assert_eq!(sample.joules("amd_energy/Esocket0"), Some(4.0));
+        assert_eq!(sample.joules("acpi_power/power1"), Some(10.0));
+        assert_eq!(sample.package_joules(), 20.0);
+        assert_eq!(sample.package_watts(), 10.0);
+
+        // A domain which becomes unreadable is left out until it recovers.
+        fs::remove_file(root.join("powercap/intel-rapl:0:0/energy_uj")).unwrap();
+        let sample = sampler.sample_at(at + Duration::from_secs(1)).unwrap();
+        assert_eq!(sample.joules("package-0/core"), None);
+        assert_eq!(sample.joules("package-0"), Some(0.0));
+
+        write(root, "powercap/intel-rapl:0:0/energy_uj", "3500000");
+        let sample = sampler.sample_at(at + Duration::from_secs(2)).unwrap();
+        assert_eq!(sample.joules("package-0/core"), None);
+        let sample = sampler.sample_at(at + Duration::from_secs(3)).unwrap();
+        assert_eq!(sample.joules("package-0/core"), Some(0.0));
+    }
+
+    #[test]
+    fn test_energy_delta() {
+        assert_eq!(energy_delta(100, 300, 1000), 200);
+        assert_eq!(energy_delta(900, 100, 1000), 200);
+        assert_eq!(energy_delta(900, 100, 0), 100);
+        assert_eq!(energy_delta(100, 100, 1000), 0);
+    }
+}
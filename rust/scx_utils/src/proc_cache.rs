@@ -0,0 +1,331 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Process Resolution Cache
+//!
+//! Schedulers often need to map a pid to its comm, uid or cgroup, e.g. to
+//! evaluate layer matchers or to annotate stats. Reading procfs for every
+//! lookup adds up quickly in periodic userspace loops, so [`ProcCache`]
+//! keeps the resolved [`ProcInfo`] around.
+//!
+//! Entries are invalidated when:
+//!
+//! - The cgroup they belong to becomes empty or is removed, which is
+//!   detected by watching the cgroup's `cgroup.events` file with inotify.
+//! - They are older than the configured TTL. This catches the changes which
+//!   can't be watched, e.g. a task moving between two populated cgroups or
+//!   changing its comm.
+//!
+//! If inotify is not available, the cache falls back to the TTL alone.
+//!
+//! ```no_run
+//! use scx_utils::ProcCache;
+//! use std::time::Duration;
+//!
+//! let mut cache = ProcCache::new(Duration::from_secs(1));
+//! let info = cache.get(1).unwrap();
+//! println!("{} uid={} cgroup={}", info.comm, info.uid, info.cgroup);
+//! ```
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use inotify::Inotify;
+use inotify::WatchDescriptor;
+use inotify::WatchMask;
+use log::debug;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProcInfo {
+    pub pid: i32,
+    pub comm: String,
+    /// Effective uid.
+    pub uid: u32,
+    /// cgroup v2 path relative to the cgroupfs root, e.g.
+    /// "/system.slice/foo.service".
+    pub cgroup: String,
+}
+
+impl ProcInfo {
+    /// Resolve @pid reading procfs directly.
+    pub fn read(pid: i32) -> Result<Self> {
+        let comm = fs::read_to_string(format!("/proc/{pid}/comm"))
+            .with_context(|| format!("Failed to read comm of pid {pid}"))?;
+        let status = fs::read_to_string(format!("/proc/{pid}/status"))
+            .with_context(|| format!("Failed to read status of pid {pid}"))?;
+        let cgroup = fs::read_to_string(format!("/proc/{pid}/cgroup"))
+            .with_context(|| format!("Failed to read cgroup of pid {pid}"))?;
+
+        Ok(Self {
+            pid,
+            comm: comm.trim_end_matches('\n').to_string(),
+            uid: parse_euid(&status).ok_or_else(|| anyhow!("No uid for pid {pid}"))?,
+            cgroup: parse_cgroup(&cgroup).ok_or_else(|| anyhow!("No cgroup v2 for pid {pid}"))?,
+        })
+    }
+}
+
+/// Parse the effective uid from the content of /proc/PID/status.
+fn parse_euid(status: &str) -> Option<u32> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Uid:"))
+        .and_then(|uids| uids.split_whitespace().nth(1))
+        .and_then(|euid| euid.parse().ok())
+}
+
+/// Parse the cgroup v2 path from the content of /proc/PID/cgroup.
+fn parse_cgroup(cgroup: &str) -> Option<String> {
+    cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(|path| path.to_string())
+}
+
+#[derive(Debug)]
+struct Entry {
+    info: ProcInfo,
+    at: Instant,
+}
+
+pub struct ProcCache {
+    ttl: Duration,
+    entries: HashMap<i32, Entry>,
+    inotify: Option<Inotify>,
+    cgroup_wds: HashMap<WatchDescriptor, String>,
+    cgroup_pids: HashMap<String, (WatchDescriptor, HashSet<i32>)>,
+    buf: Vec<u8>,
+}
+
+impl ProcCache {
+    /// Create a cache whose entries expire after @ttl at most.
+    pub fn new(ttl: Duration) -> Self {
+        let inotify = match Inotify::init() {
+            Ok(v) => Some(v),
+            Err(e) => {
+                debug!("inotify not available, falling back to TTL only ({e})");
+                None
+            }
+        };
+
+        Self {
+            ttl,
+            entries: HashMap::new(),
+            inotify,
+            cgroup_wds: HashMap::new(),
+            cgroup_pids: HashMap::new(),
+            buf: vec![0; 4096],
+        }
+    }
+
+    /// Return the information of @pid, resolving it if it's not cached or
+    /// has been invalidated.
+    pub fn get(&mut self, pid: i32) -> Result<&ProcInfo> {
+        self.process_events();
+
+        let fresh = self
+            .entries
+            .get(&pid)
+            .is_some_and(|entry| entry.at.elapsed() < self.ttl);
+        if !fresh {
+            self.invalidate(pid);
+            let info = ProcInfo::read(pid)?;
+            self.watch_cgroup(&info);
+            self.entries.insert(
+                pid,
+                Entry {
+                    info,
+                    at: Instant::now(),
+                },
+            );
+        }
+
+        Ok(&self.entries[&pid].info)
+    }
+
+    /// Drop the cached information of @pid, e.g. when the caller knows
+    /// that the task exited.
+    pub fn invalidate(&mut self, pid: i32) {
+        let Some(entry) = self.entries.remove(&pid) else {
+            return;
+        };
+
+        let cgroup = entry.info.cgroup;
+        let empty = match self.cgroup_pids.get_mut(&cgroup) {
+            Some((_, pids)) => {
+                pids.remove(&pid);
+                pids.is_empty()
+            }
+            None => false,
+        };
+        if empty {
+            self.unwatch_cgroup(&cgroup);
+        }
+    }
+
+    /// Only keep the entries of the pids for which @f returns true, e.g. to
+    /// drop the tasks which exited after a scan of /proc.
+    pub fn retain<F: FnMut(i32) -> bool>(&mut self, mut f: F) {
+        let pids: Vec<i32> = self
+            .entries
+            .keys()
+            .copied()
+            .filter(|pid| !f(*pid))
+            .collect();
+        for pid in pids {
+            self.invalidate(pid);
+        }
+    }
+
+    /// Drop all cached entries.
+    pub fn clear(&mut self) {
+        let pids: Vec<i32> = self.entries.keys().copied().collect();
+        for pid in pids {
+            self.invalidate(pid);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn watch_cgroup(&mut self, info: &ProcInfo) {
+        if let Some((_, pids)) = self.cgroup_pids.get_mut(&info.cgroup) {
+            pids.insert(info.pid);
+            return;
+        }
+
+        let Some(inotify) = self.inotify.as_mut() else {
+            return;
+        };
+
+        let path = PathBuf::from(format!("{}{}", CGROUP_ROOT, info.cgroup)).join("cgroup.events");
+        match inotify
+            .watches()
+            .add(&path, WatchMask::MODIFY | WatchMask::DELETE_SELF)
+        {
+            Ok(wd) => {
+                self.cgroup_wds.insert(wd.clone(), info.cgroup.clone());
+                self.cgroup_pids
+                    .insert(info.cgroup.clone(), (wd, HashSet::from([info.pid])));
+            }
+            Err(e) => debug!("Failed to watch {}: {}", path.display(), e),
+        }
+    }
+
+    fn unwatch_cgroup(&mut self, cgroup: &str) {
+        let Some((wd, _)) = self.cgroup_pids.remove(cgroup) else {
+            return;
+        };
+        self.cgroup_wds.remove(&wd);
+        if let Some(inotify) = self.inotify.as_mut() {
+            // Fails if the cgroup is already gone, which is fine.
+            let _ = inotify.watches().remove(wd);
+        }
+    }
+
+    // Drain the pending inotify events and invalidate the entries of the
+    // cgroups which changed.
+    fn process_events(&mut self) {
+        let mut changed = HashSet::new();
+
+        if let Some(inotify) = self.inotify.as_mut() {
+            loop {
+                let events = match inotify.read_events(&mut self.buf) {
+                    Ok(events) => events,
+                    Err(e) => {
+                        if e.kind() != std::io::ErrorKind::WouldBlock {
+                            debug!("Failed to read inotify events ({e})");
+                        }
+                        break;
+                    }
+                };
+
+                let mut nr_events = 0;
+                for event in events {
+                    nr_events += 1;
+                    // Any event means that the cgroup became empty or was
+                    // removed, in which case the watch is dropped when
+                    // the last pid is invalidated below.
+                    if let Some(cgroup) = self.cgroup_wds.get(&event.wd) {
+                        changed.insert(cgroup.clone());
+                    }
+                }
+                if nr_events == 0 {
+                    break;
+                }
+            }
+        }
+
+        for cgroup in changed {
+            let pids = match self.cgroup_pids.get(&cgroup) {
+                Some((_, pids)) => pids.iter().copied().collect::<Vec<_>>(),
+                None => continue,
+            };
+            for pid in pids {
+                self.invalidate(pid);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proc_files() {
+        let status = "Name:\tbash\nUmask:\t0022\nState:\tS (sleeping)\n\
+                      Uid:\t1000\t1001\t1000\t1000\nGid:\t100\t100\t100\t100\n";
+        assert_eq!(parse_euid(status), Some(1001));
+        assert_eq!(parse_euid("Name:\tbash\n"), None);
+
+        let cgroup = "1:name=systemd:/user.slice\n0::/user.slice/user-1000.slice/session-2.scope\n";
+        assert_eq!(
+            parse_cgroup(cgroup).as_deref(),
+            Some("/user.slice/user-1000.slice/session-2.scope")
+        );
+        assert_eq!(parse_cgroup("1:cpu:/\n"), None);
+    }
+
+    #[test]
+    fn test_cache_self() {
+        let pid = std::process::id() as i32;
+        let mut cache = ProcCache::new(Duration::from_secs(60));
+
+        let Ok(expected) = ProcInfo::read(pid) else {
+            // No cgroup v2 in the test environment.
+            return;
+        };
+        assert_eq!(cache.get(pid).unwrap(), &expected);
+        assert_eq!(cache.len(), 1);
+
+        cache.invalidate(pid);
+        assert!(cache.is_empty());
+        assert_eq!(cache.get(pid).unwrap(), &expected);
+
+        cache.retain(|p| p == pid);
+        assert_eq!(cache.len(), 1);
+        cache.retain(|p| p != pid);
+        assert!(cache.is_empty());
+
+        assert_eq!(cache.get(pid).unwrap(), &expected);
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,275 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Token and Leaky Buckets
+//!
+//! Rate limiting primitives for preemption budgets, migration throttles, log
+//! rate limiting and the like. These are the userspace counterparts of the
+//! helpers in
+//! [ratelimit.h](https://github.com/sched-ext/scx/blob/main/scheds/include/lib/ratelimit.h)
+//! and use the same integer arithmetic, so that a limit enforced in BPF and
+//! mirrored in userspace (or the other way around) makes the same decisions.
+//! The structs have the same layout as their C counterparts.
+//!
+//! Timestamps are in nsecs (see [`now_ns()`] for the clock used by
+//! `bpf_ktime_get_ns()`) and rates in units per second. A rate of 0 disables
+//! refilling / draining.
+//!
+//! ```
+//! use scx_utils::ratelimit::TokenBucket;
+//!
+//! // Allow 10 events per second with bursts of up to 5 events.
+//! let mut tb = TokenBucket::new(10, 5, 0);
+//! assert_eq!((0..10).filter(|_| tb.try_consume(1, 0)).count(), 5);
+//!
+//! // One token is refilled every 100ms.
+//! assert!(tb.try_consume(1, 100_000_000));
+//! assert!(!tb.try_consume(1, 100_000_000));
+//! ```
+
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// Return the current CLOCK_MONOTONIC time in nsecs, the clock used by
+/// `bpf_ktime_get_ns()`.
+pub fn now_ns() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * NSEC_PER_SEC + ts.tv_nsec as u64
+}
+
+/// Return the amount of units @rate produces between @last_at and @now, up
+/// to @max, and advance @last_at by the time they took. The time is rounded
+/// up so that the fractional leftover carries to the next call and units
+/// are never produced early. The products saturate, so huge rates, bursts
+/// or gaps at worst delay units to the next call. Equivalent to C
+/// `rl_advance()`.
+fn advance(last_at: &mut u64, now: u64, rate: u64, max: u64) -> u64 {
+    if now <= *last_at {
+        return 0;
+    }
+
+    let elapsed = now - *last_at;
+    if rate == 0 || max == 0 {
+        *last_at = now;
+        return 0;
+    }
+
+    if elapsed >= max.saturating_mul(NSEC_PER_SEC).div_ceil(rate) {
+        *last_at = now;
+        return max;
+    }
+
+    let units = elapsed.saturating_mul(rate) / NSEC_PER_SEC;
+    *last_at += (units * NSEC_PER_SEC).div_ceil(rate);
+    units
+}
+
+/// Token bucket holding up to `burst` tokens, refilled at `rate` tokens per
+/// second. An event costing N tokens is allowed if N tokens are available.
+/// Equivalent to C `struct scx_token_bucket`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TokenBucket {
+    pub rate: u64,
+    pub burst: u64,
+    pub tokens: u64,
+    pub last_at: u64,
+}
+
+impl TokenBucket {
+    /// Create a full bucket.
+    pub fn new(rate: u64, burst: u64, now: u64) -> Self {
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            last_at: now,
+        }
+    }
+
+    /// Refill the bucket up to @now and take @n tokens from it. Returns
+    /// true if there were enough tokens, false (leaving the tokens
+    /// untouched) otherwise.
+    pub fn try_consume(&mut self, n: u64, now: u64) -> bool {
+        self.tokens += advance(&mut self.last_at, now, self.rate, self.burst - self.tokens);
+
+        if self.tokens < n {
+            return false;
+        }
+        self.tokens -= n;
+        true
+    }
+}
+
+/// Leaky bucket used as a meter. Its level drains at `rate` units per
+/// second and an event adding N units is allowed if the level doesn't
+/// exceed `capacity` afterwards. Equivalent to C `struct scx_leaky_bucket`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LeakyBucket {
+    pub rate: u64,
+    pub capacity: u64,
+    pub level: u64,
+    pub last_at: u64,
+}
+
+impl LeakyBucket {
+    /// Create an empty bucket.
+    pub fn new(rate: u64, capacity: u64, now: u64) -> Self {
+        Self {
+            rate,
+            capacity,
+            level: 0,
+            last_at: now,
+        }
+    }
+
+    /// Drain the bucket up to @now and add @n units to it. Returns true if
+    /// the units fit, false (leaving the level untouched) otherwise.
+    pub fn try_add(&mut self, n: u64, now: u64) -> bool {
+        self.level -= advance(&mut self.last_at, now, self.rate, self.level);
+
+        if n > self.capacity - self.level {
+            return false;
+        }
+        self.level += n;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MSEC: u64 = 1_000_000;
+
+    #[test]
+    fn test_token_bucket() {
+        let mut tb = TokenBucket::new(10, 3, 0);
+        assert!(tb.try_consume(3, 0));
+        assert!(!tb.try_consume(1, 0));
+
+        // A token every 100ms, the leftover carries over.
+        assert!(!tb.try_consume(1, 99 * MSEC));
+        assert!(tb.try_consume(1, 150 * MSEC));
+        assert!(!tb.try_consume(1, 199 * MSEC));
+        assert!(tb.try_consume(1, 200 * MSEC));
+
+        // Never more than @burst tokens.
+        assert!(tb.try_consume(3, 10_000 * MSEC));
+        assert!(!tb.try_consume(1, 10_000 * MSEC));
+
+        // Time going backwards doesn't refill.
+        assert!(!tb.try_consume(1, 5_000 * MSEC));
+        assert_eq!(tb.last_at, 10_000 * MSEC);
+    }
+
+    #[test]
+    fn test_token_bucket_zero_rate() {
+        let mut tb = TokenBucket::new(0, 2, 0);
+        assert!(tb.try_consume(2, 0));
+        assert!(!tb.try_consume(1, u64::MAX));
+    }
+
+    #[test]
+    fn test_token_bucket_huge() {
+        // @burst * NSEC_PER_SEC and @elapsed * @rate overflow u64.
+        let mut tb = TokenBucket::new(u64::MAX / 2, u64::MAX / 2, 0);
+        assert!(tb.try_consume(u64::MAX / 2, 0));
+        assert!(tb.try_consume(1 << 40, 1 << 40));
+        assert!(tb.last_at <= 1 << 40);
+        assert!(tb.try_consume(u64::MAX / 4, u64::MAX));
+        assert!(tb.tokens <= tb.burst);
+
+        let mut tb = TokenBucket::new(1, u64::MAX, 0);
+        assert!(tb.try_consume(u64::MAX, 0));
+        assert!(tb.try_consume(1, NSEC_PER_SEC));
+        assert!(!tb.try_consume(1, NSEC_PER_SEC));
+    }
+
+    #[test]
+    fn test_leaky_bucket() {
+        let mut lb = LeakyBucket::new(10, 3, 0);
+        assert!(lb.try_add(2, 0));
+        assert!(!lb.try_add(2, 0));
+        assert!(lb.try_add(1, 0));
+
+        assert!(!lb.try_add(1, 99 * MSEC));
+        assert!(lb.try_add(1, 100 * MSEC));
+        assert!(lb.try_add(3, 10_000 * MSEC));
+        assert!(!lb.try_add(4, 20_000 * MSEC));
+    }
+
+    // xorshift64 so that the property tests are reproducible.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self, max: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % max
+        }
+    }
+
+    #[test]
+    fn test_token_bucket_properties() {
+        let mut rng = Rng(0x2545f4914f6cdd1d);
+
+        for _ in 0..200 {
+            let rate = rng.next(100_000);
+            let burst = rng.next(1000) + 1;
+            let start = rng.next(u32::MAX as u64);
+            let mut tb = TokenBucket::new(rate, burst, start);
+            let mut now = start;
+            let mut consumed = 0;
+
+            for _ in 0..1000 {
+                now += rng.next(10 * MSEC);
+                let n = rng.next(burst + 1);
+                let tokens = tb.tokens;
+                if tb.try_consume(n, now) {
+                    consumed += n;
+                } else {
+                    assert!(tb.tokens < n);
+                    assert!(tb.tokens >= tokens);
+                }
+
+                // Never holds more than @burst tokens and never hands out
+                // more than @burst plus what @rate produced.
+                assert!(tb.tokens <= burst);
+                assert!(tb.last_at <= now);
+                assert!(consumed <= burst + (now - start) * rate / NSEC_PER_SEC);
+            }
+        }
+    }
+
+    #[test]
+    fn test_leaky_bucket_properties() {
+        let mut rng = Rng(0x9e3779b97f4a7c15);
+
+        for _ in 0..200 {
+            let rate = rng.next(100_000);
+            let capacity = rng.next(1000) + 1;
+            let start = rng.next(u32::MAX as u64);
+            let mut lb = LeakyBucket::new(rate, capacity, start);
+            let mut tb = TokenBucket::new(rate, capacity, start);
+            let mut now = start;
+
+            for _ in 0..1000 {
+                now += rng.next(10 * MSEC);
+                let n = rng.next(capacity + 1);
+
+                // A leaky bucket meter is the dual of a token bucket.
+                assert_eq!(lb.try_add(n, now), tb.try_consume(n, now));
+                assert_eq!(lb.level, capacity - tb.tokens);
+                assert!(lb.level <= capacity);
+            }
+        }
+    }
+}
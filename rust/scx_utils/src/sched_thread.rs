@@ -0,0 +1,306 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Scheduler Thread Placement
+//!
+//! The userspace part of a scheduler competes for the CPUs with the tasks
+//! it's scheduling. When the machine is saturated, the scheduler's own
+//! threads can end up starved by the very load they are supposed to
+//! manage, e.g. delaying load balancing or the dispatch loop of userspace
+//! schedulers.
+//!
+//! [`SchedThreadArgs`] can be flattened into a scheduler's command line
+//! options to pin the scheduler's threads to designated CPUs and give them
+//! a scheduling policy. [`SchedThreadArgs::apply`] returns a
+//! [`SchedThreadGuard`] which restores the previous settings when dropped.
+
+use std::fs;
+use std::mem::size_of;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use clap::Args;
+use log::info;
+use log::warn;
+
+use crate::Cpumask;
+
+/// Scheduling policy of the scheduler's threads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchedThreadPolicy {
+    /// Keep the current scheduling class and set the nice level.
+    Nice(i32),
+    /// SCHED_FIFO with the given priority.
+    Fifo(i32),
+    /// SCHED_RR with the given priority.
+    RoundRobin(i32),
+}
+
+impl FromStr for SchedThreadPolicy {
+    type Err = anyhow::Error;
+
+    /// Parse "nice:N", "fifo:N" or "rr:N".
+    fn from_str(s: &str) -> Result<Self> {
+        let (policy, val) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("invalid policy {:?}, expected POLICY:VALUE", s))?;
+        let val: i32 = val
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid value in {:?}", s))?;
+
+        let check_rt_prio = |prio: i32| -> Result<i32> {
+            if !(1..=99).contains(&prio) {
+                bail!("real-time priority must be in [1, 99]");
+            }
+            Ok(prio)
+        };
+
+        match policy.trim() {
+            "nice" => {
+                if !(-20..=19).contains(&val) {
+                    bail!("nice level must be in [-20, 19]");
+                }
+                Ok(Self::Nice(val))
+            }
+            "fifo" => Ok(Self::Fifo(check_rt_prio(val)?)),
+            "rr" => Ok(Self::RoundRobin(check_rt_prio(val)?)),
+            _ => bail!("unknown policy {:?}, expected nice, fifo or rr", policy),
+        }
+    }
+}
+
+/// Placement of the scheduler's own userspace threads.
+#[derive(Args, Debug, Clone, Default)]
+pub struct SchedThreadArgs {
+    /// Pin the scheduler's userspace threads to the given CPUs, either as a
+    /// cpulist (e.g. "0-1,8") or as a hex mask (e.g. "0x103").
+    ///
+    /// This keeps the scheduler itself from being starved by the workload
+    /// it's scheduling when the designated CPUs are kept lightly loaded.
+    #[clap(long)]
+    pub sched_thread_cpus: Option<String>,
+
+    /// Scheduling policy of the scheduler's userspace threads: "nice:N"
+    /// keeps the current scheduling class and sets the nice level,
+    /// "fifo:N" and "rr:N" switch to SCHED_FIFO or SCHED_RR with priority N.
+    ///
+    /// Real-time policies are not inherited by the threads spawned later.
+    #[clap(long)]
+    pub sched_thread_policy: Option<SchedThreadPolicy>,
+}
+
+impl SchedThreadArgs {
+    fn cpus(&self) -> Result<Option<Cpumask>> {
+        let Some(cpus) = &self.sched_thread_cpus else {
+            return Ok(None);
+        };
+
+        let cpus = cpus.trim();
+        let mask = if cpus.starts_with("0x") || cpus == "all" {
+            Cpumask::from_str(cpus)?
+        } else {
+            Cpumask::from_cpulist(cpus)?
+        };
+        if mask.is_empty() {
+            bail!("--sched-thread-cpus can't be empty");
+        }
+        Ok(Some(mask))
+    }
+
+    /// Apply the configured placement to all the threads of the current
+    /// process, so that the scheduler isn't starved by the load it's
+    /// scheduling. Threads spawned afterwards inherit the CPU affinity and
+    /// the nice level. Returns None if nothing is configured, otherwise
+    /// keep the guard until exit to restore the previous settings.
+    ///
+    /// If a thread can't be configured, the threads configured so far are
+    /// restored before returning the error.
+    pub fn apply(&self) -> Result<Option<SchedThreadGuard>> {
+        let cpus = self.cpus()?;
+        if cpus.is_none() && self.sched_thread_policy.is_none() {
+            return Ok(None);
+        }
+
+        let mut guard = SchedThreadGuard { threads: vec![] };
+        for tid in read_tids()? {
+            let prev = ThreadSched::read(tid)?;
+            guard.threads.push(prev);
+
+            if let Some(cpus) = &cpus {
+                set_affinity(tid, cpus)
+                    .with_context(|| format!("Failed to pin thread {} to {}", tid, cpus))?;
+            }
+            if let Some(policy) = self.sched_thread_policy {
+                set_policy(tid, policy).with_context(|| {
+                    format!("Failed to set policy {:?} of thread {}", policy, tid)
+                })?;
+            }
+        }
+
+        info!(
+            "Scheduler threads: cpus={} policy={:?}",
+            cpus.map_or("any".to_string(), |cpus| format!("{}", cpus)),
+            self.sched_thread_policy
+        );
+
+        Ok(Some(guard))
+    }
+}
+
+struct ThreadSched {
+    tid: libc::pid_t,
+    cpus: libc::cpu_set_t,
+    policy: libc::c_int,
+    param: libc::sched_param,
+    nice: libc::c_int,
+}
+
+impl ThreadSched {
+    fn read(tid: libc::pid_t) -> Result<Self> {
+        let mut cpus: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        if unsafe { libc::sched_getaffinity(tid, size_of::<libc::cpu_set_t>(), &mut cpus) } < 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Failed to read the CPU affinity of thread {}", tid));
+        }
+
+        let policy = unsafe { libc::sched_getscheduler(tid) };
+        if policy < 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Failed to read the policy of thread {}", tid));
+        }
+
+        let mut param = libc::sched_param { sched_priority: 0 };
+        if unsafe { libc::sched_getparam(tid, &mut param) } < 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Failed to read the priority of thread {}", tid));
+        }
+
+        // getpriority() can legitimately return -1, check errno instead.
+        unsafe { *libc::__errno_location() = 0 };
+        let nice = unsafe { libc::getpriority(libc::PRIO_PROCESS, tid as libc::id_t) };
+        if nice == -1 && std::io::Error::last_os_error().raw_os_error() != Some(0) {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Failed to read the nice level of thread {}", tid));
+        }
+
+        Ok(Self {
+            tid,
+            cpus,
+            policy,
+            param,
+            nice,
+        })
+    }
+
+    fn restore(&self) -> std::io::Result<()> {
+        let ret = unsafe {
+            libc::sched_setscheduler(self.tid, self.policy, &self.param) < 0
+                || libc::setpriority(libc::PRIO_PROCESS, self.tid as libc::id_t, self.nice) < 0
+                || libc::sched_setaffinity(self.tid, size_of::<libc::cpu_set_t>(), &self.cpus) < 0
+        };
+        match ret {
+            true => Err(std::io::Error::last_os_error()),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Restores the CPU affinity and scheduling policy the scheduler's threads
+/// had before [`SchedThreadArgs::apply`] when dropped.
+pub struct SchedThreadGuard {
+    threads: Vec<ThreadSched>,
+}
+
+impl Drop for SchedThreadGuard {
+    fn drop(&mut self) {
+        for thread in self.threads.iter().rev() {
+            match thread.restore() {
+                // The thread may have exited in the meantime.
+                Err(e) if e.raw_os_error() == Some(libc::ESRCH) => {}
+                Err(e) => warn!("Failed to restore thread {}: {}", thread.tid, e),
+                Ok(()) => {}
+            }
+        }
+    }
+}
+
+fn read_tids() -> Result<Vec<libc::pid_t>> {
+    let mut tids = vec![];
+    for entry in fs::read_dir("/proc/self/task").context("Failed to read /proc/self/task")? {
+        if let Ok(tid) = entry?.file_name().to_string_lossy().parse() {
+            tids.push(tid);
+        }
+    }
+    tids.sort();
+    Ok(tids)
+}
+
+fn set_affinity(tid: libc::pid_t, cpus: &Cpumask) -> std::io::Result<()> {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for cpu in cpus.iter() {
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+
+    // Fails with EINVAL if none of the CPUs is online.
+    if unsafe { libc::sched_setaffinity(tid, size_of::<libc::cpu_set_t>(), &set) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn set_policy(tid: libc::pid_t, policy: SchedThreadPolicy) -> std::io::Result<()> {
+    let ret = match policy {
+        SchedThreadPolicy::Nice(nice) => unsafe {
+            libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, nice)
+        },
+        SchedThreadPolicy::Fifo(prio) | SchedThreadPolicy::RoundRobin(prio) => {
+            let class = match policy {
+                SchedThreadPolicy::Fifo(_) => libc::SCHED_FIFO,
+                _ => libc::SCHED_RR,
+            };
+            let param = libc::sched_param {
+                sched_priority: prio,
+            };
+            // Don't let the threads spawned later, e.g. by libraries,
+            // inherit the real-time policy.
+            unsafe { libc::sched_setscheduler(tid, class | libc::SCHED_RESET_ON_FORK, &param) }
+        }
+    };
+
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!(
+            "nice:-5".parse::<SchedThreadPolicy>().unwrap(),
+            SchedThreadPolicy::Nice(-5)
+        );
+        assert_eq!(
+            "fifo:10".parse::<SchedThreadPolicy>().unwrap(),
+            SchedThreadPolicy::Fifo(10)
+        );
+        assert_eq!(
+            "rr:99".parse::<SchedThreadPolicy>().unwrap(),
+            SchedThreadPolicy::RoundRobin(99)
+        );
+
+        assert!("nice:20".parse::<SchedThreadPolicy>().is_err());
+        assert!("fifo:0".parse::<SchedThreadPolicy>().is_err());
+        assert!("deadline:1".parse::<SchedThreadPolicy>().is_err());
+        assert!("fifo".parse::<SchedThreadPolicy>().is_err());
+    }
+}
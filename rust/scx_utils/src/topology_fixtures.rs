@@ -0,0 +1,294 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Synthetic Topologies
+//!
+//! Fixtures to build a [`Topology`] without reading the host's sysfs. This
+//! allows the userspace side of schedulers (domain construction, LLC
+//! grouping, capacity handling, etc.) to be exercised against machine shapes
+//! that aren't available in CI, e.g. 8-socket servers, hybrid big/LITTLE
+//! laptops or single-core VMs.
+//!
+//! A synthetic topology is described as a regular grid of nodes, LLCs, cores
+//! and SMT siblings. CPU IDs are assigned sequentially in that order, and the
+//! first `nr_little_cores` cores of each LLC can be marked as LITTLE cores:
+//!```rust
+//!     use scx_utils::topology_fixtures::SyntheticTopology;
+//!     let topo = SyntheticTopology::new(2, 4, 8, 2).build().unwrap();
+//!     assert_eq!(topo.nodes.len(), 2);
+//!     assert_eq!(topo.all_llcs.len(), 8);
+//!     assert_eq!(topo.all_cpus.len(), 128);
+//!```
+//!
+//! Note that the cpumasks of a synthetic topology are sized to the synthetic
+//! CPU count, not to the host's `NR_CPU_IDS`. Cpumask helpers which validate
+//! against the host (e.g. `Cpumask::set_cpu()`) shouldn't be used on them
+//! when the synthetic machine is larger than the host.
+
+use crate::Core;
+use crate::CoreType;
+use crate::Cpu;
+use crate::Cpumask;
+use crate::Llc;
+use crate::Node;
+use crate::Topology;
+use anyhow::bail;
+use anyhow::Result;
+use bitvec::prelude::*;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Description of a synthetic machine to be turned into a [`Topology`].
+#[derive(Clone, Debug)]
+pub struct SyntheticTopology {
+    pub nr_nodes: usize,
+    pub nr_llcs_per_node: usize,
+    pub nr_cores_per_llc: usize,
+    pub nr_cpus_per_core: usize,
+    /// Number of cores at the beginning of each LLC which are LITTLE cores.
+    pub nr_little_cores: usize,
+    /// cpu_capacity of big cores, scaled to 1024.
+    pub big_capacity: usize,
+    /// cpu_capacity of LITTLE cores, scaled to 1024.
+    pub little_capacity: usize,
+    /// Maximum frequency in kHz reported for big cores.
+    pub big_max_freq: usize,
+    /// Maximum frequency in kHz reported for LITTLE cores.
+    pub little_max_freq: usize,
+    /// NUMA distance between two different nodes.
+    pub remote_distance: usize,
+}
+
+impl SyntheticTopology {
+    /// Describe a homogeneous machine.
+    pub fn new(
+        nr_nodes: usize,
+        nr_llcs_per_node: usize,
+        nr_cores_per_llc: usize,
+        nr_cpus_per_core: usize,
+    ) -> Self {
+        Self {
+            nr_nodes,
+            nr_llcs_per_node,
+            nr_cores_per_llc,
+            nr_cpus_per_core,
+            nr_little_cores: 0,
+            big_capacity: 1024,
+            little_capacity: 512,
+            big_max_freq: 3_000_000,
+            little_max_freq: 2_000_000,
+            remote_distance: 20,
+        }
+    }
+
+    /// A single CPU without SMT.
+    pub fn single_core() -> Self {
+        Self::new(1, 1, 1, 1)
+    }
+
+    /// An 8-socket server with one LLC of 16 SMT-2 cores per socket.
+    pub fn eight_socket() -> Self {
+        Self::new(8, 1, 16, 2)
+    }
+
+    /// A hybrid laptop with 4 big cores and 8 LITTLE cores sharing a single
+    /// LLC, without SMT.
+    pub fn hybrid() -> Self {
+        Self::new(1, 1, 12, 1).with_little_cores(8)
+    }
+
+    /// Mark the first `nr_little_cores` cores of each LLC as LITTLE cores.
+    pub fn with_little_cores(mut self, nr_little_cores: usize) -> Self {
+        self.nr_little_cores = nr_little_cores;
+        self
+    }
+
+    /// Total number of CPUs in the synthetic machine.
+    pub fn nr_cpus(&self) -> usize {
+        self.nr_nodes * self.nr_llcs_per_node * self.nr_cores_per_llc * self.nr_cpus_per_core
+    }
+
+    fn new_mask(&self) -> Cpumask {
+        Cpumask::from_bitvec(bitvec![u64, Lsb0; 0; self.nr_cpus()])
+    }
+
+    /// Build the [`Topology`].
+    pub fn build(&self) -> Result<Topology> {
+        if self.nr_cpus() == 0 {
+            bail!("Synthetic topology must have at least one CPU");
+        }
+        if self.nr_little_cores > self.nr_cores_per_llc {
+            bail!(
+                "Number of LITTLE cores ({}) exceeds cores per LLC ({})",
+                self.nr_little_cores,
+                self.nr_cores_per_llc
+            );
+        }
+
+        let has_biglittle =
+            self.nr_little_cores > 0 && self.nr_little_cores < self.nr_cores_per_llc;
+        let mut span = self.new_mask();
+        let mut nodes = BTreeMap::new();
+        let mut cpu_id = 0;
+        let mut core_id = 0;
+        let mut llc_id = 0;
+
+        for node_id in 0..self.nr_nodes {
+            let mut node = Node {
+                id: node_id,
+                distance: (0..self.nr_nodes)
+                    .map(|n| {
+                        if n == node_id {
+                            10
+                        } else {
+                            self.remote_distance
+                        }
+                    })
+                    .collect(),
+                llcs: BTreeMap::new(),
+                span: self.new_mask(),
+                all_cores: BTreeMap::new(),
+                all_cpus: BTreeMap::new(),
+                #[cfg(feature = "gpu-topology")]
+                gpus: BTreeMap::new(),
+            };
+
+            for _ in 0..self.nr_llcs_per_node {
+                let mut llc = Llc {
+                    id: llc_id,
+                    kernel_id: llc_id,
+                    cores: BTreeMap::new(),
+                    span: self.new_mask(),
+                    node_id,
+                    all_cpus: BTreeMap::new(),
+                };
+
+                for core_idx in 0..self.nr_cores_per_llc {
+                    let little = core_idx < self.nr_little_cores;
+                    let core_type = if !has_biglittle {
+                        CoreType::Big { turbo: false }
+                    } else if little {
+                        CoreType::Little
+                    } else {
+                        CoreType::Big { turbo: true }
+                    };
+                    let (cpu_capacity, max_freq) = if little {
+                        (self.little_capacity, self.little_max_freq)
+                    } else {
+                        (self.big_capacity, self.big_max_freq)
+                    };
+
+                    let mut core = Core {
+                        id: core_id,
+                        kernel_id: core_idx,
+                        cluster_id: llc_id as isize,
+                        cpus: BTreeMap::new(),
+                        span: self.new_mask(),
+                        core_type: core_type.clone(),
+                        llc_id,
+                        node_id,
+                    };
+
+                    for _ in 0..self.nr_cpus_per_core {
+                        core.cpus.insert(
+                            cpu_id,
+                            Arc::new(Cpu {
+                                id: cpu_id,
+                                min_freq: max_freq / 4,
+                                max_freq,
+                                base_freq: max_freq,
+                                cpu_capacity,
+                                smt_level: 0, // Will be initialized at instantiate().
+                                pm_qos_resume_latency_us: 0,
+                                trans_lat_ns: 0,
+                                l2_id: core_id,
+                                l3_id: llc_id,
+                                cache_size: 0,
+                                core_type: core_type.clone(),
+                                core_id,
+                                llc_id,
+                                node_id,
+                                package_id: node_id,
+                                cluster_id: llc_id as isize,
+                            }),
+                        );
+                        core.span.as_raw_bitvec_mut().set(cpu_id, true);
+                        llc.span.as_raw_bitvec_mut().set(cpu_id, true);
+                        node.span.as_raw_bitvec_mut().set(cpu_id, true);
+                        span.as_raw_bitvec_mut().set(cpu_id, true);
+                        cpu_id += 1;
+                    }
+
+                    llc.cores.insert(core_id, Arc::new(core));
+                    core_id += 1;
+                }
+
+                node.llcs.insert(llc_id, Arc::new(llc));
+                llc_id += 1;
+            }
+
+            nodes.insert(node_id, node);
+        }
+
+        let mut topo = Topology::instantiate(span, nodes)?;
+        topo.smt_enabled = self.nr_cpus_per_core > 1;
+        Ok(topo)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eight_socket() {
+        let topo = SyntheticTopology::eight_socket().build().unwrap();
+        assert_eq!(topo.nodes.len(), 8);
+        assert_eq!(topo.all_llcs.len(), 8);
+        assert_eq!(topo.all_cores.len(), 128);
+        assert_eq!(topo.all_cpus.len(), 256);
+        assert!(topo.smt_enabled);
+        assert!(!topo.has_little_cores());
+        for node in topo.nodes.values() {
+            assert_eq!(node.span.weight(), 32);
+            assert_eq!(node.distance[node.id], 10);
+        }
+        assert!(topo.all_cpus.values().all(|cpu| cpu.smt_level == 2));
+    }
+
+    #[test]
+    fn test_hybrid() {
+        let topo = SyntheticTopology::hybrid().build().unwrap();
+        let nr_little = topo
+            .all_cores
+            .values()
+            .filter(|c| c.core_type == CoreType::Little)
+            .count();
+        assert!(topo.has_little_cores());
+        assert_eq!(nr_little, 8);
+        assert!(topo
+            .all_cpus
+            .values()
+            .filter(|c| c.core_type == CoreType::Little)
+            .all(|c| c.cpu_capacity == 512));
+    }
+
+    #[test]
+    fn test_single_core() {
+        let topo = SyntheticTopology::single_core().build().unwrap();
+        assert_eq!(topo.all_cpus.len(), 1);
+        assert_eq!(topo.span.weight(), 1);
+        assert!(!topo.smt_enabled);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(SyntheticTopology::new(1, 1, 0, 1).build().is_err());
+        assert!(SyntheticTopology::new(1, 1, 2, 1)
+            .with_little_cores(3)
+            .build()
+            .is_err());
+    }
+}
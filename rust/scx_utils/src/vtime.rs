@@ -0,0 +1,103 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Weighted Virtual Time Helpers
+//!
+//! Arithmetic shared by the schedulers which order tasks by weighted virtual
+//! time. Task weights follow the sched_ext convention, ranging from 1 to
+//! 10000 with [`WEIGHT_DEFAULT`] for nice 0.
+//!
+//! Scaling a runtime by a weight can overflow u64 with large runtimes (e.g. a
+//! task which has been running for a long time) and large weights, so all the
+//! intermediate math is done in 128 bits and the results saturate instead of
+//! wrapping. A weight of 0 is treated as 1.
+//!
+//! ```
+//! use scx_utils::vtime;
+//!
+//! let mut task_vtime = 0;
+//! let vtime_now = 100_000_000;
+//!
+//! // Don't let the task accumulate more than 20ms of sleep credit.
+//! task_vtime = vtime::clamp_lag(task_vtime, vtime_now, 20_000_000);
+//!
+//! // Charge 5ms of runtime to a task with twice the default weight.
+//! task_vtime += vtime::scale_by_weight_inverse(5_000_000, 200);
+//! assert_eq!(task_vtime, 82_500_000);
+//! ```
+
+/// Weight of a nice 0 task.
+pub const WEIGHT_DEFAULT: u64 = 100;
+
+/// Compute @a * @b / @c in 128 bits, saturating to u64::MAX. @c of 0 is
+/// treated as 1.
+pub fn mul_div(a: u64, b: u64, c: u64) -> u64 {
+    let res = a as u128 * b as u128 / c.max(1) as u128;
+    res.min(u64::MAX as u128) as u64
+}
+
+/// Return @value scaled proportionally to @weight, e.g. to give higher
+/// weight tasks longer time slices.
+pub fn scale_by_weight(value: u64, weight: u64) -> u64 {
+    mul_div(value, weight.max(1), WEIGHT_DEFAULT)
+}
+
+/// Return @value scaled inversely proportionally to @weight, e.g. to
+/// convert a runtime into the vtime to charge.
+pub fn scale_by_weight_inverse(value: u64, weight: u64) -> u64 {
+    mul_div(value, WEIGHT_DEFAULT, weight)
+}
+
+/// Limit how far @vtime can lag behind @vtime_now, so that tasks which slept
+/// for a long time don't gain more than @max_lag of credit when they wake up.
+pub fn clamp_lag(vtime: u64, vtime_now: u64, max_lag: u64) -> u64 {
+    vtime.max(vtime_now.saturating_sub(max_lag))
+}
+
+/// Whether @a comes before @b, tolerating the wrap-around of the u64 vtime
+/// clock as long as the two are less than 2^63 apart.
+pub fn vtime_before(a: u64, b: u64) -> bool {
+    (a.wrapping_sub(b) as i64) < 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scale_by_weight() {
+        assert_eq!(scale_by_weight(1000, WEIGHT_DEFAULT), 1000);
+        assert_eq!(scale_by_weight(1000, 200), 2000);
+        assert_eq!(scale_by_weight(1000, 1), 10);
+        assert_eq!(scale_by_weight(1000, 0), 10);
+        assert_eq!(scale_by_weight_inverse(1000, 200), 500);
+        assert_eq!(scale_by_weight_inverse(1000, 1), 100_000);
+        assert_eq!(scale_by_weight_inverse(1000, 0), 100_000);
+
+        // The intermediate products overflow u64.
+        assert_eq!(scale_by_weight(u64::MAX / 10, 1000), u64::MAX / 10 * 10);
+        assert_eq!(scale_by_weight(u64::MAX, 10000), u64::MAX);
+        assert_eq!(scale_by_weight_inverse(u64::MAX / 2, 1), u64::MAX);
+        assert_eq!(mul_div(u64::MAX, u64::MAX, u64::MAX), u64::MAX);
+        assert_eq!(mul_div(7, 3, 0), 21);
+    }
+
+    #[test]
+    fn test_clamp_lag() {
+        assert_eq!(clamp_lag(0, 100, 20), 80);
+        assert_eq!(clamp_lag(90, 100, 20), 90);
+        assert_eq!(clamp_lag(150, 100, 20), 150);
+        assert_eq!(clamp_lag(0, 10, 20), 0);
+    }
+
+    #[test]
+    fn test_vtime_before() {
+        assert!(vtime_before(1, 2));
+        assert!(!vtime_before(2, 1));
+        assert!(!vtime_before(1, 1));
+        assert!(vtime_before(u64::MAX - 1, 1));
+        assert!(!vtime_before(1, u64::MAX - 1));
+    }
+}
@@ -0,0 +1,135 @@
+#ifndef __SCX_RATELIMIT_BPF_H__
+#define __SCX_RATELIMIT_BPF_H__
+
+/*
+ * Token bucket and leaky bucket helpers to be used in BPF progs. Assumes
+ * vmlinux.h has already been included. The matching userspace
+ * implementation is scx_utils::ratelimit, which uses the same integer
+ * arithmetic so that both sides make the same decisions.
+ *
+ * Timestamps are in nsecs (e.g. bpf_ktime_get_ns()) and rates in units per
+ * second. A rate of 0 disables refilling / draining. The helpers are not
+ * atomic: protect shared buckets with a lock or keep them per-CPU.
+ */
+#define RL_NSEC_PER_SEC		1000000000ULL
+
+#define RL_FN_ATTRS __attribute__((unused, always_inline))
+
+/* @a * @b, saturated to U64_MAX instead of wrapping around. */
+static RL_FN_ATTRS u64 rl_mul_sat(u64 a, u64 b)
+{
+	if (a && b > (u64)-1 / a)
+		return (u64)-1;
+	return a * b;
+}
+
+static RL_FN_ATTRS u64 rl_div_ceil(u64 a, u64 b)
+{
+	return a / b + (a % b != 0);
+}
+
+/*
+ * Token bucket. Holds up to @burst tokens, refilled at @rate tokens per
+ * second. An event costing N tokens is allowed if N tokens are available.
+ */
+struct scx_token_bucket {
+	u64			rate;
+	u64			burst;
+	u64			tokens;
+	u64			last_at;
+};
+
+/*
+ * Leaky bucket used as a meter. Its level drains at @rate units per second
+ * and an event adding N units is allowed if the level doesn't exceed
+ * @capacity afterwards.
+ */
+struct scx_leaky_bucket {
+	u64			rate;
+	u64			capacity;
+	u64			level;
+	u64			last_at;
+};
+
+/*
+ * Return the amount of units @rate produces within @elapsed nsecs, up to
+ * @max, and advance *@last_at by the time they took. The time is rounded up
+ * so that the fractional leftover carries to the next call and units are
+ * never produced early. The products saturate, so huge rates, bursts or
+ * gaps at worst delay units to the next call.
+ */
+static RL_FN_ATTRS u64 rl_advance(u64 *last_at, u64 now, u64 rate, u64 max)
+{
+	u64 elapsed, units;
+
+	if (now <= *last_at)
+		return 0;
+
+	elapsed = now - *last_at;
+	if (!rate || !max) {
+		*last_at = now;
+		return 0;
+	}
+
+	if (elapsed >= rl_div_ceil(rl_mul_sat(max, RL_NSEC_PER_SEC), rate)) {
+		*last_at = now;
+		return max;
+	}
+
+	units = rl_mul_sat(elapsed, rate) / RL_NSEC_PER_SEC;
+	*last_at += rl_div_ceil(units * RL_NSEC_PER_SEC, rate);
+	return units;
+}
+
+static RL_FN_ATTRS void scx_token_bucket_init(struct scx_token_bucket *tb,
+					      u64 rate, u64 burst, u64 now)
+{
+	tb->rate = rate;
+	tb->burst = burst;
+	tb->tokens = burst;
+	tb->last_at = now;
+}
+
+/*
+ * Refill @tb up to @now and take @n tokens from it. Returns true if there
+ * were enough tokens, false (leaving the tokens untouched) otherwise.
+ */
+static RL_FN_ATTRS bool scx_token_bucket_consume(struct scx_token_bucket *tb,
+						 u64 n, u64 now)
+{
+	tb->tokens += rl_advance(&tb->last_at, now, tb->rate,
+				 tb->burst - tb->tokens);
+
+	if (tb->tokens < n)
+		return false;
+
+	tb->tokens -= n;
+	return true;
+}
+
+static RL_FN_ATTRS void scx_leaky_bucket_init(struct scx_leaky_bucket *lb,
+					      u64 rate, u64 capacity, u64 now)
+{
+	lb->rate = rate;
+	lb->capacity = capacity;
+	lb->level = 0;
+	lb->last_at = now;
+}
+
+/*
+ * Drain @lb up to @now and add @n units to it. Returns true if the units
+ * fit, false (leaving the level untouched) otherwise.
+ */
+static RL_FN_ATTRS bool scx_leaky_bucket_add(struct scx_leaky_bucket *lb,
+					     u64 n, u64 now)
+{
+	lb->level -= rl_advance(&lb->last_at, now, lb->rate, lb->level);
+
+	if (n > lb->capacity - lb->level)
+		return false;
+
+	lb->level += n;
+	return true;
+}
+
+#endif /* __SCX_RATELIMIT_BPF_H__ */
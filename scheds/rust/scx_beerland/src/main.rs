@@ -429,11 +429,7 @@ fn main() -> Result<()> {
     let opts = Opts::parse();
 
     if opts.version {
-        println!(
-            "{} {}",
-            SCHEDULER_NAME,
-            build_id::full_version(env!("CARGO_PKG_VERSION"))
-        );
+        println!("{:#}", scx_utils::version_info!());
         return Ok(());
     }
 
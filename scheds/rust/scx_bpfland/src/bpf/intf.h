@@ -22,6 +22,7 @@ enum consts {
 
 	/* Kernel definitions */
 	CLOCK_BOOTTIME		= 7,
+	SCHED_IDLE		= 5,
 };
 
 #ifndef __VMLINUX_H__
@@ -4,6 +4,7 @@
  */
 #include <scx/common.bpf.h>
 #include <scx/percpu.bpf.h>
+#include <lib/ratelimit.h>
 #include "intf.h"
 
 /*
@@ -58,6 +59,51 @@ const volatile u64 slice_min;
  */
 const volatile u64 slice_lag = 40ULL * NSEC_PER_MSEC;
 
+/*
+ * Run-to-parity guard.
+ *
+ * Under heavy wakeup storms the time slice assigned to each task shrinks
+ * with the amount of waiting tasks, so a task may be switched out right
+ * after it started to run. When set, a task that starts running is
+ * guaranteed to run for at least @run_to_parity_ns (capped to its full
+ * time slice) before being switched out (0 = disabled).
+ */
+const volatile u64 run_to_parity_ns;
+
+/*
+ * Time slice donation.
+ *
+ * When a task blocks or yields within @SLICE_DONATION_WINDOW_NS after
+ * waking up another task synchronously, hand the remainder of its time
+ * slice over to the wakee, so that producer-consumer pipelines keep
+ * running without being switched out.
+ */
+const volatile bool slice_donation;
+
+#define SLICE_DONATION_WINDOW_NS	(1ULL * NSEC_PER_MSEC)
+
+/*
+ * Local DSQ depth-based admission control.
+ *
+ * When more than @local_dsq_depth_max tasks are already queued to the
+ * CPU a task is waking up on, redirect the task to the least loaded CPU
+ * in the same LLC, even if the target CPU was the preferred one. This
+ * bounds the worst-case queueing delay of the wakeups (0 = disabled).
+ */
+const volatile u64 local_dsq_depth_max;
+
+/*
+ * Network IRQ collaboration.
+ *
+ * When enabled, tasks that are mostly woken up from softirq context (e.g.,
+ * NET_RX) on the CPUs serving the NIC queue IRQs (@irq_cpumask, provided by
+ * user-space) are placed close to the waking CPU, so that they can consume
+ * the received data while it's still hot in cache.
+ *
+ * User-space disables it when the NIC IRQs are served by all the CPUs.
+ */
+const volatile bool irq_affine;
+
 /*
  * Ignore synchronous wakeup events.
  */
@@ -111,11 +157,126 @@ const volatile u64 preferred_cpus[MAX_CPUS];
  */
 const volatile u64 cpu_capacity[MAX_CPUS];
 
+/*
+ * Dedicated lowest-priority queue for SCHED_IDLE and deeply niced tasks.
+ *
+ * When enabled, these tasks are only consumed when no other task is waiting
+ * to run on the CPU, except for a periodic trickle (one task every
+ * @lowpri_starvation_ns) that prevents them from being starved forever.
+ */
+const volatile bool lowpri_enabled;
+
+/*
+ * Minimum nice level for a task to be considered low priority (SCHED_IDLE
+ * tasks are always considered low priority).
+ */
+const volatile s32 lowpri_nice = 19;
+
+/*
+ * Maximum time the lowest-priority queue can go without being served.
+ */
+const volatile u64 lowpri_starvation_ns = 100ULL * NSEC_PER_MSEC;
+
+/*
+ * Timestamp of the last task consumed from the lowest-priority queue.
+ */
+static u64 lowpri_last_dispatch;
+
+/*
+ * CPU usage budget between the interactive and batch classes.
+ *
+ * When set, interactive tasks (tasks that mostly release the CPU
+ * voluntarily) can use at most @interactive_budget percent of the CPU time
+ * while batch tasks are waiting to run, guaranteeing the remaining share to
+ * the batch class. The budget is enforced by pushing forward the deadline
+ * of the interactive tasks in proportion to the deficit of the batch class.
+ *
+ * 0 disables the budget.
+ */
+const volatile u64 interactive_budget;
+
+/*
+ * Window over which the CPU usage of each class is evaluated (the usage is
+ * halved at the end of each window).
+ */
+#define BUDGET_WINDOW_NS	(100ULL * NSEC_PER_MSEC)
+
+/*
+ * Minimum % of voluntary context switches for a task to be considered
+ * interactive.
+ */
+#define INTERACTIVE_SLEEP_PCT	50ULL
+
+/*
+ * CPU usage of each class in the current budget window.
+ */
+static u64 budget_window_at;
+static u64 interactive_window_runtime, batch_window_runtime;
+
+/*
+ * Amount of runnable and running batch tasks.
+ */
+static u64 nr_batch_runnable, nr_batch_running;
+
 /*
  * Scheduling statistics.
  */
 volatile u64 nr_kthread_dispatches, nr_direct_dispatches, nr_shared_dispatches;
 
+/*
+ * Low-priority scheduling statistics: dispatches from the lowest-priority
+ * queue, CPU time consumed by low-priority tasks and by all tasks.
+ */
+volatile u64 nr_lowpri_dispatches, lowpri_runtime, tot_task_runtime;
+
+/*
+ * Budget statistics: CPU time consumed by each class and amount of
+ * interactive task deadlines pushed forward to honor the batch class share.
+ */
+volatile u64 interactive_runtime, batch_runtime, nr_budget_offsets;
+
+/*
+ * Amount of time slices extended by the run-to-parity guard.
+ */
+volatile u64 nr_parity_extends;
+
+/*
+ * Amount of wakeups redirected away from a CPU with a deep local queue.
+ */
+volatile u64 nr_depth_redirects;
+
+/*
+ * Per-CPU placements of network-heavy tasks on CPUs serving NIC IRQs and
+ * amount of placements that landed elsewhere.
+ */
+volatile u64 nr_irq_hits[MAX_CPUS], nr_irq_misses;
+
+/*
+ * Amount of times the parked CPUs have been parked and unparked.
+ */
+volatile u64 nr_park_events, nr_unpark_events;
+
+/*
+ * Amount of fork storms detected and of tasks forked during a storm.
+ */
+volatile u64 nr_fork_storms, nr_storm_forks;
+
+/*
+ * Amount of tasks whose interactivity statistics have been reset on exec.
+ */
+volatile u64 nr_exec_resets;
+
+/*
+ * Amount of time slices donated to a synchronous wakee.
+ */
+volatile u64 nr_slice_donations;
+
+/*
+ * Amount of batch task balancing rounds that moved tasks between the shared
+ * DSQs and of batch tasks moved.
+ */
+volatile u64 nr_batch_balances, nr_batch_moves;
+
 /*
  * Amount of currently running tasks.
  */
@@ -131,6 +292,11 @@ volatile u64 nr_online_cpus;
  */
 static u64 nr_cpu_ids;
 
+/*
+ * Maximum possible NUMA node number.
+ */
+static u64 nr_node_ids;
+
 /*
  * Runtime throttling.
  *
@@ -149,6 +315,106 @@ static inline void set_throttled(bool state)
 	WRITE_ONCE(cpus_throttled, state);
 }
 
+/*
+ * CPU parking.
+ *
+ * Keep the CPUs outside of @unparked_cpumask fully idle, so that they can
+ * reach deeper idle states, unless the remaining CPUs stay overloaded for
+ * more than @park_overload_ns. The CPUs are parked again once the system
+ * has not been overloaded for the same amount of time.
+ */
+const volatile bool park_enabled;
+const volatile u64 park_overload_ns = 50ULL * NSEC_PER_MSEC;
+const volatile u32 nr_parked_cpus;
+
+/*
+ * Current parking state, read by user-space to coordinate with cpuidle.
+ */
+volatile bool cpus_parked;
+
+/*
+ * Time when the overload condition was first detected, or when the system
+ * stopped being overloaded after the CPUs have been unparked (0 = no
+ * transition pending), and time of the last parking state evaluation.
+ */
+static u64 park_pending_at, park_checked_at;
+
+/*
+ * Batch task balancing.
+ *
+ * When enabled, batch tasks are queued to a shared DSQ per LLC instead of
+ * the node DSQ, so that they stay close to their cache, but then one LLC
+ * (e.g., a CCD) can build a backlog of batch tasks while the CPUs of another
+ * one are idle. At most once every BATCH_BALANCE_INTERVAL_NS, the first
+ * dispatching CPU pushes up to half of the difference from the deepest LLC
+ * batch DSQ to the shallowest one with an idle CPU, if it's shorter by more
+ * than @batch_balance_thresh tasks, and kicks the idle CPU.
+ *
+ * 0 disables the balancing.
+ */
+const volatile u64 batch_balance_thresh;
+
+/*
+ * Return true if the tasks need to be classified as interactive or batch.
+ */
+static inline bool classify_batch(void)
+{
+	return interactive_budget || batch_balance_thresh;
+}
+
+#define BATCH_BALANCE_INTERVAL_NS	(10ULL * NSEC_PER_MSEC)
+
+/*
+ * Time of the last batch task balancing round.
+ */
+static u64 batch_balance_at;
+
+/*
+ * Maximum amount of batch tasks moved across LLCs per second, with bursts
+ * of up to one second worth of moves (0 = unlimited). Only used by the
+ * balancing CPU, which is serialized by @batch_balance_at.
+ */
+const volatile u64 batch_migrate_rate;
+static struct scx_token_bucket batch_migrate_tb;
+
+/*
+ * Fork storm detection.
+ *
+ * When tasks are forked at a rate higher than @fork_storm_rate forks per
+ * second, the children can use at most half of the time slice left to
+ * their parent and don't get the wakeup credit of interactive tasks until
+ * they have been woken up FORK_STORM_DEFER_WAKEUPS times. This prevents the
+ * tasks spawned en masse by build systems and similar workloads from being
+ * prioritized as interactive right after they are created. The storm ends
+ * when the fork rate drops below half of @fork_storm_rate.
+ *
+ * 0 disables the detection.
+ */
+const volatile u64 fork_storm_rate;
+
+/*
+ * Window over which the fork rate is evaluated.
+ */
+#define FORK_STORM_WINDOW_NS		(100ULL * NSEC_PER_MSEC)
+
+/*
+ * Amount of wakeups the classification of the children forked during a
+ * storm is deferred for.
+ */
+#define FORK_STORM_DEFER_WAKEUPS	8
+
+/*
+ * Current fork storm state and fork rate (forks/sec) measured over the
+ * last window, read by user-space for the stats.
+ */
+volatile bool fork_storm;
+volatile u64 fork_rate;
+
+/*
+ * Start of the current fork rate window and forks counted in it.
+ */
+static u64 fork_window_at, nr_window_forks;
+
 /*
  * Exit information.
  */
@@ -160,6 +426,16 @@ UEI_DEFINE(uei);
  */
 private(BPFLAND) struct bpf_cpumask __kptr *primary_cpumask;
 
+/*
+ * Mask of CPUs serving NIC queue IRQs.
+ */
+private(BPFLAND) struct bpf_cpumask __kptr *irq_cpumask;
+
+/*
+ * Mask of CPUs that can be used while the parked CPUs are parked.
+ */
+private(BPFLAND) struct bpf_cpumask __kptr *unparked_cpumask;
+
 /* Primary domain includes all CPU */
 const volatile bool primary_all = true;
 
@@ -232,6 +508,14 @@ struct task_ctx {
 	u64 wakeup_freq;
 	u64 last_woke_at;
 	u64 avg_runtime;
+	u64 sleep_pct;
+	u64 irq_wake_pct;
+	u64 fork_slice;
+	u32 fork_defer;
+	bool is_batch;
+	pid_t donee_pid;
+	u64 donee_at;
+	u64 donated_slice;
 };
 
 /* Map that contains task-local storage. */
@@ -269,6 +553,40 @@ static inline u64 node_dsq(s32 cpu)
 	return nr_cpu_ids + node;
 }
 
+/*
+ * Return the DSQ id of the lowest-priority queue of the node that contains
+ * @cpu.
+ */
+static inline u64 lowpri_dsq(s32 cpu)
+{
+	int node = __COMPAT_scx_bpf_cpu_node(cpu);
+
+	return nr_cpu_ids + nr_node_ids + node;
+}
+
+/*
+ * Return the DSQ id of the batch queue of the LLC that contains @cpu.
+ *
+ * LLCs are identified by their first CPU, so reserve one DSQ per CPU.
+ */
+static inline u64 llc_batch_dsq(s32 cpu)
+{
+	return nr_cpu_ids + 2 * nr_node_ids + cpu_llc_id(cpu);
+}
+
+/*
+ * Return true if @p should be placed in the lowest-priority queue, false
+ * otherwise.
+ */
+static inline bool is_lowpri_task(const struct task_struct *p)
+{
+	if (!lowpri_enabled)
+		return false;
+
+	return p->policy == SCHED_IDLE ||
+	       (s32)p->static_prio - 120 >= lowpri_nice;
+}
+
 /*
  * Return true if the target task @p is a kernel thread.
  */
@@ -379,6 +697,40 @@ static inline bool is_cpu_faster(s32 this_cpu, s32 that_cpu)
 	return cpu_capacity[this_cpu] > cpu_capacity[that_cpu];
 }
 
+/*
+ * Return true if the parked CPUs are currently parked, false otherwise.
+ */
+static inline bool is_parking(void)
+{
+	return park_enabled && READ_ONCE(cpus_parked);
+}
+
+/*
+ * Return true if @cpu is currently parked, false otherwise.
+ */
+static bool is_cpu_parked(s32 cpu)
+{
+	const struct cpumask *unparked;
+
+	if (!is_parking())
+		return false;
+
+	unparked = cast_mask(unparked_cpumask);
+
+	return unparked && !bpf_cpumask_test_cpu(cpu, unparked);
+}
+
+/*
+ * Return true if @p can run on at least one CPU that is not parked, false
+ * otherwise.
+ */
+static bool can_avoid_parked(const struct task_struct *p)
+{
+	const struct cpumask *unparked = cast_mask(unparked_cpumask);
+
+	return !unparked || bpf_cpumask_intersects(p->cpus_ptr, unparked);
+}
+
 /*
  * Return the SMT sibling CPU of a @cpu.
  */
@@ -529,6 +881,28 @@ out:
 	return cpu;
 }
 
+/*
+ * Pick an idle CPU for task @p among the CPUs that are not parked.
+ *
+ * Return the CPU id or a negative value if an idle CPU can't be found.
+ */
+static s32 pick_idle_cpu_unparked(struct task_struct *p, s32 prev_cpu, u64 wake_flags)
+{
+	const struct cpumask *unparked = cast_mask(unparked_cpumask);
+	bool is_prev_allowed = bpf_cpumask_test_cpu(prev_cpu, p->cpus_ptr);
+
+	if (!unparked)
+		return -EBUSY;
+
+	if (preferred_idle_scan || !bpf_ksym_exists(scx_bpf_select_cpu_and))
+		return pick_idle_cpu_pref_smt(p, prev_cpu, is_prev_allowed, unparked, NULL);
+
+	if (no_wake_sync)
+		wake_flags &= ~SCX_WAKE_SYNC;
+
+	return scx_bpf_select_cpu_and(p, prev_cpu, wake_flags, unparked, 0);
+}
+
 /*
  * Pick an optimal idle CPU for task @p (as close as possible to
  * @prev_cpu).
@@ -541,6 +915,13 @@ static s32 pick_idle_cpu(struct task_struct *p, s32 prev_cpu, s32 this_cpu,
 	const struct cpumask *primary = cast_mask(primary_cpumask);
 	s32 cpu;
 
+	/*
+	 * Never pick a parked CPU, the tasks that can only run there are
+	 * handled in ops.enqueue().
+	 */
+	if (is_parking())
+		return pick_idle_cpu_unparked(p, prev_cpu, wake_flags);
+
 	/*
 	 * Use lightweight idle CPU scanning when flat or preferred idle
 	 * scan is enabled, unless the system is busy, in which case the
@@ -628,6 +1009,35 @@ static int calloc_cpumask(struct bpf_cpumask **p_cpumask)
 	return 0;
 }
 
+/*
+ * Return the deadline offset applied to @p to honor the CPU usage budget
+ * between the interactive and batch classes.
+ *
+ * Interactive tasks are pushed forward by the amount of CPU time the batch
+ * class is missing from its guaranteed share in the current window, but
+ * only while batch tasks are actually waiting to run.
+ */
+static u64 task_budget_offset(const struct task_struct *p, const struct task_ctx *tctx)
+{
+	u64 interactive_rt, batch_rt, batch_min;
+
+	if (!interactive_budget || tctx->is_batch)
+		return 0;
+
+	if (READ_ONCE(nr_batch_runnable) <= READ_ONCE(nr_batch_running))
+		return 0;
+
+	interactive_rt = READ_ONCE(interactive_window_runtime);
+	batch_rt = READ_ONCE(batch_window_runtime);
+	batch_min = (interactive_rt + batch_rt) * (100 - interactive_budget) / 100;
+	if (batch_rt >= batch_min)
+		return 0;
+
+	__sync_fetch_and_add(&nr_budget_offsets, 1);
+
+	return scale_by_task_weight_inverse(p, MIN(batch_min - batch_rt, slice_lag));
+}
+
 /*
  * Calculate and return the virtual deadline for the given task.
  *
@@ -707,7 +1117,25 @@ static u64 task_dl(struct task_struct *p, s32 cpu, struct task_ctx *tctx)
 	 * by its high total and awake vruntimes, resulting in a higher
 	 * deadline, as intended.
 	 */
-	return p->scx.dsq_vtime + tctx->awake_vtime;
+	return p->scx.dsq_vtime + tctx->awake_vtime + task_budget_offset(p, tctx);
+}
+
+/*
+ * Make sure a task that is about to run can consume at least the
+ * run-to-parity portion of its time slice.
+ */
+static void task_run_to_parity(struct task_struct *p)
+{
+	u64 parity;
+
+	if (!run_to_parity_ns)
+		return;
+
+	parity = MIN(run_to_parity_ns, scale_by_task_weight(p, slice_max));
+	if (p->scx.slice < parity) {
+		p->scx.slice = parity;
+		__sync_fetch_and_add(&nr_parity_extends, 1);
+	}
 }
 
 /*
@@ -717,6 +1145,7 @@ static u64 task_slice(const struct task_struct *p, s32 cpu)
 {
 	u64 nr_wait = scx_bpf_dsq_nr_queued(cpu_dsq(cpu)) +
 		      scx_bpf_dsq_nr_queued(node_dsq(cpu));
+	struct task_ctx *tctx;
 	u64 slice;
 
 	/*
@@ -726,68 +1155,223 @@ static u64 task_slice(const struct task_struct *p, s32 cpu)
 	 */
 	slice = scale_by_task_weight(p, slice_max) / MAX(nr_wait, 1);
 
+	/*
+	 * Tasks forked during a fork storm can't use more than the time
+	 * slice inherited from their parent.
+	 */
+	tctx = try_lookup_task_ctx(p);
+	if (tctx && tctx->fork_defer)
+		slice = MIN(slice, tctx->fork_slice);
+
 	return MAX(slice, slice_min);
 }
 
 /*
- * Pick a target CPU for a task which is being woken up.
+ * Exponential weighted moving average (EWMA).
  *
- * If a task is dispatched here, ops.enqueue() will be skipped: task will be
- * dispatched directly to the CPU returned by this callback.
+ * Copied from scx_lavd. Returns the new average as:
+ *
+ *	new_avg := (old_avg * .75) + (new_val * .25);
  */
-s32 BPF_STRUCT_OPS(bpfland_select_cpu, struct task_struct *p, s32 prev_cpu, u64 wake_flags)
+static u64 calc_avg(u64 old_val, u64 new_val)
 {
-	s32 cpu, this_cpu = bpf_get_smp_processor_id();
-	bool is_this_cpu_allowed = bpf_cpumask_test_cpu(this_cpu, p->cpus_ptr);
-
-	/*
-	 * Make sure @prev_cpu is usable, otherwise try to move close to
-	 * the waker's CPU. If the waker's CPU is also not usable, then
-	 * pick the first usable CPU.
-	 */
-	if (!bpf_cpumask_test_cpu(prev_cpu, p->cpus_ptr))
-		prev_cpu = is_this_cpu_allowed ? this_cpu : bpf_cpumask_first(p->cpus_ptr);
+	return (old_val - (old_val >> 2)) + (new_val >> 2);
+}
 
-	/*
-	 * Try to find an idle CPU and dispatch the task directly to the
-	 * target CPU.
-	 */
-	cpu = pick_idle_cpu(p, prev_cpu, is_this_cpu_allowed ? this_cpu : -1,
-			    wake_flags, false);
-	if (cpu >= 0) {
-		struct task_ctx *tctx;
+#define SOFTIRQ_OFFSET	(1U << 8)
 
-		tctx = try_lookup_task_ctx(p);
-		if (tctx) {
-			scx_bpf_dsq_insert_vtime(p, cpu_dsq(cpu),
-						 task_slice(p, cpu), task_dl(p, cpu, tctx), 0);
-			__sync_fetch_and_add(&nr_direct_dispatches, 1);
-		}
-		return cpu;
-	}
+#if defined(__TARGET_ARCH_x86) || defined(__x86_64__)
+extern const int __preempt_count __ksym;
+#endif
 
-	return prev_cpu;
+/*
+ * Return true if the current CPU is serving a softirq, e.g., a wakeup from
+ * the NET_RX softirq of a NIC queue.
+ *
+ * With PREEMPT_RT softirqs run in thread context and are never detected.
+ */
+static bool in_serving_softirq(void)
+{
+#if defined(__TARGET_ARCH_x86) || defined(__x86_64__)
+	return *(int *)bpf_this_cpu_ptr(&__preempt_count) & SOFTIRQ_OFFSET;
+#elif defined(__TARGET_ARCH_arm64) || defined(__aarch64__)
+	return bpf_get_current_task_btf()->thread_info.preempt.count & SOFTIRQ_OFFSET;
+#else
+	return false;
+#endif
 }
 
 /*
- * Return true if the task should be forced to stay on the same CPU, false
- * otherwise.
+ * Return true if @p is mostly woken up from softirq context on CPUs serving
+ * NIC IRQs, taking into account the current wakeup from @this_cpu.
  */
-static bool is_task_sticky(const struct task_ctx *tctx)
+static bool is_irq_task(struct task_ctx *tctx, s32 this_cpu)
 {
-	return sticky_tasks && tctx->avg_runtime < 10 * NSEC_PER_USEC;
+	const struct cpumask *mask = cast_mask(irq_cpumask);
+	bool from_irq = mask && bpf_cpumask_test_cpu(this_cpu, mask) &&
+			in_serving_softirq();
+
+	tctx->irq_wake_pct = calc_avg(tctx->irq_wake_pct, from_irq ? 100 : 0);
+
+	return from_irq && tctx->irq_wake_pct >= 50;
 }
 
 /*
- * Exponential weighted moving average (EWMA).
- *
- * Copied from scx_lavd. Returns the new average as:
+ * Account the placement of a network-heavy task on @cpu.
+ */
+static void account_irq_placement(s32 cpu)
+{
+	const struct cpumask *mask = cast_mask(irq_cpumask);
+
+	if (mask && (u32)cpu < MAX_CPUS && bpf_cpumask_test_cpu(cpu, mask))
+		__sync_fetch_and_add(&nr_irq_hits[cpu], 1);
+	else
+		__sync_fetch_and_add(&nr_irq_misses, 1);
+}
+
+/*
+ * Return the amount of tasks queued to the local and per-CPU DSQs of @cpu.
+ */
+static u64 cpu_queue_depth(s32 cpu)
+{
+	return scx_bpf_dsq_nr_queued(SCX_DSQ_LOCAL_ON | cpu) +
+	       scx_bpf_dsq_nr_queued(cpu_dsq(cpu));
+}
+
+/*
+ * If more than @local_dsq_depth_max tasks are queued to @cpu, return the
+ * least loaded CPU usable by @p in the same LLC, otherwise return @cpu.
+ */
+static s32 pick_shallow_cpu(const struct task_struct *p, s32 cpu)
+{
+	u64 max_cpus = MIN(nr_cpu_ids, MAX_CPUS);
+	u64 depth, min_depth;
+	s32 best_cpu = cpu;
+	int i;
+
+	if (!local_dsq_depth_max || is_pcpu_task(p))
+		return cpu;
+
+	min_depth = cpu_queue_depth(cpu);
+	if (min_depth <= local_dsq_depth_max)
+		return cpu;
+
+	bpf_for(i, 0, max_cpus) {
+		if (i == cpu || !cpus_share_cache(cpu, i) ||
+		    !bpf_cpumask_test_cpu(i, p->cpus_ptr) || is_cpu_parked(i))
+			continue;
+
+		depth = cpu_queue_depth(i);
+		if (depth < min_depth) {
+			min_depth = depth;
+			best_cpu = i;
+		}
+	}
+
+	if (best_cpu != cpu)
+		__sync_fetch_and_add(&nr_depth_redirects, 1);
+
+	return best_cpu;
+}
+
+/*
+ * Pick a target CPU for a task which is being woken up.
  *
- *	new_avg := (old_avg * .75) + (new_val * .25);
+ * If a task is dispatched here, ops.enqueue() will be skipped: task will be
+ * dispatched directly to the CPU returned by this callback.
  */
-static u64 calc_avg(u64 old_val, u64 new_val)
+s32 BPF_STRUCT_OPS(bpfland_select_cpu, struct task_struct *p, s32 prev_cpu, u64 wake_flags)
 {
-	return (old_val - (old_val >> 2)) + (new_val >> 2);
+	s32 cpu, this_cpu = bpf_get_smp_processor_id();
+	bool is_this_cpu_allowed = bpf_cpumask_test_cpu(this_cpu, p->cpus_ptr);
+	struct task_ctx *tctx = try_lookup_task_ctx(p);
+	bool irq_task = false;
+
+	/*
+	 * Make sure @prev_cpu is usable, otherwise try to move close to
+	 * the waker's CPU. If the waker's CPU is also not usable, then
+	 * pick the first usable CPU.
+	 */
+	if (!bpf_cpumask_test_cpu(prev_cpu, p->cpus_ptr))
+		prev_cpu = is_this_cpu_allowed ? this_cpu : bpf_cpumask_first(p->cpus_ptr);
+
+	/*
+	 * Remember the wakee of a synchronous wakeup, the waker is likely
+	 * going to block soon and can donate the rest of its time slice.
+	 */
+	if (slice_donation && (wake_flags & SCX_WAKE_SYNC)) {
+		struct task_ctx *waker_ctx;
+
+		waker_ctx = try_lookup_task_ctx(bpf_get_current_task_btf());
+		if (waker_ctx) {
+			waker_ctx->donee_pid = p->pid;
+			waker_ctx->donee_at = bpf_ktime_get_ns();
+		}
+	}
+
+	/*
+	 * Don't wake up a parked CPU if the task can run somewhere else:
+	 * move close to the waker's CPU if it's not parked, or to any
+	 * usable CPU that is not parked.
+	 */
+	if (is_cpu_parked(prev_cpu) && can_avoid_parked(p)) {
+		const struct cpumask *unparked = cast_mask(unparked_cpumask);
+
+		if (is_this_cpu_allowed && !is_cpu_parked(this_cpu)) {
+			prev_cpu = this_cpu;
+		} else if (unparked) {
+			cpu = bpf_cpumask_any_and_distribute(unparked, p->cpus_ptr);
+			if (cpu < nr_cpu_ids)
+				prev_cpu = cpu;
+		}
+	}
+
+	/*
+	 * Network-heavy tasks woken up by a softirq on a CPU serving NIC
+	 * IRQs: start looking for an idle CPU from the waker's CPU instead
+	 * of the previously used one, to share the cache with the IRQ
+	 * handler.
+	 */
+	if (irq_affine && tctx && is_this_cpu_allowed && is_irq_task(tctx, this_cpu)) {
+		prev_cpu = this_cpu;
+		irq_task = true;
+	}
+
+	/*
+	 * Try to find an idle CPU and dispatch the task directly to the
+	 * target CPU.
+	 */
+	cpu = pick_idle_cpu(p, prev_cpu, is_this_cpu_allowed ? this_cpu : -1,
+			    wake_flags, false);
+	if (irq_task)
+		account_irq_placement(cpu >= 0 ? cpu : prev_cpu);
+	if (cpu >= 0) {
+		/*
+		 * Low-priority tasks go through ops.enqueue() to be queued to
+		 * the lowest-priority queue, which kicks the idle CPU.
+		 */
+		if (tctx && !is_lowpri_task(p)) {
+			scx_bpf_dsq_insert_vtime(p, cpu_dsq(cpu),
+						 task_slice(p, cpu), task_dl(p, cpu, tctx), 0);
+			__sync_fetch_and_add(&nr_direct_dispatches, 1);
+		}
+		return cpu;
+	}
+
+	/*
+	 * No idle CPU available: avoid piling up the task on a CPU that
+	 * already has a deep local queue.
+	 */
+	return pick_shallow_cpu(p, prev_cpu);
+}
+
+/*
+ * Return true if the task should be forced to stay on the same CPU, false
+ * otherwise.
+ */
+static bool is_task_sticky(const struct task_ctx *tctx)
+{
+	return sticky_tasks && tctx->avg_runtime < 10 * NSEC_PER_USEC;
 }
 
 /*
@@ -838,8 +1422,22 @@ void BPF_STRUCT_OPS(bpfland_enqueue, struct task_struct *p, u64 enq_flags)
 	 * activity, dispatch it directly to the same CPU to reduce the
 	 * locking pressure on the per-CPU and per-node DSQs.
 	 */
-	if (is_task_sticky(tctx)) {
-		scx_bpf_dsq_insert(p, SCX_DSQ_LOCAL, task_slice(p, prev_cpu), enq_flags);
+	if (is_task_sticky(tctx) && !is_lowpri_task(p)) {
+		s32 cpu = prev_cpu;
+
+		/*
+		 * If ops.select_cpu() was skipped, redirect the task here
+		 * when the local queue of its CPU is too deep.
+		 */
+		if (task_should_migrate(p, enq_flags))
+			cpu = pick_shallow_cpu(p, prev_cpu);
+
+		if (cpu != prev_cpu) {
+			scx_bpf_dsq_insert(p, SCX_DSQ_LOCAL_ON | cpu, task_slice(p, cpu), enq_flags);
+			scx_bpf_kick_cpu(cpu, SCX_KICK_IDLE);
+		} else {
+			scx_bpf_dsq_insert(p, SCX_DSQ_LOCAL, task_slice(p, prev_cpu), enq_flags);
+		}
 		__sync_fetch_and_add(&nr_direct_dispatches, 1);
 		return;
 	}
@@ -882,7 +1480,7 @@ void BPF_STRUCT_OPS(bpfland_enqueue, struct task_struct *p, u64 enq_flags)
 	 * Attempt to dispatch directly to an idle CPU if ops.select_cpu() was
 	 * skipped.
 	 */
-	if (task_should_migrate(p, enq_flags)) {
+	if (task_should_migrate(p, enq_flags) && !is_lowpri_task(p)) {
 		s32 cpu;
 
 		if (is_pcpu_task(p))
@@ -902,10 +1500,44 @@ void BPF_STRUCT_OPS(bpfland_enqueue, struct task_struct *p, u64 enq_flags)
 	}
 
 	/*
-	 * Dispatch the task to the node DSQ, using the deadline-based
-	 * scheduling.
+	 * Tasks that can only run on parked CPUs would never be consumed
+	 * from the shared DSQs, so dispatch them directly to one of their
+	 * CPUs.
+	 */
+	if (is_parking() && !can_avoid_parked(p)) {
+		s32 cpu = bpf_cpumask_test_cpu(prev_cpu, p->cpus_ptr) ?
+			  prev_cpu : bpf_cpumask_first(p->cpus_ptr);
+
+		scx_bpf_dsq_insert_vtime(p, cpu_dsq(cpu),
+					 task_slice(p, cpu), task_dl(p, cpu, tctx), enq_flags);
+		__sync_fetch_and_add(&nr_direct_dispatches, 1);
+		scx_bpf_kick_cpu(cpu, SCX_KICK_IDLE);
+		return;
+	}
+
+	/*
+	 * Low-priority tasks are parked in the lowest-priority queue, which
+	 * is only consumed when there's nothing else to run. Kick their CPU
+	 * in case it's idle, e.g., if ops.select_cpu() picked it.
+	 *
+	 * Per-CPU tasks and tasks that can only run on parked CPUs are
+	 * exempt and dispatched above: as the starvation trickle is shared
+	 * by all the CPUs, they could be starved by the low-priority tasks
+	 * of the other CPUs.
+	 */
+	if (is_lowpri_task(p)) {
+		scx_bpf_dsq_insert_vtime(p, lowpri_dsq(prev_cpu),
+					 task_slice(p, prev_cpu), task_dl(p, prev_cpu, tctx), enq_flags);
+		scx_bpf_kick_cpu(prev_cpu, SCX_KICK_IDLE);
+		return;
+	}
+
+	/*
+	 * Dispatch the task to the node DSQ, or to the batch DSQ of its LLC
+	 * if batch tasks are balanced, using the deadline-based scheduling.
 	 */
-	scx_bpf_dsq_insert_vtime(p, node_dsq(prev_cpu),
+	scx_bpf_dsq_insert_vtime(p, batch_balance_thresh && tctx->is_batch ?
+				    llc_batch_dsq(prev_cpu) : node_dsq(prev_cpu),
 				 task_slice(p, prev_cpu), task_dl(p, prev_cpu, tctx), enq_flags);
 	__sync_fetch_and_add(&nr_shared_dispatches, 1);
 
@@ -955,10 +1587,205 @@ static bool consume_first_task(u64 dsq_id, struct task_struct *p)
 	return scx_bpf_dsq_move_to_local(dsq_id);
 }
 
+/*
+ * Consume a task from the lowest-priority queue of @cpu's node.
+ */
+static bool consume_lowpri_task(s32 cpu)
+{
+	if (!scx_bpf_dsq_move_to_local(lowpri_dsq(cpu)))
+		return false;
+
+	WRITE_ONCE(lowpri_last_dispatch, bpf_ktime_get_ns());
+	__sync_fetch_and_add(&nr_lowpri_dispatches, 1);
+
+	return true;
+}
+
+/*
+ * Return true if the lowest-priority queue of @cpu's node has waiting
+ * tasks and it hasn't been served for more than @lowpri_starvation_ns.
+ */
+static bool is_lowpri_starving(s32 cpu)
+{
+	u64 now = bpf_ktime_get_ns();
+
+	if (!scx_bpf_dsq_nr_queued(lowpri_dsq(cpu)))
+		return false;
+
+	return now - READ_ONCE(lowpri_last_dispatch) > lowpri_starvation_ns;
+}
+
+/*
+ * Re-evaluate the parking state from the CPU @cpu that is not parked.
+ *
+ * The parked CPUs are unparked when tasks are waiting in the per-node DSQ
+ * while all the other CPUs are busy, and they are parked again when the
+ * load fits in the CPUs that are not parked, in both cases only if the
+ * condition persists for more than @park_overload_ns.
+ */
+static void update_park_state(s32 cpu)
+{
+	const struct cpumask *unparked;
+	u64 now = bpf_ktime_get_ns(), checked_at, pending_at;
+	u64 nr_avail, nr_busy, nr_queued;
+	bool parked, transition;
+	s32 i;
+
+	/*
+	 * Evaluate the state at most once per millisecond, from a single
+	 * CPU at a time.
+	 */
+	checked_at = READ_ONCE(park_checked_at);
+	if (now - checked_at < NSEC_PER_MSEC)
+		return;
+	if (__sync_val_compare_and_swap(&park_checked_at, checked_at, now) != checked_at)
+		return;
+
+	nr_avail = READ_ONCE(nr_online_cpus);
+	nr_avail = nr_avail > nr_parked_cpus ? nr_avail - nr_parked_cpus : 1;
+	nr_queued = scx_bpf_dsq_nr_queued(node_dsq(cpu));
+	if (batch_balance_thresh)
+		nr_queued += scx_bpf_dsq_nr_queued(llc_batch_dsq(cpu));
+	nr_busy = READ_ONCE(nr_running) + nr_queued;
+
+	parked = READ_ONCE(cpus_parked);
+	if (parked)
+		transition = nr_queued && nr_busy > nr_avail;
+	else
+		transition = nr_busy < nr_avail;
+
+	if (!transition) {
+		WRITE_ONCE(park_pending_at, 0);
+		return;
+	}
+
+	pending_at = READ_ONCE(park_pending_at);
+	if (!pending_at) {
+		WRITE_ONCE(park_pending_at, now);
+		return;
+	}
+	if (now - pending_at < park_overload_ns)
+		return;
+
+	WRITE_ONCE(park_pending_at, 0);
+	WRITE_ONCE(cpus_parked, !parked);
+
+	if (!parked) {
+		__sync_fetch_and_add(&nr_park_events, 1);
+		return;
+	}
+	__sync_fetch_and_add(&nr_unpark_events, 1);
+
+	/*
+	 * Wake up the CPUs that have just been unparked, so that they can
+	 * start consuming the waiting tasks.
+	 */
+	unparked = cast_mask(unparked_cpumask);
+	if (!unparked)
+		return;
+	bpf_for(i, 0, nr_cpu_ids)
+		if (!bpf_cpumask_test_cpu(i, unparked))
+			scx_bpf_kick_cpu(i, SCX_KICK_IDLE);
+}
+
+/*
+ * Push batch tasks from the deepest LLC batch DSQ to the shallowest one that
+ * has an idle CPU, if their depths diverge beyond @batch_balance_thresh, then
+ * kick the idle CPU to consume them.
+ *
+ * The CPUs of an idle LLC don't run dispatch() until they're kicked, so the
+ * balancing is driven by whichever CPU is dispatching, typically a CPU of the
+ * busy LLC.
+ */
+static void balance_batch_tasks(void)
+{
+	u64 now = bpf_ktime_get_ns(), checked_at;
+	u64 src_dsq = 0, dst_dsq = 0;
+	u64 nr_src = 0, nr_dst = 0, nr_moved = 0, nr_max;
+	s32 dst_cpu = -ENOENT;
+	struct task_struct *p;
+	int i;
+
+	/*
+	 * Balance at most once per interval, from a single CPU at a time.
+	 */
+	checked_at = READ_ONCE(batch_balance_at);
+	if (now - checked_at < BATCH_BALANCE_INTERVAL_NS)
+		return;
+	if (__sync_val_compare_and_swap(&batch_balance_at, checked_at, now) != checked_at)
+		return;
+
+	/*
+	 * Find the deepest batch DSQ and the shallowest one among the LLCs
+	 * with an idle (and unparked) CPU.
+	 */
+	bpf_for(i, 0, nr_cpu_ids) {
+		const struct cpumask *idle_mask;
+		u64 dsq_id = llc_batch_dsq(i), nr_queued;
+		bool is_idle;
+
+		nr_queued = scx_bpf_dsq_nr_queued(dsq_id);
+		if (nr_queued > nr_src) {
+			nr_src = nr_queued;
+			src_dsq = dsq_id;
+		}
+
+		if (dst_cpu >= 0 && nr_queued >= nr_dst)
+			continue;
+		if (is_cpu_parked(i))
+			continue;
+
+		idle_mask = get_idle_cpumask(i);
+		is_idle = bpf_cpumask_test_cpu(i, idle_mask);
+		scx_bpf_put_cpumask(idle_mask);
+		if (!is_idle)
+			continue;
+
+		nr_dst = nr_queued;
+		dst_dsq = dsq_id;
+		dst_cpu = i;
+	}
+
+	if (dst_cpu < 0 || src_dsq == dst_dsq || nr_src <= nr_dst + batch_balance_thresh)
+		return;
+
+	/*
+	 * Move half of the difference, so that the two DSQs end up with a
+	 * similar depth, skipping the tasks that can't run on the idle CPU.
+	 *
+	 * Deadlines are based on the global vruntime, so the moved tasks
+	 * keep their position relative to the tasks of the target DSQ.
+	 */
+	nr_max = (nr_src - nr_dst) / 2;
+	bpf_for_each(scx_dsq, p, src_dsq, 0) {
+		if (nr_moved >= nr_max)
+			break;
+
+		if (!bpf_cpumask_test_cpu(dst_cpu, p->cpus_ptr))
+			continue;
+
+		if (batch_migrate_rate &&
+		    !scx_token_bucket_consume(&batch_migrate_tb, 1, now))
+			break;
+
+		if (__COMPAT_scx_bpf_dsq_move_vtime(BPF_FOR_EACH_ITER, p, dst_dsq, 0))
+			nr_moved++;
+		else if (batch_migrate_rate)
+			batch_migrate_tb.tokens++;
+	}
+
+	if (nr_moved) {
+		scx_bpf_kick_cpu(dst_cpu, SCX_KICK_IDLE);
+		__sync_fetch_and_add(&nr_batch_balances, 1);
+		__sync_fetch_and_add(&nr_batch_moves, nr_moved);
+	}
+}
+
 void BPF_STRUCT_OPS(bpfland_dispatch, s32 cpu, struct task_struct *prev)
 {
 	struct task_struct *p = __COMPAT_scx_bpf_dsq_peek(cpu_dsq(cpu));
 	struct task_struct *q = __COMPAT_scx_bpf_dsq_peek(node_dsq(cpu));
+	struct task_struct *b = NULL;
 
 	/*
 	 * Let the CPU go idle if the system is throttled.
@@ -966,11 +1793,42 @@ void BPF_STRUCT_OPS(bpfland_dispatch, s32 cpu, struct task_struct *prev)
 	if (is_throttled())
 		return;
 
+	/*
+	 * Parked CPUs only run the tasks that can't run anywhere else, so
+	 * that they can stay idle as long as possible.
+	 */
+	if (is_cpu_parked(cpu)) {
+		if (consume_first_task(cpu_dsq(cpu), p))
+			return;
+		if (prev && !can_avoid_parked(prev) && keep_running(prev, cpu))
+			prev->scx.slice = task_slice(prev, cpu);
+		return;
+	}
+
+	if (park_enabled)
+		update_park_state(cpu);
+
+	if (batch_balance_thresh) {
+		balance_batch_tasks();
+		b = __COMPAT_scx_bpf_dsq_peek(llc_batch_dsq(cpu));
+	}
+
+	/*
+	 * Trickle a low-priority task if the lowest-priority queue has been
+	 * starved for too long.
+	 */
+	if (lowpri_enabled && is_lowpri_starving(cpu) && consume_lowpri_task(cpu))
+		return;
+
 	/*
 	 * Try to consume the first task either from the per-CPU DSQ or the
 	 * per-node DSQ, picking the one with the minimum deadline that can
 	 * run on @cpu.
 	 */
+	if (is_deadline_min(b, p) && is_deadline_min(b, q) &&
+	    consume_first_task(llc_batch_dsq(cpu), b))
+		return;
+
 	if (!is_deadline_min(q, p)) {
 		if (consume_first_task(cpu_dsq(cpu), p) || consume_first_task(node_dsq(cpu), q))
 			return;
@@ -979,11 +1837,27 @@ void BPF_STRUCT_OPS(bpfland_dispatch, s32 cpu, struct task_struct *prev)
 			return;
 	}
 
+	if (b && consume_first_task(llc_batch_dsq(cpu), b))
+		return;
+
 	/*
 	 * If the current task expired its time slice and no other task wants
 	 * to run, simply replenish its time slice and let it run for another
 	 * round on the same CPU.
+	 *
+	 * Low-priority tasks are only considered after the regular tasks,
+	 * so a regular task can keep running over them, while a low-priority
+	 * task needs to yield to the other tasks in the lowest-priority
+	 * queue.
 	 */
+	if (prev && !is_lowpri_task(prev) && keep_running(prev, cpu)) {
+		prev->scx.slice = task_slice(prev, cpu);
+		return;
+	}
+
+	if (lowpri_enabled && consume_lowpri_task(cpu))
+		return;
+
 	if (prev && keep_running(prev, cpu))
 		prev->scx.slice = task_slice(prev, cpu);
 }
@@ -1048,6 +1922,20 @@ void BPF_STRUCT_OPS(bpfland_running, struct task_struct *p)
 	 */
 	tctx->last_run_at = bpf_ktime_get_ns();
 
+	/*
+	 * Do not let a wakeup storm switch out the task right after it
+	 * started to run.
+	 */
+	task_run_to_parity(p);
+
+	/*
+	 * Consume the time slice donated by the waker.
+	 */
+	if (tctx->donated_slice) {
+		p->scx.slice += tctx->donated_slice;
+		tctx->donated_slice = 0;
+	}
+
 	/*
 	 * Adjust target CPU frequency before the task starts to run.
 	 */
@@ -1058,6 +1946,65 @@ void BPF_STRUCT_OPS(bpfland_running, struct task_struct *p)
 	 */
 	if (time_before(vtime_now, p->scx.dsq_vtime))
 		vtime_now = p->scx.dsq_vtime;
+
+	if (classify_batch() && tctx->is_batch)
+		__sync_fetch_and_add(&nr_batch_running, 1);
+}
+
+/*
+ * Hand the remaining time slice of @p over to the task it woke up
+ * synchronously, if it's releasing the CPU right after the wakeup.
+ *
+ * The donation is only made while the wakee is still waiting to run, so
+ * that it's consumed by its next ops.running() and not by a later,
+ * unrelated run.
+ */
+static void donate_slice(struct task_struct *p, struct task_ctx *tctx, u64 now)
+{
+	struct task_struct *donee;
+	struct task_ctx *dctx;
+	pid_t pid = tctx->donee_pid;
+
+	if (!pid)
+		return;
+	tctx->donee_pid = 0;
+
+	if (now - tctx->donee_at > SLICE_DONATION_WINDOW_NS || !p->scx.slice)
+		return;
+
+	donee = bpf_task_from_pid(pid);
+	if (!donee)
+		return;
+
+	dctx = try_lookup_task_ctx(donee);
+	if (dctx && is_task_queued(donee) && !scx_bpf_task_running(donee)) {
+		dctx->donated_slice = MIN(dctx->donated_slice + p->scx.slice, slice_max);
+		__sync_fetch_and_add(&nr_slice_donations, 1);
+	}
+	bpf_task_release(donee);
+}
+
+/*
+ * Account @slice to the CPU usage of the task's class, halving the usage
+ * of both classes at the end of each budget window.
+ */
+static void update_budget(struct task_ctx *tctx, u64 slice, u64 now)
+{
+	u64 window_at = READ_ONCE(budget_window_at);
+
+	if (now - window_at >= BUDGET_WINDOW_NS &&
+	    __sync_bool_compare_and_swap(&budget_window_at, window_at, now)) {
+		WRITE_ONCE(interactive_window_runtime, interactive_window_runtime / 2);
+		WRITE_ONCE(batch_window_runtime, batch_window_runtime / 2);
+	}
+
+	if (tctx->is_batch) {
+		__sync_fetch_and_add(&batch_window_runtime, slice);
+		__sync_fetch_and_add(&batch_runtime, slice);
+	} else {
+		__sync_fetch_and_add(&interactive_window_runtime, slice);
+		__sync_fetch_and_add(&interactive_runtime, slice);
+	}
 }
 
 /*
@@ -1077,6 +2024,9 @@ void BPF_STRUCT_OPS(bpfland_stopping, struct task_struct *p, bool runnable)
 	if (!tctx)
 		return;
 
+	if (slice_donation && !runnable)
+		donate_slice(p, tctx, now);
+
 	/*
 	 * Evaluate the used time slice and actual runtime.
 	 */
@@ -1095,6 +2045,28 @@ void BPF_STRUCT_OPS(bpfland_stopping, struct task_struct *p, bool runnable)
 	p->scx.dsq_vtime += delta_vtime;
 	tctx->awake_vtime += delta_vtime;
 
+	/*
+	 * Account the CPU time used by low-priority tasks.
+	 */
+	if (lowpri_enabled) {
+		__sync_fetch_and_add(&tot_task_runtime, slice);
+		if (is_lowpri_task(p))
+			__sync_fetch_and_add(&lowpri_runtime, slice);
+	}
+
+	/*
+	 * Account the CPU time used by each class and refresh the task's
+	 * ratio of voluntary context switches, used to classify it as
+	 * interactive or batch the next time it becomes runnable.
+	 */
+	if (classify_batch()) {
+		if (interactive_budget)
+			update_budget(tctx, slice, now);
+		if (tctx->is_batch)
+			__sync_fetch_and_sub(&nr_batch_running, 1);
+		tctx->sleep_pct = calc_avg(tctx->sleep_pct, runnable ? 0 : 100);
+	}
+
 	/*
 	 * Update CPU runtime.
 	 */
@@ -1105,25 +2077,114 @@ void BPF_STRUCT_OPS(bpfland_stopping, struct task_struct *p, bool runnable)
 	cctx->tot_runtime += delta_runtime;
 }
 
+/*
+ * A yielding task gives up the rest of its time slice: donate it if it
+ * just woke up another task synchronously.
+ */
+bool BPF_STRUCT_OPS(bpfland_yield, struct task_struct *from, struct task_struct *to)
+{
+	struct task_ctx *tctx;
+
+	if (slice_donation && (tctx = try_lookup_task_ctx(from)))
+		donate_slice(from, tctx, bpf_ktime_get_ns());
+	from->scx.slice = 0;
+
+	return false;
+}
+
+/*
+ * Re-evaluate the fork rate at the end of each window and update the fork
+ * storm state accordingly.
+ */
+static void update_fork_storm(u64 now)
+{
+	u64 window_at = READ_ONCE(fork_window_at), delta, nr_forks, rate;
+
+	delta = now - window_at;
+	if (delta < FORK_STORM_WINDOW_NS ||
+	    !__sync_bool_compare_and_swap(&fork_window_at, window_at, now))
+		return;
+
+	nr_forks = READ_ONCE(nr_window_forks);
+	__sync_fetch_and_sub(&nr_window_forks, nr_forks);
+
+	rate = nr_forks * NSEC_PER_SEC / delta;
+	WRITE_ONCE(fork_rate, rate);
+
+	if (!fork_storm && rate >= fork_storm_rate) {
+		WRITE_ONCE(fork_storm, true);
+		__sync_fetch_and_add(&nr_fork_storms, 1);
+	} else if (fork_storm && rate < fork_storm_rate / 2) {
+		WRITE_ONCE(fork_storm, false);
+	}
+}
+
 void BPF_STRUCT_OPS(bpfland_runnable, struct task_struct *p, u64 enq_flags)
 {
 	u64 now = bpf_ktime_get_ns(), delta_t;
 	struct task_ctx *tctx;
 
+	if (fork_storm_rate)
+		update_fork_storm(now);
+
 	tctx = try_lookup_task_ctx(p);
 	if (!tctx)
 		return;
 
-	tctx->awake_vtime = 0;
+	/*
+	 * Tasks forked during a fork storm don't get any credit for their
+	 * wakeups until their classification is no longer deferred.
+	 */
+	if (tctx->fork_defer) {
+		tctx->fork_defer--;
+		tctx->last_woke_at = now;
+	} else {
+		tctx->awake_vtime = 0;
+
+		/*
+		 * Update the task's wakeup frequency based on the time since
+		 * the last wakeup, then cap the result to avoid large spikes.
+		 */
+		delta_t = now > tctx->last_woke_at ? now - tctx->last_woke_at : 1;
+		tctx->wakeup_freq = update_freq(tctx->wakeup_freq, delta_t);
+		tctx->wakeup_freq = MIN(tctx->wakeup_freq, MAX_WAKEUP_FREQ);
+		tctx->last_woke_at = now;
+	}
+
+	/*
+	 * Classify the task for the CPU usage budget. The class is kept
+	 * until the task goes to sleep, so that the runnable and running
+	 * batch tasks are accounted consistently.
+	 */
+	if (classify_batch()) {
+		tctx->is_batch = tctx->sleep_pct < INTERACTIVE_SLEEP_PCT ||
+				 tctx->fork_defer;
+		if (tctx->is_batch)
+			__sync_fetch_and_add(&nr_batch_runnable, 1);
+	}
+}
+
+void BPF_STRUCT_OPS(bpfland_quiescent, struct task_struct *p, u64 deq_flags)
+{
+	struct task_ctx *tctx;
+
+	if (!classify_batch() && !slice_donation)
+		return;
+
+	tctx = try_lookup_task_ctx(p);
+	if (!tctx)
+		return;
 
 	/*
-	 * Update the task's wakeup frequency based on the time since the
-	 * last wakeup, then cap the result to avoid large spikes.
+	 * Drop a donation that wasn't consumed before the task blocked, it
+	 * must not survive the sleep.
 	 */
-	delta_t = now > tctx->last_woke_at ? now - tctx->last_woke_at : 1;
-	tctx->wakeup_freq = update_freq(tctx->wakeup_freq, delta_t);
-	tctx->wakeup_freq = MIN(tctx->wakeup_freq, MAX_WAKEUP_FREQ);
-	tctx->last_woke_at = now;
+	tctx->donated_slice = 0;
+
+	if (tctx->is_batch) {
+		__sync_fetch_and_sub(&nr_batch_runnable, 1);
+		tctx->is_batch = false;
+	}
 }
 
 void BPF_STRUCT_OPS(bpfland_enable, struct task_struct *p)
@@ -1134,16 +2195,87 @@ void BPF_STRUCT_OPS(bpfland_enable, struct task_struct *p)
 	p->scx.dsq_vtime = vtime_now;
 }
 
+/*
+ * Reset the state of a task context inherited from a previous instance of
+ * the scheduler through the pinned task storage (see --persist-task-ctx).
+ * The statistics the classification is based on are kept, but the class and
+ * everything tied to the previous instance's counters and clocks starts
+ * over.
+ */
+static void rehydrate_task_ctx(struct task_ctx *tctx)
+{
+	tctx->awake_vtime = 0;
+	tctx->fork_slice = 0;
+	tctx->fork_defer = 0;
+	tctx->is_batch = false;
+	tctx->donee_pid = 0;
+	tctx->donee_at = 0;
+	tctx->donated_slice = 0;
+}
+
 s32 BPF_STRUCT_OPS(bpfland_init_task, struct task_struct *p,
 		   struct scx_init_task_args *args)
 {
 	struct task_ctx *tctx;
 
+	tctx = try_lookup_task_ctx(p);
+	if (tctx) {
+		rehydrate_task_ctx(tctx);
+		return 0;
+	}
+
 	tctx = bpf_task_storage_get(&task_ctx_stor, p, 0,
 				    BPF_LOCAL_STORAGE_GET_F_CREATE);
 	if (!tctx)
 		return -ENOMEM;
 
+	if (fork_storm_rate && args->fork) {
+		__sync_fetch_and_add(&nr_window_forks, 1);
+
+		/*
+		 * During a fork storm, split the time slice left to the
+		 * parent (the task that is forking) with the child and defer
+		 * the child's classification.
+		 */
+		if (READ_ONCE(fork_storm)) {
+			struct task_struct *parent = bpf_get_current_task_btf();
+
+			tctx->fork_slice = MIN(parent->scx.slice, slice_max) / 2;
+			tctx->fork_defer = FORK_STORM_DEFER_WAKEUPS;
+			__sync_fetch_and_add(&nr_storm_forks, 1);
+		}
+	}
+
+	return 0;
+}
+
+/*
+ * A task that execs a new program is effectively a new task: a shell
+ * that execs a compiler shouldn't keep its interactive status and a
+ * build helper that execs an editor shouldn't keep being batch. Drop the
+ * statistics the classification is based on, so the task starts over like
+ * a freshly forked one.
+ *
+ * The batch class itself is left alone, as it's accounted until the task
+ * goes to sleep.
+ */
+SEC("tp_btf/sched_process_exec")
+int BPF_PROG(bpfland_sched_process_exec, struct task_struct *p,
+	     pid_t old_pid, struct linux_binprm *bprm)
+{
+	struct task_ctx *tctx;
+
+	tctx = try_lookup_task_ctx(p);
+	if (!tctx)
+		return 0;
+
+	tctx->awake_vtime = 0;
+	tctx->wakeup_freq = 0;
+	tctx->avg_runtime = 0;
+	tctx->sleep_pct = 0;
+	tctx->irq_wake_pct = 0;
+	__sync_fetch_and_add(&nr_exec_resets, 1);
+
 	return 0;
 }
 
@@ -1210,6 +2342,62 @@ int enable_sibling_cpu(struct domain_arg *input)
 	return err;
 }
 
+SEC("syscall")
+int enable_irq_cpu(struct cpu_arg *input)
+{
+	struct bpf_cpumask *mask;
+	int err = 0;
+
+	err = init_cpumask(&irq_cpumask);
+	if (err)
+		return err;
+	/*
+	 * Mark the target CPU as serving NIC IRQs. A negative value clears
+	 * the whole mask.
+	 */
+	bpf_rcu_read_lock();
+	mask = irq_cpumask;
+	if (mask) {
+		s32 cpu = input->cpu_id;
+
+		if (cpu < 0)
+			bpf_cpumask_clear(mask);
+		else
+			bpf_cpumask_set_cpu(cpu, mask);
+	}
+	bpf_rcu_read_unlock();
+
+	return err;
+}
+
+SEC("syscall")
+int enable_unparked_cpu(struct cpu_arg *input)
+{
+	struct bpf_cpumask *mask;
+	int err = 0;
+
+	err = init_cpumask(&unparked_cpumask);
+	if (err)
+		return err;
+	/*
+	 * Mark the target CPU as usable while the CPUs are parked. A
+	 * negative value clears the whole mask.
+	 */
+	bpf_rcu_read_lock();
+	mask = unparked_cpumask;
+	if (mask) {
+		s32 cpu = input->cpu_id;
+
+		if (cpu < 0)
+			bpf_cpumask_clear(mask);
+		else
+			bpf_cpumask_set_cpu(cpu, mask);
+	}
+	bpf_rcu_read_unlock();
+
+	return err;
+}
+
 SEC("syscall")
 int enable_primary_cpu(struct cpu_arg *input)
 {
@@ -1317,6 +2505,7 @@ s32 BPF_STRUCT_OPS_SLEEPABLE(bpfland_init)
 	/* Initialize amount of online and possible CPUs */
 	nr_online_cpus = get_nr_online_cpus();
 	nr_cpu_ids = scx_bpf_nr_cpu_ids();
+	nr_node_ids = __COMPAT_scx_bpf_nr_node_ids();
 
 	/* Initialize CPUs and NUMA properties */
 	init_cpuperf_target();
@@ -1338,7 +2527,7 @@ s32 BPF_STRUCT_OPS_SLEEPABLE(bpfland_init)
 	/*
 	 * Create the per-node DSQs.
 	 */
-	bpf_for(i, 0, __COMPAT_scx_bpf_nr_node_ids()) {
+	bpf_for(i, 0, nr_node_ids) {
 		u64 dsq_id = nr_cpu_ids + i;
 
 		err = scx_bpf_create_dsq(dsq_id, i);
@@ -1348,11 +2537,62 @@ s32 BPF_STRUCT_OPS_SLEEPABLE(bpfland_init)
 		}
 	}
 
+	/*
+	 * Create the per-node lowest-priority DSQs.
+	 */
+	if (lowpri_enabled) {
+		bpf_for(i, 0, nr_node_ids) {
+			u64 dsq_id = nr_cpu_ids + nr_node_ids + i;
+
+			err = scx_bpf_create_dsq(dsq_id, i);
+			if (err) {
+				scx_bpf_error("failed to create DSQ %llu: %d", dsq_id, err);
+				return err;
+			}
+		}
+	}
+
+	/*
+	 * Create the per-LLC batch DSQs, one per CPU as the LLCs are
+	 * identified by their first CPU.
+	 */
+	if (batch_balance_thresh) {
+		if (cpu_llc_id(0) < 0) {
+			scx_bpf_error("LLC ids are not available");
+			return -ENOENT;
+		}
+		bpf_for(i, 0, nr_cpu_ids) {
+			int node = __COMPAT_scx_bpf_cpu_node(i);
+			u64 dsq_id = nr_cpu_ids + 2 * nr_node_ids + i;
+
+			err = scx_bpf_create_dsq(dsq_id, node);
+			if (err) {
+				scx_bpf_error("failed to create DSQ %llu: %d", dsq_id, err);
+				return err;
+			}
+		}
+		scx_token_bucket_init(&batch_migrate_tb, batch_migrate_rate,
+				      batch_migrate_rate, bpf_ktime_get_ns());
+	}
+
 	/* Initialize the primary scheduling domain */
 	err = init_cpumask(&primary_cpumask);
 	if (err)
 		return err;
 
+	/* Initialize the mask of CPUs serving NIC IRQs */
+	err = init_cpumask(&irq_cpumask);
+	if (err)
+		return err;
+
+	/* Initialize the mask of CPUs that are not parked */
+	err = init_cpumask(&unparked_cpumask);
+	if (err)
+		return err;
+
+	/* Start with the CPUs parked if CPU parking is enabled */
+	cpus_parked = park_enabled;
+
 	timer = bpf_map_lookup_elem(&throttle_timer, &key);
 	if (!timer) {
 		scx_bpf_error("Failed to lookup throttle timer");
@@ -1386,7 +2626,9 @@ SCX_OPS_DEFINE(bpfland_ops,
 	       .dispatch		= (void *)bpfland_dispatch,
 	       .running			= (void *)bpfland_running,
 	       .stopping		= (void *)bpfland_stopping,
+	       .yield			= (void *)bpfland_yield,
 	       .runnable		= (void *)bpfland_runnable,
+	       .quiescent		= (void *)bpfland_quiescent,
 	       .enable			= (void *)bpfland_enable,
 	       .init_task		= (void *)bpfland_init_task,
 	       .init			= (void *)bpfland_init,
@@ -11,9 +11,11 @@ pub mod bpf_intf;
 pub use bpf_intf::*;
 
 mod stats;
+use std::collections::BTreeSet;
 use std::ffi::{c_int, c_ulong};
 use std::fmt::Write;
 use std::mem::MaybeUninit;
+use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
@@ -34,7 +36,9 @@ use scx_utils::autopower::{fetch_power_profile, PowerProfile};
 use scx_utils::build_id;
 use scx_utils::compat;
 use scx_utils::libbpf_clap_opts::LibbpfOpts;
+use scx_utils::map_pin::MapPinner;
 use scx_utils::pm::{cpu_idle_resume_latency_supported, update_cpu_idle_resume_latency};
+use scx_utils::read_netdevs;
 use scx_utils::scx_ops_attach;
 use scx_utils::scx_ops_load;
 use scx_utils::scx_ops_open;
@@ -106,6 +110,47 @@ fn cpus_to_cpumask(cpus: &Vec<usize>) -> String {
     format!("0x{}", hex_str)
 }
 
+// Return the CPUs serving the NIC queue IRQs, i.e. the CPUs that handled any
+// of them according to /proc/interrupts. If none did yet, fall back to the
+// IRQ affinity masks.
+fn get_nic_irq_cpus() -> Result<Cpumask> {
+    let netdevs = read_netdevs()?;
+    let mut affinity = Cpumask::new();
+    let mut irqs = BTreeSet::new();
+    for netdev in netdevs.values() {
+        for (irq, mask) in netdev.irqs.iter() {
+            irqs.insert(*irq);
+            affinity |= mask;
+        }
+    }
+
+    let interrupts = std::fs::read_to_string("/proc/interrupts")?;
+    let mut lines = interrupts.lines();
+    let cpu_ids: Vec<usize> = lines
+        .next()
+        .unwrap_or_default()
+        .split_whitespace()
+        .filter_map(|col| col.strip_prefix("CPU")?.parse().ok())
+        .collect();
+
+    let mut cpus = Cpumask::new();
+    for line in lines {
+        let Some((irq, counts)) = line.trim_start().split_once(':') else {
+            continue;
+        };
+        if !irq.parse::<usize>().is_ok_and(|irq| irqs.contains(&irq)) {
+            continue;
+        }
+        for (cpu, count) in cpu_ids.iter().zip(counts.split_whitespace()) {
+            if count.parse::<u64>().is_ok_and(|count| count > 0) {
+                cpus.set_cpu(*cpu)?;
+            }
+        }
+    }
+
+    Ok(if cpus.is_empty() { affinity } else { cpus })
+}
+
 /// scx_bpfland: a vruntime-based sched_ext scheduler that prioritizes interactive workloads.
 ///
 /// This scheduler is derived from scx_rustland, but it is fully implemented in BPF. It has a minimal
@@ -201,6 +246,121 @@ struct Opts {
     #[clap(short = 'm', long, default_value = "auto")]
     primary_domain: String,
 
+    /// Enable a dedicated lowest-priority queue for SCHED_IDLE and deeply niced tasks.
+    ///
+    /// Tasks in this queue only run when no other task is waiting for the CPU, instead of
+    /// competing with the regular tasks. To prevent starvation, one task is still allowed to run
+    /// from this queue every --lowpri-starvation-ms. Tasks that can only run on a single CPU are
+    /// exempt and queued as regular tasks.
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    lowpri_queue: bool,
+
+    /// Minimum nice level of the tasks placed in the lowest-priority queue (SCHED_IDLE tasks are
+    /// always placed there).
+    #[clap(
+        long,
+        default_value = "19",
+        allow_hyphen_values = true,
+        value_parser = clap::value_parser!(i32).range(-20..=19)
+    )]
+    lowpri_nice: i32,
+
+    /// Maximum time in milliseconds the lowest-priority queue can go without being served.
+    #[clap(long, default_value = "100")]
+    lowpri_starvation_ms: u64,
+
+    /// Maximum percentage of CPU time that interactive tasks can use while batch tasks are
+    /// waiting to run (e.g., 80 means an 80/20 split between interactive and batch tasks).
+    ///
+    /// Tasks that mostly release the CPU voluntarily are considered interactive, the others are
+    /// considered batch. This guarantees a minimum share of CPU time to the batch tasks, so that
+    /// they are not starved by a storm of interactive tasks. A value of 0 disables the budget.
+    #[clap(long, default_value = "0", value_parser = clap::value_parser!(u64).range(0..100))]
+    interactive_budget: u64,
+
+    /// Minimum time in microseconds a task is guaranteed to run once it starts running, capped
+    /// to its full time slice (0 = disabled).
+    ///
+    /// Under heavy wakeup storms the time slice shrinks with the amount of waiting tasks, so tasks
+    /// can be switched out right after they started to run. This guard reduces the resulting
+    /// context switch thrashing at the cost of a slightly higher wakeup latency.
+    #[clap(long, default_value = "0")]
+    run_to_parity_us: u64,
+
+    /// Donate the remaining time slice of a task to the task it just woke up synchronously, when
+    /// the waker blocks or yields right after the wakeup.
+    ///
+    /// This keeps producer-consumer pipelines (e.g., pipes, RPC handoffs) running without
+    /// context switch gaps, at the cost of a less fair time slice distribution.
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    slice_donation: bool,
+
+    /// Maximum amount of tasks queued to a CPU before further wakeups are redirected to a less
+    /// loaded CPU in the same LLC (0 = disabled).
+    ///
+    /// This bounds the worst-case queueing delay of the wakeups, at the cost of a slightly lower
+    /// cache locality for tasks that would otherwise stay on their previously used CPU.
+    #[clap(long, default_value = "0")]
+    local_dsq_depth: u64,
+
+    /// Difference in queued batch tasks between the queues of two LLCs above which batch tasks
+    /// are moved from the deeper queue to the shallower one (0 = disabled).
+    ///
+    /// When enabled, batch tasks (tasks that mostly use their full time slice) are queued per
+    /// LLC instead of per NUMA node, and are classified even without --interactive-budget. The
+    /// queues are checked at most every 10ms and up to half of the difference is pushed to the
+    /// shallowest queue of an LLC with an idle CPU, which is then woken up, so that one LLC (e.g.,
+    /// a CCD) doesn't keep a backlog of batch tasks while the CPUs of another one idle.
+    /// Interactive tasks stay in the per-node queues and are never moved.
+    #[clap(long, default_value = "0")]
+    batch_balance_thresh: u64,
+
+    /// Maximum amount of batch tasks moved across LLCs per second by --batch-balance-thresh, with
+    /// bursts of up to one second worth of moves (0 = unlimited).
+    ///
+    /// Every move costs the moved task its cache footprint, this throttles the balancing when the
+    /// LLC queues keep diverging, e.g., under a fork storm.
+    #[clap(long, default_value = "0")]
+    batch_migrate_rate: u64,
+
+    /// Co-locate network-heavy tasks with the CPUs serving the NIC queue IRQs.
+    ///
+    /// Tasks that are mostly woken up from softirq context (e.g., NET_RX) on the CPUs handling the
+    /// IRQs of the network devices (detected via /proc/interrupts) are placed close to the waking
+    /// CPU, so that they can consume the received data while it's still hot in cache. This can
+    /// improve latency of request/response servers.
+    ///
+    /// The option has no effect if the NIC IRQs are served by all the CPUs (e.g., multi-queue
+    /// NICs), since every task would qualify.
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    irq_affine: bool,
+
+    /// Number of CPUs to keep parked to save power (0 = disabled).
+    ///
+    /// Parked CPUs are not used to run tasks, except the ones that can't run anywhere else, so
+    /// that they can stay idle and reach deeper idle states (their idle QoS resume latency
+    /// constraint is also lifted while they are parked). The lowest-ranked CPUs are parked,
+    /// starting from the ones outside of the primary domain.
+    ///
+    /// This can help extend battery life on laptops with mostly light workloads.
+    #[clap(long, default_value = "0")]
+    park_cpus: usize,
+
+    /// Time in milliseconds the other CPUs need to stay overloaded before the parked CPUs are
+    /// used, and need to be able to handle the load alone before they are parked again.
+    #[clap(long, default_value = "50")]
+    park_overload_ms: u64,
+
+    /// Fork rate (forks per second) above which the system is considered in a fork storm
+    /// (0 = disabled).
+    ///
+    /// During a fork storm the children can use at most half of the time slice left to their
+    /// parent and their interactive classification is deferred for their first wakeups, so that
+    /// the tasks spawned en masse by build systems are not prioritized as interactive. The storm
+    /// ends when the fork rate drops below half of this value.
+    #[clap(long, default_value = "0")]
+    fork_storm_rate: u64,
+
     /// Enable preferred idle CPU scanning.
     ///
     /// With this option enabled, the scheduler will prioritize assigning tasks to higher-ranked
@@ -231,6 +391,19 @@ struct Opts {
     #[clap(long)]
     monitor: Option<f64>,
 
+    /// Keep the per-task statistics used to classify the tasks across restarts of the scheduler.
+    ///
+    /// The task storage is pinned under /sys/fs/bpf/scx/scx_bpfland, which requires a mounted
+    /// bpffs, so that a restarted (or crashed and relaunched) scheduler doesn't need to learn
+    /// which tasks are interactive from scratch. The pin is removed on a clean exit.
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    persist_task_ctx: bool,
+
+    /// Write the last statistics and the parking state to this file if the scheduler panics or
+    /// exits with an error, for postmortem analysis (see scx_stats' read_snapshot example).
+    #[clap(long)]
+    snapshot: Option<PathBuf>,
+
     /// Enable BPF debugging via /sys/kernel/tracing/trace_pipe.
     #[clap(short = 'd', long, action = clap::ArgAction::SetTrue)]
     debug: bool,
@@ -258,7 +431,10 @@ struct Scheduler<'a> {
     topo: Topology,
     power_profile: PowerProfile,
     stats_server: StatsServer<(), Metrics>,
+    parked_cpus: Vec<usize>,
+    cpus_parked: bool,
     user_restart: bool,
+    pinner: Option<MapPinner>,
 }
 
 impl<'a> Scheduler<'a> {
@@ -296,6 +472,17 @@ impl<'a> Scheduler<'a> {
                 )
             })?;
 
+        // Determine the CPUs to park.
+        let parked_cpus = Self::resolve_parked_cpus(&topo, &domain, opts.park_cpus)?;
+        let irq_cpus = if opts.irq_affine {
+            Self::resolve_irq_cpus(&topo)
+        } else {
+            Cpumask::new()
+        };
+        if !parked_cpus.is_empty() {
+            info!("Parked CPUs: {:?}", parked_cpus);
+        }
+
         info!(
             "{} {} {}",
             SCHEDULER_NAME,
@@ -344,6 +531,20 @@ impl<'a> Scheduler<'a> {
         rodata.slice_lag = opts.slice_us_lag * 1000;
         rodata.throttle_ns = opts.throttle_us * 1000;
         rodata.primary_all = domain.weight() == *NR_CPU_IDS;
+        rodata.lowpri_enabled = opts.lowpri_queue;
+        rodata.lowpri_nice = opts.lowpri_nice;
+        rodata.lowpri_starvation_ns = opts.lowpri_starvation_ms * 1000000;
+        rodata.interactive_budget = opts.interactive_budget;
+        rodata.run_to_parity_ns = opts.run_to_parity_us * 1000;
+        rodata.slice_donation = opts.slice_donation;
+        rodata.local_dsq_depth_max = opts.local_dsq_depth;
+        rodata.batch_balance_thresh = opts.batch_balance_thresh;
+        rodata.batch_migrate_rate = opts.batch_migrate_rate;
+        rodata.irq_affine = !irq_cpus.is_empty();
+        rodata.park_enabled = !parked_cpus.is_empty();
+        rodata.park_overload_ns = opts.park_overload_ms * 1000000;
+        rodata.nr_parked_cpus = parked_cpus.len() as u32;
+        rodata.fork_storm_rate = opts.fork_storm_rate;
 
         // Generate the list of available CPUs sorted by capacity in descending order.
         let mut cpus: Vec<_> = topo.all_cpus.values().collect();
@@ -379,6 +580,16 @@ impl<'a> Scheduler<'a> {
             skel.struct_ops.bpfland_ops_mut().flags
         );
 
+        let pinner = if opts.persist_task_ctx {
+            let mut pinner = MapPinner::new(SCHEDULER_NAME)?;
+            if pinner.pin(&mut skel.maps.task_ctx_stor)? {
+                info!("Restoring the task statistics of the previous instance");
+            }
+            Some(pinner)
+        } else {
+            None
+        };
+
         // Load the BPF program for validation.
         let mut skel = scx_ops_load!(skel, bpfland_ops, uei)?;
 
@@ -404,6 +615,16 @@ impl<'a> Scheduler<'a> {
             Self::init_smt_domains(&mut skel, &topo)?;
         }
 
+        // Initialize the CPUs serving NIC IRQs.
+        if !irq_cpus.is_empty() {
+            Self::init_irq_domain(&mut skel, &irq_cpus)?;
+        }
+
+        // Initialize the CPUs that are not parked.
+        if !parked_cpus.is_empty() {
+            Self::init_park_domain(&mut skel, &topo, &parked_cpus)?;
+        }
+
         // Attach the scheduler.
         let struct_ops = Some(scx_ops_attach!(skel, bpfland_ops)?);
         let stats_server = StatsServer::new(stats::server_data()).launch()?;
@@ -415,10 +636,57 @@ impl<'a> Scheduler<'a> {
             topo,
             power_profile,
             stats_server,
+            parked_cpus,
+            cpus_parked: false,
             user_restart: false,
+            pinner,
         })
     }
 
+    fn enable_irq_cpu(skel: &mut BpfSkel<'_>, cpu: i32) -> Result<(), u32> {
+        let prog = &mut skel.progs.enable_irq_cpu;
+        let mut args = cpu_arg {
+            cpu_id: cpu as c_int,
+        };
+        let input = ProgramInput {
+            context_in: Some(unsafe {
+                std::slice::from_raw_parts_mut(
+                    &mut args as *mut _ as *mut u8,
+                    std::mem::size_of_val(&args),
+                )
+            }),
+            ..Default::default()
+        };
+        let out = prog.test_run(input).unwrap();
+        if out.return_value != 0 {
+            return Err(out.return_value);
+        }
+
+        Ok(())
+    }
+
+    fn enable_unparked_cpu(skel: &mut BpfSkel<'_>, cpu: i32) -> Result<(), u32> {
+        let prog = &mut skel.progs.enable_unparked_cpu;
+        let mut args = cpu_arg {
+            cpu_id: cpu as c_int,
+        };
+        let input = ProgramInput {
+            context_in: Some(unsafe {
+                std::slice::from_raw_parts_mut(
+                    &mut args as *mut _ as *mut u8,
+                    std::mem::size_of_val(&args),
+                )
+            }),
+            ..Default::default()
+        };
+        let out = prog.test_run(input).unwrap();
+        if out.return_value != 0 {
+            return Err(out.return_value);
+        }
+
+        Ok(())
+    }
+
     fn enable_primary_cpu(skel: &mut BpfSkel<'_>, cpu: i32) -> Result<(), u32> {
         let prog = &mut skel.progs.enable_primary_cpu;
         let mut args = cpu_arg {
@@ -487,6 +755,116 @@ impl<'a> Scheduler<'a> {
         Ok(())
     }
 
+    // Return the CPUs serving NIC IRQs, or an empty mask if --irq-affine can't tell network-heavy
+    // tasks apart, i.e. when there are no NIC IRQs or they're spread across all the CPUs (e.g.,
+    // multi-queue NICs).
+    fn resolve_irq_cpus(topo: &Topology) -> Cpumask {
+        let cpus = match get_nic_irq_cpus() {
+            Ok(cpus) => cpus,
+            Err(err) => {
+                warn!("failed to read NIC IRQ CPUs: {}", err);
+                return Cpumask::new();
+            }
+        };
+        if cpus.is_empty() {
+            warn!("no NIC IRQs found, --irq-affine has no effect");
+            return cpus;
+        }
+        if topo.all_cpus.keys().all(|cpu| cpus.test_cpu(*cpu)) {
+            warn!("NIC IRQs are served by all the CPUs, --irq-affine has no effect");
+            return Cpumask::new();
+        }
+        info!("NIC IRQ CPUs = 0x{:x}", cpus);
+
+        cpus
+    }
+
+    fn init_irq_domain(skel: &mut BpfSkel<'_>, cpus: &Cpumask) -> Result<()> {
+        for cpu in cpus.iter() {
+            if let Err(err) = Self::enable_irq_cpu(skel, cpu as i32) {
+                bail!("failed to add CPU {} to NIC IRQ CPUs: error {}", cpu, err);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Pick the @nr_parked lowest-ranked CPUs, starting from the ones outside of the primary
+    // domain.
+    fn resolve_parked_cpus(
+        topo: &Topology,
+        domain: &Cpumask,
+        nr_parked: usize,
+    ) -> Result<Vec<usize>> {
+        if nr_parked == 0 {
+            return Ok(vec![]);
+        }
+        if nr_parked >= topo.all_cpus.len() {
+            bail!(
+                "--park-cpus must be lower than the number of CPUs ({})",
+                topo.all_cpus.len()
+            );
+        }
+
+        let mut cpus: Vec<_> = topo.all_cpus.values().collect();
+        cpus.sort_by_key(|cpu| (domain.test_cpu(cpu.id), cpu.cpu_capacity));
+
+        let mut parked: Vec<usize> = cpus.iter().take(nr_parked).map(|cpu| cpu.id).collect();
+        parked.sort();
+
+        Ok(parked)
+    }
+
+    fn init_park_domain(
+        skel: &mut BpfSkel<'_>,
+        topo: &Topology,
+        parked_cpus: &[usize],
+    ) -> Result<()> {
+        // Clear the unparked CPUs by passing a negative CPU id.
+        if let Err(err) = Self::enable_unparked_cpu(skel, -1) {
+            bail!("failed to reset unparked CPUs: error {}", err);
+        }
+
+        for cpu in topo.all_cpus.keys() {
+            if parked_cpus.contains(cpu) {
+                continue;
+            }
+            if let Err(err) = Self::enable_unparked_cpu(skel, *cpu as i32) {
+                bail!("failed to add CPU {} to unparked CPUs: error {}", cpu, err);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Lift the idle QoS resume latency constraint of the parked CPUs while they are parked, so
+    // that they can reach the deepest idle states, and restore it when they are unparked.
+    fn refresh_park_idle_qos(&mut self) {
+        if self.parked_cpus.is_empty() || !cpu_idle_resume_latency_supported() {
+            return;
+        }
+
+        let parked = self.skel.maps.bss_data.as_ref().unwrap().cpus_parked;
+        if parked == self.cpus_parked {
+            return;
+        }
+        self.cpus_parked = parked;
+
+        for &cpu in self.parked_cpus.iter() {
+            // A value of 0 means no constraint.
+            let latency_us = if parked {
+                0
+            } else if self.opts.idle_resume_us >= 0 {
+                self.opts.idle_resume_us as i32
+            } else {
+                self.topo.all_cpus[&cpu].pm_qos_resume_latency_us as i32
+            };
+            if let Err(err) = update_cpu_idle_resume_latency(cpu, latency_us) {
+                warn!("failed to update idle QoS of CPU {}: {}", cpu, err);
+            }
+        }
+    }
+
     // Update hint for the cpufreq governor.
     fn init_cpufreq_perf(
         skel: &mut BpfSkel<'_>,
@@ -591,6 +969,29 @@ impl<'a> Scheduler<'a> {
             nr_kthread_dispatches: bss_data.nr_kthread_dispatches,
             nr_direct_dispatches: bss_data.nr_direct_dispatches,
             nr_shared_dispatches: bss_data.nr_shared_dispatches,
+            nr_lowpri_dispatches: bss_data.nr_lowpri_dispatches,
+            lowpri_runtime: bss_data.lowpri_runtime,
+            tot_task_runtime: bss_data.tot_task_runtime,
+            interactive_runtime: bss_data.interactive_runtime,
+            batch_runtime: bss_data.batch_runtime,
+            nr_budget_offsets: bss_data.nr_budget_offsets,
+            nr_parity_extends: bss_data.nr_parity_extends,
+            nr_depth_redirects: bss_data.nr_depth_redirects,
+            nr_batch_balances: bss_data.nr_batch_balances,
+            nr_batch_moves: bss_data.nr_batch_moves,
+            nr_irq_hits: bss_data.nr_irq_hits[..*NR_CPU_IDS].iter().sum(),
+            nr_irq_misses: bss_data.nr_irq_misses,
+            cpus_parked: bss_data.cpus_parked as u64,
+            nr_park_events: bss_data.nr_park_events,
+            nr_unpark_events: bss_data.nr_unpark_events,
+            fork_storm: bss_data.fork_storm as u64,
+            fork_rate: bss_data.fork_rate,
+            nr_fork_storms: bss_data.nr_fork_storms,
+            nr_storm_forks: bss_data.nr_storm_forks,
+            nr_exec_resets: bss_data.nr_exec_resets,
+            nr_slice_donations: bss_data.nr_slice_donations,
+            irq_hits: bss_data.nr_irq_hits[..*NR_CPU_IDS].to_vec(),
+            ..Default::default()
         }
     }
 
@@ -598,13 +999,23 @@ impl<'a> Scheduler<'a> {
         uei_exited!(&self.skel, uei)
     }
 
-    fn run(&mut self, shutdown: Arc<AtomicBool>) -> Result<UserExitInfo> {
+    fn run(
+        &mut self,
+        shutdown: Arc<AtomicBool>,
+        snapshotter: Option<&StatsSnapshotter>,
+    ) -> Result<UserExitInfo> {
         let (res_ch, req_ch) = self.stats_server.channels();
         while !shutdown.load(Ordering::Relaxed) && !self.exited() {
             if self.refresh_sched_domain() {
                 self.user_restart = true;
                 break;
             }
+            self.refresh_park_idle_qos();
+            if let Some(snapshotter) = snapshotter {
+                snapshotter.update_stats("top", &self.get_metrics())?;
+                snapshotter.set_state("cpus_parked", self.cpus_parked);
+                snapshotter.set_state("nr_parked_cpus", self.parked_cpus.len() as u64);
+            }
             match req_ch.recv_timeout(Duration::from_secs(1)) {
                 Ok(()) => res_ch.send(self.get_metrics())?,
                 Err(RecvTimeoutError::Timeout) => {}
@@ -613,7 +1024,14 @@ impl<'a> Scheduler<'a> {
         }
 
         let _ = self.struct_ops.take();
-        uei_report!(&self.skel, uei)
+        let res = uei_report!(&self.skel, uei);
+        if let (Err(err), Some(snapshotter)) = (&res, snapshotter) {
+            match snapshotter.dump(&format!("{:#}", err)) {
+                Ok(()) => info!("Stats snapshot written to {:?}", snapshotter.path()),
+                Err(e) => warn!("Failed to write stats snapshot ({:#})", e),
+            }
+        }
+        res
     }
 }
 
@@ -622,7 +1040,7 @@ impl Drop for Scheduler<'_> {
         info!("Unregister {SCHEDULER_NAME} scheduler");
 
         // Restore default CPU idle QoS resume latency.
-        if self.opts.idle_resume_us >= 0 {
+        if self.opts.idle_resume_us >= 0 || !self.parked_cpus.is_empty() {
             if cpu_idle_resume_latency_supported() {
                 for cpu in self.topo.all_cpus.values() {
                     update_cpu_idle_resume_latency(cpu.id, cpu.pm_qos_resume_latency_us as i32)
@@ -637,11 +1055,7 @@ fn main() -> Result<()> {
     let opts = Opts::parse();
 
     if opts.version {
-        println!(
-            "{} {}",
-            SCHEDULER_NAME,
-            build_id::full_version(env!("CARGO_PKG_VERSION"))
-        );
+        println!("{:#}", scx_utils::version_info!());
         return Ok(());
     }
 
@@ -694,13 +1108,27 @@ fn main() -> Result<()> {
         }
     }
 
+    let snapshotter = opts
+        .snapshot
+        .as_ref()
+        .map(|path| StatsSnapshotter::new(SCHEDULER_NAME, path));
+    if let Some(snapshotter) = snapshotter.as_ref() {
+        snapshotter.install_panic_hook();
+    }
+
     let mut open_object = MaybeUninit::uninit();
     loop {
         let mut sched = Scheduler::init(&opts, &mut open_object)?;
-        if !sched.run(shutdown.clone())?.should_restart() {
+        if !sched
+            .run(shutdown.clone(), snapshotter.as_ref())?
+            .should_restart()
+        {
             if sched.user_restart {
                 continue;
             }
+            if let Some(pinner) = sched.pinner.as_ref() {
+                pinner.unpin_all()?;
+            }
             break;
         }
     }
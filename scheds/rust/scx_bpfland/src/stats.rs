@@ -25,19 +25,88 @@ pub struct Metrics {
     pub nr_direct_dispatches: u64,
     #[stat(desc = "Number of regular task dispatches")]
     pub nr_shared_dispatches: u64,
+    #[stat(desc = "Number of dispatches from the lowest-priority queue")]
+    pub nr_lowpri_dispatches: u64,
+    #[stat(desc = "CPU time used by low-priority tasks (ns)")]
+    pub lowpri_runtime: u64,
+    #[stat(desc = "CPU time used by all tasks (ns)")]
+    pub tot_task_runtime: u64,
+    #[stat(desc = "% of the task CPU time used by low-priority tasks")]
+    pub pc_lowpri: f64,
+    #[stat(desc = "CPU time used by interactive tasks (ns)")]
+    pub interactive_runtime: u64,
+    #[stat(desc = "CPU time used by batch tasks (ns)")]
+    pub batch_runtime: u64,
+    #[stat(desc = "% of the interactive and batch CPU time used by batch tasks")]
+    pub pc_batch: f64,
+    #[stat(desc = "Number of interactive deadlines pushed forward to honor the batch share")]
+    pub nr_budget_offsets: u64,
+    #[stat(desc = "Number of time slices extended by the run-to-parity guard")]
+    pub nr_parity_extends: u64,
+    #[stat(desc = "Number of wakeups redirected away from CPUs with a deep local queue")]
+    pub nr_depth_redirects: u64,
+    #[stat(desc = "Number of batch task balancing rounds that moved tasks across LLCs")]
+    pub nr_batch_balances: u64,
+    #[stat(desc = "Number of batch tasks moved across LLCs by the balancing")]
+    pub nr_batch_moves: u64,
+    #[stat(desc = "Number of network-heavy task placements on CPUs serving NIC IRQs")]
+    pub nr_irq_hits: u64,
+    #[stat(desc = "Number of network-heavy task placements on other CPUs")]
+    pub nr_irq_misses: u64,
+    #[stat(
+        desc = "Per-CPU network-heavy task placements on CPUs serving NIC IRQs",
+        _om_label = "cpu"
+    )]
+    pub irq_hits: Vec<u64>,
+    #[stat(desc = "1 if the parked CPUs are currently parked")]
+    pub cpus_parked: u64,
+    #[stat(desc = "Number of times the parked CPUs have been parked")]
+    pub nr_park_events: u64,
+    #[stat(desc = "Number of times the parked CPUs have been unparked due to overload")]
+    pub nr_unpark_events: u64,
+    #[stat(desc = "1 if a fork storm is in progress")]
+    pub fork_storm: u64,
+    #[stat(desc = "Fork rate over the last detection window (forks/sec)")]
+    pub fork_rate: u64,
+    #[stat(desc = "Number of fork storms detected")]
+    pub nr_fork_storms: u64,
+    #[stat(desc = "Number of tasks forked during a fork storm")]
+    pub nr_storm_forks: u64,
+    #[stat(desc = "Number of tasks whose interactivity statistics were reset on exec")]
+    pub nr_exec_resets: u64,
+    #[stat(desc = "Number of time slices donated to a synchronous wakee")]
+    pub nr_slice_donations: u64,
 }
 
 impl Metrics {
     fn format<W: Write>(&self, w: &mut W) -> Result<()> {
         writeln!(
             w,
-            "[{}] tasks -> r: {:>2}/{:<2} | dispatch -> k: {:<5} d: {:<5} s: {:<5}",
+            "[{}] tasks -> r: {:>2}/{:<2} | dispatch -> k: {:<5} d: {:<5} s: {:<5} | lowpri -> d: {:<5} {:>5.1}% | batch -> {:>5.1}% o: {:<5} | parity: {:<5} depth: {:<5} | bal -> r: {:<5} m: {:<5} | irq -> h: {:<5} m: {:<5} | park -> {} p: {:<3} u: {:<3} | fork -> {} r: {:<5} s: {:<5} | exec: {:<5} | donate: {:<5}",
             crate::SCHEDULER_NAME,
             self.nr_running,
             self.nr_cpus,
             self.nr_kthread_dispatches,
             self.nr_direct_dispatches,
-            self.nr_shared_dispatches
+            self.nr_shared_dispatches,
+            self.nr_lowpri_dispatches,
+            self.pc_lowpri,
+            self.pc_batch,
+            self.nr_budget_offsets,
+            self.nr_parity_extends,
+            self.nr_depth_redirects,
+            self.nr_batch_balances,
+            self.nr_batch_moves,
+            self.nr_irq_hits,
+            self.nr_irq_misses,
+            if self.cpus_parked != 0 { "on " } else { "off" },
+            self.nr_park_events,
+            self.nr_unpark_events,
+            if self.fork_storm != 0 { "on " } else { "off" },
+            self.fork_rate,
+            self.nr_storm_forks,
+            self.nr_exec_resets,
+            self.nr_slice_donations
         )?;
         Ok(())
     }
@@ -47,6 +116,40 @@ impl Metrics {
             nr_kthread_dispatches: self.nr_kthread_dispatches - rhs.nr_kthread_dispatches,
             nr_direct_dispatches: self.nr_direct_dispatches - rhs.nr_direct_dispatches,
             nr_shared_dispatches: self.nr_shared_dispatches - rhs.nr_shared_dispatches,
+            nr_lowpri_dispatches: self.nr_lowpri_dispatches - rhs.nr_lowpri_dispatches,
+            lowpri_runtime: self.lowpri_runtime - rhs.lowpri_runtime,
+            tot_task_runtime: self.tot_task_runtime - rhs.tot_task_runtime,
+            pc_lowpri: match self.tot_task_runtime - rhs.tot_task_runtime {
+                0 => 0.0,
+                tot => (self.lowpri_runtime - rhs.lowpri_runtime) as f64 * 100.0 / tot as f64,
+            },
+            interactive_runtime: self.interactive_runtime - rhs.interactive_runtime,
+            batch_runtime: self.batch_runtime - rhs.batch_runtime,
+            pc_batch: match (self.interactive_runtime - rhs.interactive_runtime)
+                + (self.batch_runtime - rhs.batch_runtime)
+            {
+                0 => 0.0,
+                tot => (self.batch_runtime - rhs.batch_runtime) as f64 * 100.0 / tot as f64,
+            },
+            nr_budget_offsets: self.nr_budget_offsets - rhs.nr_budget_offsets,
+            nr_parity_extends: self.nr_parity_extends - rhs.nr_parity_extends,
+            nr_depth_redirects: self.nr_depth_redirects - rhs.nr_depth_redirects,
+            nr_batch_balances: self.nr_batch_balances - rhs.nr_batch_balances,
+            nr_batch_moves: self.nr_batch_moves - rhs.nr_batch_moves,
+            nr_irq_hits: self.nr_irq_hits - rhs.nr_irq_hits,
+            nr_irq_misses: self.nr_irq_misses - rhs.nr_irq_misses,
+            nr_park_events: self.nr_park_events - rhs.nr_park_events,
+            nr_unpark_events: self.nr_unpark_events - rhs.nr_unpark_events,
+            nr_fork_storms: self.nr_fork_storms - rhs.nr_fork_storms,
+            nr_storm_forks: self.nr_storm_forks - rhs.nr_storm_forks,
+            nr_exec_resets: self.nr_exec_resets - rhs.nr_exec_resets,
+            nr_slice_donations: self.nr_slice_donations - rhs.nr_slice_donations,
+            irq_hits: self
+                .irq_hits
+                .iter()
+                .zip(rhs.irq_hits.iter().chain(std::iter::repeat(&0)))
+                .map(|(cur, prev)| cur - prev)
+                .collect(),
             ..self.clone()
         }
     }
@@ -14,7 +14,6 @@ use log::warn;
 use scx_p2dq::SchedulerOpts as P2dqOpts;
 use scx_userspace_arena::alloc::Allocator;
 use scx_userspace_arena::alloc::HeapAllocator;
-use scx_utils::build_id;
 use scx_utils::compat;
 use scx_utils::compat::tracefs_mount;
 use scx_utils::init_libbpf_logging;
@@ -721,10 +720,7 @@ pub fn validate_kprobes(kprobes: &[String]) -> Result<()> {
 
 pub fn run(args: Args) -> Result<()> {
     if args.version {
-        println!(
-            "scx_chaos: {}",
-            build_id::full_version(env!("CARGO_PKG_VERSION"))
-        );
+        println!("{:#}", scx_utils::version_info!());
         return Ok(());
     }
 
@@ -742,10 +738,7 @@ pub fn run(args: Args) -> Result<()> {
     })
     .context("Error setting Ctrl-C handler")?;
 
-    info!(
-        "Running scx_chaos (build ID: {})",
-        build_id::full_version(env!("CARGO_PKG_VERSION"))
-    );
+    info!("Running {}", scx_utils::version_info!());
 
     if let Some(intv) = args.monitor {
         return stats::monitor(Duration::from_secs_f64(intv), shutdown);
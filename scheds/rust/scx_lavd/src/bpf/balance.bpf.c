@@ -19,16 +19,32 @@
 
 extern const volatile u8	mig_delta_pct;
 
+/*
+ * Shift of the migration threshold, scaled by the number of CPUs from
+ * userspace.
+ */
+const volatile u8		mig_shift = LAVD_CPDOM_MIG_SHIFT;
+
 u64 __attribute__ ((noinline)) calc_mig_delta(u64 avg_sc_load, int nz_qlen)
 {
 	/*
 	 * Note that added "noinline" to make the verifier happy.
 	 */
 	if (nz_qlen >= sys_stat.nr_active_cpdoms)
-		return avg_sc_load >> LAVD_CPDOM_MIG_SHIFT_OL;
+		return avg_sc_load >> (mig_shift + 1);
 	if (nz_qlen == 0)
-		return avg_sc_load >> LAVD_CPDOM_MIG_SHIFT_UL;
-	return avg_sc_load >> LAVD_CPDOM_MIG_SHIFT;
+		return avg_sc_load >> (mig_shift - 1);
+	return avg_sc_load >> mig_shift;
+}
+
+static u32 get_mig_prob_ft(void)
+{
+	/*
+	 * Try stealing roughly twice per system statistics interval.
+	 */
+	u32 ft = sys_stat_interval_ns / LAVD_SLICE_MAX_NS_DFL;
+
+	return ft ?: 1;
 }
 
 __weak
@@ -294,7 +310,7 @@ static bool try_to_steal_task(struct cpdom_ctx *cpdomc)
 	 * thundering herd problem. In other words, one out of nr_cpus
 	 * will try to steal a task at a moment.
 	 */
-	if (!prob_x_out_of_y(1, cpdomc->nr_active_cpus * LAVD_CPDOM_MIG_PROB_FT))
+	if (!prob_x_out_of_y(1, cpdomc->nr_active_cpus * get_mig_prob_ft()))
 		return false;
 
 	/*
@@ -355,7 +371,7 @@ static bool try_to_steal_task(struct cpdom_ctx *cpdomc)
 		 * increases, so, on the other hand, it increases the chance
 		 * of closer migration.
 		 */
-		if (!prob_x_out_of_y(1, LAVD_CPDOM_MIG_PROB_FT))
+		if (!prob_x_out_of_y(1, get_mig_prob_ft()))
 			break;
 	}
 
@@ -152,8 +152,18 @@ s32 find_cpu_in(const struct cpumask *src_mask, struct cpu_ctx *cpuc_cur)
 }
 
 
-static s32 pick_idle_cpu_at_cpdom(struct pick_ctx *ctx, s64 cpdom, u64 scope,
-			   bool *is_idle)
+static __always_inline
+void exclude_throttled(struct pick_ctx *ctx, bool no_thr)
+{
+	struct bpf_cpumask *thr = thr_cpumask;
+
+	if (no_thr && thr)
+		bpf_cpumask_andnot(ctx->temp_mask, cast_mask(ctx->temp_mask),
+				   cast_mask(thr));
+}
+
+static s32 __pick_idle_cpu_at_cpdom(struct pick_ctx *ctx, s64 cpdom, u64 scope,
+				    bool *is_idle, bool no_thr)
 {
 	struct bpf_cpumask *cpd_mask;
 	struct cpdom_ctx *cpdc;
@@ -171,6 +181,7 @@ static s32 pick_idle_cpu_at_cpdom(struct pick_ctx *ctx, s64 cpdom, u64 scope,
 	if (!ctx->iat_empty && cpdc->nr_active_cpus && cpdc->is_big) {
 		bpf_cpumask_and(ctx->temp_mask,
 				cast_mask(cpd_mask), cast_mask(ctx->iat_mask));
+		exclude_throttled(ctx, no_thr);
 		cpu = scx_bpf_pick_idle_cpu(cast_mask(ctx->temp_mask), scope);
 		if (cpu >= 0) {
 			*is_idle = true;
@@ -180,6 +191,7 @@ static s32 pick_idle_cpu_at_cpdom(struct pick_ctx *ctx, s64 cpdom, u64 scope,
 	if (!ctx->ia_empty && cpdc->nr_active_cpus) {
 		bpf_cpumask_and(ctx->temp_mask,
 				cast_mask(cpd_mask), cast_mask(ctx->ia_mask));
+		exclude_throttled(ctx, no_thr);
 		cpu = scx_bpf_pick_idle_cpu(cast_mask(ctx->temp_mask), scope);
 		if (cpu >= 0) {
 			*is_idle = true;
@@ -189,6 +201,7 @@ static s32 pick_idle_cpu_at_cpdom(struct pick_ctx *ctx, s64 cpdom, u64 scope,
 	if (!ctx->io_empty) {
 		bpf_cpumask_and(ctx->temp_mask,
 				cast_mask(cpd_mask), cast_mask(ctx->io_mask));
+		exclude_throttled(ctx, no_thr);
 		cpu = scx_bpf_pick_idle_cpu(cast_mask(ctx->temp_mask), scope);
 		if (cpu >= 0) {
 			*is_idle = true;
@@ -198,6 +211,23 @@ static s32 pick_idle_cpu_at_cpdom(struct pick_ctx *ctx, s64 cpdom, u64 scope,
 	return -ENOENT;
 }
 
+static s32 pick_idle_cpu_at_cpdom(struct pick_ctx *ctx, s64 cpdom, u64 scope,
+			   bool *is_idle)
+{
+	s32 cpu;
+
+	/*
+	 * When thermal-aware, prefer CPUs which are not throttled and fall
+	 * back to the throttled ones only when there is nothing else.
+	 */
+	if (thermal_aware) {
+		cpu = __pick_idle_cpu_at_cpdom(ctx, cpdom, scope, is_idle, true);
+		if (cpu >= 0)
+			return cpu;
+	}
+	return __pick_idle_cpu_at_cpdom(ctx, cpdom, scope, is_idle, false);
+}
+
 static __always_inline
 s32 cpumask_any_dsitribute(struct pick_ctx *ctx)
 {
@@ -236,6 +266,43 @@ s32 pick_random_cpu(struct pick_ctx *ctx)
 	return (cpuc0->cur_sc_util < cpuc1->cur_sc_util) ? cpu0 : cpu1;
 }
 
+static
+s32 pick_idle_cpu_io(struct pick_ctx *ctx, bool *is_idle)
+{
+	s32 cpu;
+
+	/*
+	 * An IO-bound task runs only briefly after each wake-up, so what
+	 * matters is to run it as soon as possible rather than to find the
+	 * best CPU for it. Stay on the previous CPU if it is idle for cache
+	 * locality. Otherwise, take any idle CPU even if its SMT sibling is
+	 * busy, leaving fully idle cores for CPU-bound tasks.
+	 */
+	cpu = ctx->prev_cpu;
+	if (((ctx->a_mask && bpf_cpumask_test_cpu(cpu, cast_mask(ctx->a_mask))) ||
+	     (ctx->o_mask && bpf_cpumask_test_cpu(cpu, cast_mask(ctx->o_mask)))) &&
+	    scx_bpf_test_and_clear_cpu_idle(cpu)) {
+		*is_idle = true;
+		return cpu;
+	}
+
+	if (ctx->a_mask) {
+		cpu = scx_bpf_pick_idle_cpu(cast_mask(ctx->a_mask), 0);
+		if (cpu >= 0) {
+			*is_idle = true;
+			return cpu;
+		}
+	}
+	if (ctx->o_mask) {
+		cpu = scx_bpf_pick_idle_cpu(cast_mask(ctx->o_mask), 0);
+		if (cpu >= 0) {
+			*is_idle = true;
+			return cpu;
+		}
+	}
+	return -ENOENT;
+}
+
 static
 s32 find_sticky_cpu_at_cpdom(struct pick_ctx *ctx, s32 sticky_cpu, s64 sticky_cpdom)
 {
@@ -331,6 +398,14 @@ bool test_cpu_stickable(struct pick_ctx *ctx, struct sticky_ctx *sctx,
 		if (!cpuc || sctx->i_m >= 2 || sctx->i_nm >= 2)
 			return false;
 
+		/*
+		 * Do not let a latency-critical task stick to a throttled
+		 * CPU, so it can look for a faster one instead.
+		 */
+		if (thermal_aware && READ_ONCE(cpuc->is_throttled) &&
+		    is_lat_cri(ctx->taskc))
+			return false;
+
 		if (is_task_big == cpuc->big_core)
 			sctx->cpuc_match[sctx->i_m++] = cpuc;
 		else
@@ -358,6 +433,56 @@ bool is_sync_wakeup(struct pick_ctx *ctx)
 	return true;
 }
 
+static
+s32 cpu_node_id(s32 cpu)
+{
+	struct cpdom_ctx *cpdomc;
+	struct cpu_ctx *cpuc;
+
+	if (cpu < 0 || !(cpuc = get_cpu_ctx_id(cpu)))
+		return -ENOENT;
+
+	cpdomc = MEMBER_VPTR(cpdom_ctxs, [cpuc->cpdom_id]);
+	return cpdomc ? cpdomc->numa_id : -ENOENT;
+}
+
+static
+bool hold_wakee_on_node(struct pick_ctx *ctx, s32 waker_cpu)
+{
+	task_ctx *taskc = ctx->taskc;
+	s32 prev_node, pref_node = -1;
+
+	if (!numa_wake_hold_ns)
+		return false;
+
+	/*
+	 * A synchronous waker on a remote NUMA node tends to drag the wakee
+	 * over to its node. Resist it when the wakee's memory and history
+	 * are on its current node, which is when pulling it cross-node
+	 * hurts the most: the wakee has been running on its node for a
+	 * while and NUMA balancing (if available) doesn't prefer another
+	 * node. Still, let it go when it's queued for too long at home.
+	 */
+	prev_node = cpu_node_id(ctx->prev_cpu);
+	if (prev_node < 0 || prev_node == cpu_node_id(waker_cpu))
+		return false;
+
+	if (bpf_core_field_exists(ctx->p->numa_preferred_nid))
+		pref_node = BPF_CORE_READ(ctx->p, numa_preferred_nid);
+	if (pref_node >= 0 && pref_node != prev_node)
+		return false;
+
+	if (taskc->node_id != prev_node ||
+	    time_delta(scx_bpf_now(), taskc->node_clk) < numa_wake_hold_ns)
+		return false;
+
+	if (taskc->avg_qdelay >= numa_wake_qdelay_ns)
+		return false;
+
+	ctx->cpuc_cur->nr_xnode_held++;
+	return true;
+}
+
 static
 s32 find_sticky_cpu_and_cpdom(struct pick_ctx *ctx, s64 *sticky_cpdom)
 {
@@ -373,7 +498,8 @@ s32 find_sticky_cpu_and_cpdom(struct pick_ctx *ctx, s64 *sticky_cpdom)
 	test_cpu_stickable(ctx, &sctx, ctx->prev_cpu, ctx->is_task_big);
 	if (is_sync_wakeup(ctx)) {
 		s32 waker_cpu = bpf_get_smp_processor_id();
-		if (waker_cpu != ctx->prev_cpu) {
+		if (waker_cpu != ctx->prev_cpu &&
+		    !hold_wakee_on_node(ctx, waker_cpu)) {
 			ctx->sync_waker_cpu = waker_cpu;
 			test_cpu_stickable(ctx, &sctx, ctx->sync_waker_cpu, ctx->is_task_big);
 		}
@@ -555,6 +681,7 @@ s32 __attribute__ ((noinline)) pick_idle_cpu(struct pick_ctx *ctx, bool *is_idle
 	 *    (if it exists) will be resolved by the load balancing mechanism.
 	 */
 	bpf_rcu_read_lock();
+	ctx->sync_waker_cpu = -ENOENT;
 
 	/*
 	 * If a task can run only on a single CPU (e.g., per-CPU kworker),
@@ -591,11 +718,20 @@ s32 __attribute__ ((noinline)) pick_idle_cpu(struct pick_ctx *ctx, bool *is_idle
 	}
 	/* NOTE: Now task @p can run on either active or overflow set. */
 
+	/*
+	 * If @p is IO-bound, place it on an idle CPU quickly, bypassing
+	 * the placement logic for CPU-bound tasks below.
+	 */
+	if (io_boost && test_task_flag(ctx->taskc, LAVD_FLAG_IO_BOUND)) {
+		cpu = pick_idle_cpu_io(ctx, is_idle);
+		if (cpu >= 0)
+			goto unlock_out;
+	}
+
 	/*
 	 * Find a sticky cpu and domain considering the core & task type
 	 * to set an anchor for proximity.
 	 */
-	ctx->sync_waker_cpu = -ENOENT;
 	ctx->is_task_big = is_perf_cri(ctx->taskc);
 	sticky_cpu = find_sticky_cpu_and_cpdom(ctx, &sticky_cpdom);
 
@@ -793,6 +929,17 @@ unlock_out:
 	if (cpu < 0)
 		cpu = pick_random_cpu(ctx);
 
+	/*
+	 * Count the wakees which ended up on a remote waker's node.
+	 */
+	if (ctx->sync_waker_cpu >= 0 && nr_cpdoms > 1) {
+		s32 node = cpu_node_id(cpu);
+
+		if (node >= 0 && node != cpu_node_id(ctx->prev_cpu) &&
+		    node == cpu_node_id(ctx->sync_waker_cpu))
+			ctx->cpuc_cur->nr_xnode_pull++;
+	}
+
 	/*
 	 * Clean up.
 	 */
@@ -36,6 +36,8 @@ enum {
 	LAVD_CPDOM_MAX_NR		= 128, /* maximum number of compute domain */
 	LAVD_CPDOM_MAX_DIST		= 3,  /* maximum distance from one compute domain to another */
 
+	LAVD_STRICT_CGRP_MAX		= 64, /* maximum number of --strict-cgroup cgroups */
+
 	LAVD_PCO_STATE_MAX		= 11, /* maximum number of performance vs. CPU order states */
 
 	LAVD_STATUS_STR_LEN		= 4,  /* {LR: Latency-critical, Regular}
@@ -60,6 +62,9 @@ struct sys_stat {
 	u32	avg_lat_cri;	/* average latency criticality (LC) */
 	u32	max_lat_cri;	/* maximum latency criticality (LC) */
 	u32	thr_lat_cri;	/* latency criticality threshold for kicking */
+	u32	preempt_aggr;	/* preemption aggressiveness [0, LAVD_SCALE] */
+	u32	preempt_shift;	/* effective preempt_shift from preempt_aggr */
+	u32	strict_throttled; /* number of CPUs on which the strict band is over its budget */
 
 	u32	min_perf_cri;	/* minimum performance criticality */
 	u32	avg_perf_cri;	/* average performance criticality */
@@ -78,6 +83,11 @@ struct sys_stat {
 	u64	nr_big;		/* scheduled on big core */
 	u64	nr_pc_on_big;	/* performance-critical tasks scheduled on big core */
 	u64	nr_lc_on_big;	/* latency-critical tasks scheduled on big core */
+	u64	nr_frame_paced;	/* frame-paced tasks scheduled */
+	u64	nr_io_bound;	/* IO-bound tasks scheduled */
+	u64	nr_strict;	/* tasks scheduled in the strict priority band */
+	u64	nr_xnode_pull;	/* wakees pulled to a remote waker's node */
+	u64	nr_xnode_held;	/* wakees held on their node against a remote waker */
 };
 
 /*
@@ -111,8 +121,26 @@ struct task_ctx_x {
 	u64	dsq_id;		/* CPU's associated DSQ */
 	u64	dsq_consume_lat; /* DSQ's consume latency */
 	u64	last_slice_used;	/* time(ns) used in last scheduled interval: [last running, last stopping] */
+	u64	frame_period;	/* average wake-up interval in ns within the frame period range */
+	u32	frame_conf;	/* confidence of the frame period detection */
+};
+
+
+/*
+ * Per-task hints set by applications through the pinned task hint map.
+ * See --task-hint-map.
+ */
+enum {
+	LAVD_HINT_NONE			= 0,
+	LAVD_HINT_LAT_CRI		= 1, /* latency-critical, e.g., game or audio threads */
+	LAVD_HINT_BACKGROUND		= 2, /* background work, never latency-critical */
+	LAVD_HINT_STRICT		= 3, /* strict priority band, e.g., pro-audio threads */
 };
 
+struct task_hint {
+	u64	hint;		/* LAVD_HINT_* */
+	u64	__reserved[3];
+};
 
 /*
  * introspection
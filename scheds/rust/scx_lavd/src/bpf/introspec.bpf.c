@@ -83,6 +83,8 @@ int submit_task_ctx(struct task_struct *p, task_ctx __arg_arena *taskc, u32 cpu_
 	m->taskc_x.dsq_id = cpdomc->id;
 	m->taskc_x.dsq_consume_lat = cpdomc->dsq_consume_lat;
 	m->taskc_x.last_slice_used = taskc->last_slice_used;
+	m->taskc_x.frame_period = taskc->frame_period;
+	m->taskc_x.frame_conf = taskc->frame_conf;
 
 	bpf_ringbuf_submit(m, 0);
 
@@ -17,10 +17,125 @@
 #include <bpf/bpf_tracing.h>
 #include <lib/cgroup.h>
 
+/*
+ * Hints set by applications for their own tasks, keyed by pidfd from
+ * userspace. The map is pinned with --task-hint-map.
+ */
+struct {
+	__uint(type, BPF_MAP_TYPE_TASK_STORAGE);
+	__uint(map_flags, BPF_F_NO_PREALLOC);
+	__type(key, int);
+	__type(value, struct task_hint);
+} scx_lavd_task_hint_map SEC(".maps");
+
+const volatile bool	task_hint_map_enabled;
+
+/*
+ * Honor LAVD_HINT_STRICT from the hint map. Disabled when non-root users can
+ * write the map, since the strict band preempts everything else.
+ */
+const volatile bool	task_hint_strict_enabled;
+
+/*
+ * Cgroups whose tasks, including the tasks of their descendants, run in the
+ * strict priority band, keyed by cgroup id. Filled from --strict-cgroup.
+ */
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__uint(max_entries, LAVD_STRICT_CGRP_MAX);
+	__type(key, u64);
+	__type(value, u8);
+} strict_cgrp_map SEC(".maps");
+
+const volatile bool	strict_cgrp_enabled;
+
+static u64 get_task_hint(struct task_struct *p)
+{
+	struct task_hint *hint;
+
+	if (!task_hint_map_enabled)
+		return LAVD_HINT_NONE;
+
+	hint = bpf_task_storage_get(&scx_lavd_task_hint_map, p, NULL, 0);
+	if (!hint || hint->hint > LAVD_HINT_STRICT)
+		return LAVD_HINT_NONE;
+	return hint->hint;
+}
+
+/*
+ * Let a forked task inherit its parent's hint so that a hint set by a
+ * wrapper command before exec() covers all the threads and children of the
+ * application. Should be called from ops.init_task().
+ */
+__hidden
+void inherit_task_hint(struct task_struct *p)
+{
+	struct task_hint *hint;
+	u64 parent_hint;
+
+	parent_hint = get_task_hint(bpf_get_current_task_btf());
+	if (parent_hint == LAVD_HINT_NONE)
+		return;
+
+	hint = bpf_task_storage_get(&scx_lavd_task_hint_map, p, NULL,
+				    BPF_LOCAL_STORAGE_GET_F_CREATE);
+	if (hint)
+		hint->hint = parent_hint;
+}
+
+/*
+ * Tag @taskc as strict if @cgrp or one of its ancestors is in
+ * strict_cgrp_map. Should be called whenever the task's cgroup is set.
+ */
+__hidden
+void update_strict_cgrp(task_ctx *taskc, struct cgroup *cgrp)
+{
+	struct cgroup *ancestor;
+	u64 cgrp_id;
+	int level;
+
+	reset_task_flag(taskc, LAVD_FLAG_STRICT_CGRP);
+	if (!strict_cgrp_enabled)
+		return;
+
+	bpf_for(level, 1, cgrp->level + 1) {
+		if (!(ancestor = bpf_cgroup_ancestor(cgrp, level)))
+			continue;
+		cgrp_id = ancestor->kn->id;
+		bpf_cgroup_release(ancestor);
+
+		if (bpf_map_lookup_elem(&strict_cgrp_map, &cgrp_id)) {
+			set_task_flag(taskc, LAVD_FLAG_STRICT_CGRP);
+			return;
+		}
+	}
+}
+
+static bool is_strict_task(struct task_struct *p, task_ctx *taskc)
+{
+	return test_task_flag(taskc, LAVD_FLAG_STRICT_CGRP) ||
+	       (task_hint_strict_enabled && get_task_hint(p) == LAVD_HINT_STRICT);
+}
 
 static u64 calc_weight_factor(struct task_struct *p, task_ctx *taskc)
 {
 	u64 weight_boost = 1;
+	u64 hint = get_task_hint(p);
+
+	/*
+	 * Background tasks are never prioritized for their scheduling
+	 * context, only their nice priority is respected.
+	 */
+	if (hint == LAVD_HINT_BACKGROUND)
+		return p->scx.weight + 1;
+
+	/*
+	 * Trust the application telling us that the task is
+	 * latency-critical. A strict-band task is at least as
+	 * latency-critical, including when the band is throttled.
+	 */
+	if (hint == LAVD_HINT_LAT_CRI || is_strict_task(p, taskc))
+		weight_boost += LAVD_LC_WEIGHT_BOOST_HIGH;
 
 	/*
 	 * Prioritize a wake-up task since this is a clear sign of immediate
@@ -163,11 +278,12 @@ static void calc_lat_cri(struct task_struct *p, task_ctx *taskc)
 	 * wakee, and backward propagation is to boost the low-priority waker
 	 * (i.e., priority inversion) for the next time. Propagation decays
 	 * geometrically and is capped to a limit to prevent unlimited cyclic
-	 * inflation of latency-criticality.
-	 *
+	 * inflation of latency-criticality. Background tasks don't inherit
+	 * any latency criticality.
 	 */
 	lat_cri_giver = taskc->lat_cri_waker + taskc->lat_cri_wakee;
-	if (lat_cri_giver > (2 * lat_cri)) {
+	if (lat_cri_giver > (2 * lat_cri) &&
+	    get_task_hint(p) != LAVD_HINT_BACKGROUND) {
 		/*
 		 * The amount of latency criticality inherited needs to be
 		 * limited, so the task's latency criticality portion should
@@ -292,5 +408,22 @@ u64 calc_when_to_run(struct task_struct *p, task_ctx *taskc)
 	 */
 	dl_delta = calc_virtual_deadline_delta(p, taskc);
 	clc = READ_ONCE(cur_logical_clk) - LAVD_DL_COMPETE_WINDOW;
+
+	/*
+	 * A task in the strict priority band runs ahead of all regular tasks
+	 * as long as the band stays within the budget of the task's CPU. Once
+	 * the band is throttled there, its tasks compete as regular
+	 * latency-critical tasks until the budget is paid back.
+	 */
+	if (is_strict_task(p, taskc)) {
+		struct cpu_ctx *cpuc = get_cpu_ctx_id(scx_bpf_task_cpu(p));
+
+		if (cpuc && !READ_ONCE(cpuc->strict_throttled)) {
+			set_task_flag(taskc, LAVD_FLAG_STRICT);
+			return clc;
+		}
+	}
+	reset_task_flag(taskc, LAVD_FLAG_STRICT);
+
 	return clc + dl_delta;
 }
@@ -85,9 +85,10 @@ enum consts_internal {
 	LAVD_LC_WAKE_INTERVAL_MIN	= LAVD_SLICE_MIN_NS_DFL,
 	LAVD_LC_INH_RECEIVER_SHIFT	= 2, /* 25.0% of receiver's latency criticality */
 	LAVD_LC_INH_GIVER_SHIFT		= 3, /* 12.5 of giver's latency criticality */
+	LAVD_LC_STRICT			= 0xfffe, /* strict band, just below a CPU taken by RT/DL */
 
-	LAVD_SYS_STAT_INTERVAL_NS	= (10ULL * NSEC_PER_MSEC),
-	LAVD_SYS_STAT_DECAY_TIMES	= ((2ULL * LAVD_TIME_ONE_SEC) / LAVD_SYS_STAT_INTERVAL_NS),
+	LAVD_SYS_STAT_INTERVAL_NS	= (10ULL * NSEC_PER_MSEC), /* default, scaled by # CPUs */
+	LAVD_SYS_STAT_DECAY_PERIOD	= (2ULL * LAVD_TIME_ONE_SEC),
 
 	LAVD_CPU_UTIL_MAX_FOR_CPUPERF	= p2s(85), /* 85.0% */
 	LAVD_CPU_UTIL_THR_FOR_MAX_FREQ	= p2s(80), /* cpu utilization threshold to update max freq */
@@ -98,18 +99,27 @@ enum consts_internal {
 						      it is likely that the actual utilization is even
 						      higher than that. */
 	LAVD_CC_CPU_PIN_INTERVAL	= (250ULL * NSEC_PER_MSEC),
-	LAVD_CC_CPU_PIN_INTERVAL_DIV	= (LAVD_CC_CPU_PIN_INTERVAL / LAVD_SYS_STAT_INTERVAL_NS),
 
 	LAVD_AP_HIGH_UTIL_DFL_SMT_RT	= p2s(25),
 	LAVD_AP_HIGH_UTIL_DFL_NO_SMT_RT	= p2s(50), /* 50%: balanced mode when 10% < cpu util <= 50%,
 							  performance mode when cpu util > 50% */
 
-	LAVD_CPDOM_MIG_SHIFT_UL		= 2, /* when under-loaded:  1/2**2 = [-25.0%, +25.0%] */
-	LAVD_CPDOM_MIG_SHIFT		= 3, /* when midely loaded: 1/2**3 = [-12.5%, +12.5%] */
-	LAVD_CPDOM_MIG_SHIFT_OL		= 4, /* when over-loaded:   1/2**4 = [-6.25%, +6.25%] */
-	LAVD_CPDOM_MIG_PROB_FT		= (LAVD_SYS_STAT_INTERVAL_NS / LAVD_SLICE_MAX_NS_DFL), /* roughly twice per interval */
+	LAVD_CPDOM_MIG_SHIFT		= 3, /* default when midely loaded: 1/2**3 = [-12.5%, +12.5%],
+						one less when under-loaded and one more when over-loaded */
 
 	LAVD_FUTEX_OP_INVALID		= -1,
+
+	LAVD_FRAME_PERIOD_MIN		= (4ULL * NSEC_PER_MSEC), /* 250 Hz */
+	LAVD_FRAME_PERIOD_MAX		= (34ULL * NSEC_PER_MSEC), /* ~30 Hz */
+	LAVD_FRAME_JITTER_SHIFT		= 3, /* 12.5% of the frame period */
+	LAVD_FRAME_CONF_MAX		= 16, /* maximum frame pacing confidence */
+	LAVD_FRAME_CONF_THRESH		= 8, /* confidence to be considered frame-paced */
+
+	LAVD_IO_RATIO_THRESH		= (LAVD_SCALE >> 1), /* 50% of sleeps are waiting for IO */
+
+	LAVD_PREEMPT_SHIFT_SPAN		= 2, /* preempt_shift +2 when idle, -2 when contended */
+	LAVD_PREEMPT_SHIFT_MAX		= 10,
+	LAVD_PREEMPT_QLEN_MAX		= 2, /* queued tasks per active CPU to be fully contended */
 };
 
 enum consts_flags {
@@ -125,6 +135,10 @@ enum consts_flags {
 	LAVD_FLAG_IDLE_CPU_PICKED	= (0x1 << 9), /* an idle CPU is picked at ops.select_cpu() */
 	LAVD_FLAG_KSOFTIRQD		= (0x1 << 10), /* ksoftirqd/%u thread */
 	LAVD_FLAG_WOKEN_BY_RT_DL	= (0x1 << 11), /* woken by a RT/DL task */
+	LAVD_FLAG_FRAME_PACED		= (0x1 << 12), /* task wakes up at a stable frame period */
+	LAVD_FLAG_IO_BOUND		= (0x1 << 13), /* task frequently blocks on IO */
+	LAVD_FLAG_STRICT		= (0x1 << 14), /* task runs in the strict priority band */
+	LAVD_FLAG_STRICT_CGRP		= (0x1 << 15), /* task is in a --strict-cgroup cgroup */
 };
 
 /*
@@ -172,6 +186,14 @@ struct task_ctx {
 	pid_t	pid;			/* pid for this task */
 	pid_t	waker_pid;		/* last waker's PID */
 	char	waker_comm[TASK_COMM_LEN + 1]; /* last waker's comm */
+	u64	last_wakeup_clk;	/* last time when a task was woken up */
+	u32	frame_period;		/* average wake-up interval of a frame-paced task */
+	u32	frame_jitter;		/* average deviation of the wake-up interval from frame_period */
+	u8	frame_conf;		/* confidence that the task is frame-paced [0, LAVD_FRAME_CONF_MAX] */
+	u16	io_ratio;		/* average ratio of sleeps waiting for IO [0, LAVD_SCALE] */
+	u8	node_id;		/* NUMA node where a task ran last time */
+	u64	node_clk;		/* when a task started running on node_id */
+	u64	avg_qdelay;		/* average queueing delay from runnable to running */
 } __attribute__((aligned(CACHELINE_SIZE)));
 
 /*
@@ -202,6 +224,7 @@ struct cpdom_ctx {
 	u32	cap_sum_active_cpus;		    /* the sum of capacities of active CPUs in this domain */
 	u32	cap_sum_temp;			    /* temp for cap_sum_active_cpus */
 	u32	dsq_consume_lat;		    /* latency to consume from dsq, shows how contended the dsq is */
+	u64	nr_sched;			    /* number of schedules on this domain so far */
 
 } __attribute__((aligned(CACHELINE_SIZE)));
 
@@ -285,6 +308,16 @@ struct cpu_ctx {
 	struct bpf_cpumask __kptr *tmp_t_mask;
 	struct bpf_cpumask __kptr *tmp_t2_mask;
 	struct bpf_cpumask __kptr *tmp_t3_mask;
+
+	volatile u32	nr_frame_paced;	/* number of frame-paced tasks scheduled */
+	volatile u32	nr_io_bound;	/* number of IO-bound tasks scheduled */
+	volatile u32	nr_xnode_pull;	/* number of wakees pulled to a remote waker's node */
+	volatile u32	nr_xnode_held;	/* number of wakees held on their node against a remote waker */
+	volatile u32	nr_strict;	/* number of strict-band tasks scheduled */
+	volatile u64	tot_strict_time; /* total time this CPU has spent running strict-band tasks */
+	u64		strict_debt;	/* strict-band time beyond this CPU's budget */
+	volatile u8	strict_throttled; /* is the strict band over this CPU's budget? */
+	volatile u8	is_throttled;	/* is this CPU thermally throttled? */
 } __attribute__((aligned(CACHELINE_SIZE)));
 
 extern const volatile u64	nr_llcs;	/* number of LLC domains */
@@ -294,13 +327,22 @@ extern volatile u64		nr_cpus_onln;	/* current number of online CPUs */
 extern const volatile u16	cpu_capacity[LAVD_CPU_ID_MAX];
 extern const volatile u8	cpu_big[LAVD_CPU_ID_MAX];
 extern const volatile u8	cpu_turbo[LAVD_CPU_ID_MAX];
+extern volatile u8		cpu_throttled[LAVD_CPU_ID_MAX];
 
 /* Logging helpers. */
 
 extern const volatile bool	no_wake_sync;
 extern const volatile bool	no_slice_boost;
+extern const volatile bool	no_frame_pacing;
+extern const volatile bool	io_boost;
+extern const volatile u64	numa_wake_hold_ns;
+extern const volatile u64	numa_wake_qdelay_ns;
+extern const volatile bool	thermal_aware;
 extern const volatile u8	verbose;
 
+extern const volatile u64	sys_stat_interval_ns;
+extern const volatile u8	mig_shift;
+
 #define debugln(fmt, ...)						\
 ({									\
 	if (verbose > 0)						\
@@ -423,6 +465,7 @@ extern struct bpf_cpumask __kptr *turbo_cpumask; /* CPU mask for turbo CPUs */
 extern struct bpf_cpumask __kptr *big_cpumask; /* CPU mask for big CPUs */
 extern struct bpf_cpumask __kptr *active_cpumask; /* CPU mask for active CPUs */
 extern struct bpf_cpumask __kptr *ovrflw_cpumask; /* CPU mask for overflow CPUs */
+extern struct bpf_cpumask __kptr *thr_cpumask; /* CPU mask for thermally throttled CPUs */
 
 /* Load balancer helpers. */
 
@@ -510,5 +553,8 @@ bool consume_task(u64 cpu_dsq_id, u64 cpdom_dsq_id);
 
 extern u64 cur_logical_clk;
 u64 calc_when_to_run(struct task_struct *p, task_ctx *taskc);
+void update_strict_cgrp(task_ctx *taskc, struct cgroup *cgrp);
+
+void inherit_task_hint(struct task_struct *p);
 
 #endif /* __LAVD_H */
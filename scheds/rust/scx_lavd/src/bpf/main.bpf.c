@@ -285,6 +285,21 @@ static u64 calc_time_slice(task_ctx *taskc, struct cpu_ctx *cpuc)
 		return taskc->slice;
 	}
 
+	/*
+	 * If the task is frame-paced, align its time slice to the detected
+	 * frame period: give it enough time to complete the work of a frame
+	 * in one go, but never more than the period itself, so the task is
+	 * done before the next frame (e.g., vsync) begins.
+	 */
+	if (test_task_flag(taskc, LAVD_FLAG_FRAME_PACED) &&
+	    !cpuc->nr_pinned_tasks) {
+		u64 s = taskc->avg_runtime + LAVD_SLICE_BOOST_BONUS;
+		taskc->slice = clamp(s, slice_min_ns,
+				     max(taskc->frame_period, slice_min_ns));
+		set_task_flag(taskc, LAVD_FLAG_SLICE_BOOST);
+		return taskc->slice;
+	}
+
 	/*
 	 * If the task's avg_runtime is greater than the regular time slice
 	 * (i.e., taskc->avg_runtime > sys_stat.slice), that means the task
@@ -358,6 +373,7 @@ static void update_stat_for_running(struct task_struct *p,
 {
 	u64 wait_period, interval;
 	struct cpu_ctx *prev_cpuc;
+	struct cpdom_ctx *cpdomc;
 
 	/*
 	 * Since this is the start of a new schedule for @p, we update run
@@ -377,6 +393,23 @@ static void update_stat_for_running(struct task_struct *p,
 		taskc->resched_interval = time_delta(now,
 						     taskc->last_running_clk);
 	}
+
+	/*
+	 * Track the NUMA node history and the queueing delay of @p for the
+	 * wake-affinity override of NUMA-remote wakers.
+	 */
+	if (numa_wake_hold_ns) {
+		cpdomc = MEMBER_VPTR(cpdom_ctxs, [cpuc->cpdom_id]);
+		if (cpdomc && (cpdomc->numa_id != taskc->node_id ||
+			       !taskc->node_clk)) {
+			taskc->node_id = cpdomc->numa_id;
+			taskc->node_clk = now;
+		}
+		if (taskc->last_runnable_clk > taskc->last_running_clk) {
+			taskc->avg_qdelay = calc_avg(taskc->avg_qdelay,
+				time_delta(now, taskc->last_runnable_clk));
+		}
+	}
 	taskc->prev_cpu_id = taskc->cpu_id;
 	taskc->cpu_id = cpuc->cpu_id;
 
@@ -420,7 +453,8 @@ static void update_stat_for_running(struct task_struct *p,
 	 * Update running task's information for preemption
 	 */
 	cpuc->flags = taskc->flags;
-	cpuc->lat_cri = taskc->lat_cri;
+	cpuc->lat_cri = test_task_flag(taskc, LAVD_FLAG_STRICT) ?
+			LAVD_LC_STRICT : taskc->lat_cri;
 	cpuc->running_clk = now;
 	cpuc->est_stopping_clk = get_est_stopping_clk(taskc, now);
 
@@ -433,6 +467,13 @@ static void update_stat_for_running(struct task_struct *p,
 	if (is_perf_cri(taskc))
 		cpuc->nr_perf_cri++;
 
+	if (test_task_flag(taskc, LAVD_FLAG_FRAME_PACED))
+		cpuc->nr_frame_paced++;
+	if (test_task_flag(taskc, LAVD_FLAG_IO_BOUND))
+		cpuc->nr_io_bound++;
+	if (test_task_flag(taskc, LAVD_FLAG_STRICT))
+		cpuc->nr_strict++;
+
 	prev_cpuc = get_cpu_ctx_id(taskc->prev_cpu_id);
 	if (prev_cpuc && prev_cpuc->cpdom_id != cpuc->cpdom_id)
 		cpuc->nr_x_migration++;
@@ -482,6 +523,8 @@ static void account_task_runtime(struct task_struct *p,
 	WRITE_ONCE(cpuc->tot_task_time, cpuc->tot_task_time + task_time);
 	WRITE_ONCE(cpuc->tot_svc_time, cpuc->tot_svc_time + svc_time);
 	WRITE_ONCE(cpuc->tot_sc_time, cpuc->tot_sc_time + sc_time);
+	if (test_task_flag(taskc, LAVD_FLAG_STRICT))
+		WRITE_ONCE(cpuc->tot_strict_time, cpuc->tot_strict_time + runtime);
 
 	taskc->acc_runtime += runtime;
 	taskc->svc_time += svc_time;
@@ -550,6 +593,39 @@ static void update_stat_for_refill(struct task_struct *p,
 	taskc->avg_runtime = calc_avg(taskc->avg_runtime, taskc->acc_runtime);
 }
 
+static int cgroup_throttled(struct task_struct *p, task_ctx *taskc, bool put_aside)
+{
+	struct cgroup *cgrp;
+	int ret, ret2;
+
+	/*
+	 * Under CPU bandwidth control using cpu.max, we should first check
+	 * if the cgroup is throttled or not. If not, we will go ahead.
+	 * Otherwise, we should put the task aside for later execution.
+	 * In the forced mode, we should enqueue the task even if the cgroup
+	 * is throttled (-EAGAIN).
+	 *
+	 * Note that we cannot use scx_bpf_task_cgroup() here because this can
+	 * be called only from ops.enqueue() and ops.dispatch().
+	 */
+	cgrp = bpf_cgroup_from_id(taskc->cgrp_id);
+	if (!cgrp) {
+		debugln("Failed to lookup a cgroup: %llu", taskc->cgrp_id);
+		return -ESRCH;
+	}
+
+	ret = scx_cgroup_bw_throttled(cgrp);
+	if ((ret == -EAGAIN) && put_aside) {
+		ret2 = scx_cgroup_bw_put_aside(p, (u64)taskc, p->scx.dsq_vtime, cgrp);
+		if (ret2) {
+			bpf_cgroup_release(cgrp);
+			return ret2;
+		}
+	}
+	bpf_cgroup_release(cgrp);
+	return ret;
+}
+
 s32 BPF_STRUCT_OPS(lavd_select_cpu, struct task_struct *p, s32 prev_cpu,
 		   u64 wake_flags)
 {
@@ -595,7 +671,15 @@ s32 BPF_STRUCT_OPS(lavd_select_cpu, struct task_struct *p, s32 prev_cpu,
 			goto out;
 		}
 
-		if (!queued_on_cpu(cpuc)) {
+		/*
+		 * Direct dispatch skips ops.enqueue(), so leave a task of a
+		 * throttled cgroup to ops.enqueue() to be put aside there.
+		 * Otherwise, cpu.max would be bypassed by every wakeup which
+		 * finds an idle CPU.
+		 */
+		if (!queued_on_cpu(cpuc) &&
+		    !(enable_cpu_bw && (p->pid != lavd_pid) &&
+		      (cgroup_throttled(p, ictx.taskc, false) == -EAGAIN))) {
 			p->scx.dsq_vtime = calc_when_to_run(p, ictx.taskc);
 			p->scx.slice = LAVD_SLICE_MAX_NS_DFL;
 			scx_bpf_dsq_insert(p, SCX_DSQ_LOCAL, p->scx.slice, 0);
@@ -608,39 +692,6 @@ out:
 	return cpu_id;
 }
 
-static int cgroup_throttled(struct task_struct *p, task_ctx *taskc, bool put_aside)
-{
-	struct cgroup *cgrp;
-	int ret, ret2;
-
-	/*
-	 * Under CPU bandwidth control using cpu.max, we should first check
-	 * if the cgroup is throttled or not. If not, we will go ahead.
-	 * Otherwise, we should put the task aside for later execution.
-	 * In the forced mode, we should enqueue the task even if the cgroup
-	 * is throttled (-EAGAIN).
-	 *
-	 * Note that we cannot use scx_bpf_task_cgroup() here because this can
-	 * be called only from ops.enqueue() and ops.dispatch().
-	 */
-	cgrp = bpf_cgroup_from_id(taskc->cgrp_id);
-	if (!cgrp) {
-		debugln("Failed to lookup a cgroup: %llu", taskc->cgrp_id);
-		return -ESRCH;
-	}
-
-	ret = scx_cgroup_bw_throttled(cgrp);
-	if ((ret == -EAGAIN) && put_aside) {
-		ret2 = scx_cgroup_bw_put_aside(p, (u64)taskc, p->scx.dsq_vtime, cgrp);
-		if (ret2) {
-			bpf_cgroup_release(cgrp);
-			return ret2;
-		}
-	}
-	bpf_cgroup_release(cgrp);
-	return ret;
-}
-
 void BPF_STRUCT_OPS(lavd_enqueue, struct task_struct *p, u64 enq_flags)
 {
 	struct cpu_ctx *cpuc, *cpuc_cur;
@@ -1113,6 +1164,57 @@ consume_out:
 	consume_prev(prev, taskc_prev, cpuc);
 }
 
+/*
+ * Detect a frame-paced task, which wakes up at a stable interval (e.g.,
+ * ~16ms for 60 Hz or ~8ms for 120 Hz), typical of games, video players,
+ * and compositors.
+ *
+ * The wake-up interval and its deviation are tracked using EWMAs. The
+ * detection confidence grows when the interval is within the supported
+ * frame period range and its jitter is small compared to the period, and
+ * it decays otherwise.
+ */
+static void update_frame_pacing(task_ctx *taskc, u64 now)
+{
+	u64 interval, dev;
+
+	if (no_frame_pacing)
+		return;
+
+	interval = time_delta(now, taskc->last_wakeup_clk);
+	taskc->last_wakeup_clk = now;
+
+	if (interval < LAVD_FRAME_PERIOD_MIN ||
+	    interval > LAVD_FRAME_PERIOD_MAX) {
+		if (taskc->frame_conf > 0)
+			taskc->frame_conf--;
+		goto out;
+	}
+
+	if (!taskc->frame_period)
+		taskc->frame_period = interval;
+
+	dev = interval > taskc->frame_period ?
+	      interval - taskc->frame_period :
+	      taskc->frame_period - interval;
+	taskc->frame_jitter = calc_avg32(taskc->frame_jitter, dev);
+	taskc->frame_period = calc_avg32(taskc->frame_period, interval);
+
+	if (taskc->frame_jitter <=
+	    (taskc->frame_period >> LAVD_FRAME_JITTER_SHIFT)) {
+		if (taskc->frame_conf < LAVD_FRAME_CONF_MAX)
+			taskc->frame_conf++;
+	} else if (taskc->frame_conf > 0) {
+		taskc->frame_conf--;
+	}
+
+out:
+	if (taskc->frame_conf >= LAVD_FRAME_CONF_THRESH)
+		set_task_flag(taskc, LAVD_FLAG_FRAME_PACED);
+	else
+		reset_task_flag(taskc, LAVD_FLAG_FRAME_PACED);
+}
+
 void BPF_STRUCT_OPS(lavd_runnable, struct task_struct *p, u64 enq_flags)
 {
 	struct task_struct *waker;
@@ -1141,6 +1243,12 @@ void BPF_STRUCT_OPS(lavd_runnable, struct task_struct *p, u64 enq_flags)
 	if (!(enq_flags & SCX_ENQ_WAKEUP))
 		return;
 
+	/*
+	 * Track the wake-up interval to detect frame-paced tasks.
+	 */
+	now = scx_bpf_now();
+	update_frame_pacing(p_taskc, now);
+
 	/*
 	 * Filter out unrelated tasks. We keep track of tasks under the same
 	 * parent process to confine the waker-wakee relationship within
@@ -1179,7 +1287,6 @@ void BPF_STRUCT_OPS(lavd_runnable, struct task_struct *p, u64 enq_flags)
 	/*
 	 * Update wake frequency.
 	 */
-	now = scx_bpf_now();
 	interval = time_delta(now, READ_ONCE(waker_taskc->last_runnable_clk));
 	if (interval >= LAVD_LC_WAKE_INTERVAL_MIN) {
 		WRITE_ONCE(waker_taskc->wake_freq,
@@ -1292,7 +1399,8 @@ void BPF_STRUCT_OPS(lavd_tick, struct task_struct *p)
 	 * Under the CPU bandwidth control with cpu.max, check if the cgroup
 	 * is throttled before executing the task.
 	 */
-	if (enable_cpu_bw && (cgroup_throttled(p, taskc, false) == -EAGAIN)) {
+	if (enable_cpu_bw && (p->pid != lavd_pid) &&
+	    (cgroup_throttled(p, taskc, false) == -EAGAIN)) {
 		preempt_at_tick(p, cpuc);
 		return;
 	}
@@ -1322,6 +1430,29 @@ void BPF_STRUCT_OPS(lavd_stopping, struct task_struct *p, bool runnable)
 	update_stat_for_stopping(p, taskc, cpuc);
 }
 
+/*
+ * Detect an IO-bound task, which goes to sleep mostly waiting for IO
+ * completion. Track how often a task sleeps in iowait using a moving
+ * average and classify the task as IO-bound when the ratio is over
+ * LAVD_IO_RATIO_THRESH.
+ */
+static void update_io_bound(struct task_struct *p, task_ctx *taskc)
+{
+	u32 in_iowait;
+
+	if (!io_boost)
+		return;
+
+	in_iowait = BPF_CORE_READ_BITFIELD(p, in_iowait);
+	taskc->io_ratio = calc_avg32(taskc->io_ratio,
+				     in_iowait ? LAVD_SCALE : 0);
+
+	if (taskc->io_ratio >= LAVD_IO_RATIO_THRESH)
+		set_task_flag(taskc, LAVD_FLAG_IO_BOUND);
+	else
+		reset_task_flag(taskc, LAVD_FLAG_IO_BOUND);
+}
+
 void BPF_STRUCT_OPS(lavd_quiescent, struct task_struct *p, u64 deq_flags)
 {
 	struct cpu_ctx *cpuc;
@@ -1365,6 +1496,11 @@ void BPF_STRUCT_OPS(lavd_quiescent, struct task_struct *p, u64 deq_flags)
 		taskc->wait_freq = calc_avg_freq(taskc->wait_freq, interval);
 		taskc->last_quiescent_clk = now;
 	}
+
+	/*
+	 * Track whether the task is going to sleep waiting for IO.
+	 */
+	update_io_bound(p, taskc);
 }
 
 static void cpu_ctx_init_online(struct cpu_ctx *cpuc, u32 cpu_id, u64 now)
@@ -1689,9 +1825,13 @@ s32 BPF_STRUCT_OPS_SLEEPABLE(lavd_init_task, struct task_struct *p,
 	taskc->pinned_cpu_id = -ENOENT;
 	taskc->pid = p->pid;
 	taskc->cgrp_id = args->cgroup->kn->id;
+	update_strict_cgrp(taskc, args->cgroup);
 
 	set_on_core_type(taskc, p->cpus_ptr);
 
+	if (args->fork)
+		inherit_task_hint(p);
+
 	return 0;
 }
 
@@ -1789,6 +1929,10 @@ static int init_cpumasks(void)
 	err = calloc_cpumask(&big_cpumask);
 	if (err)
 		goto out;
+
+	err = calloc_cpumask(&thr_cpumask);
+	if (err)
+		goto out;
 out:
 	bpf_rcu_read_unlock();
 	return err;
@@ -2039,6 +2183,7 @@ void BPF_STRUCT_OPS(lavd_cgroup_move, struct task_struct *p,
 	if (!taskc)
 	       scx_bpf_error("Failed to get a task context: %d", p->pid);
 	taskc->cgrp_id = to->kn->id;
+	update_strict_cgrp(taskc, to);
 }
 
 void BPF_STRUCT_OPS(lavd_cgroup_set_bandwidth, struct cgroup *cgrp,
@@ -33,6 +33,9 @@ const volatile u8	cpu_big[LAVD_CPU_ID_MAX];
 /* Is a CPU a turbo core? */
 const volatile u8	cpu_turbo[LAVD_CPU_ID_MAX];
 
+/* Is a CPU thermally throttled? Updated by the user space. */
+volatile u8		cpu_throttled[LAVD_CPU_ID_MAX];
+
 
 /*
  * Compute domain properties
@@ -440,7 +443,7 @@ int do_core_compaction(void)
 				/* This CPU is in the overflow set. */
 
 				if ((bpf_get_prandom_u32() %
-					    LAVD_CC_CPU_PIN_INTERVAL_DIV)) {
+					    (LAVD_CC_CPU_PIN_INTERVAL / sys_stat_interval_ns))) {
 					/*
 					 * This is the case when a CPU belongs to the
 					 * overflow set even though that CPU was not an
@@ -833,6 +836,41 @@ unlock_out:
 	return err;
 }
 
+__weak
+int update_thr_cpumask(void)
+{
+	struct bpf_cpumask *thr;
+	struct cpu_ctx *cpuc;
+	int cpu;
+
+	bpf_rcu_read_lock();
+	thr = thr_cpumask;
+	if (!thr) {
+		bpf_rcu_read_unlock();
+		return -ENOMEM;
+	}
+
+	bpf_for(cpu, 0, nr_cpu_ids) {
+		if (cpu >= LAVD_CPU_ID_MAX)
+			break;
+
+		cpuc = get_cpu_ctx_id(cpu);
+		if (!cpuc)
+			continue;
+
+		if (READ_ONCE(cpu_throttled[cpu])) {
+			bpf_cpumask_set_cpu(cpu, thr);
+			WRITE_ONCE(cpuc->is_throttled, true);
+		} else {
+			bpf_cpumask_clear_cpu(cpu, thr);
+			WRITE_ONCE(cpuc->is_throttled, false);
+		}
+	}
+
+	bpf_rcu_read_unlock();
+	return 0;
+}
+
 __hidden
 int update_cpuperf_target(struct cpu_ctx *cpuc)
 {
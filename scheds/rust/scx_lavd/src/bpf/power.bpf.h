@@ -8,6 +8,7 @@
 int do_core_compaction(void);
 int update_thr_perf_cri(void);
 int reinit_active_cpumask_for_performance(void);
+int update_thr_cpumask(void);
 bool is_perf_cri(task_ctx *taskc);
 
 extern bool			have_little_core;
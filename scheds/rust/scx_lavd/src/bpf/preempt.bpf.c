@@ -67,13 +67,37 @@ static bool can_x_kick_cpu2(struct preemption_info *prm_x,
 static void init_prm_by_task(struct preemption_info *prm_task,
 			     task_ctx *taskc, u64 now)
 {
+	prm_task->cpuc = NULL;
+
+	/*
+	 * A strict-band task outranks any regular task no matter how long
+	 * it is expected to run.
+	 */
+	if (test_task_flag(taskc, LAVD_FLAG_STRICT)) {
+		prm_task->est_stopping_clk = 0;
+		prm_task->lat_cri = LAVD_LC_STRICT;
+		return;
+	}
+
 	prm_task->est_stopping_clk = get_est_stopping_clk(taskc, now);
 	prm_task->lat_cri = taskc->lat_cri;
-	prm_task->cpuc = NULL;
 }
 
 static bool is_worth_kick_other_task(task_ctx *taskc)
 {
+	/*
+	 * A strict-band task always preempts regular tasks. Its CPU time is
+	 * bounded by the band's budget rather than by its greediness.
+	 */
+	if (test_task_flag(taskc, LAVD_FLAG_STRICT))
+		return true;
+
+	/*
+	 * Don't even try to perform expensive preemption for greedy tasks.
+	 */
+	if (test_task_flag(taskc, LAVD_FLAG_IS_GREEDY))
+		return false;
+
 	/*
 	 * Preemption is not free. It is expensive involving context switching,
 	 * etc. Hence, we first judiciously check whether it is worth trying to
@@ -344,11 +368,9 @@ void try_find_and_kick_victim_cpu(struct task_struct *p,
 	u64 now, dur, cpdom_id, new_slice = 0;
 
 	/*
-	 * Don't even try to perform expensive preemption for greedy tasks.
-	 * And, check if it is worth to try to kick other CPU.
+	 * Check if it is worth to try to kick other CPU.
 	 */
-	if (test_task_flag(taskc, LAVD_FLAG_IS_GREEDY) ||
-	    !is_worth_kick_other_task(taskc))
+	if (!is_worth_kick_other_task(taskc))
 		return;
 
 	/*
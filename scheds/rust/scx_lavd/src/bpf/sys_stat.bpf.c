@@ -16,6 +16,7 @@
 
 struct sys_stat		__weak	sys_stat;
 const volatile u8	__weak preempt_shift;
+const volatile bool	__weak no_adaptive_preempt;
 volatile u64		__weak performance_mode_ns;
 volatile u64		__weak balanced_mode_ns;
 volatile u64		__weak powersave_mode_ns;
@@ -25,6 +26,17 @@ extern volatile bool		__weak no_core_compaction;
 extern volatile bool		__weak reinit_cpumask_for_performance;
 const volatile bool	__weak is_autopilot_on;
 
+/*
+ * Share of each CPU's time the strict priority band may use.
+ */
+const volatile u8	strict_budget_pct = 20;
+
+/*
+ * Interval of the system statistics update, scaled by the number of CPUs
+ * from userspace.
+ */
+const volatile u64	sys_stat_interval_ns = LAVD_SYS_STAT_INTERVAL_NS;
+
 int do_autopilot(void);
 u32 calc_avg32(u32 old_val, u32 new_val);
 u64 calc_avg(u64 old_val, u64 new_val);
@@ -65,6 +77,12 @@ struct sys_stat_ctx {
 	u32		nr_big;
 	u32		nr_pc_on_big;
 	u32		nr_lc_on_big;
+	u32		nr_frame_paced;
+	u32		nr_io_bound;
+	u32		nr_strict;
+	u32		nr_strict_throttled;
+	u32		nr_xnode_pull;
+	u32		nr_xnode_held;
 	u64		min_perf_cri;
 	u64		avg_perf_cri;
 	u64		max_perf_cri;
@@ -76,6 +94,27 @@ struct sys_stat_ctx {
 
 static struct sys_stat_ctx ctx;
 
+/*
+ * The strict priority band preempts regular tasks unconditionally, so bound
+ * its time on @cpuc to strict_budget_pct of the CPU's time over the last
+ * interval of @duration. The budget is per CPU as a strict task pinned to a
+ * CPU could otherwise starve that CPU while staying far below a share of the
+ * whole system. Any usage beyond the budget is carried over as debt and the
+ * band stays throttled on the CPU until the debt is paid back, which keeps
+ * the long-term usage within the budget. Return true if throttled.
+ */
+static bool update_strict_throttle(struct cpu_ctx *cpuc, u64 duration)
+{
+	u64 budget = (duration * strict_budget_pct) / 100;
+	u64 debt = cpuc->strict_debt + cpuc->tot_strict_time;
+
+	cpuc->tot_strict_time = 0;
+	cpuc->strict_debt = debt > budget ? debt - budget : 0;
+	WRITE_ONCE(cpuc->strict_throttled, cpuc->strict_debt > 0);
+
+	return cpuc->strict_debt > 0;
+}
+
 static void init_sys_stat_ctx(void)
 {
 	struct sys_stat_ctx *c = &ctx;
@@ -163,6 +202,9 @@ static void collect_sys_stat(void)
 		c->tot_svc_time += cpuc->tot_svc_time;
 		cpuc->tot_svc_time = 0;
 
+		if (update_strict_throttle(cpuc, c->duration))
+			c->nr_strict_throttled++;
+
 		/*
 		 * If the CPU is in an idle state (i.e., idle_start_clk is
 		 * non-zero), accumulate the current idle period so far.
@@ -236,6 +278,7 @@ static void collect_sys_stat(void)
 	 */
 	bpf_for(cpu, 0, nr_cpu_ids) {
 		struct cpu_ctx *cpuc = get_cpu_ctx_id(cpu);
+		struct cpdom_ctx *cpdomc;
 		if (!cpuc) {
 			c->compute_total = 0;
 			break;
@@ -267,6 +310,21 @@ static void collect_sys_stat(void)
 		c->nr_x_migration += cpuc->nr_x_migration;
 		cpuc->nr_x_migration = 0;
 
+		c->nr_frame_paced += cpuc->nr_frame_paced;
+		cpuc->nr_frame_paced = 0;
+
+		c->nr_io_bound += cpuc->nr_io_bound;
+		cpuc->nr_io_bound = 0;
+
+		c->nr_strict += cpuc->nr_strict;
+		cpuc->nr_strict = 0;
+
+		c->nr_xnode_pull += cpuc->nr_xnode_pull;
+		cpuc->nr_xnode_pull = 0;
+
+		c->nr_xnode_held += cpuc->nr_xnode_held;
+		cpuc->nr_xnode_held = 0;
+
 		/*
 		 * Accumulate task's latency criticlity information.
 		 *
@@ -277,6 +335,14 @@ static void collect_sys_stat(void)
 		c->sum_lat_cri += cpuc->sum_lat_cri;
 		cpuc->sum_lat_cri = 0;
 
+		/*
+		 * Keep a running count per compute domain for the per-cluster
+		 * placement statistics.
+		 */
+		cpdomc = MEMBER_VPTR(cpdom_ctxs, [cpuc->cpdom_id]);
+		if (cpdomc)
+			cpdomc->nr_sched += cpuc->nr_sched;
+
 		c->nr_sched += cpuc->nr_sched;
 		cpuc->nr_sched = 0;
 
@@ -331,6 +397,47 @@ static void collect_sys_stat(void)
 	}
 }
 
+static void update_preempt_aggr(void)
+{
+	u64 qlen_aggr, aggr, nr_active;
+	s32 shift;
+
+	if (no_adaptive_preempt) {
+		sys_stat.preempt_aggr = 0;
+		sys_stat.preempt_shift = preempt_shift;
+		return;
+	}
+
+	/*
+	 * Preemption pays off when latency-critical tasks have to wait for a
+	 * CPU. When the system is mostly idle, they will find an idle CPU
+	 * anyway, so only the most latency-critical ones should kick others.
+	 * When the system is contended, more of them should. The
+	 * aggressiveness is the higher of the CPU utilization and the run
+	 * queue pressure, where LAVD_PREEMPT_QLEN_MAX queued tasks per active
+	 * CPU counts as fully contended.
+	 */
+	nr_active = max(sys_stat.nr_active, 1);
+	qlen_aggr = (sys_stat.nr_queued_task << LAVD_SHIFT) /
+		    (nr_active * LAVD_PREEMPT_QLEN_MAX);
+	aggr = min(max(sys_stat.avg_util, qlen_aggr), LAVD_SCALE);
+	sys_stat.preempt_aggr = aggr;
+
+	/*
+	 * Map the aggressiveness to [preempt_shift + LAVD_PREEMPT_SHIFT_SPAN,
+	 * preempt_shift - LAVD_PREEMPT_SHIFT_SPAN] so that preempt_shift is
+	 * used at 50% aggressiveness. A smaller shift lowers the latency
+	 * criticality threshold for kicking.
+	 */
+	shift = preempt_shift + LAVD_PREEMPT_SHIFT_SPAN -
+		(s32)((2 * LAVD_PREEMPT_SHIFT_SPAN * aggr) >> LAVD_SHIFT);
+	if (shift < 0)
+		shift = 0;
+	if (shift > LAVD_PREEMPT_SHIFT_MAX)
+		shift = LAVD_PREEMPT_SHIFT_MAX;
+	sys_stat.preempt_shift = shift;
+}
+
 static void calc_sys_stat(void)
 {
 	struct sys_stat_ctx *c = &ctx;
@@ -344,6 +451,7 @@ static void calc_sys_stat(void)
 	c->duration_total = c->duration * nr_cpus_onln;
 	c->compute_total = time_delta(c->duration_total, c->idle_total);
 	c->cur_util = (c->compute_total << LAVD_SHIFT) / c->duration_total;
+	WRITE_ONCE(sys_stat.strict_throttled, c->nr_strict_throttled);
 
 	/*
 	 * Calculate the scaled CPU utilization that includes everything
@@ -410,8 +518,9 @@ static void calc_sys_stat(void)
 	sys_stat.avg_sc_util = calc_asym_avg(sys_stat.avg_sc_util, c->cur_sc_util);
 	sys_stat.max_lat_cri = calc_avg32(sys_stat.max_lat_cri, c->max_lat_cri);
 	sys_stat.avg_lat_cri = calc_avg32(sys_stat.avg_lat_cri, c->avg_lat_cri);
+	update_preempt_aggr();
 	sys_stat.thr_lat_cri = sys_stat.max_lat_cri - ((sys_stat.max_lat_cri -
-				sys_stat.avg_lat_cri) >> preempt_shift);
+				sys_stat.avg_lat_cri) >> sys_stat.preempt_shift);
 
 	if (have_little_core) {
 		sys_stat.min_perf_cri =
@@ -431,7 +540,7 @@ static void calc_sys_stat(void)
 	 * Half the statistics every minitue so the statistics hold the
 	 * information on a few minutes.
 	 */
-	if (cnt++ == LAVD_SYS_STAT_DECAY_TIMES) {
+	if (cnt++ >= LAVD_SYS_STAT_DECAY_PERIOD / sys_stat_interval_ns) {
 		cnt = 0;
 		sys_stat.nr_sched >>= 1;
 		sys_stat.nr_preempt >>= 1;
@@ -441,6 +550,11 @@ static void calc_sys_stat(void)
 		sys_stat.nr_big >>= 1;
 		sys_stat.nr_pc_on_big >>= 1;
 		sys_stat.nr_lc_on_big >>= 1;
+		sys_stat.nr_frame_paced >>= 1;
+		sys_stat.nr_io_bound >>= 1;
+		sys_stat.nr_strict >>= 1;
+		sys_stat.nr_xnode_pull >>= 1;
+		sys_stat.nr_xnode_held >>= 1;
 
 		__sync_fetch_and_sub(&performance_mode_ns, performance_mode_ns/2);
 		__sync_fetch_and_sub(&balanced_mode_ns, balanced_mode_ns/2);
@@ -455,6 +569,11 @@ static void calc_sys_stat(void)
 	sys_stat.nr_big += c->nr_big;
 	sys_stat.nr_pc_on_big += c->nr_pc_on_big;
 	sys_stat.nr_lc_on_big += c->nr_lc_on_big;
+	sys_stat.nr_frame_paced += c->nr_frame_paced;
+	sys_stat.nr_io_bound += c->nr_io_bound;
+	sys_stat.nr_strict += c->nr_strict;
+	sys_stat.nr_xnode_pull += c->nr_xnode_pull;
+	sys_stat.nr_xnode_held += c->nr_xnode_held;
 
 	update_power_mode_time();
 }
@@ -514,6 +633,12 @@ int update_sys_stat(void)
 		reinit_active_cpumask_for_performance();
 	}
 
+	/*
+	 * Reflect the thermal throttling state reported by the user space.
+	 */
+	if (thermal_aware)
+		update_thr_cpumask();
+
 	/*
 	 * Update time slice and performance criticality threshold.
 	 */
@@ -535,7 +660,7 @@ static int update_timer_cb(void *map, int *key, struct bpf_timer *timer)
 
 	update_sys_stat();
 
-	err = bpf_timer_start(timer, LAVD_SYS_STAT_INTERVAL_NS, 0);
+	err = bpf_timer_start(timer, sys_stat_interval_ns, 0);
 	if (err)
 		scx_bpf_error("Failed to arm update timer");
 
@@ -570,7 +695,7 @@ s32 init_sys_stat(u64 now)
 	}
 	bpf_timer_init(timer, &update_timer, CLOCK_BOOTTIME);
 	bpf_timer_set_callback(timer, update_timer_cb);
-	err = bpf_timer_start(timer, LAVD_SYS_STAT_INTERVAL_NS, 0);
+	err = bpf_timer_start(timer, sys_stat_interval_ns, 0);
 	if (err) {
 		scx_bpf_error("Failed to arm update timer");
 		return err;
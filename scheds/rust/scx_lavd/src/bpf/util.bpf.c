@@ -25,6 +25,7 @@ private(LAVD) struct bpf_cpumask __kptr *turbo_cpumask; /* CPU mask for turbo CP
 private(LAVD) struct bpf_cpumask __kptr *big_cpumask; /* CPU mask for big CPUs */
 private(LAVD) struct bpf_cpumask __kptr *active_cpumask; /* CPU mask for active CPUs */
 private(LAVD) struct bpf_cpumask __kptr *ovrflw_cpumask; /* CPU mask for overflow CPUs */
+private(LAVD) struct bpf_cpumask __kptr *thr_cpumask; /* CPU mask for thermally throttled CPUs */
 
 const volatile u64	nr_llcs;	/* number of LLC domains */
 const volatile u64	__nr_cpu_ids;	/* maximum CPU IDs */
@@ -42,6 +43,11 @@ volatile bool		no_freq_scaling;
 
 const volatile bool	no_wake_sync;
 const volatile bool	no_slice_boost;
+const volatile bool	no_frame_pacing;
+const volatile bool	io_boost;
+const volatile u64	numa_wake_hold_ns;
+const volatile u64	numa_wake_qdelay_ns;
+const volatile bool	thermal_aware;
 const volatile bool	per_cpu_dsq;
 const volatile bool	enable_cpu_bw;
 const volatile bool	is_autopilot_on;
@@ -18,6 +18,11 @@ extern volatile bool		no_freq_scaling;
 
 extern const volatile bool	no_wake_sync;
 extern const volatile bool	no_slice_boost;
+extern const volatile bool	no_frame_pacing;
+extern const volatile bool	io_boost;
+extern const volatile u64	numa_wake_hold_ns;
+extern const volatile u64	numa_wake_qdelay_ns;
+extern const volatile bool	thermal_aware;
 extern const volatile bool	per_cpu_dsq;
 extern const volatile bool	enable_cpu_bw;
 extern const volatile bool	is_autopilot_on;
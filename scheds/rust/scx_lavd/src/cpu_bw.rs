@@ -0,0 +1,151 @@
+// SPDX-License-Identifier: GPL-2.0
+//
+// Copyright (c) 2026 Valve Corporation.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+// Measure the usage over a fixed interval so that short bursts allowed
+// within a period don't show up as quota violations.
+const UPDATE_INTERVAL: Duration = Duration::from_secs(5);
+
+// Walking the whole hierarchy is expensive on hosts with many cgroups, so
+// only the cgroups known to have a cpu.max limit are re-read on update and
+// newly limited cgroups are looked for less often.
+const RESCAN_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct CgroupBw {
+    quota_us: u64,
+    period_us: u64,
+    usage_us: u64,
+}
+
+/// Track how closely the cgroups with a cpu.max limit stick to their quota
+/// under --enable-cpu-bw, i.e. the CPU time they actually consumed relative
+/// to what their quota allows.
+#[derive(Debug)]
+pub struct CpuBwMonitor {
+    cgroups: BTreeMap<PathBuf, CgroupBw>,
+    usage_pcts: Vec<f64>,
+    updated_at: Instant,
+    scanned_at: Instant,
+}
+
+impl CpuBwMonitor {
+    pub fn new() -> Self {
+        let mut cgroups = BTreeMap::new();
+        scan_cgroups(Path::new(CGROUP_ROOT), &mut cgroups);
+
+        Self {
+            cgroups,
+            usage_pcts: vec![],
+            updated_at: Instant::now(),
+            scanned_at: Instant::now(),
+        }
+    }
+
+    /// Re-read the usage of the limited cgroups and recompute how much of
+    /// their quota they used since the last update. Cgroups which got a
+    /// limit are picked up by the next rescan and reported from the update
+    /// after it on.
+    pub fn update(&mut self) {
+        let elapsed = self.updated_at.elapsed();
+        if elapsed < UPDATE_INTERVAL {
+            return;
+        }
+        self.updated_at = Instant::now();
+
+        let cgroups = if self.scanned_at.elapsed() >= RESCAN_INTERVAL {
+            self.scanned_at = Instant::now();
+            let mut cgroups = BTreeMap::new();
+            scan_cgroups(Path::new(CGROUP_ROOT), &mut cgroups);
+            cgroups
+        } else {
+            self.cgroups
+                .keys()
+                .filter_map(|path| read_cgroup_bw(path).map(|bw| (path.clone(), bw)))
+                .collect()
+        };
+
+        self.usage_pcts.clear();
+        for (path, cur) in cgroups.iter() {
+            let Some(prev) = self.cgroups.get(path) else {
+                continue;
+            };
+            if prev.quota_us != cur.quota_us || prev.period_us != cur.period_us {
+                continue;
+            }
+
+            let allowed_us =
+                cur.quota_us as f64 * elapsed.as_micros() as f64 / cur.period_us as f64;
+            let used_us = cur.usage_us.saturating_sub(prev.usage_us) as f64;
+            self.usage_pcts.push(100.0 * used_us / allowed_us);
+        }
+
+        self.cgroups = cgroups;
+    }
+
+    /// Number of cgroups with a cpu.max limit.
+    pub fn nr_cgroups(&self) -> u32 {
+        self.cgroups.len() as u32
+    }
+
+    /// Average % of the quota used by the limited cgroups.
+    pub fn avg_usage_pct(&self) -> f64 {
+        match self.usage_pcts.len() {
+            0 => 0.0,
+            n => self.usage_pcts.iter().sum::<f64>() / n as f64,
+        }
+    }
+
+    /// Highest % of the quota used by a limited cgroup. Above 100% means
+    /// that the limit isn't fully enforced.
+    pub fn max_usage_pct(&self) -> f64 {
+        self.usage_pcts.iter().copied().fold(0.0, f64::max)
+    }
+}
+
+fn scan_cgroups(dir: &Path, cgroups: &mut BTreeMap<PathBuf, CgroupBw>) {
+    if let Some(bw) = read_cgroup_bw(dir) {
+        cgroups.insert(dir.to_path_buf(), bw);
+    }
+
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            scan_cgroups(&entry.path(), cgroups);
+        }
+    }
+}
+
+fn read_cgroup_bw(dir: &Path) -> Option<CgroupBw> {
+    // e.g. "50000 100000", or "max 100000" if there's no limit.
+    let max = fs::read_to_string(dir.join("cpu.max")).ok()?;
+    let mut it = max.split_whitespace();
+    let quota_us = it.next()?.parse::<u64>().ok()?;
+    let period_us = it.next()?.parse::<u64>().ok().filter(|v| *v > 0)?;
+
+    let stat = fs::read_to_string(dir.join("cpu.stat")).ok()?;
+    let usage_us = stat
+        .lines()
+        .find_map(|line| line.strip_prefix("usage_usec "))
+        .and_then(|v| v.trim().parse::<u64>().ok())?;
+
+    Some(CgroupBw {
+        quota_us,
+        period_us,
+        usage_us,
+    })
+}
@@ -32,12 +32,14 @@ pub struct CpuId {
     //
     // - numa_adx: a NUMA domain within a system
     // - pd_adx: a performance domain (CPU frequency domain) within a system
+    // - cluster_adx: a CPU cluster within a system (ARM big.LITTLE only)
     //   - llc_rdx: an LLC domain (CCX) under a NUMA domain
     //   - llc_kernel_id: physical LLC domain ID provided by the kernel
     //     - core_rdx: a core under a LLC domain
     //       - cpu_rdx: a CPU under a core
     pub numa_adx: usize,
     pub pd_adx: usize,
+    pub cluster_adx: usize,
     pub llc_adx: usize,
     pub llc_rdx: usize,
     pub llc_kernel_id: usize,
@@ -58,6 +60,7 @@ pub struct ComputeDomainId {
     pub llc_adx: usize,
     pub llc_rdx: usize,
     pub llc_kernel_id: usize,
+    pub cluster_adx: usize,
     pub is_big: bool,
 }
 
@@ -93,6 +96,7 @@ pub struct CpuOrder {
     pub smt_enabled: bool,
     pub has_biglittle: bool,
     pub has_energy_model: bool,
+    pub use_clusters: bool,
 }
 
 impl CpuOrder {
@@ -123,6 +127,7 @@ impl CpuOrder {
             smt_enabled: ctx.smt_enabled,
             has_biglittle: ctx.has_biglittle,
             has_energy_model: ctx.has_energy_model,
+            use_clusters: ctx.use_clusters,
         })
     }
 }
@@ -134,6 +139,8 @@ struct CpuOrderCtx {
     smt_enabled: bool,
     has_biglittle: bool,
     has_energy_model: bool,
+    use_clusters: bool,
+    clusters: BTreeMap<usize, (usize, bool)>, // cpu_adx -> (cluster_adx, is_big)
 }
 
 impl CpuOrderCtx {
@@ -148,6 +155,12 @@ impl CpuOrderCtx {
         let has_biglittle = topo.has_little_cores();
         let has_energy_model = em.is_ok();
 
+        // On ARM big.LITTLE, the CPUs of a cluster share the same core type
+        // and frequency domain, and there can be more than two core types
+        // (e.g., little/mid/prime). Classify the cores per cluster.
+        let clusters = Self::get_clusters(&topo);
+        let use_clusters = cfg!(target_arch = "aarch64") && has_biglittle && !clusters.is_empty();
+
         debug!("{:#?}", topo);
         debug!("{:#?}", em);
 
@@ -157,9 +170,47 @@ impl CpuOrderCtx {
             smt_enabled,
             has_biglittle,
             has_energy_model,
+            use_clusters,
+            clusters,
         })
     }
 
+    /// Map each CPU to its cluster and decide whether the cluster is big.
+    /// A cluster is big when its capacity (the highest one of its CPUs) is
+    /// closer to the most capable cluster than to the least capable one.
+    /// This keeps the mid clusters of three-tier SoCs on the big side and
+    /// is not skewed by how many CPUs each cluster has, unlike comparing
+    /// against the average capacity. Returns an empty map when the cluster
+    /// topology is not available.
+    fn get_clusters(topo: &Topology) -> BTreeMap<usize, (usize, bool)> {
+        let mut cluster_ids: BTreeMap<(usize, isize), usize> = BTreeMap::new();
+        let mut cluster_caps: BTreeMap<usize, usize> = BTreeMap::new();
+        let mut cpu_clusters: BTreeMap<usize, usize> = BTreeMap::new();
+
+        for (&cpu_adx, cpu) in topo.all_cpus.iter() {
+            if cpu.cluster_id < 0 {
+                return BTreeMap::new();
+            }
+            let nr_clusters = cluster_ids.len();
+            let cluster_adx = *cluster_ids
+                .entry((cpu.package_id, cpu.cluster_id))
+                .or_insert(nr_clusters);
+            let cap = cluster_caps.entry(cluster_adx).or_insert(0);
+            *cap = (*cap).max(cpu.cpu_capacity);
+            cpu_clusters.insert(cpu_adx, cluster_adx);
+        }
+
+        let max_cap = cluster_caps.values().copied().max().unwrap_or(0);
+        let min_cap = cluster_caps.values().copied().min().unwrap_or(0);
+        cpu_clusters
+            .into_iter()
+            .map(|(cpu_adx, cluster_adx)| {
+                let is_big = cluster_caps[&cluster_adx] * 2 > max_cap + min_cap;
+                (cpu_adx, (cluster_adx, is_big))
+            })
+            .collect()
+    }
+
     /// Build a CPU preference order based on its optimization target
     fn build_topo_order(&self, prefer_powersave: bool) -> Option<Vec<CpuId>> {
         let mut cpu_ids = Vec::new();
@@ -171,10 +222,18 @@ impl CpuOrderCtx {
                 for (core_rdx, (_core_adx, core)) in llc.cores.iter().enumerate() {
                     for (cpu_rdx, (cpu_adx, cpu)) in core.cpus.iter().enumerate() {
                         let cpu_adx = *cpu_adx;
-                        let pd_adx = Self::get_pd_id(&self.em, cpu_adx, llc_adx);
+                        let (cluster_adx, big_core) = match self.use_clusters {
+                            true => self.clusters[&cpu_adx],
+                            false => (0, cpu.core_type != CoreType::Little),
+                        };
+                        let pd_adx = match self.use_clusters {
+                            true => Self::get_pd_id(&self.em, cpu_adx, cluster_adx),
+                            false => Self::get_pd_id(&self.em, cpu_adx, llc_adx),
+                        };
                         let cpu_id = CpuId {
                             numa_adx,
                             pd_adx,
+                            cluster_adx,
                             llc_adx,
                             llc_rdx,
                             core_rdx,
@@ -183,7 +242,7 @@ impl CpuOrderCtx {
                             smt_level: cpu.smt_level,
                             cache_size: cpu.cache_size,
                             cpu_cap: cpu.cpu_capacity,
-                            big_core: cpu.core_type != CoreType::Little,
+                            big_core,
                             turbo_core: cpu.core_type == CoreType::Big { turbo: true },
                             cpu_sibling: smt_siblings[cpu_adx] as usize,
                             llc_kernel_id: llc.kernel_id,
@@ -283,6 +342,7 @@ impl CpuOrderCtx {
 
         // Creat a compute domain map, where a compute domain is a CPUs that
         // are under the same node and LLC (virtual and physical) and have the same core type.
+        // On ARM big.LITTLE, a compute domain also does not span clusters.
         let mut cpdom_id = 0;
         let mut cpdom_map: BTreeMap<ComputeDomainId, ComputeDomain> = BTreeMap::new();
         let mut cpdom_types: BTreeMap<usize, bool> = BTreeMap::new();
@@ -292,6 +352,7 @@ impl CpuOrderCtx {
                 llc_adx: cpu_id.llc_adx,
                 llc_rdx: cpu_id.llc_rdx,
                 llc_kernel_id: cpu_id.llc_kernel_id,
+                cluster_adx: cpu_id.cluster_adx,
                 is_big: cpu_id.big_core,
             };
             let value = cpdom_map.entry(key.clone()).or_insert_with(|| {
@@ -416,11 +477,12 @@ impl CpuOrderCtx {
     }
 
     /// Get the performance domain (i.e., CPU frequency domain) ID for a CPU.
-    /// If the energy model is not available, use LLC ID instead.
-    fn get_pd_id(em: &Result<EnergyModel>, cpu_adx: usize, llc_adx: usize) -> usize {
+    /// If the energy model is not available, use the given fallback ID
+    /// (LLC ID, or cluster ID on ARM big.LITTLE) instead.
+    fn get_pd_id(em: &Result<EnergyModel>, cpu_adx: usize, fallback_id: usize) -> usize {
         match em {
             Ok(em) => em.get_pd_by_cpu_id(cpu_adx).unwrap().id,
-            Err(_) => llc_adx,
+            Err(_) => fallback_id,
         }
     }
 
@@ -439,6 +501,9 @@ impl CpuOrderCtx {
             }
             if from.llc_kernel_id != to.llc_kernel_id {
                 d += 1;
+            } else if from.cluster_adx != to.cluster_adx {
+                // ARM clusters sharing an LLC (e.g., DynamIQ)
+                d += 1;
             }
         }
         d
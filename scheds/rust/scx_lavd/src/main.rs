@@ -13,7 +13,12 @@ pub use bpf_intf::*;
 
 mod cpu_order;
 use scx_utils::init_libbpf_logging;
+mod cpu_bw;
 mod stats;
+mod task_hint;
+mod thermal;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::ffi::c_int;
 use std::ffi::CStr;
 use std::mem;
@@ -29,6 +34,7 @@ use anyhow::Context;
 use anyhow::Result;
 use clap::Parser;
 use clap_num::number_range;
+use cpu_bw::CpuBwMonitor;
 use cpu_order::CpuOrder;
 use cpu_order::PerfCpuOrder;
 use crossbeam::channel;
@@ -64,10 +70,25 @@ use stats::SchedSamples;
 use stats::StatsReq;
 use stats::StatsRes;
 use stats::SysStats;
+use task_hint::TaskHint;
+use thermal::ThermalMonitor;
 use tracing::{debug, info, warn};
 use tracing_subscriber::filter::EnvFilter;
 
 const SCHEDULER_NAME: &str = "scx_lavd";
+
+/// Number of CPUs the built-in defaults of the system statistics interval
+/// and the migration threshold are tuned for.
+const SCALE_BASE_NR_CPUS: usize = 16;
+
+/// Baseline values of LAVD_SYS_STAT_INTERVAL_NS and LAVD_CPDOM_MIG_SHIFT.
+const SYS_STAT_INTERVAL_US_DFL: u64 = 10000;
+const MIG_SHIFT_DFL: u8 = 3;
+
+/// Fixed-point scale of the utilization and the preemption aggressiveness
+/// in sys_stat, LAVD_SCALE in BPF.
+const LAVD_SCALE: u64 = 1 << 10;
+
 /// scx_lavd: Latency-criticality Aware Virtual Deadline (LAVD) scheduler
 ///
 /// The rust part is minimal. It processes command line options and logs out
@@ -136,6 +157,23 @@ struct Opts {
     #[clap(long = "mig-delta-pct", default_value = "0", value_parser=Opts::mig_delta_pct_range)]
     mig_delta_pct: u8,
 
+    /// Interval of the system statistics update in microseconds (5000-100000).
+    /// The update walks all the CPUs and drives the core compaction and the
+    /// cross-domain load balancing. When not specified, it is 10 msec on
+    /// machines with fewer than 32 CPUs and grows with the number of CPUs up
+    /// to 40 msec to keep its overhead in check on large servers.
+    #[clap(long = "sys-stat-interval-us", value_parser=Opts::sys_stat_interval_us_range)]
+    sys_stat_interval_us: Option<u64>,
+
+    /// Shift (1-7) of the load imbalance threshold for cross-domain task
+    /// migration when the domains are mildly loaded. The threshold is
+    /// avg_load / 2^N, using N-1 when under-loaded and N+1 when over-loaded.
+    /// When not specified, it is 3 (12.5%) on machines with fewer than 64
+    /// CPUs and 4 (6.25%) on larger ones, where the same ratio hides a lot
+    /// more queued tasks in a domain.
+    #[clap(long = "mig-shift", value_parser=Opts::mig_shift_range)]
+    mig_shift: Option<u8>,
+
     /// Slice duration in microseconds to use for all tasks when pinned tasks
     /// are running on a CPU. Must be between slice-min-us and slice-max-us.
     /// When this option is enabled, pinned tasks are always enqueued to per-CPU DSQs
@@ -149,10 +187,17 @@ struct Opts {
     /// Limit the ratio of preemption to the roughly top P% of latency-critical
     /// tasks. When N is given as an argument, P is 0.5^N * 100. The default
     /// value is 6, which limits the preemption for the top 1.56% of
-    /// latency-critical tasks.
+    /// latency-critical tasks. Unless --no-adaptive-preempt is given, N is
+    /// the midpoint and is raised by up to 2 when the system is idle and
+    /// lowered by up to 2 when it is contended.
     #[clap(long = "preempt-shift", default_value = "6", value_parser=Opts::preempt_shift_range)]
     preempt_shift: u8,
 
+    /// Use a fixed preemption threshold from --preempt-shift instead of
+    /// scaling it with the CPU utilization and the run queue lengths.
+    #[clap(long = "no-adaptive-preempt", action = clap::ArgAction::SetTrue)]
+    no_adaptive_preempt: bool,
+
     /// List of CPUs in preferred order (e.g., "0-3,7,6,5,4"). The scheduler
     /// uses the CPU preference mode only when the core compaction is enabled
     /// (i.e., balanced or powersave mode is specified as an option or chosen
@@ -181,6 +226,78 @@ struct Opts {
     #[clap(long = "no-slice-boost", action = clap::ArgAction::SetTrue)]
     no_slice_boost: bool,
 
+    /// Disable slice alignment for periodic frame-paced tasks (e.g., games
+    /// and video players waking up at a stable ~8ms or ~16ms interval).
+    #[clap(long = "no-frame-pacing", action = clap::ArgAction::SetTrue)]
+    no_frame_pacing: bool,
+
+    /// Detect tasks that frequently block on IO and place their wake-ups
+    /// on an idle CPU as quickly as possible, since they run only briefly.
+    #[clap(long = "io-boost", action = clap::ArgAction::SetTrue)]
+    io_boost: bool,
+
+    /// On multi-node systems, keep a synchronously woken task on its NUMA
+    /// node instead of pulling it to the waker's remote node once it has
+    /// been running on its node for this long (in msec) and NUMA balancing
+    /// does not prefer another node. 0 disables.
+    #[clap(long = "numa-wake-hold-ms", default_value = "0")]
+    numa_wake_hold_ms: u64,
+
+    /// Let a task held on its NUMA node by --numa-wake-hold-ms be pulled to
+    /// the waker's node anyway when its average queueing delay is at least
+    /// this long (in usec).
+    #[clap(long = "numa-wake-qdelay-us", default_value = "1000")]
+    numa_wake_qdelay_us: u64,
+
+    /// Track thermal throttling of CPUs and avoid placing tasks on
+    /// throttled CPUs when possible. Latency-critical tasks do not stick to
+    /// a throttled CPU at all. Requires the thermal_throttle counters in
+    /// sysfs (e.g., Intel CPUs).
+    #[clap(long = "thermal-aware", action = clap::ArgAction::SetTrue)]
+    thermal_aware: bool,
+
+    /// Pin the task hint map at the specified path so that tasks can be
+    /// tagged as latency-critical, background or strict (see --hint). Tasks
+    /// inherit the hint of the task which forked them. Only root can write
+    /// the map unless --task-hint-group is set.
+    #[clap(long, default_value = "")]
+    task_hint_map: String,
+
+    /// Make --task-hint-map writable by the members of this group. Note that
+    /// the map itself can't tell who owns a task, so only grant the group
+    /// to trusted users; --hint refuses to hint tasks of other users.
+    #[clap(long, default_value = "", requires = "task_hint_map")]
+    task_hint_group: String,
+
+    /// Percentage (1-100) of each CPU's time the strict priority band may
+    /// use. Strict tasks always preempt regular tasks until the band exceeds
+    /// this budget on their CPU, after which they are scheduled there as
+    /// regular latency-critical tasks until the overuse is paid back.
+    #[clap(long = "strict-budget-pct", default_value = "20", value_parser=Opts::strict_budget_pct_range)]
+    strict_budget_pct: u8,
+
+    /// Run the tasks of this cgroup v2 path (e.g., /sys/fs/cgroup/audio.slice)
+    /// and of its descendants in the strict priority band. Can be repeated.
+    /// Strict can also be set through --task-hint-map, but only while the
+    /// map is restricted to root (i.e., without --task-hint-group).
+    #[clap(long)]
+    strict_cgroup: Vec<String>,
+
+    /// Set the hint of the task given by --hint-pid, or run the trailing
+    /// command with the hint, through the map pinned by a running scx_lavd
+    /// at --task-hint-map, e.g., `scx_lavd --task-hint-map PATH --hint
+    /// latency-critical -- CMD ARGS`. Scheduler is not launched.
+    #[clap(long, value_enum)]
+    hint: Option<TaskHint>,
+
+    /// The task to set --hint for.
+    #[clap(long, requires = "hint")]
+    hint_pid: Option<i32>,
+
+    /// The command to run with --hint.
+    #[clap(last = true, requires = "hint")]
+    hint_cmd: Vec<String>,
+
     /// Enables DSQs per CPU, this enables task queuing and dispatching
     /// from CPU specific DSQs. This generally increases L1/L2 cache
     /// locality for tasks and lowers lock contention compared to shared DSQs,
@@ -189,8 +306,10 @@ struct Opts {
     #[clap(long = "per-cpu-dsq", action = clap::ArgAction::SetTrue)]
     per_cpu_dsq: bool,
 
-    /// Enable CPU bandwidth control using cpu.max in cgroup v2.
-    /// This is a highly experimental feature.
+    /// Enable CPU bandwidth control using cpu.max in cgroup v2. Tasks of
+    /// cgroups which ran out of their quota are held back until the next
+    /// period. How much of their quota the limited cgroups actually use is
+    /// reported in the stats. This is a highly experimental feature.
     #[clap(long = "enable-cpu-bw", action = clap::ArgAction::SetTrue)]
     enable_cpu_bw: bool,
 
@@ -210,6 +329,16 @@ struct Opts {
     #[clap(long = "no-freq-scaling", action = clap::ArgAction::SetTrue)]
     no_freq_scaling: bool,
 
+    /// Do not apply the tuning profile for ARM big.LITTLE systems (e.g.,
+    /// laptops and SBCs). On such systems, the profile doubles the default
+    /// interval of the system statistics update to cut periodic wake-ups on
+    /// battery-powered devices, and raises the default cross-domain
+    /// migration threshold by one step as a migration between clusters
+    /// loses the cluster-private caches. Explicitly given
+    /// --sys-stat-interval-us and --mig-shift always take precedence.
+    #[clap(long = "no-arm-profile", action = clap::ArgAction::SetTrue)]
+    no_arm_profile: bool,
+
     /// Enable stats monitoring with the specified interval.
     #[clap(long)]
     stats: Option<f64>,
@@ -368,6 +497,37 @@ impl Opts {
     fn mig_delta_pct_range(s: &str) -> Result<u8, String> {
         number_range(s, 0, 100)
     }
+
+    fn strict_budget_pct_range(s: &str) -> Result<u8, String> {
+        number_range(s, 1, 100)
+    }
+
+    fn sys_stat_interval_us_range(s: &str) -> Result<u64, String> {
+        number_range(s, 5000, 100000)
+    }
+
+    fn mig_shift_range(s: &str) -> Result<u8, String> {
+        number_range(s, 1, 7)
+    }
+
+    /// log2 of how many times larger than the baseline the machine is.
+    fn cpu_scale_shift(nr_cpus: usize) -> u32 {
+        (nr_cpus / SCALE_BASE_NR_CPUS).max(1).ilog2()
+    }
+
+    fn sys_stat_interval_us(&self, nr_cpus: usize, arm_profile: bool) -> u64 {
+        self.sys_stat_interval_us.unwrap_or_else(|| {
+            let scale = 1 + Self::cpu_scale_shift(nr_cpus).min(3) as u64;
+            SYS_STAT_INTERVAL_US_DFL * scale * (1 + arm_profile as u64)
+        })
+    }
+
+    fn mig_shift(&self, nr_cpus: usize, arm_profile: bool) -> u8 {
+        self.mig_shift.unwrap_or_else(|| {
+            let tighten = (Self::cpu_scale_shift(nr_cpus) >= 2) as u8;
+            MIG_SHIFT_DFL + tighten + arm_profile as u8
+        })
+    }
 }
 
 unsafe impl Plain for msg_task_ctx {}
@@ -394,6 +554,10 @@ struct Scheduler<'a> {
     monitor_tid: Option<ThreadId>,
     stats_server: StatsServer<StatsReq, StatsRes>,
     mseq_id: u64,
+    thermal: Option<ThermalMonitor>,
+    cpu_bw: Option<CpuBwMonitor>,
+    cpdom_clusters: BTreeMap<usize, usize>,
+    prev_cpdom_nr_sched: Vec<u64>,
 }
 
 impl<'a> Scheduler<'a> {
@@ -441,12 +605,30 @@ impl<'a> Scheduler<'a> {
         // Initialize skel according to @opts.
         Self::init_globals(&mut skel, &opts, &order, debug_level);
 
+        // Reuse the hint map pinned by a previous instance if there's one so
+        // that the hints survive scheduler restarts.
+        if !opts.task_hint_map.is_empty() {
+            skel.maps
+                .scx_lavd_task_hint_map
+                .set_pin_path(&opts.task_hint_map)?;
+            skel.maps
+                .rodata_data
+                .as_mut()
+                .unwrap()
+                .task_hint_map_enabled = true;
+        }
+
         // Initialize arena
         let mut skel = scx_ops_load!(skel, lavd_ops, uei)?;
         let task_size = std::mem::size_of::<types::task_ctx>();
         let arenalib = ArenaLib::init(skel.object_mut(), task_size, *NR_CPU_IDS)?;
         arenalib.setup()?;
 
+        if !opts.task_hint_map.is_empty() {
+            task_hint::restrict_map(&opts.task_hint_map, &opts.task_hint_group)?;
+        }
+        task_hint::add_strict_cgroups(&skel.maps.strict_cgrp_map, &opts.strict_cgroup)?;
+
         // Attach.
         let struct_ops = Some(scx_ops_attach!(skel, lavd_ops)?);
         let stats_server = StatsServer::new(stats::server_data(*NR_CPU_IDS as u64)).launch()?;
@@ -462,6 +644,28 @@ impl<'a> Scheduler<'a> {
             .unwrap();
         let rb_mgr = builder.build().unwrap();
 
+        let thermal = if opts.thermal_aware {
+            let thermal = ThermalMonitor::new(*NR_CPU_IDS);
+            if !thermal.is_supported() {
+                warn!("No thermal throttle counters found, --thermal-aware has no effect.");
+            }
+            Some(thermal)
+        } else {
+            None
+        };
+
+        let cpu_bw = opts.enable_cpu_bw.then(CpuBwMonitor::new);
+
+        // Per-cluster placement statistics on ARM big.LITTLE.
+        let cpdom_clusters = match order.use_clusters {
+            true => order
+                .cpdom_map
+                .iter()
+                .map(|(k, v)| (v.cpdom_id, k.cluster_adx))
+                .collect(),
+            false => BTreeMap::new(),
+        };
+
         Ok(Self {
             skel,
             struct_ops,
@@ -471,6 +675,10 @@ impl<'a> Scheduler<'a> {
             monitor_tid: None,
             stats_server,
             mseq_id: 0,
+            thermal,
+            cpu_bw,
+            cpdom_clusters,
+            prev_cpdom_nr_sched: vec![0; LAVD_CPDOM_MAX_NR as usize],
         })
     }
 
@@ -618,10 +826,41 @@ impl<'a> Scheduler<'a> {
         rodata.slice_min_ns = opts.slice_min_us * 1000;
         rodata.pinned_slice_ns = opts.pinned_slice_us.map(|v| v * 1000).unwrap_or(0);
         rodata.preempt_shift = opts.preempt_shift;
+        rodata.no_adaptive_preempt = opts.no_adaptive_preempt;
+        rodata.strict_budget_pct = opts.strict_budget_pct;
+        rodata.strict_cgrp_enabled = !opts.strict_cgroup.is_empty();
+        rodata.task_hint_strict_enabled = opts.task_hint_group.is_empty();
         rodata.mig_delta_pct = opts.mig_delta_pct;
+        let arm_profile = order.use_clusters && !opts.no_arm_profile;
+        if arm_profile {
+            info!("ARM big.LITTLE profile is enabled.");
+        }
+        rodata.sys_stat_interval_ns = opts.sys_stat_interval_us(order.nr_cpus, arm_profile) * 1000;
+        rodata.mig_shift = opts.mig_shift(order.nr_cpus, arm_profile);
+        info!(
+            "Scaled parameters for {} CPUs: sys-stat-interval-us={} mig-shift={}",
+            order.nr_cpus,
+            rodata.sys_stat_interval_ns / 1000,
+            rodata.mig_shift
+        );
         rodata.no_use_em = opts.no_use_em as u8;
         rodata.no_wake_sync = opts.no_wake_sync;
         rodata.no_slice_boost = opts.no_slice_boost;
+        rodata.no_frame_pacing = opts.no_frame_pacing;
+        rodata.io_boost = opts.io_boost;
+        let nr_nodes = order
+            .cpdom_map
+            .keys()
+            .map(|k| k.numa_adx)
+            .collect::<BTreeSet<_>>()
+            .len();
+        if nr_nodes > 1 {
+            rodata.numa_wake_hold_ns = opts.numa_wake_hold_ms * 1_000_000;
+            rodata.numa_wake_qdelay_ns = opts.numa_wake_qdelay_us * 1000;
+        } else if opts.numa_wake_hold_ms > 0 {
+            info!("Single NUMA node, ignoring --numa-wake-hold-ms.");
+        }
+        rodata.thermal_aware = opts.thermal_aware;
         rodata.per_cpu_dsq = opts.per_cpu_dsq;
         rodata.enable_cpu_bw = opts.enable_cpu_bw;
 
@@ -696,6 +935,8 @@ impl<'a> Scheduler<'a> {
             dsq_id: tx.dsq_id,
             dsq_consume_lat: tx.dsq_consume_lat,
             slice_used: tx.last_slice_used,
+            frame_period: tx.frame_period,
+            frame_conf: tx.frame_conf,
         }) {
             Ok(()) | Err(TrySendError::Full(_)) => 0,
             Err(e) => panic!("failed to send on intrspc_tx ({})", e),
@@ -718,6 +959,26 @@ impl<'a> Scheduler<'a> {
         return 100. * x as f64 / y as f64;
     }
 
+    /// % of the schedules on each CPU cluster since the last call.
+    fn get_cluster_placement(&mut self) -> BTreeMap<usize, f64> {
+        let bss_data = self.skel.maps.bss_data.as_ref().unwrap();
+        let mut nr_scheds: BTreeMap<usize, u64> = BTreeMap::new();
+        for (&cpdom_id, &cluster_adx) in self.cpdom_clusters.iter() {
+            let cur = bss_data.cpdom_ctxs[cpdom_id].nr_sched;
+            let prev = std::mem::replace(&mut self.prev_cpdom_nr_sched[cpdom_id], cur);
+            *nr_scheds.entry(cluster_adx).or_default() += cur.saturating_sub(prev);
+        }
+
+        let total: u64 = nr_scheds.values().sum();
+        nr_scheds
+            .into_iter()
+            .map(|(cluster_adx, nr)| match total {
+                0 => (cluster_adx, 0.0),
+                _ => (cluster_adx, Self::get_pc(nr, total)),
+            })
+            .collect()
+    }
+
     fn get_power_mode(power_mode: i32) -> &'static str {
         match power_mode as u32 {
             LAVD_PM_PERFORMANCE => "performance",
@@ -739,8 +1000,10 @@ impl<'a> Scheduler<'a> {
                     return Ok(StatsRes::Bye);
                 }
                 self.mseq_id += 1;
+                let pc_clusters = self.get_cluster_placement();
 
                 let bss_data = self.skel.maps.bss_data.as_ref().unwrap();
+                let rodata = self.skel.maps.rodata_data.as_ref().unwrap();
                 let st = bss_data.sys_stat;
 
                 let mseq = self.mseq_id;
@@ -748,6 +1011,9 @@ impl<'a> Scheduler<'a> {
                 let nr_active = st.nr_active;
                 let nr_sched = st.nr_sched;
                 let nr_preempt = st.nr_preempt;
+                let pc_preempt_aggr = Self::get_pc(st.preempt_aggr as u64, LAVD_SCALE);
+                let preempt_shift = st.preempt_shift;
+                let pc_util = Self::get_pc(st.avg_util, LAVD_SCALE);
                 let pc_pc = Self::get_pc(st.nr_perf_cri, nr_sched);
                 let pc_lc = Self::get_pc(st.nr_lat_cri, nr_sched);
                 let pc_x_migration = Self::get_pc(st.nr_x_migration, nr_sched);
@@ -756,6 +1022,12 @@ impl<'a> Scheduler<'a> {
                 let pc_big = Self::get_pc(nr_big, nr_sched);
                 let pc_pc_on_big = Self::get_pc(st.nr_pc_on_big, nr_big);
                 let pc_lc_on_big = Self::get_pc(st.nr_lc_on_big, nr_big);
+                let pc_frame_paced = Self::get_pc(st.nr_frame_paced, nr_sched);
+                let pc_io_bound = Self::get_pc(st.nr_io_bound, nr_sched);
+                let pc_strict = Self::get_pc(st.nr_strict, nr_sched);
+                let strict_throttled = st.strict_throttled;
+                let nr_xnode_pull = st.nr_xnode_pull;
+                let nr_xnode_held = st.nr_xnode_held;
                 let power_mode = Self::get_power_mode(bss_data.power_mode);
                 let total_time = bss_data.performance_mode_ns
                     + bss_data.balanced_mode_ns
@@ -763,6 +1035,20 @@ impl<'a> Scheduler<'a> {
                 let pc_performance = Self::get_pc(bss_data.performance_mode_ns, total_time);
                 let pc_balanced = Self::get_pc(bss_data.balanced_mode_ns, total_time);
                 let pc_powersave = Self::get_pc(bss_data.powersave_mode_ns, total_time);
+                let (nr_throttled, throttled_cpus) = match &self.thermal {
+                    Some(thermal) => (thermal.nr_throttled(), thermal.throttled_cpus()),
+                    None => (0, String::new()),
+                };
+                let (nr_bw_cgroups, pc_bw_quota_avg, pc_bw_quota_max) = match &self.cpu_bw {
+                    Some(cpu_bw) => (
+                        cpu_bw.nr_cgroups(),
+                        cpu_bw.avg_usage_pct(),
+                        cpu_bw.max_usage_pct(),
+                    ),
+                    None => (0, 0.0, 0.0),
+                };
+                let sys_stat_interval_us = rodata.sys_stat_interval_ns / 1000;
+                let mig_shift = rodata.mig_shift as u32;
 
                 StatsRes::SysStats(SysStats {
                     mseq,
@@ -770,6 +1056,9 @@ impl<'a> Scheduler<'a> {
                     nr_active,
                     nr_sched,
                     nr_preempt,
+                    pc_preempt_aggr,
+                    preempt_shift,
+                    pc_util,
                     pc_pc,
                     pc_lc,
                     pc_x_migration,
@@ -777,10 +1066,24 @@ impl<'a> Scheduler<'a> {
                     pc_big,
                     pc_pc_on_big,
                     pc_lc_on_big,
+                    pc_frame_paced,
+                    pc_io_bound,
+                    pc_strict,
+                    strict_throttled,
+                    nr_xnode_pull,
+                    nr_xnode_held,
                     power_mode: power_mode.to_string(),
                     pc_performance,
                     pc_balanced,
                     pc_powersave,
+                    nr_throttled,
+                    throttled_cpus,
+                    nr_bw_cgroups,
+                    pc_bw_quota_avg,
+                    pc_bw_quota_max,
+                    sys_stat_interval_us,
+                    mig_shift,
+                    pc_clusters,
                 })
             }
             StatsReq::SchedSamplesNr {
@@ -864,6 +1167,17 @@ impl<'a> Scheduler<'a> {
         (true, profile)
     }
 
+    fn update_thermal(&mut self) {
+        let Some(thermal) = self.thermal.as_mut() else {
+            return;
+        };
+
+        let bss_data = self.skel.maps.bss_data.as_mut().unwrap();
+        for cpu in thermal.update() {
+            bss_data.cpu_throttled[cpu] = thermal.is_throttled(cpu) as u8;
+        }
+    }
+
     fn run(&mut self, opts: &Opts, shutdown: Arc<AtomicBool>) -> Result<UserExitInfo> {
         let (res_ch, req_ch) = self.stats_server.channels();
         let mut autopower = opts.autopower;
@@ -881,6 +1195,10 @@ impl<'a> Scheduler<'a> {
             if autopower {
                 (autopower, profile) = self.update_power_profile(profile);
             }
+            self.update_thermal();
+            if let Some(cpu_bw) = self.cpu_bw.as_mut() {
+                cpu_bw.update();
+            }
 
             match req_ch.recv_timeout(Duration::from_secs(1)) {
                 Ok(req) => {
@@ -944,10 +1262,7 @@ fn init_log(opts: &Opts) {
 #[clap_main::clap_main]
 fn main(mut opts: Opts) -> Result<()> {
     if opts.version {
-        println!(
-            "scx_lavd {}",
-            build_id::full_version(env!("CARGO_PKG_VERSION"))
-        );
+        println!("{:#}", scx_utils::version_info!());
         return Ok(());
     }
 
@@ -962,6 +1277,10 @@ fn main(mut opts: Opts) -> Result<()> {
         return Ok(());
     }
 
+    if let Some(hint) = opts.hint {
+        return task_hint::run(&opts.task_hint_map, hint, opts.hint_pid, &opts.hint_cmd);
+    }
+
     init_log(&opts);
 
     if opts.verbose > 0 {
@@ -34,6 +34,15 @@ pub struct SysStats {
     #[stat(desc = "Number of task preemption triggered")]
     pub nr_preempt: u64,
 
+    #[stat(desc = "% of preemption aggressiveness, max of CPU util and queue pressure")]
+    pub pc_preempt_aggr: f64,
+
+    #[stat(desc = "Effective preempt shift from the aggressiveness")]
+    pub preempt_shift: u32,
+
+    #[stat(desc = "% of average CPU utilization")]
+    pub pc_util: f64,
+
     #[stat(desc = "% of performance-critical tasks")]
     pub pc_pc: f64,
 
@@ -55,6 +64,26 @@ pub struct SysStats {
     #[stat(desc = "% of latency-critical tasks scheduled on big cores")]
     pub pc_lc_on_big: f64,
 
+    #[stat(desc = "% of frame-paced tasks")]
+    pub pc_frame_paced: f64,
+
+    #[stat(desc = "% of IO-bound tasks")]
+    pub pc_io_bound: f64,
+
+    #[stat(desc = "% of tasks scheduled in the strict priority band")]
+    pub pc_strict: f64,
+
+    #[stat(
+        desc = "Number of CPUs on which the strict band is over its budget (--strict-budget-pct)"
+    )]
+    pub strict_throttled: u32,
+
+    #[stat(desc = "Number of sync wakees pulled to the waker's remote NUMA node")]
+    pub nr_xnode_pull: u64,
+
+    #[stat(desc = "Number of sync wakees held on their NUMA node (--numa-wake-hold-ms)")]
+    pub nr_xnode_held: u64,
+
     #[stat(desc = "Current power mode")]
     pub power_mode: String,
 
@@ -66,18 +95,46 @@ pub struct SysStats {
 
     #[stat(desc = "% of powersave mode")]
     pub pc_powersave: f64,
+
+    #[stat(desc = "Number of thermally throttled CPUs (--thermal-aware)")]
+    pub nr_throttled: u32,
+
+    #[stat(desc = "Thermally throttled CPUs (--thermal-aware)")]
+    pub throttled_cpus: String,
+
+    #[stat(desc = "Number of cgroups with a cpu.max limit (--enable-cpu-bw)")]
+    pub nr_bw_cgroups: u32,
+
+    #[stat(desc = "Average % of cpu.max quota used by limited cgroups (--enable-cpu-bw)")]
+    pub pc_bw_quota_avg: f64,
+
+    #[stat(desc = "Highest % of cpu.max quota used by a limited cgroup (--enable-cpu-bw)")]
+    pub pc_bw_quota_max: f64,
+
+    #[stat(desc = "Interval of the system statistics update in usec (scaled by # CPUs)")]
+    pub sys_stat_interval_us: u64,
+
+    #[stat(desc = "Shift of the cross-domain migration threshold (scaled by # CPUs)")]
+    pub mig_shift: u32,
+
+    #[stat(
+        desc = "% of tasks scheduled on each CPU cluster (ARM big.LITTLE)",
+        _om_label = "cluster"
+    )]
+    pub pc_clusters: BTreeMap<usize, f64>,
 }
 
 impl SysStats {
     pub fn format_header<W: Write>(w: &mut W) -> Result<()> {
         writeln!(
             w,
-            "\x1b[93m| {:8} | {:9} | {:9} | {:8} | {:9} | {:8} | {:8} | {:8} | {:8} | {:8} | {:8} | {:8} | {:11} | {:12} | {:12} | {:12} |\x1b[0m",
+            "\x1b[93m| {:8} | {:9} | {:9} | {:8} | {:9} | {:9} | {:8} | {:8} | {:8} | {:8} | {:8} | {:8} | {:8} | {:8} | {:8} | {:8} | {:11} | {:12} | {:12} | {:12} | {:8} | {:8} |\x1b[0m",
             "MSEQ",
             "# Q TASK",
             "# ACT CPU",
             "# SCHED",
             "# PREEMPT",
+            "PRMT-AGR%",
             "PERF-CR%",
             "LAT-CR%",
             "X-MIG%",
@@ -85,10 +142,15 @@ impl SysStats {
             "BIG%",
             "PC/BIG%",
             "LC/BIG%",
+            "FRAME%",
+            "IO%",
+            "XN-PULL",
             "POWER MODE",
             "PERFORMANCE%",
             "BALANCED%",
             "POWERSAVE%",
+            "# THRTL",
+            "BW-MAX%",
         )?;
         Ok(())
     }
@@ -106,12 +168,13 @@ impl SysStats {
 
         writeln!(
             w,
-            "{color}| {:8} | {:9} | {:9} | {:8} | {:9} | {:8} | {:8} | {:8} | {:8} | {:8} | {:8} | {:8} | {:11} | {:12} | {:12} | {:12} |\x1b[0m",
+            "{color}| {:8} | {:9} | {:9} | {:8} | {:9} | {:9} | {:8} | {:8} | {:8} | {:8} | {:8} | {:8} | {:8} | {:8} | {:8} | {:8} | {:11} | {:12} | {:12} | {:12} | {:8} | {:8} |\x1b[0m",
             self.mseq,
             self.nr_queued_task,
             self.nr_active,
             self.nr_sched,
             self.nr_preempt,
+            GPoint(self.pc_preempt_aggr),
             GPoint(self.pc_pc),
             GPoint(self.pc_lc),
             GPoint(self.pc_x_migration),
@@ -119,10 +182,15 @@ impl SysStats {
             GPoint(self.pc_big),
             GPoint(self.pc_pc_on_big),
             GPoint(self.pc_lc_on_big),
+            GPoint(self.pc_frame_paced),
+            GPoint(self.pc_io_bound),
+            self.nr_xnode_pull,
             self.power_mode,
             GPoint(self.pc_performance),
             GPoint(self.pc_balanced),
             GPoint(self.pc_powersave),
+            self.nr_throttled,
+            GPoint(self.pc_bw_quota_max),
         )?;
         Ok(())
     }
@@ -190,13 +258,19 @@ pub struct SchedSample {
     pub dsq_id: u64,
     #[stat(desc = "Consume latency of this DSQ (shows how contended the DSQ is)")]
     pub dsq_consume_lat: u64,
+    #[stat(
+        desc = "Average wake-up interval of this task in ns within the frame period range (4-34ms), tracked even if it's not frame-paced, see frame_conf (0 if never seen)"
+    )]
+    pub frame_period: u64,
+    #[stat(desc = "Confidence of the frame period detection")]
+    pub frame_conf: u32,
 }
 
 impl SchedSample {
     pub fn format_header<W: Write>(w: &mut W) -> Result<()> {
         writeln!(
             w,
-            "\x1b[93m| {:6} | {:7} | {:17} | {:5} | {:4} | {:8} | {:8} | {:8} | {:17} | {:8} | {:8} | {:8} | {:7} | {:8} | {:12} | {:12} | {:9} | {:9} | {:9} | {:9} | {:8} | {:8} | {:8} | {:8} | {:9} | {:6} | {:6} | {:10} |\x1b[0m",
+            "\x1b[93m| {:6} | {:7} | {:17} | {:5} | {:4} | {:8} | {:8} | {:8} | {:17} | {:8} | {:8} | {:8} | {:7} | {:8} | {:12} | {:12} | {:9} | {:9} | {:9} | {:9} | {:8} | {:8} | {:8} | {:8} | {:9} | {:6} | {:6} | {:10} | {:9} | {:6} |\x1b[0m",
             "MSEQ",
             "PID",
             "COMM",
@@ -225,6 +299,8 @@ impl SchedSample {
             "NR_ACT",
             "DSQ_ID",
             "DSQ_LAT_NS",
+            "FRAME_NS",
+            "FR_CNF",
         )?;
         Ok(())
     }
@@ -236,7 +312,7 @@ impl SchedSample {
 
         writeln!(
             w,
-            "| {:6} | {:7} | {:17} | {:5} | {:4} | {:8} | {:8} | {:8} | {:17} | {:8} | {:8} | {:8} | {:7} | {:8} | {:12} | {:12} | {:9} | {:9} | {:9} | {:9} | {:8} | {:8} | {:8} | {:8} | {:9} | {:6} | {:12} | {:12} |",
+            "| {:6} | {:7} | {:17} | {:5} | {:4} | {:8} | {:8} | {:8} | {:17} | {:8} | {:8} | {:8} | {:7} | {:8} | {:12} | {:12} | {:9} | {:9} | {:9} | {:9} | {:8} | {:8} | {:8} | {:8} | {:9} | {:6} | {:12} | {:12} | {:9} | {:6} |",
             self.mseq,
             self.pid,
             self.comm,
@@ -265,6 +341,8 @@ impl SchedSample {
             self.nr_active,
             self.dsq_id,
             self.dsq_consume_lat,
+            self.frame_period,
+            self.frame_conf,
         )?;
         Ok(())
     }
@@ -0,0 +1,162 @@
+// SPDX-License-Identifier: GPL-2.0
+//
+// Copyright (c) 2026 Valve Corporation.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::mem::size_of;
+use std::os::fd::AsRawFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use clap::ValueEnum;
+use libbpf_rs::MapCore;
+use libbpf_rs::MapFlags;
+use libbpf_rs::MapHandle;
+
+use crate::bpf_intf;
+
+/// Hint an application can give for its own tasks through the task hint map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TaskHint {
+    /// Clear the hint.
+    None,
+    /// Always treat the task as latency-critical, e.g., game or audio threads.
+    LatencyCritical,
+    /// Never treat the task as latency-critical, e.g., builds or indexers.
+    Background,
+    /// Run the task in the strict priority band, which preempts all regular
+    /// tasks but is bounded by --strict-budget-pct, e.g., pro-audio threads.
+    /// Ignored if --task-hint-group is set, see --strict-cgroup instead.
+    Strict,
+}
+
+impl TaskHint {
+    fn value(&self) -> u64 {
+        (match self {
+            Self::None => bpf_intf::LAVD_HINT_NONE,
+            Self::LatencyCritical => bpf_intf::LAVD_HINT_LAT_CRI,
+            Self::Background => bpf_intf::LAVD_HINT_BACKGROUND,
+            Self::Strict => bpf_intf::LAVD_HINT_STRICT,
+        }) as u64
+    }
+}
+
+fn group_id(name: &str) -> Result<u32> {
+    let cname = CString::new(name)?;
+    let grp = unsafe { libc::getgrnam(cname.as_ptr()) };
+    if grp.is_null() {
+        bail!("Unknown group {:?}", name);
+    }
+    Ok(unsafe { (*grp).gr_gid })
+}
+
+/// Restrict the map pinned at @path to root, or to root and the members of
+/// @group if not empty. The map is keyed by pidfd, which anyone can open
+/// for any task, so whoever can write the map can re-class any task. Should
+/// be called after the scheduler is loaded and the map is pinned.
+pub fn restrict_map(path: &str, group: &str) -> Result<()> {
+    if group.is_empty() {
+        return fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("Failed to restrict task hint map {}", path));
+    }
+
+    std::os::unix::fs::chown(path, Some(0), Some(group_id(group)?))
+        .with_context(|| format!("Failed to change the group of task hint map {}", path))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o660))
+        .with_context(|| format!("Failed to restrict task hint map {}", path))
+}
+
+/// Register the cgroups at @paths in @map so that their tasks and the tasks
+/// of their descendants run in the strict priority band. Should be called
+/// before the scheduler is attached.
+pub fn add_strict_cgroups<M: MapCore>(map: &M, paths: &[String]) -> Result<()> {
+    if paths.len() > bpf_intf::LAVD_STRICT_CGRP_MAX as usize {
+        bail!(
+            "Too many --strict-cgroup, at most {} are supported",
+            bpf_intf::LAVD_STRICT_CGRP_MAX
+        );
+    }
+
+    for path in paths {
+        // The cgroup id is the inode number of its cgroupfs directory.
+        let cgrp_id = fs::metadata(path)
+            .with_context(|| format!("Failed to look up cgroup {}", path))?
+            .ino();
+        map.update(&cgrp_id.to_ne_bytes(), &[1u8], MapFlags::ANY)
+            .with_context(|| format!("Failed to add strict cgroup {}", path))?;
+    }
+    Ok(())
+}
+
+fn pidfd_open(pid: i32) -> Result<OwnedFd> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error()).context(format!("pidfd_open({}) failed", pid));
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as i32) })
+}
+
+/// Set the hint of task @pid through the task hint map pinned at @path.
+pub fn set_task_hint(path: &str, pid: i32, hint: TaskHint) -> Result<()> {
+    let map = MapHandle::from_pinned_path(path).with_context(|| {
+        format!(
+            "Failed to open task hint map {}, is scx_lavd running with --task-hint-map?",
+            path
+        )
+    })?;
+
+    // Members of --task-hint-group may only hint their own tasks.
+    let euid = unsafe { libc::geteuid() };
+    let owner = fs::metadata(format!("/proc/{}", pid))
+        .with_context(|| format!("Failed to look up task {}", pid))?
+        .uid();
+    if euid != 0 && euid != owner {
+        bail!("Task {} is not owned by the current user", pid);
+    }
+
+    // Task storage maps are keyed by pidfd.
+    let pidfd = pidfd_open(pid)?;
+    let key = pidfd.as_raw_fd().to_ne_bytes();
+
+    if hint == TaskHint::None {
+        return match map.delete(&key) {
+            Err(e) if e.kind() == libbpf_rs::ErrorKind::NotFound => Ok(()),
+            res => res.context("Failed to clear the task hint"),
+        };
+    }
+
+    let mut value = vec![0u8; size_of::<bpf_intf::task_hint>()];
+    value[..8].copy_from_slice(&hint.value().to_ne_bytes());
+    map.update(&key, &value, MapFlags::ANY)
+        .context("Failed to set the task hint")
+}
+
+/// Handle --hint: set the hint of @pid, or of ourselves and then exec @cmd
+/// so that the command and all the tasks it forks inherit the hint.
+pub fn run(path: &str, hint: TaskHint, pid: Option<i32>, cmd: &[String]) -> Result<()> {
+    if path.is_empty() {
+        bail!("--hint requires --task-hint-map");
+    }
+
+    match (pid, cmd.split_first()) {
+        (Some(pid), None) => set_task_hint(path, pid, hint),
+        (None, Some((prog, args))) => {
+            set_task_hint(path, std::process::id() as i32, hint)?;
+            let err = Command::new(prog).args(args).exec();
+            Err(err).context(format!("Failed to execute {}", prog))
+        }
+        _ => bail!("--hint requires either --hint-pid or a command to run"),
+    }
+}
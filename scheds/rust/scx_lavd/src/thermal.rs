@@ -0,0 +1,112 @@
+// SPDX-License-Identifier: GPL-2.0
+//
+// Copyright (c) 2026 Valve Corporation.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+use std::fs;
+use std::time::Duration;
+use std::time::Instant;
+use tracing::debug;
+
+// A CPU is considered throttled when its core or package throttle counter
+// advanced since the previous update.
+const THROTTLE_COUNTERS: [&str; 2] = ["core_throttle_count", "package_throttle_count"];
+
+// Sample the counters at a fixed interval so that the throttling state does
+// not depend on how often update() is called.
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub struct ThermalMonitor {
+    counts: Vec<Option<u64>>,
+    throttled: Vec<bool>,
+    updated_at: Instant,
+}
+
+impl ThermalMonitor {
+    pub fn new(nr_cpu_ids: usize) -> Self {
+        let counts: Vec<Option<u64>> = (0..nr_cpu_ids).map(read_throttle_count).collect();
+        debug!(
+            "Thermal throttle counters available on {} CPUs",
+            counts.iter().filter(|c| c.is_some()).count()
+        );
+
+        Self {
+            counts,
+            throttled: vec![false; nr_cpu_ids],
+            updated_at: Instant::now(),
+        }
+    }
+
+    /// Whether any CPU exposes throttle counters.
+    pub fn is_supported(&self) -> bool {
+        self.counts.iter().any(|c| c.is_some())
+    }
+
+    /// Re-read the throttle counters and return the CPUs whose throttling
+    /// state changed since the last update. A failed read, e.g. of a CPU
+    /// being offlined, keeps the last good count and state of the CPU, and
+    /// the CPU is read again on the next update.
+    pub fn update(&mut self) -> Vec<usize> {
+        let mut changed = vec![];
+
+        if !self.is_supported() || self.updated_at.elapsed() < UPDATE_INTERVAL {
+            return changed;
+        }
+        self.updated_at = Instant::now();
+
+        for cpu in 0..self.counts.len() {
+            let Some(cur) = read_throttle_count(cpu) else {
+                continue;
+            };
+            let throttled = self.counts[cpu].is_some_and(|prev| cur > prev);
+
+            self.counts[cpu] = Some(cur);
+            if self.throttled[cpu] != throttled {
+                self.throttled[cpu] = throttled;
+                changed.push(cpu);
+            }
+        }
+
+        changed
+    }
+
+    pub fn is_throttled(&self, cpu: usize) -> bool {
+        self.throttled[cpu]
+    }
+
+    pub fn nr_throttled(&self) -> u32 {
+        self.throttled.iter().filter(|t| **t).count() as u32
+    }
+
+    /// Throttled CPUs as a comma-separated list, e.g. "0,1,6".
+    pub fn throttled_cpus(&self) -> String {
+        self.throttled
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| **t)
+            .map(|(cpu, _)| cpu.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+fn read_throttle_count(cpu: usize) -> Option<u64> {
+    let mut found = false;
+    let mut sum = 0;
+
+    for counter in THROTTLE_COUNTERS {
+        let path = format!("/sys/devices/system/cpu/cpu{cpu}/thermal_throttle/{counter}");
+        if let Some(v) = fs::read_to_string(path)
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+        {
+            found = true;
+            sum += v;
+        }
+    }
+
+    found.then_some(sum)
+}
@@ -37,6 +37,9 @@ enum consts {
 	MAX_LAYER_NAME		= 128,
 	MAX_LAYERS		= 16,
 	MAX_CGROUP_REGEXES	= 16,
+	/* cgroup regexes followed by the cgroup prefix/suffix/substr matches */
+	MAX_CGROUP_MATCH_IDS	= 64,
+	CGROUP_MATCH_ID_NONE	= MAX_CGROUP_MATCH_IDS,
 	MAX_LAYER_WEIGHT	= 10000,
 	MIN_LAYER_WEIGHT	= 1,
 	DEFAULT_LAYER_WEIGHT	= 100,
@@ -44,6 +47,8 @@ enum consts {
 	RUNTIME_DECAY_FACTOR	= 4,
 	LAYER_LAT_DECAY_FACTOR	= 32,
 	CLEAR_PREEMPTING_AFTER	= 10000000,	/* 10ms */
+	BW_PERIOD_NS		= 100000000,	/* 100ms */
+	PROC_LLC_MIG_DAMP_NS	= 100000000,	/* 100ms */
 
 	DSQ_ID_SPECIAL_MASK	= 0xc0000000,
 	HI_FB_DSQ_BASE		= 0x40000000,
@@ -86,6 +91,8 @@ static inline void ___consts_sanity_check___(void) {
 		       "MAX_LAYERS too high");
 	/* cgroup regex matching uses u64 as match bitmap */
 	_Static_assert(MAX_CGROUP_REGEXES <= 64, "MAX_CGROUP_REGEXES too high for u64 bitmap");
+	_Static_assert(MAX_CGROUP_MATCH_IDS <= 64, "MAX_CGROUP_MATCH_IDS too high for u64 bitmap");
+	_Static_assert(MAX_CGROUP_REGEXES <= MAX_CGROUP_MATCH_IDS, "MAX_CGROUP_REGEXES too high");
 }
 
 enum layer_kind {
@@ -118,6 +125,7 @@ enum global_stat_id {
 	GSTAT_SKIP_PREEMPT,
 	GSTAT_FIXUP_VTIME,
 	GSTAT_PREEMPTING_MISMATCH,
+	GSTAT_RESTORED_LAYER,
 	NR_GSTATS,
 };
 
@@ -132,6 +140,7 @@ enum layer_stat_id {
 	LSTAT_MIN_EXEC,
 	LSTAT_MIN_EXEC_NS,
 	LSTAT_OPEN_IDLE,
+	LSTAT_EMPTY_IDLE,
 	LSTAT_AFFN_VIOL,
 	LSTAT_KEEP_FAIL_MAX_EXEC,
 	LSTAT_KEEP_FAIL_BUSY,
@@ -149,11 +158,16 @@ enum layer_stat_id {
 	LSTAT_XNUMA_MIGRATION,
 	LSTAT_XLLC_MIGRATION,
 	LSTAT_XLLC_MIGRATION_SKIP,
+	LSTAT_PROC_LLC_KEEP,
 	LSTAT_XLAYER_WAKE,
 	LSTAT_XLAYER_REWAKE,
 	LSTAT_LLC_DRAIN_TRY,
 	LSTAT_LLC_DRAIN,
 	LSTAT_SKIP_REMOTE_NODE,
+	LSTAT_BW_THROTTLE,
+	LSTAT_BW_SLICE_CUT,
+	LSTAT_CONTEND_WIN,
+	LSTAT_CONTEND_LOSS,
 	NR_LSTATS,
 };
 
@@ -238,6 +252,7 @@ struct llc_ctx {
 	u64			queued_runtime[MAX_LAYERS];
 	u64			lo_fb_seq;
 	u64			lstats[MAX_LAYERS][NR_LLC_LSTATS];
+	s64			contention_deficit[MAX_LAYERS];	/* CONTENTION_DRR */
 	struct llc_prox_map	prox_map;
 };
 
@@ -286,6 +301,7 @@ struct layer_match {
 	char		cgroup_suffix[MAX_PATH];
 	char		cgroup_substr[MAX_PATH];
 	u32		cgroup_regex_id;
+	u32		cgroup_match_id;	/* CGROUP_MATCH_ID_NONE if not pre-computed */
 	char		comm_prefix[MAX_COMM];
 	char		pcomm_prefix[MAX_COMM];
 	int		nice;
@@ -338,6 +354,19 @@ enum layer_task_place {
 	PLACEMENT_FLOAT,
 };
 
+enum layer_task_order {
+	ORDER_VTIME,
+	ORDER_FIFO,
+	ORDER_DEADLINE,
+};
+
+/* How layers with queued tasks contend for a CPU being dispatched */
+enum contention_policy {
+	CONTENTION_STRICT,		/* follow the CPU's layer order */
+	CONTENTION_WEIGHTED_RANDOM,	/* random pick weighted by layer weight */
+	CONTENTION_DRR,			/* deficit round-robin by layer weight */
+};
+
 struct layer {
 	struct layer_match_ands	matches[MAX_LAYER_MATCH_ORS];
 	unsigned int		nr_match_ors;
@@ -346,7 +375,7 @@ struct layer {
 	u64			max_exec_ns;
 	u64			yield_step_ns;
 	u64			slice_ns;
-	bool			fifo;
+	enum layer_task_order	task_order;
 	u32			weight;
 	u64			disallow_open_after_ns;
 	u64			disallow_preempt_after_ns;
@@ -359,6 +388,7 @@ struct layer {
 	bool			allow_node_aligned;
 	bool			skip_remote_node;
 	bool			prev_over_idle_core;
+	bool			keep_process_together;
 	int			growth_algo;
 
 	u64			nr_tasks;
@@ -383,6 +413,10 @@ struct layer {
 	bool			periodically_refresh;
 	u8			cpuset[MAX_CPUS_U8];
 	u64			member_expire_ms;
+
+	u64			bw_quota_ns;	/* 0 if util_cap is not set */
+	u64			bw_used_ns;
+	bool			bw_throttled;
 };
 
 struct scx_cmd {
@@ -391,6 +425,11 @@ struct scx_cmd {
 	u8			cmd[SCXCMD_COMLEN];
 } __attribute__((packed));
 
+struct restored_layer {
+	u32			layer_id;
+	u64			start_ticks;	/* /proc/PID/stat starttime, guards against pid reuse */
+};
+
 struct hint_layer_info {
 	u32			layer_id;
 	u64			system_cpu_util_below;	/* ratio * 10000, u64::MAX = disabled */
@@ -43,6 +43,7 @@ const volatile u32 nr_llcs = 32;	/* !0 for veristat, set during init */
 const volatile bool smt_enabled = true;
 const volatile bool has_little_cores = true;
 const volatile bool xnuma_preemption = false;
+const volatile u32 contention_policy = CONTENTION_STRICT;
 const volatile s32 __sibling_cpu[MAX_CPUS];
 const volatile bool monitor_disable = false;
 const volatile unsigned char all_cpus[MAX_CPUS_U8];
@@ -64,7 +65,10 @@ volatile u64 layer_refresh_seq_avgruntime;
 
 /* Flag to enable or disable antistall feature */
 const volatile bool enable_antistall = true;
+const volatile bool enable_util_cap = false;
 const volatile bool enable_match_debug = false;
+const volatile bool save_task_layers = false;
+const volatile bool restore_task_layers = false;
 const volatile bool enable_gpu_support = false;
 const volatile u32 nr_cgroup_regexes = 0;
 /* Delay permitted, in seconds, before antistall activates */
@@ -119,6 +123,13 @@ const volatile bool task_hint_map_enabled;
 
 /* EWMA value updated from userspace */
 u64 system_cpu_util_ewma = 0;
+
+/*
+ * Set from userspace while the system utilization is below
+ * --empty-machine-util-pct. Confined layers may then use any unprotected
+ * idle CPU so that they don't leave most of an idle machine unused.
+ */
+bool empty_machine = false;
 u64 layer_dsq_insert_ewma[MAX_LAYERS];
 
 static inline s32 prio_to_nice(s32 static_prio)
@@ -248,6 +259,31 @@ struct {
 	__uint(map_flags, BPF_F_NO_PREALLOC);
 } layer_match_dbg SEC(".maps");
 
+/*
+ * Task to layer assignments for --state-file. task_layers is maintained while
+ * running and saved by userspace on exit. restored_layers is filled from the
+ * state file of the previous instance on startup and consulted once per task
+ * instead of matching.
+ */
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__type(key, u32);
+	__type(value, u32);
+	__uint(max_entries, MAX_TASKS);
+	__uint(map_flags, BPF_F_NO_PREALLOC);
+} task_layers SEC(".maps");
+
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__type(key, u32);
+	__type(value, struct restored_layer);
+	__uint(max_entries, MAX_TASKS);
+	__uint(map_flags, BPF_F_NO_PREALLOC);
+} restored_layers SEC(".maps");
+
+/* Length of a clock tick in ns, the unit of restored_layer->start_ticks */
+const volatile u64 clock_tick_ns = NSEC_PER_SEC / 100;
+
 /*
  * Maps for storing GPU usage information. Keys are the tgid of the
  * task group that has recently used a GPU API call. The value represents
@@ -612,6 +648,9 @@ struct task_ctx {
 	u64			dsq_id;
 	u32			llc_id;
 
+	/* deadline added to dsq_vtime by ORDER_DEADLINE layers */
+	u64			dl_offset;
+
 	/* for llcc->queue_runtime */
 	u32			qrt_layer_id;
 	u32			qrt_llc_id;
@@ -1164,6 +1203,49 @@ out_put:
 	return cpu;
 }
 
+/*
+ * Home LLC of each process with threads in keep_process_together layers.
+ * Keyed by tgid. The home follows the threads when they have to leave it but
+ * only after PROC_LLC_MIG_DAMP_NS since the last move to avoid bouncing the
+ * whole process around.
+ */
+struct proc_llc {
+	u32			llc_id;
+	u64			moved_at;
+};
+
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__type(key, u32);
+	__type(value, struct proc_llc);
+	__uint(max_entries, MAX_TASKS);
+	__uint(map_flags, BPF_F_NO_PREALLOC);
+} proc_llcs SEC(".maps");
+
+static struct proc_llc *lookup_proc_llc(struct task_struct *p, u32 llc_id)
+{
+	struct proc_llc *procl, new_procl = { .llc_id = llc_id };
+	u32 tgid = p->tgid;
+
+	if ((procl = bpf_map_lookup_elem(&proc_llcs, &tgid)))
+		return procl;
+
+	new_procl.moved_at = bpf_ktime_get_ns();
+	bpf_map_update_elem(&proc_llcs, &tgid, &new_procl, BPF_NOEXIST);
+	return bpf_map_lookup_elem(&proc_llcs, &tgid);
+}
+
+static void maybe_move_proc_llc(struct proc_llc *procl, s32 cpu)
+{
+	u32 llc_id = cpu_to_llc_id(cpu);
+
+	if (llc_id == procl->llc_id)
+		return;
+
+	WRITE_ONCE(procl->llc_id, llc_id);
+	WRITE_ONCE(procl->moved_at, bpf_ktime_get_ns());
+}
+
 static __always_inline
 s32 pick_idle_cpu(struct task_struct *p, s32 prev_cpu,
 		  struct cpu_ctx *cpuc, struct task_ctx *taskc, struct layer *layer,
@@ -1172,6 +1254,7 @@ s32 pick_idle_cpu(struct task_struct *p, s32 prev_cpu,
 	const struct cpumask *idle_smtmask, *layer_cpumask, *layered_cpumask, *cpumask;
 	bool is_float = layer->task_place == PLACEMENT_FLOAT;
 	struct bpf_cpumask *unprot_mask;
+	struct proc_llc *procl = NULL;
 	struct cpu_ctx *prev_cpuc;
 	u32 layer_id = layer->id;
 	u64 cpus_seq;
@@ -1223,7 +1306,7 @@ s32 pick_idle_cpu(struct task_struct *p, s32 prev_cpu,
 		bool has_idle;
 		cpumask = scx_bpf_get_idle_cpumask();
 
-		if (layer->kind == LAYER_KIND_CONFINED) {
+		if (layer->kind == LAYER_KIND_CONFINED && !READ_ONCE(empty_machine)) {
 			has_idle = bpf_cpumask_intersects(layered_cpumask, cpumask);
 		} else {
 			maybe_refresh_layered_cpus_unprotected(p, taskc, layered_cpumask);
@@ -1266,6 +1349,31 @@ s32 pick_idle_cpu(struct task_struct *p, s32 prev_cpu,
 	if (cpu >=0)
 		goto out_put;
 
+	/*
+	 * Keep the threads of the same process in the process's home LLC. If
+	 * the home LLC is full, stay on the previous CPU unless the home hasn't
+	 * moved for a while, in which case let the task go and the home follow.
+	 */
+	if (nr_llcs > 1 && layer->keep_process_together &&
+	    (procl = lookup_proc_llc(p, prev_cpuc->llc_id))) {
+		u32 home_llc_id = READ_ONCE(procl->llc_id);
+
+		maybe_refresh_layered_cpus_llc(p, taskc, layer_cpumask,
+					       home_llc_id, cpus_seq);
+		if (!(cpumask = cast_mask(taskc->layered_llc_mask))) {
+			cpu = -1;
+			goto out_put;
+		}
+		if ((cpu = pick_idle_cpu_from(cpumask, prev_cpu, idle_smtmask, layer)) >= 0)
+			goto out_put;
+
+		if (bpf_ktime_get_ns() - READ_ONCE(procl->moved_at) < PROC_LLC_MIG_DAMP_NS) {
+			lstat_inc(LSTAT_PROC_LLC_KEEP, layer, cpuc);
+			cpu = -1;
+			goto out_put;
+		}
+	}
+
 	/*
 	 * Try a CPU in the previous LLC.
 	 */
@@ -1308,16 +1416,20 @@ no_locality:
 		goto out_put;
 
 	/*
-	 * If the layer is an open one, we can try the whole machine.
+	 * If the layer is an open one, we can try the whole machine. So can
+	 * confined layers while the machine is mostly empty.
 	 */
-	if (layer->kind != LAYER_KIND_CONFINED) {
+	if (layer->kind != LAYER_KIND_CONFINED || READ_ONCE(empty_machine)) {
 	    maybe_refresh_layered_cpus_unprotected(p, taskc, layered_cpumask);
 	    unprot_mask = taskc->layered_unprotected_mask;
 	    if (!unprot_mask)
 		    unprot_mask = unprotected_cpumask;
 
 	    if ((cpu = pick_idle_cpu_from(cast_mask(unprot_mask), prev_cpu, idle_smtmask, layer)) >= 0) {
-		lstat_inc(LSTAT_OPEN_IDLE, layer, cpuc);
+		if (layer->kind == LAYER_KIND_CONFINED)
+			lstat_inc(LSTAT_EMPTY_IDLE, layer, cpuc);
+		else
+			lstat_inc(LSTAT_OPEN_IDLE, layer, cpuc);
 		goto out_put;
 	    }
 	}
@@ -1334,6 +1446,8 @@ out_put:
 	if (cpu >= 0) {
 		if (READ_ONCE(layer->check_no_idle))
 			WRITE_ONCE(layer->check_no_idle, false);
+		if (procl)
+			maybe_move_proc_llc(procl, cpu);
 	} else if (taskc->all_cpuset_allowed) {
 		if (!READ_ONCE(layer->check_no_idle))
 			WRITE_ONCE(layer->check_no_idle, true);
@@ -1368,6 +1482,31 @@ bool maybe_update_task_llc(struct task_struct *p, struct task_ctx *taskc, s32 ne
 	return true;
 }
 
+/*
+ * ORDER_DEADLINE layers queue tasks by their vtime plus a deadline which is
+ * proportional to the expected runtime and inversely proportional to the
+ * weight, so that short running tasks get ahead of CPU hogs without giving up
+ * fairness. The offset is only for ordering within the DSQ and must not be
+ * charged to the task, strip it once the task leaves the DSQ.
+ */
+static u64 task_dl_offset(struct task_struct *p, struct task_ctx *taskc,
+			  struct layer *layer)
+{
+	u64 runtime = taskc->runtime_avg;
+
+	if (runtime > layer->slice_ns)
+		runtime = layer->slice_ns;
+	return runtime * 100 / p->scx.weight;
+}
+
+static void task_strip_dl_offset(struct task_struct *p, struct task_ctx *taskc)
+{
+	if (taskc->dl_offset) {
+		p->scx.dsq_vtime -= taskc->dl_offset;
+		taskc->dl_offset = 0;
+	}
+}
+
 s32 BPF_STRUCT_OPS(layered_select_cpu, struct task_struct *p, s32 prev_cpu, u64 wake_flags)
 {
 	struct cpu_ctx *cpuc;
@@ -1389,6 +1528,13 @@ s32 BPF_STRUCT_OPS(layered_select_cpu, struct task_struct *p, s32 prev_cpu, u64
 	if (taskc->layer_id == MAX_LAYERS || !(layer = lookup_layer(taskc->layer_id)))
 		return prev_cpu;
 
+	/*
+	 * A layer over its util_cap budget waits in its DSQ, which isn't
+	 * consumed until the budget is replenished. Don't claim an idle CPU.
+	 */
+	if (READ_ONCE(layer->bw_throttled))
+		return prev_cpu;
+
 	if (layer->task_place == PLACEMENT_STICK)
 		cpu = prev_cpu;
 	else
@@ -1576,7 +1722,7 @@ void BPF_STRUCT_OPS(layered_enqueue, struct task_struct *p, u64 enq_flags)
 	bool wakeup = enq_flags & SCX_ENQ_WAKEUP;
 	s32 cpu, task_cpu = scx_bpf_task_cpu(p);
 	u32 llc_id, layer_id;
-	bool yielding, try_preempt_first;
+	bool yielding, try_preempt_first, throttled;
 	u64 queued_runtime;
 	u64 *lstats;
 
@@ -1611,26 +1757,35 @@ void BPF_STRUCT_OPS(layered_enqueue, struct task_struct *p, u64 enq_flags)
 	try_preempt_first = cpuc->try_preempt_first;
 	cpuc->try_preempt_first = false;
 
+	/*
+	 * A layer over its util_cap budget neither direct dispatches nor
+	 * preempts, see layered_select_cpu().
+	 */
+	throttled = READ_ONCE(layer->bw_throttled);
+
 	/*
 	 * Does @p prefer to preempt its previous CPU even when there are other
 	 * idle CPUs? If @p was already on the CPU (!wakeup), layered_dispatch()
 	 * already decided that @p shouldn't continue running on it. Don't
 	 * override the decision.
 	 */
-	if (try_preempt_first && wakeup && !yielding &&
+	if (try_preempt_first && wakeup && !yielding && !throttled &&
 	    try_preempt_cpu(task_cpu, p, taskc, layer, PREEMPT_FIRST))
 		return;
 
 	/*
 	 * If select_cpu() was skipped, try direct dispatching to an idle CPU.
 	 */
-	if (!__COMPAT_is_enq_cpu_selected(enq_flags) || try_preempt_first) {
+	if ((!__COMPAT_is_enq_cpu_selected(enq_flags) || try_preempt_first) && !throttled) {
 		cpu = pick_idle_cpu(p, task_cpu, cpuc, taskc, layer, false);
 		if (cpu < 0)
 			goto skip_ddsp;
 
-		/* Non-confined layers can run anywhere. */
-		if (layer->kind != LAYER_KIND_CONFINED)
+		/*
+		 * Non-confined layers can run anywhere, and so can confined
+		 * ones while the machine is mostly empty.
+		 */
+		if (layer->kind != LAYER_KIND_CONFINED || READ_ONCE(empty_machine))
 			goto do_ddsp;
 
 		struct cpu_ctx *target_cpuc = lookup_cpu_ctx(cpu);
@@ -1656,7 +1811,7 @@ skip_ddsp:
 	/*
 	 * No idle CPU, try preempting.
 	 */
-	if (layer->preempt && !yielding) {
+	if (layer->preempt && !yielding && !throttled) {
 		/*
 		 * See try_preempt_first block above for explanation on the
 		 * wakeup test.
@@ -1749,6 +1904,7 @@ skip_ddsp:
 	 * 8192 came from 100x for min weight, 20x for typical max_exec_us, and
 	 * ~4x for buffer.
 	 */
+	task_strip_dl_offset(p, taskc);
 	maybe_update_task_llc(p, taskc, task_cpu);
 
 	u64 vtime = p->scx.dsq_vtime;
@@ -1831,10 +1987,19 @@ skip_ddsp:
 	lstats[LLC_LSTAT_CNT]++;
 
 	taskc->dsq_id = layer_dsq_id(layer_id, llc_id);
-	if (layer->fifo)
+	switch (layer->task_order) {
+	case ORDER_FIFO:
 		scx_bpf_dsq_insert(p, taskc->dsq_id, layer->slice_ns, enq_flags);
-	else
+		break;
+	case ORDER_DEADLINE:
+		taskc->dl_offset = task_dl_offset(p, taskc, layer);
+		scx_bpf_dsq_insert_vtime(p, taskc->dsq_id, layer->slice_ns,
+					 vtime + taskc->dl_offset, enq_flags);
+		break;
+	default:
 		scx_bpf_dsq_insert_vtime(p, taskc->dsq_id, layer->slice_ns, vtime, enq_flags);
+		break;
+	}
 	lstat_inc(LSTAT_ENQ_DSQ, layer, cpuc);
 
 	/*
@@ -1872,8 +2037,27 @@ static inline void check_member_expired(struct task_ctx *taskc, u64 now)
 		taskc->refresh_layer = true;
 }
 
+/*
+ * Charge @used to @layer's bandwidth budget for the current period and
+ * throttle the layer once the budget is exhausted. The budget is replenished
+ * by util_cap_replenish().
+ */
+static void layer_charge_bw(struct layer *layer, struct cpu_ctx *cpuc, u64 used)
+{
+	if (!layer->bw_quota_ns)
+		return;
+
+	if (__sync_fetch_and_add(&layer->bw_used_ns, used) + used < layer->bw_quota_ns ||
+	    READ_ONCE(layer->bw_throttled))
+		return;
+
+	WRITE_ONCE(layer->bw_throttled, true);
+	lstat_inc(LSTAT_BW_THROTTLE, layer, cpuc);
+}
+
 static void account_used(struct task_struct *p, struct cpu_ctx *cpuc, struct task_ctx *taskc, u64 now)
 {
+	struct layer *layer;
 	s32 task_lid;
 	u64 used;
 	u64 bytes;
@@ -1926,6 +2110,9 @@ static void account_used(struct task_struct *p, struct cpu_ctx *cpuc, struct tas
 
 	if (cpuc->running_fallback)
 		gstat_add(GSTAT_FB_CPU_USAGE, cpuc, used);
+
+	if (enable_util_cap && (layer = lookup_layer(task_lid)))
+		layer_charge_bw(layer, cpuc, used);
 }
 
 static bool keep_running(struct cpu_ctx *cpuc, struct task_struct *p,
@@ -1934,6 +2121,10 @@ static bool keep_running(struct cpu_ctx *cpuc, struct task_struct *p,
 	if (cpuc->yielding || !max_exec_ns)
 		goto no;
 
+	/* throttled layers don't get to continue */
+	if (READ_ONCE(layer->bw_throttled))
+		goto no;
+
 	/* does it wanna? */
 	if (!(p->scx.flags & SCX_TASK_QUEUED))
 		goto no;
@@ -2189,6 +2380,10 @@ static __always_inline bool try_consume_layer(u32 layer_id, struct cpu_ctx *cpuc
 	if (layer->kind == LAYER_KIND_CONFINED && cpuc->layer_id != layer_id)
 		return false;
 
+	/* the layer exhausted its util_cap budget for the current period */
+	if (READ_ONCE(layer->bw_throttled))
+		return false;
+
 	skip_remote_node = layer->skip_remote_node;
 
 	bpf_for(u, 0, llc_pmap->sys_end) {
@@ -2226,25 +2421,192 @@ static __always_inline bool try_consume_layer(u32 layer_id, struct cpu_ctx *cpuc
 	return false;
 }
 
+/*
+ * Layers which stop contending keep their DRR deficits and CPUs in the same
+ * LLC race updating them. Pull @deficit, the value just written to @dp, back
+ * into bounds so that no layer can build up an unbounded credit or debt.
+ */
+#define DRR_MAX_DEFICIT		((s64)MAX_LAYER_WEIGHT * MAX_LAYERS)
+
+static __always_inline s64 clamp_deficit(s64 *dp, s64 deficit)
+{
+	if (deficit > DRR_MAX_DEFICIT) {
+		__sync_fetch_and_add(dp, DRR_MAX_DEFICIT - deficit);
+		return DRR_MAX_DEFICIT;
+	}
+	if (deficit < -DRR_MAX_DEFICIT) {
+		__sync_fetch_and_add(dp, -DRR_MAX_DEFICIT - deficit);
+		return -DRR_MAX_DEFICIT;
+	}
+	return deficit;
+}
+
+/*
+ * Find the layers in @layer_order contending for @cpuc, i.e. the ones which
+ * have tasks queued on @llcc and may run on @cpuc. If more than one layer
+ * contends, return their mask and set @winner to the index in @layer_order
+ * of the layer to try first according to contention_policy, or to -1 to
+ * follow @layer_order. Otherwise, return 0.
+ */
+static __noinline u32 layer_contenders(u32 *layer_order, u32 nr, u32 exclude_layer_id,
+				       struct cpu_ctx *cpuc, struct llc_ctx *llcc,
+				       s32 *winner)
+{
+	u64 sum_weight = 0, seen_weight = 0, nr_contenders = 0;
+	s64 max_deficit = 0, deficit;
+	struct layer *layer;
+	u32 mask = 0, u;
+
+	*winner = -1;
+
+	if (nr >= MAX_LAYERS)
+		return 0;
+
+	bpf_for(u, 0, nr) {
+		u32 layer_id = layer_order[u];
+
+		if (layer_id == exclude_layer_id || !(layer = lookup_layer(layer_id)))
+			continue;
+
+		/* same conditions as try_consume_layer() */
+		if (layer->kind == LAYER_KIND_CONFINED && cpuc->layer_id != layer_id)
+			continue;
+		if (READ_ONCE(layer->bw_throttled))
+			continue;
+
+		if (!scx_bpf_dsq_nr_queued(layer_dsq_id(layer_id, llcc->id)))
+			continue;
+
+		mask |= 1 << layer_id;
+		sum_weight += layer->weight;
+		nr_contenders++;
+	}
+
+	if (nr_contenders < 2)
+		return 0;
+
+	if (contention_policy == CONTENTION_STRICT)
+		return mask;
+
+	bpf_for(u, 0, nr) {
+		u32 layer_id = layer_order[u];
+
+		if (!(mask & (1 << layer_id)) || !(layer = lookup_layer(layer_id)))
+			continue;
+
+		switch (contention_policy) {
+		case CONTENTION_WEIGHTED_RANDOM:
+			/*
+			 * Weighted reservoir sampling: the layer replaces the
+			 * current pick with the probability of its share of the
+			 * weights seen so far.
+			 */
+			seen_weight += layer->weight;
+			if (bpf_get_prandom_u32() % seen_weight < layer->weight)
+				*winner = u;
+			break;
+		case CONTENTION_DRR:
+			/*
+			 * Every contending layer earns its weight and the
+			 * layer with the largest deficit wins and pays for
+			 * the round, so that the layers win in proportion to
+			 * their weights in a deterministic order. The deficits
+			 * are per LLC as that's where the layers contend, and
+			 * are shared by the LLC's CPUs, so update atomically.
+			 */
+			if (layer_id >= MAX_LAYERS)
+				break;
+			deficit = __sync_fetch_and_add(&llcc->contention_deficit[layer_id],
+						       layer->weight) + layer->weight;
+			deficit = clamp_deficit(&llcc->contention_deficit[layer_id], deficit);
+			if (*winner < 0 || deficit > max_deficit) {
+				max_deficit = deficit;
+				*winner = u;
+			}
+			break;
+		}
+	}
+
+	if (contention_policy == CONTENTION_DRR && *winner >= 0 && *winner < MAX_LAYERS) {
+		u32 layer_id = layer_order[*winner];
+
+		if (layer_id >= MAX_LAYERS)
+			return mask;
+
+		deficit = __sync_fetch_and_sub(&llcc->contention_deficit[layer_id],
+					       (s64)sum_weight) - (s64)sum_weight;
+		clamp_deficit(&llcc->contention_deficit[layer_id], deficit);
+	}
+
+	return mask;
+}
+
+/*
+ * Count a contention win for @winner_id and a loss for the other layers in
+ * @contenders.
+ */
+static __noinline void record_contention(u32 contenders, u32 winner_id,
+					 struct cpu_ctx *cpuc)
+{
+	struct layer *layer;
+	u32 layer_id;
+
+	bpf_for(layer_id, 0, nr_layers) {
+		if (!(contenders & (1 << layer_id)) || layer_id == winner_id)
+			continue;
+		if ((layer = lookup_layer(layer_id)))
+			lstat_inc(LSTAT_CONTEND_LOSS, layer, cpuc);
+	}
+
+	if ((layer = lookup_layer(winner_id)))
+		lstat_inc(LSTAT_CONTEND_WIN, layer, cpuc);
+}
+
 static __always_inline
 bool try_consume_layers(u32 *layer_order, u32 nr, u32 exclude_layer_id,
 			struct cpu_ctx *cpuc, struct llc_ctx *llcc)
 {
-	u32 u;
+	u32 contenders = 0, u;
+	s32 winner = -1;
 
 	if (nr >= MAX_LAYERS) {
 		scx_bpf_error("nr=%u too high", nr);
 		return false;
 	}
 
-	bpf_for(u, 0, nr) {
-		u32 layer_id = layer_order[u];
+	if (nr > 1)
+		contenders = layer_contenders(layer_order, nr, exclude_layer_id,
+					      cpuc, llcc, &winner);
+
+	/*
+	 * Try the contention winner first, if any, and then the rest in
+	 * @layer_order.
+	 */
+	bpf_for(u, 0, nr + 1) {
+		u32 idx, layer_id;
+
+		if (u == 0) {
+			if (winner < 0)
+				continue;
+			idx = winner;
+		} else {
+			idx = u - 1;
+			if ((s32)idx == winner)
+				continue;
+		}
+
+		if (idx >= MAX_LAYERS)
+			break;
+		layer_id = layer_order[idx];
 
 		if (layer_id == exclude_layer_id)
 			continue;
 
-		if (try_consume_layer(layer_id, cpuc, llcc))
+		if (try_consume_layer(layer_id, cpuc, llcc)) {
+			if (contenders)
+				record_contention(contenders, layer_id, cpuc);
 			return true;
+		}
 	}
 
 	return false;
@@ -2461,7 +2823,8 @@ replenish:
          * Do not refresh the slice in case we need the task to be reenqueued
          * for layer membership change and subsequent CPU selection.
          */
-        if (prev_taskc && prev_layer && !is_task_layer_hint_stale(prev, prev_taskc))
+        if (prev_taskc && prev_layer && !READ_ONCE(prev_layer->bw_throttled) &&
+	    !is_task_layer_hint_stale(prev, prev_taskc))
 		prev->scx.slice = prev_layer->slice_ns;
 }
 
@@ -2469,11 +2832,46 @@ void BPF_STRUCT_OPS(layered_tick, struct task_struct *p)
 {
 	struct cpu_ctx *cpuc;
 	struct task_ctx *taskc;
+	struct layer *layer;
 
 	if (!(cpuc = lookup_cpu_ctx(-1)) || !(taskc = lookup_task_ctx(p)))
 		return;
 
 	account_used(p, cpuc, taskc, scx_bpf_now());
+
+	/*
+	 * If the layer exhausted its util_cap budget, cut the slice short so
+	 * that the CPU goes through dispatch which won't pick the layer again
+	 * until the budget is replenished.
+	 */
+	if (enable_util_cap && (layer = lookup_layer(taskc->layer_id)) &&
+	    READ_ONCE(layer->bw_throttled) && p->scx.slice) {
+		p->scx.slice = 0;
+		lstat_inc(LSTAT_BW_SLICE_CUT, layer, cpuc);
+	}
+}
+
+/*
+ * Look up the result of a cgroup prefix/suffix/substr match pre-computed by
+ * the userspace cgroup watcher. Returns false if the result isn't available
+ * (e.g. the cgroup was just created), in which case the match should be
+ * evaluated against the cgroup path.
+ */
+static bool lookup_cgroup_match(struct task_struct *p, struct layer_match *match,
+				bool *result)
+{
+	u64 cgroup_id, *bitmap_ptr;
+
+	if (match->cgroup_match_id >= MAX_CGROUP_MATCH_IDS)
+		return false;
+
+	cgroup_id = p->cgroups->dfl_cgrp->kn->id;
+	bitmap_ptr = bpf_map_lookup_elem(&cgroup_match_bitmap, &cgroup_id);
+	if (!bitmap_ptr)
+		return false;
+
+	*result = *bitmap_ptr & (1ULL << match->cgroup_match_id);
+	return true;
 }
 
 static __noinline bool match_one(struct layer *layer, struct layer_match *match, struct task_ctx *taskc,
@@ -2484,12 +2882,18 @@ static __noinline bool match_one(struct layer *layer, struct layer_match *match,
 
 	switch (match->kind) {
 	case MATCH_CGROUP_PREFIX: {
+		if (lookup_cgroup_match(p, match, &result))
+			return result;
 		return match_str(match->cgroup_prefix, cgrp_path, STR_PREFIX);
 	}
 	case MATCH_CGROUP_SUFFIX: {
+		if (lookup_cgroup_match(p, match, &result))
+			return result;
 		return match_str(match->cgroup_suffix, cgrp_path, STR_SUFFIX);
 	}
 	case MATCH_CGROUP_CONTAINS: {
+		if (lookup_cgroup_match(p, match, &result))
+			return result;
 		return match_str(match->cgroup_substr, cgrp_path, STR_SUBSTR);
 	}
 	case MATCH_CGROUP_REGEX: {
@@ -2823,6 +3227,12 @@ static void switch_to_layer(struct task_struct *p, struct task_ctx *taskc, u64 l
 	}
 	__sync_fetch_and_add(&layer->nr_tasks, 1);
 
+	if (save_task_layers) {
+		u32 pid = p->pid, lid = layer_id;
+
+		bpf_map_update_elem(&task_layers, &pid, &lid, BPF_ANY);
+	}
+
 	refresh_cpus_flags(taskc, p->cpus_ptr);
 
 	/*
@@ -2836,6 +3246,7 @@ static void switch_to_layer(struct task_struct *p, struct task_ctx *taskc, u64 l
 	 * needs to be supported.
 	 */
 	p->scx.dsq_vtime = llcc->vtime_now[layer_id];
+	taskc->dl_offset = 0;
 }
 
 static void maybe_refresh_layer(struct task_struct *p __arg_trusted, struct task_ctx *taskc, u64 now)
@@ -2847,6 +3258,38 @@ static void maybe_refresh_layer(struct task_struct *p __arg_trusted, struct task
 	if (!taskc->refresh_layer)
 		return;
 
+	/*
+	 * On a rolling restart, put the task back into the layer it was in
+	 * under the previous instance instead of matching it again. This only
+	 * applies to the first assignment, later refreshes match as usual.
+	 */
+	if (restore_task_layers && taskc->layer_id == MAX_LAYERS) {
+		struct restored_layer *restored;
+		struct cpu_ctx *cpuc;
+		u32 pid = p->pid;
+
+		if ((restored = bpf_map_lookup_elem(&restored_layers, &pid))) {
+			/*
+			 * The pid may have been reused by an unrelated task
+			 * since the state was saved, in which case the start
+			 * time doesn't match.
+			 */
+			bool same_task = p->start_boottime / clock_tick_ns ==
+					 restored->start_ticks;
+
+			layer_id = restored->layer_id;
+			bpf_map_delete_elem(&restored_layers, &pid);
+			if (same_task && layer_id < nr_layers) {
+				taskc->refresh_layer = false;
+				taskc->layer_refresh_seq = layer_refresh_seq_avgruntime;
+				switch_to_layer(p, taskc, layer_id, now);
+				if ((cpuc = lookup_cpu_ctx(-1)))
+					gstat_inc(GSTAT_RESTORED_LAYER, cpuc);
+				return;
+			}
+		}
+	}
+
 	/*
 	 * If cgroup regex matching is configured, check if the cgroup bitmap
 	 * entry is ready. If not, return without clearing refresh_layer so we
@@ -3102,6 +3545,7 @@ void BPF_STRUCT_OPS(layered_running, struct task_struct *p)
 	}
 	taskc->last_cpu = task_cpu;
 
+	task_strip_dl_offset(p, taskc);
 	maybe_update_task_llc(p, taskc, task_cpu);
 	if (time_before(llcc->vtime_now[layer_id], p->scx.dsq_vtime))
 		llcc->vtime_now[layer_id] = p->scx.dsq_vtime;
@@ -3501,6 +3945,14 @@ void BPF_STRUCT_OPS(layered_exit_task, struct task_struct *p,
 	if (enable_match_debug && (pid = p->pid))
 		bpf_map_delete_elem(&layer_match_dbg, &pid);
 
+	if (save_task_layers && (pid = p->pid))
+		bpf_map_delete_elem(&task_layers, &pid);
+
+	if (p->pid == p->tgid) {
+		pid = p->tgid;
+		bpf_map_delete_elem(&proc_llcs, &pid);
+	}
+
 	if (!(cpuc = lookup_cpu_ctx(-1)) || !(taskc = lookup_task_ctx(p)))
 		return;
 
@@ -3630,6 +4082,7 @@ void BPF_STRUCT_OPS(layered_dump, struct scx_dump_ctx *dctx)
  */
 struct layered_timer layered_timers[MAX_TIMERS] = {
 	{15LLU * NSEC_PER_SEC, CLOCK_BOOTTIME, 0},
+	{BW_PERIOD_NS, CLOCK_BOOTTIME, 0},
 };
 
 /**
@@ -3743,6 +4196,33 @@ static u64 antistall_scan(void)
 	return layered_timers[ANTISTALL_TIMER].interval_ns;
 }
 
+/*
+ * Start a new bandwidth period for util_cap layers. Throttled layers get
+ * their budgets back and an idle CPU is kicked so that their queued tasks
+ * don't have to wait for an unrelated scheduling event.
+ */
+static u64 util_cap_replenish(void)
+{
+	struct layer *layer;
+	u32 layer_id;
+
+	if (!enable_util_cap)
+		return 0;
+
+	bpf_for(layer_id, 0, nr_layers) {
+		if (!(layer = lookup_layer(layer_id)))
+			break;
+
+		WRITE_ONCE(layer->bw_used_ns, 0);
+		if (READ_ONCE(layer->bw_throttled)) {
+			WRITE_ONCE(layer->bw_throttled, false);
+			layer_kick_idle_cpu(layer);
+		}
+	}
+
+	return layered_timers[UTIL_CAP_TIMER].interval_ns;
+}
+
 /*
  * Timer callback that runs all registered timers. If a timer returns a non
  * zero value it is rerun after the return value (in nanoseconds).
@@ -3752,6 +4232,8 @@ u64 run_timer_cb(int key)
 	switch (key) {
 	case ANTISTALL_TIMER:
 		return antistall_scan();
+	case UTIL_CAP_TIMER:
+		return util_cap_replenish();
 	case MAX_TIMERS:
 	default:
 		return 0;
@@ -2,6 +2,7 @@
 
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2.
+use std::collections::BTreeMap;
 use std::fs;
 use std::io::Read;
 
@@ -26,6 +27,8 @@ pub struct LayerSpec {
     #[serde(skip)]
     pub cpuset: Option<Cpumask>,
     pub comment: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
     pub template: Option<LayerMatch>,
     pub matches: Vec<Vec<LayerMatch>>,
     pub kind: LayerKind,
@@ -63,6 +66,17 @@ impl LayerSpec {
     }
 }
 
+impl LayerCommon {
+    /// The ordering within the layer. The older "fifo" flag is still
+    /// honored and equivalent to Fifo.
+    pub fn ordering(&self) -> LayerOrdering {
+        match self.fifo {
+            true => LayerOrdering::Fifo,
+            false => self.ordering.clone(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub enum LayerPlacement {
     #[default]
@@ -71,6 +85,14 @@ pub enum LayerPlacement {
     Floating,
 }
 
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LayerOrdering {
+    #[default]
+    Vtime,
+    Fifo,
+    Deadline,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum LayerMatch {
     CgroupPrefix(String),
@@ -114,6 +136,8 @@ pub struct LayerCommon {
     #[serde(default)]
     pub fifo: bool,
     #[serde(default)]
+    pub ordering: LayerOrdering,
+    #[serde(default)]
     pub preempt: bool,
     #[serde(default)]
     pub preempt_first: bool,
@@ -126,6 +150,8 @@ pub struct LayerCommon {
     #[serde(default)]
     pub prev_over_idle_core: bool,
     #[serde(default)]
+    pub keep_process_together: bool,
+    #[serde(default)]
     pub weight: u32,
     #[serde(default)]
     pub disallow_open_after_us: Option<u64>,
@@ -164,6 +190,9 @@ pub enum LayerKind {
         #[serde(default)]
         membw_gb: Option<f64>,
 
+        #[serde(default)]
+        util_cap: bool,
+
         #[serde(default)]
         protected: bool,
 
@@ -183,6 +212,9 @@ pub enum LayerKind {
         #[serde(default)]
         membw_gb: Option<f64>,
 
+        #[serde(default)]
+        util_cap: bool,
+
         #[serde(default)]
         protected: bool,
 
@@ -229,6 +261,13 @@ impl LayerKind {
         }
     }
 
+    pub fn util_cap(&self) -> bool {
+        match self {
+            LayerKind::Confined { util_cap, .. } | LayerKind::Grouped { util_cap, .. } => *util_cap,
+            _ => false,
+        }
+    }
+
     pub fn util_includes_open_cputime(&self) -> bool {
         match self {
             LayerKind::Grouped {
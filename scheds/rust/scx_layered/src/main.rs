@@ -3,6 +3,8 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2.
 mod bpf_skel;
+mod plan;
+mod state;
 mod stats;
 
 use std::collections::BTreeMap;
@@ -66,11 +68,13 @@ use scx_utils::CoreType;
 use scx_utils::Cpumask;
 use scx_utils::Llc;
 use scx_utils::NetDev;
+use scx_utils::SchedThreadArgs;
 use scx_utils::Topology;
 use scx_utils::TopologyArgs;
 use scx_utils::UserExitInfo;
 use scx_utils::NR_CPUS_POSSIBLE;
 use scx_utils::NR_CPU_IDS;
+use state::SchedState;
 use stats::LayerStats;
 use stats::StatsReq;
 use stats::StatsRes;
@@ -123,6 +127,7 @@ lazy_static! {
                 name: "batch".into(),
                 comment: Some("tasks under system.slice or tasks with nice value > 0".into()),
                 cpuset: None,
+                metadata: BTreeMap::new(),
                 template: None,
                 matches: vec![
                     vec![LayerMatch::CgroupPrefix("system.slice/".into())],
@@ -134,6 +139,7 @@ lazy_static! {
                     cpus_range_frac: None,
                     protected: false,
                     membw_gb: None,
+                    util_cap: false,
                     common: LayerCommon {
                         min_exec_us: 1000,
                         yield_ignore: 0.0,
@@ -143,9 +149,11 @@ lazy_static! {
                         allow_node_aligned: false,
                         skip_remote_node: false,
                         prev_over_idle_core: false,
+                        keep_process_together: false,
                         idle_smt: None,
                         slice_us: 20000,
                         fifo: false,
+                        ordering: LayerOrdering::Vtime,
                         weight: DEFAULT_LAYER_WEIGHT,
                         disallow_open_after_us: None,
                         disallow_preempt_after_us: None,
@@ -164,6 +172,7 @@ lazy_static! {
                 name: "immediate".into(),
                 comment: Some("tasks under workload.slice with nice value < 0".into()),
                 cpuset: None,
+                metadata: BTreeMap::new(),
                 template: None,
                 matches: vec![vec![
                     LayerMatch::CgroupPrefix("workload.slice/".into()),
@@ -179,9 +188,11 @@ lazy_static! {
                         allow_node_aligned: true,
                         skip_remote_node: false,
                         prev_over_idle_core: true,
+                        keep_process_together: false,
                         idle_smt: None,
                         slice_us: 20000,
                         fifo: false,
+                        ordering: LayerOrdering::Vtime,
                         weight: DEFAULT_LAYER_WEIGHT,
                         disallow_open_after_us: None,
                         disallow_preempt_after_us: None,
@@ -200,6 +211,7 @@ lazy_static! {
                 name: "stress-ng".into(),
                 comment: Some("stress-ng test layer".into()),
                 cpuset: None,
+                metadata: BTreeMap::new(),
                 template: None,
                 matches: vec![
                     vec![LayerMatch::CommPrefix("stress-ng".into()),],
@@ -211,6 +223,7 @@ lazy_static! {
                     protected: false,
                     cpus_range_frac: None,
                     membw_gb: None,
+                    util_cap: false,
                     common: LayerCommon {
                         min_exec_us: 800,
                         yield_ignore: 0.0,
@@ -220,9 +233,11 @@ lazy_static! {
                         allow_node_aligned: false,
                         skip_remote_node: false,
                         prev_over_idle_core: false,
+                        keep_process_together: false,
                         idle_smt: None,
                         slice_us: 800,
                         fifo: false,
+                        ordering: LayerOrdering::Vtime,
                         weight: DEFAULT_LAYER_WEIGHT,
                         disallow_open_after_us: None,
                         disallow_preempt_after_us: None,
@@ -241,6 +256,7 @@ lazy_static! {
                 name: "normal".into(),
                 comment: Some("the rest".into()),
                 cpuset: None,
+                metadata: BTreeMap::new(),
                 template: None,
                 matches: vec![vec![]],
                 kind: LayerKind::Grouped {
@@ -250,6 +266,7 @@ lazy_static! {
                     protected: false,
                     cpus_range_frac: None,
                     membw_gb: None,
+                    util_cap: false,
                     common: LayerCommon {
                         min_exec_us: 200,
                         yield_ignore: 0.0,
@@ -259,9 +276,11 @@ lazy_static! {
                         allow_node_aligned: false,
                         skip_remote_node: false,
                         prev_over_idle_core: false,
+                        keep_process_together: false,
                         idle_smt: None,
                         slice_us: 20000,
                         fifo: false,
+                        ordering: LayerOrdering::Vtime,
                         weight: DEFAULT_LAYER_WEIGHT,
                         disallow_open_after_us: None,
                         disallow_preempt_after_us: None,
@@ -280,6 +299,17 @@ lazy_static! {
     };
 }
 
+/// How layers with queued tasks contend for a CPU. See --contention.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum ContentionPolicy {
+    /// Follow the CPU's layer order.
+    Strict,
+    /// Pick randomly, weighted by the layer weights.
+    WeightedRandom,
+    /// Deficit round-robin by the layer weights.
+    Drr,
+}
+
 /// scx_layered: A highly configurable multi-layer sched_ext scheduler
 ///
 /// scx_layered allows classifying tasks into multiple layers and applying
@@ -434,6 +464,17 @@ lazy_static! {
 ///   layers. Tasks in this group will spill into occupied CPUs if there are
 ///   no unoccupied idle CPUs.
 ///
+/// Confined and Grouped layers can set "util_cap" to enforce the upper
+/// bound of "util_range" as a hard bandwidth cap. Normally, exceeding the
+/// upper bound only grows the layer's CPU allocation. With "util_cap", the
+/// layer is additionally throttled once it consumes more than the upper
+/// bound times its allocated CPUs within a 100ms period: its tasks are no
+/// longer dispatched, directly to idle CPUs or by preempting either, and
+/// running tasks have their slices cut short until the next period. This is
+/// useful for strict isolation between tenants. Per-CPU kthreads, the
+/// scheduler's own tasks and tasks with restricted affinity, which go
+/// through the fallback DSQs, are exempt.
+///
 /// All layers take the following options:
 ///
 /// - min_exec_us: Minimum execution time in microseconds. Whenever a task
@@ -446,7 +487,15 @@ lazy_static! {
 ///
 /// - slice_us: Scheduling slice duration in microseconds.
 ///
-/// - fifo: Use FIFO queues within the layer instead of the default vtime.
+/// - ordering: How tasks are ordered within the layer. "Vtime", the
+///   default, orders by weighted virtual time so that CPU time is shared
+///   fairly. "Fifo" runs tasks in the order they were enqueued, which is
+///   the cheapest and suits batch layers. "Deadline" orders by virtual
+///   time plus a deadline proportional to the task's average runtime so
+///   that short running tasks get ahead of CPU hogs, which suits
+///   interactive layers.
+///
+/// - fifo: *** DEPRECATED *** Same as "ordering": "Fifo".
 ///
 /// - preempt: If true, tasks in the layer will preempt tasks which belong
 ///   to other non-preempting layers when no idle CPUs are available.
@@ -467,11 +516,21 @@ lazy_static! {
 ///   when picking a CPU for tasks on this layer, even if that CPUs SMT
 ///   sibling is processing a task.
 ///
+/// - keep_process_together: On multi-LLC systems, keep the threads of the
+///   same process within a single LLC of the layer's CPUs. If the LLC is
+///   busy, tasks stay put instead of migrating unless the process hasn't
+///   moved for 100ms, which keeps processes that span multiple CCDs, e.g.
+///   game engines and JVMs, from bouncing across them.
+///
 /// - weight: Weight of the layer, which is a range from 1 to 10000 with a
 ///   default of 100. Layer weights are used during contention to prevent
 ///   starvation across layers. Weights are used in combination with
 ///   utilization to determine the infeasible adjusted weight with higher
 ///   weights having a larger adjustment in adjusted utilization.
+///   If a layer's weight entitles it to more CPUs than its cpuset and
+///   cpus_range allow, the weight is lowered to what the layer can use and
+///   the excess is distributed among the other layers. The effective
+///   weights are logged and reported in the stats.
 ///
 /// - disallow_open_after_us: Duration to wait after machine reaches saturation
 ///   before confining tasks in Open layers.
@@ -526,6 +585,17 @@ lazy_static! {
 ///
 ///   $ scx_layered -e example.json
 ///
+/// A layer config can also carry a "metadata" object of arbitrary strings,
+/// e.g. the owner of the layer, a ticket and a description. It doesn't
+/// affect scheduling and is reported as is in the per-layer statistics, so
+/// that whoever inspects a misbehaving layer knows who to contact:
+///
+///   "metadata": {
+///     "owner": "web-team",
+///     "ticket": "T123456",
+///     "description": "frontend request handlers"
+///   },
+///
 /// Note that the last layer in the configuration must have an empty match set
 /// as a catch-all for tasks which haven't been matched into previous layers.
 ///
@@ -613,6 +683,17 @@ struct Opts {
     #[clap(long)]
     xnuma_preemption: bool,
 
+    /// How layers with tasks queued on a CPU's LLC contend for the CPU.
+    /// "strict" follows the CPU's layer order, which prefers the layers
+    /// owning the CPU. "weighted-random" picks a layer randomly in
+    /// proportion to the layer weights. "drr" lets the layers win in
+    /// proportion to their weights in a deterministic deficit round-robin
+    /// order. The other layers are tried in the CPU's layer order if the
+    /// picked one has nothing to run. Per-layer win/loss counts are
+    /// reported in the stats.
+    #[clap(long, value_enum, default_value = "strict")]
+    contention: ContentionPolicy,
+
     /// Disable monitor
     #[clap(long)]
     monitor_disable: bool,
@@ -667,6 +748,14 @@ struct Opts {
     #[clap(long, default_value = "false")]
     disable_antistall: bool,
 
+    /// When the system CPU utilization drops below this percentage, relax
+    /// confinement so that confined layers may also use idle unprotected
+    /// CPUs. This avoids a saturated confined layer being stuck on its own
+    /// CPUs while most of the machine is idle. Confinement is restored once
+    /// the utilization climbs 5% above the threshold. 0 disables.
+    #[clap(long, default_value = "0.0")]
+    empty_machine_util_pct: f64,
+
     /// Enable numa topology based gpu task affinitization.
     #[clap(long, default_value = "false")]
     enable_gpu_affinitize: bool,
@@ -683,6 +772,14 @@ struct Opts {
     #[clap(long, default_value = "false")]
     enable_match_debug: bool,
 
+    /// Save the task to layer assignments and the CPU allocations of the
+    /// layers to this file on exit and restore them on startup. This allows
+    /// rolling restarts, e.g. for upgrades, without long-running tasks
+    /// being re-matched and the layers re-growing from scratch. Layers are
+    /// restored by name and the file is ignored after a reboot.
+    #[clap(long)]
+    state_file: Option<PathBuf>,
+
     /// Maximum task runnable_at delay (in seconds) before antistall turns on
     #[clap(long, default_value = "3")]
     antistall_sec: u64,
@@ -747,6 +844,13 @@ struct Opts {
     #[clap(long, default_value = "false")]
     print_and_exit: bool,
 
+    /// Dry run: print the initial CPU-to-layer allocation, the effective layer weights and which
+    /// layers the currently running tasks would be matched into for the specified config file,
+    /// and exit without loading the BPF scheduler. Layer specs given as arguments are included
+    /// before the config file.
+    #[clap(long)]
+    plan: Option<String>,
+
     /// Enable affinitized task to use hi fallback queue to get more CPU time.
     #[clap(long, default_value = "")]
     hi_fb_thread_name: String,
@@ -754,6 +858,9 @@ struct Opts {
     #[clap(flatten, next_help_heading = "Topology Options")]
     topology: TopologyArgs,
 
+    #[clap(flatten, next_help_heading = "Scheduler Thread Options")]
+    sched_thread: SchedThreadArgs,
+
     #[clap(flatten, next_help_heading = "Libbpf Options")]
     pub libbpf: LibbpfOpts,
 }
@@ -764,7 +871,7 @@ enum CgroupEvent {
     Created {
         path: String,
         cgroup_id: u64,    // inode number
-        match_bitmap: u64, // bitmap of matched regex rules
+        match_bitmap: u64, // bitmap of matched cgroup rules
     },
     Removed {
         path: String,
@@ -772,6 +879,38 @@ enum CgroupEvent {
     },
 }
 
+// Cgroup match rules evaluated by the cgroup watcher. The results are stored
+// in the cgroup_match_bitmap BPF map, indexed by the rule id.
+#[derive(Debug, Clone)]
+enum CgroupMatcher {
+    Prefix(String),
+    Suffix(String),
+    Contains(String),
+    Regex(Regex),
+}
+
+impl CgroupMatcher {
+    fn is_match(&self, path: &Path) -> bool {
+        match self {
+            // Regexes match the full cgroupfs path.
+            Self::Regex(regex) => regex.is_match(&path.to_string_lossy()),
+            // String matches follow format_cgrp_path() in BPF, which is
+            // relative to the cgroupfs root and always ends with '/'.
+            Self::Prefix(prefix) => Self::bpf_cgrp_path(path).starts_with(prefix.as_str()),
+            Self::Suffix(suffix) => Self::bpf_cgrp_path(path).ends_with(suffix.as_str()),
+            Self::Contains(substr) => Self::bpf_cgrp_path(path).contains(substr.as_str()),
+        }
+    }
+
+    fn bpf_cgrp_path(path: &Path) -> String {
+        match path.strip_prefix("/sys/fs/cgroup") {
+            Ok(rel) if rel.as_os_str().is_empty() => "/".to_string(),
+            Ok(rel) => format!("{}/", rel.to_string_lossy()),
+            Err(_) => format!("{}/", path.to_string_lossy()),
+        }
+    }
+}
+
 fn read_total_cpu(reader: &fb_procfs::ProcReader) -> Result<fb_procfs::CpuStat> {
     reader
         .read_stat()
@@ -1310,6 +1449,8 @@ struct Layer {
     nr_llc_cpus: Vec<usize>,
     cpus: Cpumask,
     allowed_cpus: Cpumask,
+    eff_weight: u32,
+    metadata: BTreeMap<String, String>,
 }
 
 fn get_kallsyms_addr(sym_name: &str) -> Result<u64> {
@@ -1350,6 +1491,106 @@ fn resolve_cpus_pct_range(
     }
 }
 
+/// Maximum number of CPUs each layer can use, see calc_effective_weights().
+/// Open layers don't compete for CPUs and have no cap.
+fn layer_cpu_caps(layers: &[Layer], nr_cpus: usize) -> Vec<Option<usize>> {
+    layers
+        .iter()
+        .map(|layer| match &layer.kind {
+            LayerKind::Confined {
+                cpus_range,
+                cpus_range_frac,
+                ..
+            }
+            | LayerKind::Grouped {
+                cpus_range,
+                cpus_range_frac,
+                ..
+            } => {
+                let max = resolve_cpus_pct_range(cpus_range, cpus_range_frac, nr_cpus)
+                    .map(|range| range.1)
+                    .unwrap_or(nr_cpus);
+                Some(max.min(layer.allowed_cpus.weight()))
+            }
+            LayerKind::Open { .. } => None,
+        })
+        .collect()
+}
+
+/// Layer weights entitle layers to a share of the CPUs under contention,
+/// which can be more than a layer is able to use, e.g. when the share is
+/// larger than its cpuset or cpus_range. Cap the weights of such infeasible
+/// layers to what they can use and redistribute the excess among the other
+/// layers in proportion to their weights. Layers without a cap in @caps
+/// don't compete for CPUs and keep their weights. Returns the effective
+/// weights.
+fn calc_effective_weights(weights: &[u32], caps: &[Option<usize>], nr_cpus: usize) -> Vec<u32> {
+    let total: u64 = weights
+        .iter()
+        .zip(caps)
+        .filter(|(_, cap)| cap.is_some())
+        .map(|(weight, _)| *weight as u64)
+        .sum();
+    let mut capped = vec![false; weights.len()];
+
+    if total == 0 || nr_cpus == 0 {
+        return weights.to_vec();
+    }
+
+    // Shares of the CPUs left after the capped layers got what they can use.
+    let calc_left = |capped: &[bool]| -> (f64, u64) {
+        let mut nr_left = nr_cpus as f64;
+        let mut weight_left = 0;
+        for (i, cap) in caps.iter().enumerate() {
+            match cap {
+                Some(cap) if capped[i] => nr_left -= *cap as f64,
+                Some(_) => weight_left += weights[i] as u64,
+                None => {}
+            }
+        }
+        (nr_left.max(0.0), weight_left)
+    };
+
+    // Capping a layer only increases the shares of the others, keep going
+    // until all the remaining layers can use their shares.
+    loop {
+        let (nr_left, weight_left) = calc_left(&capped);
+        if weight_left == 0 {
+            break;
+        }
+
+        let mut progress = false;
+        for (i, cap) in caps.iter().enumerate() {
+            if let Some(cap) = cap {
+                let share = nr_left * weights[i] as f64 / weight_left as f64;
+                if !capped[i] && share > *cap as f64 {
+                    capped[i] = true;
+                    progress = true;
+                }
+            }
+        }
+
+        if !progress {
+            break;
+        }
+    }
+
+    let (nr_left, weight_left) = calc_left(&capped);
+    weights
+        .iter()
+        .zip(caps)
+        .enumerate()
+        .map(|(i, (weight, cap))| {
+            let share = match cap {
+                None => return *weight,
+                Some(cap) if capped[i] => *cap as f64,
+                Some(_) => nr_left * *weight as f64 / weight_left as f64,
+            };
+            ((share * total as f64 / nr_cpus as f64).round() as u32).max(MIN_LAYER_WEIGHT)
+        })
+        .collect()
+}
+
 impl Layer {
     fn new(spec: &LayerSpec, topo: &Topology, core_order: &Vec<usize>) -> Result<Self> {
         let name = &spec.name;
@@ -1449,6 +1690,8 @@ impl Layer {
             nr_llc_cpus: vec![0; topo.all_llcs.len()],
             cpus: Cpumask::new(),
             allowed_cpus,
+            eff_weight: kind.common().weight,
+            metadata: spec.metadata.clone(),
         })
     }
 
@@ -1725,30 +1968,67 @@ struct Scheduler<'a> {
     proc_reader: fb_procfs::ProcReader,
     sched_stats: Stats,
 
-    cgroup_regexes: Option<HashMap<u32, Regex>>,
+    cgroup_matchers: Option<HashMap<u32, CgroupMatcher>>,
 
     nr_layer_cpus_ranges: Vec<(usize, usize)>,
     processing_dur: Duration,
 
+    empty_machine_util_pct: f64,
+    empty_machine: bool,
+
     topo: Arc<Topology>,
     netdevs: BTreeMap<String, NetDev>,
     stats_server: StatsServer<StatsReq, StatsRes>,
     gpu_task_handler: GpuTaskAffinitizer,
+    state_file: Option<PathBuf>,
 }
 
 impl<'a> Scheduler<'a> {
+    // Cgroup prefix/suffix/substr matches are pre-computed by the cgroup
+    // watcher as long as there are ids left after the regexes, identical
+    // ones share the same id. BPF falls back to matching the cgroup path
+    // for the ones without an id.
+    fn cgroup_match_id(
+        cgroup_matchers: &mut HashMap<u32, CgroupMatcher>,
+        matcher: CgroupMatcher,
+    ) -> u32 {
+        let existing = cgroup_matchers
+            .iter()
+            .find_map(|(id, m)| match (m, &matcher) {
+                (CgroupMatcher::Prefix(a), CgroupMatcher::Prefix(b))
+                | (CgroupMatcher::Suffix(a), CgroupMatcher::Suffix(b))
+                | (CgroupMatcher::Contains(a), CgroupMatcher::Contains(b))
+                    if a == b =>
+                {
+                    Some(*id)
+                }
+                _ => None,
+            });
+        if let Some(id) = existing {
+            return id;
+        }
+
+        let id = (bpf_intf::consts_MAX_CGROUP_REGEXES..bpf_intf::consts_MAX_CGROUP_MATCH_IDS)
+            .find(|id| !cgroup_matchers.contains_key(id))
+            .unwrap_or(bpf_intf::consts_CGROUP_MATCH_ID_NONE);
+        if id != bpf_intf::consts_CGROUP_MATCH_ID_NONE {
+            cgroup_matchers.insert(id, matcher);
+        }
+        id
+    }
+
     fn init_layers(
         skel: &mut OpenBpfSkel,
         specs: &[LayerSpec],
         topo: &Topology,
-    ) -> Result<HashMap<u32, Regex>> {
+    ) -> Result<HashMap<u32, CgroupMatcher>> {
         skel.maps.rodata_data.as_mut().unwrap().nr_layers = specs.len() as u32;
         let mut perf_set = false;
 
         let mut layer_iteration_order = (0..specs.len()).collect::<Vec<_>>();
         let mut layer_weights: Vec<usize> = vec![];
         let mut cgroup_regex_id = 0;
-        let mut cgroup_regexes = HashMap::new();
+        let mut cgroup_matchers = HashMap::new();
 
         for (spec_i, spec) in specs.iter().enumerate() {
             let layer = &mut skel.maps.bss_data.as_mut().unwrap().layers[spec_i];
@@ -1764,10 +2044,18 @@ impl<'a> Scheduler<'a> {
                         LayerMatch::CgroupPrefix(prefix) => {
                             mt.kind = bpf_intf::layer_match_kind_MATCH_CGROUP_PREFIX as i32;
                             copy_into_cstr(&mut mt.cgroup_prefix, prefix.as_str());
+                            mt.cgroup_match_id = Self::cgroup_match_id(
+                                &mut cgroup_matchers,
+                                CgroupMatcher::Prefix(prefix.clone()),
+                            );
                         }
                         LayerMatch::CgroupSuffix(suffix) => {
                             mt.kind = bpf_intf::layer_match_kind_MATCH_CGROUP_SUFFIX as i32;
                             copy_into_cstr(&mut mt.cgroup_suffix, suffix.as_str());
+                            mt.cgroup_match_id = Self::cgroup_match_id(
+                                &mut cgroup_matchers,
+                                CgroupMatcher::Suffix(suffix.clone()),
+                            );
                         }
                         LayerMatch::CgroupRegex(regex_str) => {
                             if cgroup_regex_id >= bpf_intf::consts_MAX_CGROUP_REGEXES {
@@ -1784,12 +2072,16 @@ impl<'a> Scheduler<'a> {
                             let regex = Regex::new(regex_str).with_context(|| {
                                 format!("Invalid regex '{}' in layer '{}'", regex_str, spec.name)
                             })?;
-                            cgroup_regexes.insert(cgroup_regex_id, regex);
+                            cgroup_matchers.insert(cgroup_regex_id, CgroupMatcher::Regex(regex));
                             cgroup_regex_id += 1;
                         }
                         LayerMatch::CgroupContains(substr) => {
                             mt.kind = bpf_intf::layer_match_kind_MATCH_CGROUP_CONTAINS as i32;
                             copy_into_cstr(&mut mt.cgroup_substr, substr.as_str());
+                            mt.cgroup_match_id = Self::cgroup_match_id(
+                                &mut cgroup_matchers,
+                                CgroupMatcher::Contains(substr.clone()),
+                            );
                         }
                         LayerMatch::CommPrefix(prefix) => {
                             mt.kind = bpf_intf::layer_match_kind_MATCH_COMM_PREFIX as i32;
@@ -1918,10 +2210,10 @@ impl<'a> Scheduler<'a> {
                     allow_node_aligned,
                     skip_remote_node,
                     prev_over_idle_core,
+                    keep_process_together,
                     growth_algo,
                     nodes,
                     slice_us,
-                    fifo,
                     weight,
                     disallow_open_after_us,
                     disallow_preempt_after_us,
@@ -1932,7 +2224,12 @@ impl<'a> Scheduler<'a> {
                 } = spec.kind.common();
 
                 layer.slice_ns = *slice_us * 1000;
-                layer.fifo.write(*fifo);
+                layer.task_order =
+                    crate::types::layer_task_order(match spec.kind.common().ordering() {
+                        LayerOrdering::Vtime => bpf_intf::layer_task_order_ORDER_VTIME,
+                        LayerOrdering::Fifo => bpf_intf::layer_task_order_ORDER_FIFO,
+                        LayerOrdering::Deadline => bpf_intf::layer_task_order_ORDER_DEADLINE,
+                    } as u32);
                 layer.min_exec_ns = min_exec_us * 1000;
                 layer.yield_step_ns = if *yield_ignore > 0.999 {
                     0
@@ -1950,6 +2247,7 @@ impl<'a> Scheduler<'a> {
                 layer.allow_node_aligned.write(*allow_node_aligned);
                 layer.skip_remote_node.write(*skip_remote_node);
                 layer.prev_over_idle_core.write(*prev_over_idle_core);
+                layer.keep_process_together.write(*keep_process_together);
                 layer.growth_algo = growth_algo.as_bpf_enum();
                 layer.weight = *weight;
                 layer.member_expire_ms = *member_expire_ms;
@@ -2020,7 +2318,7 @@ impl<'a> Scheduler<'a> {
             warn!("cpufreq support not available, ignoring perf configurations");
         }
 
-        Ok(cgroup_regexes)
+        Ok(cgroup_matchers)
     }
 
     fn init_nodes(skel: &mut OpenBpfSkel, _opts: &Opts, topo: &Topology) {
@@ -2343,6 +2641,7 @@ impl<'a> Scheduler<'a> {
         open_object: &'a mut MaybeUninit<OpenObject>,
         hint_to_layer_map: &HashMap<u64, HintLayerInfo>,
         membw_tracking: bool,
+        state: Option<&SchedState>,
     ) -> Result<Self> {
         let nr_layers = layer_specs.len();
         let mut disable_topology = opts.disable_topology.unwrap_or(false);
@@ -2513,12 +2812,23 @@ impl<'a> Scheduler<'a> {
         rodata.smt_enabled = topo.smt_enabled;
         rodata.has_little_cores = topo.has_little_cores();
         rodata.xnuma_preemption = opts.xnuma_preemption;
+        rodata.contention_policy = match opts.contention {
+            ContentionPolicy::Strict => bpf_intf::contention_policy_CONTENTION_STRICT,
+            ContentionPolicy::WeightedRandom => {
+                bpf_intf::contention_policy_CONTENTION_WEIGHTED_RANDOM
+            }
+            ContentionPolicy::Drr => bpf_intf::contention_policy_CONTENTION_DRR,
+        };
         rodata.antistall_sec = opts.antistall_sec;
         rodata.monitor_disable = opts.monitor_disable;
         rodata.lo_fb_wait_ns = opts.lo_fb_wait_us * 1000;
         rodata.lo_fb_share_ppk = ((opts.lo_fb_share * 1024.0) as u32).clamp(1, 1024);
         rodata.enable_antistall = !opts.disable_antistall;
+        rodata.enable_util_cap = layer_specs.iter().any(|spec| spec.kind.util_cap());
         rodata.enable_match_debug = opts.enable_match_debug;
+        rodata.save_task_layers = opts.state_file.is_some();
+        rodata.restore_task_layers = state.is_some();
+        rodata.clock_tick_ns = 1_000_000_000 / unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as u64;
         rodata.enable_gpu_support = opts.enable_gpu_support;
         rodata.kfuncs_supported_in_syscall = kfuncs_in_syscall;
 
@@ -2605,8 +2915,11 @@ impl<'a> Scheduler<'a> {
             rodata.enable_hi_fb_thread_name_match = true;
         }
 
-        let cgroup_regexes = Self::init_layers(&mut skel, &layer_specs, &topo)?;
-        skel.maps.rodata_data.as_mut().unwrap().nr_cgroup_regexes = cgroup_regexes.len() as u32;
+        let cgroup_matchers = Self::init_layers(&mut skel, &layer_specs, &topo)?;
+        skel.maps.rodata_data.as_mut().unwrap().nr_cgroup_regexes = cgroup_matchers
+            .values()
+            .filter(|m| matches!(m, CgroupMatcher::Regex(_)))
+            .count() as u32;
         Self::init_nodes(&mut skel, opts, &topo);
 
         let mut skel = scx_ops_load!(skel, layered, uei)?;
@@ -2653,6 +2966,10 @@ impl<'a> Scheduler<'a> {
             layers.push(Layer::new(spec, &topo, growth_order)?);
         }
 
+        if let Some(state) = state {
+            state.restore_tasks(&mut skel, &layers)?;
+        }
+
         let mut idle_qos_enabled = layers
             .iter()
             .any(|layer| layer.kind.common().idle_resume_us.unwrap_or(0) > 0);
@@ -2714,7 +3031,7 @@ impl<'a> Scheduler<'a> {
             GpuTaskAffinitizer::new(opts.gpu_affinitize_secs, opts.enable_gpu_affinitize);
         gpu_task_handler.init(topo.clone());
 
-        let sched = Self {
+        let mut sched = Self {
             struct_ops: Some(struct_ops),
             layer_specs,
 
@@ -2727,10 +3044,13 @@ impl<'a> Scheduler<'a> {
 
             sched_stats: Stats::new(&mut skel, &proc_reader, &gpu_task_handler)?,
 
-            cgroup_regexes: Some(cgroup_regexes),
+            cgroup_matchers: Some(cgroup_matchers),
             nr_layer_cpus_ranges: vec![(0, 0); nr_layers],
             processing_dur: Default::default(),
 
+            empty_machine_util_pct: opts.empty_machine_util_pct,
+            empty_machine: false,
+
             proc_reader,
             skel,
 
@@ -2738,8 +3058,13 @@ impl<'a> Scheduler<'a> {
             netdevs,
             stats_server,
             gpu_task_handler,
+            state_file: opts.state_file.clone(),
         };
 
+        if let Some(state) = state {
+            sched.restore_cpus(state)?;
+        }
+
         info!("Layered Scheduler Attached. Run `scx_layered --monitor` for metrics.");
 
         Ok(sched)
@@ -2764,6 +3089,16 @@ impl<'a> Scheduler<'a> {
             bpf_layer.nr_llc_cpus[llc_id] = nr_llc_cpus as u32;
         }
 
+        // The upper bound of util_range as a hard cap on the layer's CPU
+        // time per bandwidth period. Always allow at least one CPU's worth
+        // as an empty layer can still run through fallback.
+        bpf_layer.bw_quota_ns = match layer.kind.util_range() {
+            Some((_, high)) if layer.kind.util_cap() => {
+                (high * layer.nr_cpus.max(1) as f64 * bpf_intf::consts_BW_PERIOD_NS as f64) as u64
+            }
+            _ => 0,
+        };
+
         bpf_layer.refresh_cpus = 1;
     }
 
@@ -2930,15 +3265,51 @@ impl<'a> Scheduler<'a> {
         targets
     }
 
+    /// Update the effective weights of the layers, see
+    /// calc_effective_weights(), and log the layers whose weights are
+    /// infeasible whenever they change.
+    fn refresh_effective_weights(&mut self) {
+        let nr_cpus = self.cpu_pool.topo.all_cpus.len();
+        let weights: Vec<u32> = self
+            .layers
+            .iter()
+            .map(|layer| layer.kind.common().weight)
+            .collect();
+        let caps = layer_cpu_caps(&self.layers, nr_cpus);
+
+        let eff_weights = calc_effective_weights(&weights, &caps, nr_cpus);
+
+        for (i, layer) in self.layers.iter_mut().enumerate() {
+            if layer.eff_weight == eff_weights[i] {
+                continue;
+            }
+            if eff_weights[i] < weights[i] {
+                info!(
+                    "Layer {} weight {} is infeasible with {} usable CPUs, using {}",
+                    layer.name,
+                    weights[i],
+                    caps[i].unwrap_or(nr_cpus),
+                    eff_weights[i]
+                );
+            } else if eff_weights[i] > weights[i] {
+                info!(
+                    "Layer {} weight {} raised to {} with the excess of infeasible layers",
+                    layer.name, weights[i], eff_weights[i]
+                );
+            }
+            layer.eff_weight = eff_weights[i];
+        }
+    }
+
     /// Given (target, min) pair for each layer which was determined
     /// assuming infinite number of CPUs, distribute the actual CPUs
-    /// according to their weights.
+    /// according to their effective weights.
     fn weighted_target_nr_cpus(&self, targets: &[(usize, usize)]) -> Vec<usize> {
         let mut nr_left = self.cpu_pool.topo.all_cpus.len();
         let weights: Vec<usize> = self
             .layers
             .iter()
-            .map(|layer| layer.kind.common().weight as usize)
+            .map(|layer| layer.eff_weight as usize)
             .collect();
         let mut cands: BTreeMap<usize, (usize, usize, usize)> = targets
             .iter()
@@ -3287,10 +3658,97 @@ impl<'a> Scheduler<'a> {
         Ok(updated)
     }
 
+    /// Give the CPUs left over by the other layers to the open layers and
+    /// push the updated cpumasks to BPF.
+    fn commit_cpumasks(&mut self) {
+        for (idx, layer) in self.layers.iter_mut().enumerate() {
+            if !matches!(layer.kind, LayerKind::Open { .. }) {
+                continue;
+            }
+
+            let bpf_layer = &mut self.skel.maps.bss_data.as_mut().unwrap().layers[idx];
+            let available_cpus = self.cpu_pool.available_cpus().and(&layer.allowed_cpus);
+            let nr_available_cpus = available_cpus.weight();
+
+            // Open layers need the intersection of allowed cpus and
+            // available cpus.
+            layer.cpus = available_cpus;
+            layer.nr_cpus = nr_available_cpus;
+            Self::update_bpf_layer_cpumask(layer, bpf_layer);
+        }
+
+        self.skel.maps.bss_data.as_mut().unwrap().fallback_cpu = self.cpu_pool.fallback_cpu as u32;
+
+        for (lidx, layer) in self.layers.iter().enumerate() {
+            self.nr_layer_cpus_ranges[lidx] = (
+                self.nr_layer_cpus_ranges[lidx].0.min(layer.nr_cpus),
+                self.nr_layer_cpus_ranges[lidx].1.max(layer.nr_cpus),
+            );
+        }
+
+        // Trigger updates on the BPF side.
+        let input = ProgramInput {
+            ..Default::default()
+        };
+        let prog = &mut self.skel.progs.refresh_layer_cpumasks;
+        let _ = prog.test_run(input);
+
+        // Update empty_layers.
+        let empty_layer_ids: Vec<u32> = self
+            .layers
+            .iter()
+            .enumerate()
+            .filter(|(_idx, layer)| layer.nr_cpus == 0)
+            .map(|(idx, _layer)| idx as u32)
+            .collect();
+        for i in 0..self.layers.len() {
+            self.skel.maps.bss_data.as_mut().unwrap().empty_layer_ids[i] =
+                empty_layer_ids.get(i).cloned().unwrap_or(MAX_LAYERS as u32);
+        }
+        self.skel.maps.bss_data.as_mut().unwrap().nr_empty_layer_ids = empty_layer_ids.len() as u32;
+    }
+
+    /// Grow the layers back to the number of CPUs they had under the
+    /// previous instance. Layers still shrink gradually from there if they
+    /// don't need the CPUs anymore.
+    fn restore_cpus(&mut self, state: &SchedState) -> Result<()> {
+        for idx in 0..self.layers.len() {
+            let layer = &mut self.layers[idx];
+            if matches!(layer.kind, LayerKind::Open { .. })
+                || layer.growth_algo == LayerGrowthAlgo::StickyDynamic
+            {
+                continue;
+            }
+            let Some(target) = state.nr_cpus(layer) else {
+                continue;
+            };
+
+            let mut alloced = false;
+            while layer.nr_cpus < target {
+                if layer.alloc_some_cpus(&mut self.cpu_pool)? == 0 {
+                    break;
+                }
+                alloced = true;
+            }
+
+            if alloced {
+                debug!("[{}] restored {} CPUs", &layer.name, layer.nr_cpus);
+                Self::update_bpf_layer_cpumask(
+                    layer,
+                    &mut self.skel.maps.bss_data.as_mut().unwrap().layers[idx],
+                );
+            }
+        }
+
+        self.commit_cpumasks();
+        Ok(())
+    }
+
     fn refresh_cpumasks(&mut self) -> Result<()> {
         let layer_is_open = |layer: &Layer| matches!(layer.kind, LayerKind::Open { .. });
 
         let mut updated = false;
+        self.refresh_effective_weights();
         let targets = self.calc_target_nr_cpus();
         let targets = self.weighted_target_nr_cpus(&targets);
 
@@ -3423,53 +3881,7 @@ impl<'a> Scheduler<'a> {
 
         // Give the rest to the open layers.
         if updated {
-            for (idx, layer) in self.layers.iter_mut().enumerate() {
-                if !layer_is_open(layer) {
-                    continue;
-                }
-
-                let bpf_layer = &mut self.skel.maps.bss_data.as_mut().unwrap().layers[idx];
-                let available_cpus = self.cpu_pool.available_cpus().and(&layer.allowed_cpus);
-                let nr_available_cpus = available_cpus.weight();
-
-                // Open layers need the intersection of allowed cpus and
-                // available cpus.
-                layer.cpus = available_cpus;
-                layer.nr_cpus = nr_available_cpus;
-                Self::update_bpf_layer_cpumask(layer, bpf_layer);
-            }
-
-            self.skel.maps.bss_data.as_mut().unwrap().fallback_cpu =
-                self.cpu_pool.fallback_cpu as u32;
-
-            for (lidx, layer) in self.layers.iter().enumerate() {
-                self.nr_layer_cpus_ranges[lidx] = (
-                    self.nr_layer_cpus_ranges[lidx].0.min(layer.nr_cpus),
-                    self.nr_layer_cpus_ranges[lidx].1.max(layer.nr_cpus),
-                );
-            }
-
-            // Trigger updates on the BPF side.
-            let input = ProgramInput {
-                ..Default::default()
-            };
-            let prog = &mut self.skel.progs.refresh_layer_cpumasks;
-            let _ = prog.test_run(input);
-
-            // Update empty_layers.
-            let empty_layer_ids: Vec<u32> = self
-                .layers
-                .iter()
-                .enumerate()
-                .filter(|(_idx, layer)| layer.nr_cpus == 0)
-                .map(|(idx, _layer)| idx as u32)
-                .collect();
-            for i in 0..self.layers.len() {
-                self.skel.maps.bss_data.as_mut().unwrap().empty_layer_ids[i] =
-                    empty_layer_ids.get(i).cloned().unwrap_or(MAX_LAYERS as u32);
-            }
-            self.skel.maps.bss_data.as_mut().unwrap().nr_empty_layer_ids =
-                empty_layer_ids.len() as u32;
+            self.commit_cpumasks();
         }
 
         let _ = self.update_netdev_cpumasks();
@@ -3496,6 +3908,34 @@ impl<'a> Scheduler<'a> {
         Ok(())
     }
 
+    // Relax confinement while the machine is mostly idle. Leaving the state
+    // requires the utilization to climb above the threshold with some
+    // hysteresis so that it doesn't flap around the threshold.
+    fn refresh_empty_machine(&mut self) {
+        const HYSTERESIS_PCT: f64 = 5.0;
+
+        if self.empty_machine_util_pct <= 0.0 {
+            return;
+        }
+
+        let util_pct = self.sched_stats.cpu_busy * 100.0;
+        let empty_machine = if self.empty_machine {
+            util_pct < self.empty_machine_util_pct + HYSTERESIS_PCT
+        } else {
+            util_pct < self.empty_machine_util_pct
+        };
+
+        if empty_machine != self.empty_machine {
+            debug!(
+                "Empty machine mode {} at {:.1}% utilization",
+                if empty_machine { "entered" } else { "left" },
+                util_pct
+            );
+            self.empty_machine = empty_machine;
+            self.skel.maps.bss_data.as_mut().unwrap().empty_machine = empty_machine;
+        }
+    }
+
     fn step(&mut self) -> Result<()> {
         let started_at = Instant::now();
         self.sched_stats.refresh(
@@ -3524,6 +3964,7 @@ impl<'a> Scheduler<'a> {
                 (self.sched_stats.layer_dsq_insert_ewma[layer_id] * 10000.0) as u64;
         }
 
+        self.refresh_empty_machine();
         self.refresh_cpumasks()?;
         self.refresh_idle_qos()?;
         self.gpu_task_handler.maybe_affinitize();
@@ -3551,7 +3992,7 @@ impl<'a> Scheduler<'a> {
     // Helper function to process a cgroup creation (common logic for walkdir and inotify)
     fn process_cgroup_creation(
         path: &Path,
-        cgroup_regexes: &HashMap<u32, Regex>,
+        cgroup_matchers: &HashMap<u32, CgroupMatcher>,
         cgroup_path_to_id: &mut HashMap<String, u64>,
         sender: &crossbeam::channel::Sender<CgroupEvent>,
     ) {
@@ -3565,10 +4006,10 @@ impl<'a> Scheduler<'a> {
             })
             .unwrap_or(0);
 
-        // Build match bitmap by testing against CgroupRegex rules
+        // Build match bitmap by testing against the cgroup match rules
         let mut match_bitmap = 0u64;
-        for (rule_id, regex) in cgroup_regexes {
-            if regex.is_match(&path_str) {
+        for (rule_id, matcher) in cgroup_matchers {
+            if matcher.is_match(path) {
                 match_bitmap |= 1u64 << rule_id;
             }
         }
@@ -3588,7 +4029,7 @@ impl<'a> Scheduler<'a> {
 
     fn start_cgroup_watcher(
         shutdown: Arc<AtomicBool>,
-        cgroup_regexes: HashMap<u32, Regex>,
+        cgroup_matchers: HashMap<u32, CgroupMatcher>,
     ) -> Result<Receiver<CgroupEvent>> {
         let mut inotify = Inotify::init().context("Failed to initialize inotify")?;
         let mut wd_to_path = HashMap::new();
@@ -3622,7 +4063,7 @@ impl<'a> Scheduler<'a> {
                 let path = entry.path();
                 Self::process_cgroup_creation(
                     path,
-                    &cgroup_regexes,
+                    &cgroup_matchers,
                     &mut cgroup_path_to_id,
                     &sender,
                 );
@@ -3692,7 +4133,7 @@ impl<'a> Scheduler<'a> {
 
                         Self::process_cgroup_creation(
                             &path,
-                            &cgroup_regexes,
+                            &cgroup_matchers,
                             &mut cgroup_path_to_id,
                             &sender,
                         );
@@ -3781,12 +4222,12 @@ impl<'a> Scheduler<'a> {
         let mut next_layer_refresh_at = Instant::now() + self.layer_refresh_intv;
         let mut cpus_ranges = HashMap::<ThreadId, Vec<(usize, usize)>>::new();
 
-        // Start the cgroup watcher only if there are CgroupRegex rules
-        let cgroup_regexes = self.cgroup_regexes.take().unwrap();
-        let cgroup_event_rx = if !cgroup_regexes.is_empty() {
+        // Start the cgroup watcher only if there are cgroup match rules
+        let cgroup_matchers = self.cgroup_matchers.take().unwrap();
+        let cgroup_event_rx = if !cgroup_matchers.is_empty() {
             Some(Self::start_cgroup_watcher(
                 shutdown.clone(),
-                cgroup_regexes,
+                cgroup_matchers,
             )?)
         } else {
             None
@@ -3863,18 +4304,26 @@ impl<'a> Scheduler<'a> {
 
                 recv(cgroup_rx) -> event => match event {
                     Ok(CgroupEvent::Created { path, cgroup_id, match_bitmap }) => {
-                        // Insert into BPF map
-                        self.skel.maps.cgroup_match_bitmap.update(
+                        // Insert into BPF map. Without CgroupRegex rules, BPF can
+                        // fall back to matching the cgroup path, so a full map
+                        // isn't fatal.
+                        let res = self.skel.maps.cgroup_match_bitmap.update(
                             &cgroup_id.to_ne_bytes(),
                             &match_bitmap.to_ne_bytes(),
                             libbpf_rs::MapFlags::ANY,
-                        ).with_context(|| format!(
-                            "Failed to insert cgroup {}({}) into BPF map. Cgroup map may be full \
-                             (max 16384 entries). Aborting.",
-                            cgroup_id, path
-                        ))?;
-
-                        debug!("Added cgroup {} to BPF map with bitmap 0x{:x}", cgroup_id, match_bitmap);
+                        );
+                        if let Err(e) = res {
+                            if self.skel.maps.rodata_data.as_ref().unwrap().nr_cgroup_regexes > 0 {
+                                bail!(
+                                    "Failed to insert cgroup {}({}) into BPF map. Cgroup map may be \
+                                     full (max 16384 entries). Aborting: {}",
+                                    cgroup_id, path, e
+                                );
+                            }
+                            warn!("Failed to insert cgroup {}({}) into BPF map: {}", cgroup_id, path, e);
+                        } else {
+                            debug!("Added cgroup {} to BPF map with bitmap 0x{:x}", cgroup_id, match_bitmap);
+                        }
                     }
                     Ok(CgroupEvent::Removed { path, cgroup_id }) => {
                         // Delete from BPF map
@@ -3895,6 +4344,17 @@ impl<'a> Scheduler<'a> {
             }
         }
 
+        if let Some(path) = &self.state_file {
+            match SchedState::collect(&self.skel, &self.layers) {
+                Ok(state) => {
+                    if let Err(e) = state.save(path) {
+                        warn!("Failed to save state ({:?})", &e);
+                    }
+                }
+                Err(e) => warn!("Failed to collect state ({:?})", &e),
+            }
+        }
+
         let _ = self.struct_ops.take();
         uei_report!(&self.skel, uei)
     }
@@ -4293,10 +4753,7 @@ fn setup_membw_tracking(skel: &mut OpenBpfSkel) -> Result<u64> {
 #[clap_main::clap_main]
 fn main(opts: Opts) -> Result<()> {
     if opts.version {
-        println!(
-            "scx_layered {}",
-            build_id::full_version(env!("CARGO_PKG_VERSION"))
-        );
+        println!("{:#}", scx_utils::version_info!());
         return Ok(());
     }
 
@@ -4391,7 +4848,8 @@ fn main(opts: Opts) -> Result<()> {
         false => LayerConfig { specs: vec![] },
     };
 
-    for (idx, input) in opts.specs.iter().enumerate() {
+    let plan_spec = opts.plan.as_ref().map(|path| format!("f:{}", path));
+    for (idx, input) in opts.specs.iter().chain(plan_spec.iter()).enumerate() {
         let specs = LayerSpec::parse(input)
             .context(format!("Failed to parse specs[{}] ({:?})", idx, input))?;
 
@@ -4489,14 +4947,25 @@ fn main(opts: Opts) -> Result<()> {
     debug!("specs={}", serde_json::to_string_pretty(&layer_config)?);
     let hint_to_layer_map = verify_layer_specs(&layer_config.specs)?;
 
+    if opts.plan.is_some() {
+        return plan::print_plan(&mut std::io::stdout(), &opts, &layer_config.specs);
+    }
+
+    let _sched_thread = opts.sched_thread.apply()?;
+
     let mut open_object = MaybeUninit::uninit();
     loop {
+        let state = match &opts.state_file {
+            Some(path) => SchedState::load(path)?,
+            None => None,
+        };
         let mut sched = Scheduler::init(
             &opts,
             &layer_config.specs,
             &mut open_object,
             &hint_to_layer_map,
             membw_required,
+            state.as_ref(),
         )?;
         if !sched.run(shutdown.clone())?.should_restart() {
             break;
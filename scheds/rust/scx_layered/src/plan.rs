@@ -0,0 +1,410 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use anyhow::Result;
+use regex::Regex;
+use scx_utils::ProcCache;
+use scx_utils::Topology;
+
+use crate::calc_effective_weights;
+use crate::layer_cpu_caps;
+use crate::resolve_cpus_pct_range;
+use crate::CgroupMatcher;
+use crate::CpuPool;
+use crate::Layer;
+use crate::LayerGrowthAlgo;
+use crate::LayerKind;
+use crate::LayerMatch;
+use crate::LayerSpec;
+use crate::Opts;
+
+const PF_KTHREAD: u64 = 0x00200000;
+
+/// Attributes of a running task which the layer matches are evaluated
+/// against, read from procfs.
+struct TaskInfo {
+    tid: u32,
+    tgid: u32,
+    ppid: u32,
+    comm: String,
+    pcomm: String,
+    nice: i32,
+    euid: u32,
+    egid: u32,
+    nspid: u32,
+    nsid: u64,
+    cgrp_path: String,
+    is_kthread: bool,
+}
+
+impl TaskInfo {
+    fn read(tgid: u32, tid: u32, procs: &mut ProcCache) -> Option<Self> {
+        let dir = format!("/proc/{}/task/{}", tgid, tid);
+        let read = |path: String| fs::read_to_string(path).ok();
+
+        let comm = read(format!("{}/comm", dir))?.trim_end().to_string();
+        // Shared by all the threads of the process.
+        let pcomm = procs.get(tgid as i32).ok()?.comm.clone();
+
+        // comm may contain spaces and parentheses, skip past the last ')'.
+        let stat = read(format!("{}/stat", dir))?;
+        let stat: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+        let ppid = stat.get(1)?.parse().ok()?;
+        let flags: u64 = stat.get(6)?.parse().ok()?;
+        let nice = stat.get(16)?.parse().ok()?;
+
+        let status = read(format!("{}/status", dir))?;
+        let status_field = |name: &str| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix(name))
+                .map(|val| val.split_whitespace().collect::<Vec<_>>())
+                .unwrap_or_default()
+        };
+        let euid = status_field("Uid:").get(1)?.parse().ok()?;
+        let egid = status_field("Gid:").get(1)?.parse().ok()?;
+        let nspid = status_field("NSpid:")
+            .last()
+            .and_then(|pid| pid.parse().ok())
+            .unwrap_or(tid);
+
+        let nsid = fs::read_link(format!("{}/ns/pid", dir))
+            .ok()
+            .and_then(|link| {
+                link.to_string_lossy()
+                    .strip_prefix("pid:[")?
+                    .strip_suffix(']')?
+                    .parse()
+                    .ok()
+            })
+            .unwrap_or(0);
+
+        let cgrp = read(format!("{}/cgroup", dir))
+            .and_then(|cgroup| {
+                cgroup
+                    .lines()
+                    .find_map(|line| line.strip_prefix("0::"))
+                    .map(|path| path.trim_end_matches('/').to_string())
+            })
+            .unwrap_or_default();
+
+        Some(Self {
+            tid,
+            tgid,
+            ppid,
+            comm,
+            pcomm,
+            nice,
+            euid,
+            egid,
+            nspid,
+            nsid,
+            cgrp_path: format!("/sys/fs/cgroup{}", cgrp),
+            is_kthread: flags & PF_KTHREAD != 0,
+        })
+    }
+
+    fn read_all() -> Vec<Self> {
+        let ids = |path: &str| -> Vec<u32> {
+            fs::read_dir(path)
+                .map(|dir| {
+                    dir.filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        // The report is a single pass, so the entries never need to expire.
+        let mut procs = ProcCache::new(Duration::MAX);
+        let mut tasks = vec![];
+        for tgid in ids("/proc") {
+            for tid in ids(&format!("/proc/{}/task", tgid)) {
+                // The task may have exited in the meantime.
+                if let Some(task) = Self::read(tgid, tid, &mut procs) {
+                    tasks.push(task);
+                }
+            }
+        }
+        tasks
+    }
+}
+
+/// Evaluate @mt against @task following match_one() in BPF. Returns None if
+/// the result depends on runtime state which only the scheduler knows about.
+fn match_one(mt: &LayerMatch, task: &TaskInfo, regexes: &HashMap<String, Regex>) -> Option<bool> {
+    let cgrp = Path::new(&task.cgrp_path);
+
+    Some(match mt {
+        LayerMatch::CgroupPrefix(prefix) => CgroupMatcher::Prefix(prefix.clone()).is_match(cgrp),
+        LayerMatch::CgroupSuffix(suffix) => CgroupMatcher::Suffix(suffix.clone()).is_match(cgrp),
+        LayerMatch::CgroupContains(substr) => {
+            CgroupMatcher::Contains(substr.clone()).is_match(cgrp)
+        }
+        LayerMatch::CgroupRegex(regex) => regexes[regex].is_match(&task.cgrp_path),
+        LayerMatch::CommPrefix(prefix) => task.comm.starts_with(prefix.as_str()),
+        LayerMatch::CommPrefixExclude(prefix) => !task.comm.starts_with(prefix.as_str()),
+        LayerMatch::PcommPrefix(prefix) => task.pcomm.starts_with(prefix.as_str()),
+        LayerMatch::PcommPrefixExclude(prefix) => !task.pcomm.starts_with(prefix.as_str()),
+        LayerMatch::NiceAbove(nice) => task.nice > *nice,
+        LayerMatch::NiceBelow(nice) => task.nice < *nice,
+        LayerMatch::NiceEquals(nice) => task.nice == *nice,
+        LayerMatch::UIDEquals(uid) => task.euid == *uid,
+        LayerMatch::GIDEquals(gid) => task.egid == *gid,
+        LayerMatch::PIDEquals(pid) => task.tid == *pid,
+        LayerMatch::PPIDEquals(ppid) => task.ppid == *ppid,
+        LayerMatch::TGIDEquals(tgid) => task.tgid == *tgid,
+        LayerMatch::NSPIDEquals(nsid, pid) => task.nsid == *nsid && task.nspid == *pid,
+        LayerMatch::NSEquals(nsid) => task.nsid == *nsid as u64,
+        LayerMatch::IsGroupLeader(leader) => (task.tid == task.tgid) == *leader,
+        // BPF only tests PF_KTHREAD regardless of the polarity.
+        LayerMatch::IsKthread(_) => task.is_kthread,
+        LayerMatch::CmdJoin(_)
+        | LayerMatch::UsedGpuTid(_)
+        | LayerMatch::UsedGpuPid(_)
+        | LayerMatch::AvgRuntime(..)
+        | LayerMatch::HintEquals(_)
+        | LayerMatch::SystemCpuUtilBelow(_)
+        | LayerMatch::DsqInsertBelow(_)
+        | LayerMatch::NumaNode(_) => return None,
+    })
+}
+
+/// OR of ANDs as in match_layer() in BPF, None if undetermined.
+fn match_layer(
+    spec: &LayerSpec,
+    task: &TaskInfo,
+    regexes: &HashMap<String, Regex>,
+) -> Option<bool> {
+    let mut result = Some(false);
+
+    for ands in spec.matches.iter() {
+        let mut matched = Some(true);
+        for mt in ands.iter() {
+            match match_one(mt, task, regexes) {
+                Some(true) => {}
+                Some(false) => {
+                    matched = Some(false);
+                    break;
+                }
+                None => matched = None,
+            }
+        }
+
+        match matched {
+            Some(true) => return Some(true),
+            Some(false) => {}
+            None => result = None,
+        }
+    }
+
+    result
+}
+
+/// Print what scx_layered would do with @specs on this host without loading
+/// the BPF scheduler: the initial CPU allocation of the layers, their
+/// effective weights and which layers the currently running tasks would be
+/// matched into.
+pub fn print_plan<W: Write>(w: &mut W, opts: &Opts, specs: &[LayerSpec]) -> Result<()> {
+    let disable_topology = opts.disable_topology.unwrap_or(false);
+    let topo = Arc::new(if disable_topology {
+        Topology::with_flattened_llc_node()?
+    } else if opts.topology.virt_llc.is_some() {
+        Topology::with_args(&opts.topology)?
+    } else {
+        Topology::new()?
+    });
+    let nr_cpus = topo.all_cpus.len();
+
+    // Same as Scheduler::init().
+    let disable_topology =
+        disable_topology || (topo.nodes.len() == 1 && topo.nodes[&0].llcs.len() == 1);
+    let specs: Vec<LayerSpec> = specs
+        .iter()
+        .cloned()
+        .map(|mut spec| {
+            if disable_topology {
+                spec.kind.common_mut().nodes.clear();
+                spec.kind.common_mut().llcs.clear();
+            }
+            spec
+        })
+        .collect();
+
+    let mut cpu_pool = CpuPool::new(topo.clone())?;
+    let growth_orders = LayerGrowthAlgo::layer_core_orders(&cpu_pool, &specs, &topo)?;
+    let mut layers = vec![];
+    for (idx, spec) in specs.iter().enumerate() {
+        let growth_order = growth_orders
+            .get(&idx)
+            .with_context(|| "layer has no growth order".to_string())?;
+        layers.push(Layer::new(spec, &topo, growth_order)?);
+    }
+
+    let weights: Vec<u32> = layers
+        .iter()
+        .map(|layer| layer.kind.common().weight)
+        .collect();
+    let caps = layer_cpu_caps(&layers, nr_cpus);
+    let eff_weights = calc_effective_weights(&weights, &caps, nr_cpus);
+
+    // Without any utilization history, confined and grouped layers start
+    // from the minimum of their cpus_range, see calc_target_nr_cpus() and
+    // weighted_target_nr_cpus().
+    let mut nr_left = nr_cpus;
+    let mut targets = vec![0; layers.len()];
+    for (idx, layer) in layers.iter().enumerate() {
+        if let LayerKind::Confined {
+            cpus_range,
+            cpus_range_frac,
+            ..
+        }
+        | LayerKind::Grouped {
+            cpus_range,
+            cpus_range_frac,
+            ..
+        } = &layer.kind
+        {
+            let min = resolve_cpus_pct_range(cpus_range, cpus_range_frac, nr_cpus)?.0;
+            targets[idx] = min.min(nr_left);
+            nr_left -= targets[idx];
+        }
+    }
+
+    let mut ascending: Vec<(usize, usize)> = targets.iter().copied().enumerate().collect();
+    ascending.sort_by(|a, b| a.1.cmp(&b.1));
+    for &(idx, target) in &ascending {
+        let layer = &mut layers[idx];
+        if matches!(layer.kind, LayerKind::Open { .. })
+            || layer.growth_algo == LayerGrowthAlgo::StickyDynamic
+        {
+            continue;
+        }
+        while layer.cpus.weight() < target {
+            if layer.alloc_some_cpus(&mut cpu_pool)? == 0 {
+                break;
+            }
+        }
+    }
+
+    for layer in layers.iter_mut() {
+        if matches!(layer.kind, LayerKind::Open { .. }) {
+            layer.cpus = cpu_pool.available_cpus().and(&layer.allowed_cpus);
+            layer.nr_cpus = layer.cpus.weight();
+        }
+    }
+
+    writeln!(
+        w,
+        "topology: cpus={} nodes={} llcs={}{}",
+        nr_cpus,
+        topo.nodes.len(),
+        topo.all_llcs.len(),
+        if disable_topology {
+            " (topology awareness disabled)"
+        } else {
+            ""
+        }
+    )?;
+
+    writeln!(w, "\ninitial allocation:")?;
+    for (idx, layer) in layers.iter().enumerate() {
+        let kind = match &layer.kind {
+            LayerKind::Confined { .. } => "confined",
+            LayerKind::Grouped { .. } => "grouped",
+            LayerKind::Open { .. } => "open",
+        };
+        writeln!(
+            w,
+            "  {:<16} {:<8} weight={:>5} eff_weight={:>5} cpus={:>4} {}",
+            layer.name, kind, weights[idx], eff_weights[idx], layer.nr_cpus, &layer.cpus
+        )?;
+        if layer.growth_algo == LayerGrowthAlgo::StickyDynamic {
+            writeln!(
+                w,
+                "  {:<16} sticky_dynamic, LLCs are assigned at runtime",
+                ""
+            )?;
+        }
+        if eff_weights[idx] < weights[idx] {
+            writeln!(
+                w,
+                "  {:<16} weight infeasible with {} usable CPUs",
+                "",
+                caps[idx].unwrap_or(nr_cpus)
+            )?;
+        }
+    }
+    let available_cpus = cpu_pool.available_cpus();
+    writeln!(
+        w,
+        "  {:<16} cpus={:>4} {}",
+        "(unallocated)",
+        available_cpus.weight(),
+        &available_cpus
+    )?;
+
+    let mut regexes = HashMap::new();
+    for spec in specs.iter() {
+        for mt in spec.matches.iter().flatten() {
+            if let LayerMatch::CgroupRegex(regex) = mt {
+                let compiled = Regex::new(regex).with_context(|| {
+                    format!("Invalid regex '{}' in layer '{}'", regex, spec.name)
+                })?;
+                regexes.insert(regex.clone(), compiled);
+            }
+        }
+    }
+
+    let tasks = TaskInfo::read_all();
+    let mut nr_matched = vec![0; specs.len()];
+    let mut nr_undetermined = vec![0; specs.len()];
+    let mut nr_unmatched = 0;
+    'tasks: for task in tasks.iter() {
+        for (idx, spec) in specs.iter().enumerate() {
+            match match_layer(spec, task, &regexes) {
+                Some(true) => nr_matched[idx] += 1,
+                None => nr_undetermined[idx] += 1,
+                Some(false) => continue,
+            }
+            continue 'tasks;
+        }
+        nr_unmatched += 1;
+    }
+
+    writeln!(w, "\nmatcher coverage of {} running tasks:", tasks.len())?;
+    for (idx, spec) in specs.iter().enumerate() {
+        writeln!(
+            w,
+            "  {:<16} matched={:>6} undetermined={:>6}",
+            spec.name, nr_matched[idx], nr_undetermined[idx]
+        )?;
+    }
+    writeln!(w, "  {:<16} {:>14}", "(unmatched)", nr_unmatched)?;
+
+    if nr_undetermined.iter().any(|&nr| nr > 0) {
+        writeln!(
+            w,
+            "\nundetermined tasks depend on runtime state (e.g. AvgRuntime, UsedGpuTid) \
+             and may end up in the layer or any later one"
+        )?;
+    }
+    if nr_unmatched > 0 {
+        writeln!(
+            w,
+            "\nWARNING: {} tasks don't match any layer, the last layer should have an \
+             empty match set",
+            nr_unmatched
+        )?;
+    }
+
+    Ok(())
+}
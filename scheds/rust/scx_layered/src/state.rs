@@ -0,0 +1,196 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Context;
+use anyhow::Result;
+use libbpf_rs::MapCore as _;
+use scx_layered::bpf_intf;
+use serde::Deserialize;
+use serde::Serialize;
+use tracing::info;
+use tracing::warn;
+
+use crate::BpfSkel;
+use crate::Layer;
+
+const MAX_TASKS: usize = bpf_intf::consts_MAX_TASKS as usize;
+
+fn boot_id() -> String {
+    fs::read_to_string("/proc/sys/kernel/random/boot_id")
+        .map(|id| id.trim().to_string())
+        .unwrap_or_default()
+}
+
+/// Start time of task @pid in clock ticks since boot, i.e. the starttime
+/// field of /proc/PID/stat, which tells apart tasks sharing the same pid.
+fn task_start_ticks(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // comm can contain spaces and parentheses, skip to after its last ')'.
+    // starttime is the 22nd field and the fields after comm start at the
+    // 3rd one.
+    let fields = &stat[stat.rfind(')')? + 1..];
+    fields.split_whitespace().nth(22 - 3)?.parse().ok()
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TaskState {
+    pub pid: u32,
+    pub start_ticks: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LayerState {
+    pub name: String,
+    pub nr_cpus: usize,
+    pub tasks: Vec<TaskState>,
+}
+
+/// Task to layer assignments and CPU allocations saved to --state-file on
+/// exit so that the next instance can pick up where this one left off.
+/// Layers are identified by name, so layers which were added, removed or
+/// reordered across the restart are handled gracefully.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SchedState {
+    pub boot_id: String,
+    pub layers: Vec<LayerState>,
+}
+
+impl SchedState {
+    /// Load the state saved by the previous instance. Returns None if there
+    /// is none or if it was saved before the last reboot, in which case the
+    /// pids in it are meaningless.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let json = match fs::read_to_string(path) {
+            Ok(v) => v,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => Err(e).with_context(|| format!("reading state file {:?}", path))?,
+        };
+
+        let state: Self = match serde_json::from_str(&json) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Ignoring malformed state file {:?} ({})", path, &e);
+                return Ok(None);
+            }
+        };
+
+        if state.boot_id != boot_id() {
+            info!("Ignoring state file {:?} from a previous boot", path);
+            return Ok(None);
+        }
+        Ok(Some(state))
+    }
+
+    /// Collect the current task to layer assignments from BPF.
+    pub fn collect(skel: &BpfSkel, layers: &[Layer]) -> Result<Self> {
+        let mut tasks: BTreeMap<usize, Vec<TaskState>> = BTreeMap::new();
+        for key in skel.maps.task_layers.keys() {
+            let Some(val) = skel
+                .maps
+                .task_layers
+                .lookup(&key, libbpf_rs::MapFlags::ANY)?
+            else {
+                continue;
+            };
+            let pid = u32::from_ne_bytes(key.as_slice().try_into()?);
+            let layer_id = u32::from_ne_bytes(val.as_slice().try_into()?) as usize;
+            let Some(start_ticks) = task_start_ticks(pid) else {
+                continue;
+            };
+            tasks
+                .entry(layer_id)
+                .or_default()
+                .push(TaskState { pid, start_ticks });
+        }
+
+        Ok(Self {
+            boot_id: boot_id(),
+            layers: layers
+                .iter()
+                .enumerate()
+                .map(|(idx, layer)| LayerState {
+                    name: layer.name.clone(),
+                    nr_cpus: layer.nr_cpus,
+                    tasks: tasks.remove(&idx).unwrap_or_default(),
+                })
+                .collect(),
+        })
+    }
+
+    /// Write the state atomically so that a crash while saving doesn't
+    /// leave a truncated file behind for the next instance.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string(self)?)
+            .with_context(|| format!("writing state file {:?}", &tmp))?;
+        fs::rename(&tmp, path).with_context(|| format!("renaming {:?} to {:?}", &tmp, path))?;
+
+        let nr_tasks: usize = self.layers.iter().map(|l| l.tasks.len()).sum();
+        info!(
+            "Saved {} task assignments of {} layers to {:?}",
+            nr_tasks,
+            self.layers.len(),
+            path
+        );
+        Ok(())
+    }
+
+    /// Index of the current layer named @name.
+    fn layer_idx(layers: &[Layer], name: &str) -> Option<usize> {
+        layers.iter().position(|layer| layer.name == name)
+    }
+
+    /// Hand the saved task to layer assignments over to BPF, which puts
+    /// each task back into its layer when it's first seen instead of
+    /// matching it. Tasks which exited across the restart are skipped, and
+    /// so are pids reused by other tasks, which have a different start time.
+    /// BPF checks the start time again in case the pid is reused later.
+    pub fn restore_tasks(&self, skel: &mut BpfSkel, layers: &[Layer]) -> Result<usize> {
+        let mut nr_restored = 0;
+        for lstate in self.layers.iter() {
+            let Some(idx) = Self::layer_idx(layers, &lstate.name) else {
+                warn!("Layer {:?} is gone, re-matching its tasks", &lstate.name);
+                continue;
+            };
+
+            for task in lstate.tasks.iter() {
+                if nr_restored >= MAX_TASKS {
+                    warn!("Too many tasks in the state file, re-matching the rest");
+                    return Ok(nr_restored);
+                }
+                if task_start_ticks(task.pid) != Some(task.start_ticks) {
+                    continue;
+                }
+
+                let mut val = vec![0u8; std::mem::size_of::<bpf_intf::restored_layer>()];
+                let val_ptr = val.as_mut_ptr() as *mut bpf_intf::restored_layer;
+                unsafe {
+                    (*val_ptr).layer_id = idx as u32;
+                    (*val_ptr).start_ticks = task.start_ticks;
+                }
+                skel.maps.restored_layers.update(
+                    &task.pid.to_ne_bytes(),
+                    &val,
+                    libbpf_rs::MapFlags::ANY,
+                )?;
+                nr_restored += 1;
+            }
+        }
+
+        info!("Restoring layer assignments of {} tasks", nr_restored);
+        Ok(nr_restored)
+    }
+
+    /// Number of CPUs layer @layer had under the previous instance.
+    pub fn nr_cpus(&self, layer: &Layer) -> Option<usize> {
+        self.layers
+            .iter()
+            .find(|lstate| lstate.name == layer.name)
+            .map(|lstate| lstate.nr_cpus)
+    }
+}
@@ -43,6 +43,7 @@ const GSTAT_SKIP_PREEMPT: usize = bpf_intf::global_stat_id_GSTAT_SKIP_PREEMPT as
 const GSTAT_FIXUP_VTIME: usize = bpf_intf::global_stat_id_GSTAT_FIXUP_VTIME as usize;
 const GSTAT_PREEMPTING_MISMATCH: usize =
     bpf_intf::global_stat_id_GSTAT_PREEMPTING_MISMATCH as usize;
+const GSTAT_RESTORED_LAYER: usize = bpf_intf::global_stat_id_GSTAT_RESTORED_LAYER as usize;
 
 const LSTAT_SEL_LOCAL: usize = bpf_intf::layer_stat_id_LSTAT_SEL_LOCAL as usize;
 const LSTAT_ENQ_LOCAL: usize = bpf_intf::layer_stat_id_LSTAT_ENQ_LOCAL as usize;
@@ -53,6 +54,7 @@ const LSTAT_ENQ_DSQ: usize = bpf_intf::layer_stat_id_LSTAT_ENQ_DSQ as usize;
 const LSTAT_MIN_EXEC: usize = bpf_intf::layer_stat_id_LSTAT_MIN_EXEC as usize;
 const LSTAT_MIN_EXEC_NS: usize = bpf_intf::layer_stat_id_LSTAT_MIN_EXEC_NS as usize;
 const LSTAT_OPEN_IDLE: usize = bpf_intf::layer_stat_id_LSTAT_OPEN_IDLE as usize;
+const LSTAT_EMPTY_IDLE: usize = bpf_intf::layer_stat_id_LSTAT_EMPTY_IDLE as usize;
 const LSTAT_AFFN_VIOL: usize = bpf_intf::layer_stat_id_LSTAT_AFFN_VIOL as usize;
 const LSTAT_KEEP: usize = bpf_intf::layer_stat_id_LSTAT_KEEP as usize;
 const LSTAT_KEEP_FAIL_MAX_EXEC: usize = bpf_intf::layer_stat_id_LSTAT_KEEP_FAIL_MAX_EXEC as usize;
@@ -71,11 +73,16 @@ const LSTAT_MIGRATION: usize = bpf_intf::layer_stat_id_LSTAT_MIGRATION as usize;
 const LSTAT_XNUMA_MIGRATION: usize = bpf_intf::layer_stat_id_LSTAT_XNUMA_MIGRATION as usize;
 const LSTAT_XLLC_MIGRATION: usize = bpf_intf::layer_stat_id_LSTAT_XLLC_MIGRATION as usize;
 const LSTAT_XLLC_MIGRATION_SKIP: usize = bpf_intf::layer_stat_id_LSTAT_XLLC_MIGRATION_SKIP as usize;
+const LSTAT_PROC_LLC_KEEP: usize = bpf_intf::layer_stat_id_LSTAT_PROC_LLC_KEEP as usize;
 const LSTAT_XLAYER_WAKE: usize = bpf_intf::layer_stat_id_LSTAT_XLAYER_WAKE as usize;
 const LSTAT_XLAYER_REWAKE: usize = bpf_intf::layer_stat_id_LSTAT_XLAYER_REWAKE as usize;
 const LSTAT_LLC_DRAIN_TRY: usize = bpf_intf::layer_stat_id_LSTAT_LLC_DRAIN_TRY as usize;
 const LSTAT_LLC_DRAIN: usize = bpf_intf::layer_stat_id_LSTAT_LLC_DRAIN as usize;
 const LSTAT_SKIP_REMOTE_NODE: usize = bpf_intf::layer_stat_id_LSTAT_SKIP_REMOTE_NODE as usize;
+const LSTAT_BW_THROTTLE: usize = bpf_intf::layer_stat_id_LSTAT_BW_THROTTLE as usize;
+const LSTAT_BW_SLICE_CUT: usize = bpf_intf::layer_stat_id_LSTAT_BW_SLICE_CUT as usize;
+const LSTAT_CONTEND_WIN: usize = bpf_intf::layer_stat_id_LSTAT_CONTEND_WIN as usize;
+const LSTAT_CONTEND_LOSS: usize = bpf_intf::layer_stat_id_LSTAT_CONTEND_LOSS as usize;
 
 const LLC_LSTAT_LAT: usize = bpf_intf::llc_layer_stat_id_LLC_LSTAT_LAT as usize;
 const LLC_LSTAT_CNT: usize = bpf_intf::llc_layer_stat_id_LLC_LSTAT_CNT as usize;
@@ -146,6 +153,8 @@ pub struct LayerStats {
     pub min_exec_us: u64,
     #[stat(desc = "% dispatched into idle CPUs occupied by other layers")]
     pub open_idle: f64,
+    #[stat(desc = "% confined tasks dispatched outside the layer while the machine was empty")]
+    pub empty_idle: f64,
     #[stat(desc = "% preempted other tasks")]
     pub preempt: f64,
     #[stat(desc = "% preempted XLLC tasks")]
@@ -184,6 +193,8 @@ pub struct LayerStats {
     pub xllc_migration: f64,
     #[stat(desc = "% migration skipped across LLCs due to xllc_mig_min_us")]
     pub xllc_migration_skip: f64,
+    #[stat(desc = "% migration skipped to keep the process in its home LLC")]
+    pub proc_llc_keep: f64,
     #[stat(desc = "% wakers across layers")]
     pub xlayer_wake: f64,
     #[stat(desc = "% rewakers across layers where waker has waken the task previously")]
@@ -194,6 +205,14 @@ pub struct LayerStats {
     pub llc_drain: f64,
     #[stat(desc = "% skip LLC dispatch on remote node")]
     pub skip_remote_node: f64,
+    #[stat(desc = "count of times the layer was throttled due to util_cap")]
+    pub bw_throttle: u64,
+    #[stat(desc = "count of slices cut short while the layer was throttled")]
+    pub bw_slice_cut: u64,
+    #[stat(desc = "count of times the layer won the contention for a CPU (--contention)")]
+    pub contend_win: u64,
+    #[stat(desc = "count of times the layer lost the contention for a CPU (--contention)")]
+    pub contend_loss: u64,
     #[stat(desc = "mask of allocated CPUs", _om_skip)]
     pub cpus: Vec<u64>,
     #[stat(desc = "count of CPUs assigned")]
@@ -202,18 +221,27 @@ pub struct LayerStats {
     pub min_nr_cpus: u32,
     #[stat(desc = "maximum # of CPUs assigned")]
     pub max_nr_cpus: u32,
-    #[stat(desc = "count of CPUs assigned per LLC")]
+    #[stat(desc = "configured weight")]
+    pub weight: u32,
+    #[stat(desc = "effective weight after correcting infeasible weights")]
+    pub eff_weight: u32,
+    #[stat(desc = "count of CPUs assigned per LLC", _om_label = "llc")]
     pub nr_llc_cpus: Vec<u32>,
     #[stat(desc = "slice duration config")]
     pub slice_us: u64,
-    #[stat(desc = "Per-LLC scheduling event fractions")]
+    #[stat(desc = "Per-LLC scheduling event fractions", _om_label = "llc")]
     pub llc_fracs: Vec<f64>,
-    #[stat(desc = "Per-LLC average latency")]
+    #[stat(desc = "Per-LLC average latency", _om_label = "llc")]
     pub llc_lats: Vec<f64>,
     #[stat(desc = "Layer memory bandwidth as a % of total allowed (0 for \"no limit\"")]
     pub membw_pct: f64,
     #[stat(desc = "DSQ insertion ratio EWMA (10s window)")]
     pub dsq_insert_ewma: f64,
+    #[stat(
+        desc = "Metadata from the layer spec (owner, ticket, description, ...)",
+        _om_skip
+    )]
+    pub metadata: BTreeMap<String, String>,
 }
 
 impl LayerStats {
@@ -285,6 +313,7 @@ impl LayerStats {
             min_exec: lstat_pct(LSTAT_MIN_EXEC),
             min_exec_us: (lstat(LSTAT_MIN_EXEC_NS) / 1000) as u64,
             open_idle: lstat_pct(LSTAT_OPEN_IDLE),
+            empty_idle: lstat_pct(LSTAT_EMPTY_IDLE),
             preempt: lstat_pct(LSTAT_PREEMPT),
             preempt_xllc: lstat_pct(LSTAT_PREEMPT_XLLC),
             preempt_xnuma: lstat_pct(LSTAT_PREEMPT_XNUMA),
@@ -306,13 +335,20 @@ impl LayerStats {
             xlayer_rewake: lstat_pct(LSTAT_XLAYER_REWAKE),
             xllc_migration: lstat_pct(LSTAT_XLLC_MIGRATION),
             xllc_migration_skip: lstat_pct(LSTAT_XLLC_MIGRATION_SKIP),
+            proc_llc_keep: lstat_pct(LSTAT_PROC_LLC_KEEP),
             llc_drain_try: lstat_pct(LSTAT_LLC_DRAIN_TRY),
             llc_drain: lstat_pct(LSTAT_LLC_DRAIN),
             skip_remote_node: lstat_pct(LSTAT_SKIP_REMOTE_NODE),
+            bw_throttle: lstat(LSTAT_BW_THROTTLE) as u64,
+            bw_slice_cut: lstat(LSTAT_BW_SLICE_CUT) as u64,
+            contend_win: lstat(LSTAT_CONTEND_WIN) as u64,
+            contend_loss: lstat(LSTAT_CONTEND_LOSS) as u64,
             cpus: layer.cpus.as_raw_slice().to_vec(),
             cur_nr_cpus: layer.cpus.weight() as u32,
             min_nr_cpus: nr_cpus_range.0 as u32,
             max_nr_cpus: nr_cpus_range.1 as u32,
+            weight: layer.kind.common().weight,
+            eff_weight: layer.eff_weight,
             nr_llc_cpus: layer.nr_llc_cpus.iter().map(|&v| v as u32).collect(),
             slice_us: stats.layer_slice_us[lidx],
             llc_fracs: {
@@ -332,6 +368,7 @@ impl LayerStats {
                 .collect(),
             membw_pct: membw_frac * 100.0,
             dsq_insert_ewma: stats.layer_dsq_insert_ewma[lidx] * 100.0,
+            metadata: layer.metadata.clone(),
         }
     }
 
@@ -378,13 +415,15 @@ impl LayerStats {
 
         writeln!(
             w,
-            "  {:<width$}  open_idle={} mig={} xnuma_mig={} xllc_mig/skip={}/{} affn_viol={}",
+            "  {:<width$}  open/empty_idle={}/{} mig={} xnuma_mig={} xllc_mig/skip={}/{} proc_keep={} affn_viol={}",
             "",
             fmt_pct(self.open_idle),
+            fmt_pct(self.empty_idle),
             fmt_pct(self.migration),
             fmt_pct(self.xnuma_migration),
             fmt_pct(self.xllc_migration),
             fmt_pct(self.xllc_migration_skip),
+            fmt_pct(self.proc_llc_keep),
             fmt_pct(self.affn_viol),
             width = header_width,
         )?;
@@ -416,11 +455,15 @@ impl LayerStats {
 
         writeln!(
             w,
-            "  {:<width$}  slice={}ms min_exec={}/{:7.2}ms",
+            "  {:<width$}  slice={}ms min_exec={}/{:7.2}ms bw_throttle/cut={}/{} contend_win/loss={}/{}",
             "",
             self.slice_us as f64 / 1000.0,
             fmt_pct(self.min_exec),
             self.min_exec_us as f64 / 1000.0,
+            fmt_num(self.bw_throttle),
+            fmt_num(self.bw_slice_cut),
+            fmt_num(self.contend_win),
+            fmt_num(self.contend_loss),
             width = header_width
         )?;
 
@@ -428,11 +471,13 @@ impl LayerStats {
 
         writeln!(
             w,
-            "  {:<width$}  cpus={:3} [{:3},{:3}] {}",
+            "  {:<width$}  cpus={:3} [{:3},{:3}] weight={}/{} {}",
             "",
             self.cur_nr_cpus,
             self.min_nr_cpus,
             self.max_nr_cpus,
+            self.eff_weight,
+            self.weight,
             &cpumask,
             width = header_width
         )?;
@@ -479,6 +524,21 @@ impl LayerStats {
             );
         }
 
+        if !self.metadata.is_empty() {
+            let metadata: Vec<String> = self
+                .metadata
+                .iter()
+                .map(|(k, v)| format!("{}={:?}", k, v))
+                .collect();
+            writeln!(
+                w,
+                "  {:<width$}  {}",
+                "",
+                metadata.join(" "),
+                width = header_width,
+            )?;
+        }
+
         Ok(())
     }
 }
@@ -533,6 +593,8 @@ pub struct SysStats {
     pub fixup_vtime: u64,
     #[stat(desc = "Number of times cpuc->preempting_task didn't come on the CPU")]
     pub preempting_mismatch: u64,
+    #[stat(desc = "Number of tasks put back into their layer from --state-file")]
+    pub restored_layer: u64,
     #[stat(desc = "fallback CPU")]
     pub fallback_cpu: u32,
     #[stat(desc = "per-layer statistics")]
@@ -597,6 +659,7 @@ impl SysStats {
             skip_preempt: stats.bpf_stats.gstats[GSTAT_SKIP_PREEMPT],
             fixup_vtime: stats.bpf_stats.gstats[GSTAT_FIXUP_VTIME],
             preempting_mismatch: stats.bpf_stats.gstats[GSTAT_PREEMPTING_MISMATCH],
+            restored_layer: stats.bpf_stats.gstats[GSTAT_RESTORED_LAYER],
             fallback_cpu: fallback_cpu as u32,
             fallback_cpu_util: stats.bpf_stats.gstats[GSTAT_FB_CPU_USAGE] as f64
                 / elapsed_ns as f64
@@ -642,8 +705,12 @@ impl SysStats {
 
         writeln!(
             w,
-            "skip_preempt={} antistall={} fixup_vtime={} preempting_mismatch={}",
-            self.skip_preempt, self.antistall, self.fixup_vtime, self.preempting_mismatch
+            "skip_preempt={} antistall={} fixup_vtime={} preempting_mismatch={} restored={}",
+            self.skip_preempt,
+            self.antistall,
+            self.fixup_vtime,
+            self.preempting_mismatch,
+            self.restored_layer
         )?;
 
         writeln!(
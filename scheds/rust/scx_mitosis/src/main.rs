@@ -574,10 +574,7 @@ fn read_cpu_ctxs(skel: &BpfSkel) -> Result<Vec<bpf_intf::cpu_ctx>> {
 #[clap_main::clap_main]
 fn main(opts: Opts) -> Result<()> {
     if opts.version {
-        println!(
-            "scx_mitosis {}",
-            build_id::full_version(env!("CARGO_PKG_VERSION"))
-        );
+        println!("{:#}", scx_utils::version_info!());
         return Ok(());
     }
 
@@ -353,10 +353,7 @@ impl Drop for Scheduler<'_> {
 #[clap_main::clap_main]
 fn main(opts: CliOpts) -> Result<()> {
     if opts.version {
-        println!(
-            "scx_p2dq: {}",
-            build_id::full_version(env!("CARGO_PKG_VERSION"))
-        );
+        println!("{:#}", scx_utils::version_info!());
         return Ok(());
     }
 
@@ -218,6 +218,16 @@ please open a GitHub issue.
 }
 
 fn main() -> Result<()> {
+    // Keep the scheduler free of option parsing, only honor the --version
+    // convention of the other schedulers.
+    if std::env::args()
+        .skip(1)
+        .any(|arg| arg == "--version" || arg == "-V")
+    {
+        println!("{:#}", scx_utils::version_info!());
+        return Ok(());
+    }
+
     print_warning();
 
     // Initialize and load the FIFO scheduler.
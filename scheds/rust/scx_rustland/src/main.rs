@@ -11,7 +11,6 @@ mod bpf;
 use bpf::*;
 
 mod stats;
-use std::collections::BTreeSet;
 use std::io::{self};
 use std::mem::MaybeUninit;
 use std::time::Duration;
@@ -23,9 +22,12 @@ use libbpf_rs::OpenObject;
 use log::info;
 use log::warn;
 use procfs::process::Process;
+use scx_rustland_core::RunQueue;
+use scx_rustland_core::RunQueueKind;
 use scx_stats::prelude::*;
-use scx_utils::build_id;
 use scx_utils::libbpf_clap_opts::LibbpfOpts;
+use scx_utils::vtime;
+use scx_utils::SchedThreadArgs;
 use scx_utils::UserExitInfo;
 use stats::Metrics;
 
@@ -56,7 +58,8 @@ const SCHEDULER_NAME: &str = "RustLand";
 /// exec_runtime, resulting in earlier deadlines. In contrast, CPU-intensive tasks that don’t sleep
 /// accumulate a larger exec_runtime and thus get scheduled later.
 ///
-/// All the tasks are stored in a BTreeSet (TaskTree), using the deadline as the ordering key.
+/// All the tasks are stored in a run-queue (a BTreeSet by default, see `--runqueue`), using the
+/// deadline as the ordering key.
 /// Once the order of execution is determined all tasks are sent back to the BPF counterpart
 /// (scx_rustland_core) to be dispatched.
 ///
@@ -84,6 +87,12 @@ struct Opts {
     #[clap(short = 'l', long, action = clap::ArgAction::SetTrue)]
     percpu_local: bool,
 
+    /// Run-queue used to order the tasks: "btree" (exact ordering), "pairing" (pairing heap, exact
+    /// ordering, cheaper inserts) or "wheel" (timing wheel, deadlines approximated to the minimum
+    /// time slice).
+    #[clap(long, default_value = "btree")]
+    runqueue: RunQueueKind,
+
     /// If specified, only tasks which have their scheduling policy set to SCHED_EXT using
     /// sched_setscheduler(2) are switched. Otherwise, all tasks are switched.
     #[clap(short = 'p', long, action = clap::ArgAction::SetTrue)]
@@ -115,6 +124,9 @@ struct Opts {
     #[clap(short = 'V', long, action = clap::ArgAction::SetTrue)]
     version: bool,
 
+    #[clap(flatten, next_help_heading = "Scheduler Thread Options")]
+    sched_thread: SchedThreadArgs,
+
     #[clap(flatten, next_help_heading = "Libbpf Options")]
     pub libbpf: LibbpfOpts,
 }
@@ -152,7 +164,7 @@ struct Scheduler<'a> {
     bpf: BpfScheduler<'a>,                  // BPF connector
     opts: &'a Opts,                         // scheduler options
     stats_server: StatsServer<(), Metrics>, // statistics
-    tasks: BTreeSet<Task>,                  // tasks ordered by deadline
+    tasks: Box<dyn RunQueue<Task>>,         // tasks ordered by deadline
     vruntime_now: u64,     // Tracks the latest observed (max) vruntime across tasks
     init_page_faults: u64, // Initial page faults counter
     slice_ns: u64,         // Default time slice (in ns)
@@ -179,9 +191,8 @@ impl<'a> Scheduler<'a> {
         )?;
 
         info!(
-            "{} version {} - scx_rustland_core {}",
-            SCHEDULER_NAME,
-            build_id::full_version(env!("CARGO_PKG_VERSION")),
+            "{} - scx_rustland_core {}",
+            scx_utils::version_info!(),
             scx_rustland_core::VERSION
         );
 
@@ -190,7 +201,9 @@ impl<'a> Scheduler<'a> {
             bpf,
             opts,
             stats_server,
-            tasks: BTreeSet::new(),
+            tasks: opts
+                .runqueue
+                .build(slice_ns_min, |task: &Task| task.deadline),
             vruntime_now: 0,
             init_page_faults: 0,
             slice_ns,
@@ -230,12 +243,12 @@ impl<'a> Scheduler<'a> {
 
     // Return a value proportional to the task's weight.
     fn scale_by_task_weight(task: &QueuedTask, value: u64) -> u64 {
-        value * task.weight / 100
+        vtime::scale_by_weight(value, task.weight)
     }
 
     // Return a value inversely proportional to the task's weight.
     fn scale_by_task_weight_inverse(task: &QueuedTask, value: u64) -> u64 {
-        value * 100 / task.weight
+        vtime::scale_by_weight_inverse(value, task.weight)
     }
 
     /// Updates a task's virtual runtime based on kernel information and
@@ -249,8 +262,7 @@ impl<'a> Scheduler<'a> {
             self.vruntime_now
         } else {
             // Prevent sleeping tasks from gaining more than one full slice of vruntime credit.
-            let vruntime_min = self.vruntime_now.saturating_sub(self.slice_ns);
-            task.vtime.max(vruntime_min)
+            vtime::clamp_lag(task.vtime, self.vruntime_now, self.slice_ns)
         };
 
         // Compute the time slice the task just consumed.
@@ -272,7 +284,7 @@ impl<'a> Scheduler<'a> {
     /// dispatching failed (the task is automatically re-enqueued in that case).
     fn dispatch_task(&mut self) -> bool {
         // Retrieve the next task to dispatch, if any.
-        let Some(task) = self.tasks.pop_first() else {
+        let Some(task) = self.tasks.pop() else {
             return true;
         };
 
@@ -384,9 +396,8 @@ fn main() -> Result<()> {
 
     if opts.version {
         println!(
-            "{} version {} - scx_rustland_core {}",
-            SCHEDULER_NAME,
-            build_id::full_version(env!("CARGO_PKG_VERSION")),
+            "{:#}\n  scx_rustland_core: {}",
+            scx_utils::version_info!(),
             scx_rustland_core::VERSION
         );
         return Ok(());
@@ -421,6 +432,8 @@ fn main() -> Result<()> {
         }
     }
 
+    let _sched_thread = opts.sched_thread.apply()?;
+
     let mut open_object = MaybeUninit::uninit();
     loop {
         let mut sched = Scheduler::init(&opts, &mut open_object)?;
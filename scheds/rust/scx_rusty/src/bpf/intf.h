@@ -50,6 +50,16 @@ enum consts {
 	DL_FREQ_FT_MAX		= 100000,
 	DL_MAX_LAT_PRIO		= 39,
 
+	/*
+	 * With --min-service-us-per-s, each domain's DSQ is scanned for
+	 * starving tasks at most once per this interval.
+	 */
+	DL_SERVER_INTV_NS	= (10 * NSEC_PER_MSEC),
+
+	/* Maximum number of colocation groups and threads in them */
+	MAX_COLOC_GROUPS	= 16,
+	MAX_COLOC_TASKS		= 8192,
+
 	/*
 	 * When userspace load balancer is trying to determine the tasks to push
 	 * out from an overloaded domain, it looks at the the following number
@@ -77,11 +87,21 @@ enum stat_idx {
 	RUSTY_STAT_DSQ_DISPATCH,
 	RUSTY_STAT_GREEDY_LOCAL,
 	RUSTY_STAT_GREEDY_XNUMA,
+	RUSTY_STAT_DL_SERVER,
+	RUSTY_STAT_ORPHAN_DISPATCH,
+	RUSTY_STAT_SHED_DISPATCH,
 
 	/* Extra stats that don't contribute to total */
 	RUSTY_STAT_REPATRIATE,
 	RUSTY_STAT_KICK_GREEDY,
 	RUSTY_STAT_LOAD_BALANCE,
+	RUSTY_STAT_DL_SERVER_NS,
+	RUSTY_STAT_ORPHANED,
+	RUSTY_STAT_XNODE_MIGRATION,
+	RUSTY_STAT_XNODE_RESIDENCY_NS,
+	RUSTY_STAT_COLOC_PLACE,
+	RUSTY_STAT_SHED,
+	RUSTY_STAT_SHED_NS,
 
 	/* Errors */
 	RUSTY_STAT_TASK_GET_ERR,
@@ -79,16 +79,44 @@ const volatile u32 load_half_life = 1000000000	/* 1s */;
 
 const volatile bool kthreads_local;
 const volatile bool fifo_sched = false;
+const volatile bool single_dom;
 const volatile bool direct_greedy_numa;
 const volatile bool mempolicy_affinity;
-const volatile u32 greedy_threshold;
-const volatile u32 greedy_threshold_x_numa;
+/* updated by userspace at runtime, see Tunables */
+volatile u32 greedy_threshold;
+volatile u32 greedy_threshold_x_numa;
 const volatile u32 rusty_perf_mode;
 const volatile u32 debug;
+const volatile u64 min_service_ns;
+
+/*
+ * Colocation groups, including the audio threads of --audio-affinity.
+ * Userspace fills @coloc_pids with the group of each member thread and
+ * points @coloc_doms at the domains the groups are kept on.
+ * @coloc_nr_runs and @coloc_nr_home_runs count how often the members ran and
+ * how often they did so on their group's domain.
+ */
+const volatile u32 nr_coloc_groups;
+volatile u32 coloc_doms[MAX_COLOC_GROUPS];
+volatile u64 coloc_nr_runs[MAX_COLOC_GROUPS];
+volatile u64 coloc_nr_home_runs[MAX_COLOC_GROUPS];
 
 /* base slice duration */
 volatile u64 slice_ns;
 
+/*
+ * Load shedding. When the average queue delay stays above @shed_delay_ns for
+ * @shed_window_ns, tasks are queued FIFO on the local DSQ of their CPU,
+ * skipping domain placement and greedy execution, until the delay drops
+ * below half of the threshold. @shed_at is the time shedding started, 0 if
+ * not shedding.
+ */
+const volatile u64 shed_delay_ns;
+const volatile u64 shed_window_ns;
+volatile u64 queue_delay_avg;
+volatile u64 shed_over_at;
+volatile u64 shed_at;
+
 struct bpfmask_wrapper {
 	struct bpf_cpumask __kptr *instance;
 };
@@ -186,6 +214,9 @@ struct pcpu_ctx {
 
 struct pcpu_ctx pcpu_ctx[MAX_CPUS];
 
+/* last time each domain's DSQ was scanned for starving tasks */
+u64 dl_server_at[MAX_DOMS];
+
 /*
  * Numa node context
  */
@@ -219,6 +250,14 @@ struct {
 	__uint(map_flags, 0);
 } task_masks SEC(".maps");
 
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__type(key, u32);
+	__type(value, u32);
+	__uint(max_entries, MAX_COLOC_TASKS);
+	__uint(map_flags, 0);
+} coloc_pids SEC(".maps");
+
 static struct task_ctx *try_lookup_task_ctx(struct task_struct *p)
 {
 	struct task_ctx __arena *taskc = sdt_task_data(p);
@@ -787,6 +826,8 @@ static void clamp_task_vtime(struct task_struct *p, struct task_ctx *taskc, u64
 	}
 }
 
+u32 dom_node_id(u32 dom_id);
+
 static bool task_set_domain(struct task_struct *p __arg_trusted,
 			    u32 new_dom_id, bool init_dsq_vtime)
 {
@@ -807,9 +848,19 @@ static bool task_set_domain(struct task_struct *p __arg_trusted,
 	if (!old_domc)
 		return false;
 
+	/*
+	 * None of the domains intersects @p's cpumask, e.g. because the CPUs
+	 * it's affine to went offline or came online after the domains were
+	 * built. Re-home @p on its own cpumask, it's then dispatched directly
+	 * to one of its CPUs until it can be placed in a domain again.
+	 */
 	if (new_dom_id == NO_DOM_FOUND) {
-		bpf_cpumask_clear(t_cpumask);
-		return !(p->scx.flags & SCX_TASK_QUEUED);
+		if (!taskc->orphaned) {
+			taskc->orphaned = true;
+			stat_add(RUSTY_STAT_ORPHANED, 1);
+		}
+		bpf_cpumask_copy(t_cpumask, p->cpus_ptr);
+		return true;
 	}
 
 	new_domc = try_lookup_dom_ctx_arena(new_dom_id);
@@ -840,8 +891,21 @@ static bool task_set_domain(struct task_struct *p __arg_trusted,
 		if (!init_dsq_vtime)
 			dom_xfer_task(p, new_dom_id, now);
 
+		/*
+		 * Account how long @p stayed on its node before being moved to
+		 * another one.
+		 */
+		if (init_dsq_vtime) {
+			taskc->node_at = now;
+		} else if (dom_node_id(old_dom_id) != dom_node_id(new_dom_id)) {
+			stat_add(RUSTY_STAT_XNODE_MIGRATION, 1);
+			stat_add(RUSTY_STAT_XNODE_RESIDENCY_NS, now - taskc->node_at);
+			taskc->node_at = now;
+		}
+
 		taskc->target_dom = new_dom_id;
 		taskc->domc = new_domc;
+		taskc->orphaned = false;
 
 		cast_kern(new_domc);
 		p->scx.dsq_vtime = dom_min_vruntime(new_domc);
@@ -853,6 +917,48 @@ static bool task_set_domain(struct task_struct *p __arg_trusted,
 	return taskc->target_dom == new_dom_id;
 }
 
+/*
+ * Move @p to the domain of its colocation group if userspace put it in one.
+ * Members are skipped by the userspace load balancer, the whole group is
+ * moved by userspace instead.
+ */
+static void coloc_place(struct task_struct *p __arg_trusted, struct task_ctx *taskc)
+{
+	u32 pid = p->pid, gid, dom_id;
+	u32 *gidp;
+
+	taskc->in_coloc = false;
+	if (!(gidp = bpf_map_lookup_elem(&coloc_pids, &pid)))
+		return;
+
+	gid = *gidp;
+	if (gid >= MAX_COLOC_GROUPS || gid >= nr_coloc_groups)
+		return;
+
+	taskc->in_coloc = true;
+	taskc->coloc_grp = gid;
+
+	dom_id = coloc_doms[gid];
+	if (dom_id >= nr_doms || taskc->target_dom == dom_id ||
+	    !(taskc->dom_mask & (1LLU << dom_id)))
+		return;
+
+	if (task_set_domain(p, dom_id, false))
+		stat_add(RUSTY_STAT_COLOC_PLACE, 1);
+}
+
+static void coloc_account(struct task_ctx *taskc)
+{
+	u32 gid = taskc->coloc_grp;
+
+	if (gid >= MAX_COLOC_GROUPS)
+		return;
+
+	__sync_fetch_and_add(&coloc_nr_runs[gid], 1);
+	if (cpu_to_dom_id(bpf_get_smp_processor_id()) == coloc_doms[gid])
+		__sync_fetch_and_add(&coloc_nr_home_runs[gid], 1);
+}
+
 
 static s32 try_sync_wakeup(struct task_struct *p, struct task_ctx *taskc,
 			   s32 prev_cpu)
@@ -921,6 +1027,21 @@ s32 BPF_STRUCT_OPS(rusty_select_cpu, struct task_struct *p, s32 prev_cpu,
 	if (!(taskc = lookup_task_ctx_mask(p, &p_cpumask)) || !p_cpumask)
 		goto enoent;
 
+	if (nr_coloc_groups)
+		coloc_place(p, taskc);
+
+	/*
+	 * @p can't run in any domain or we're shedding load, let ->enqueue()
+	 * dispatch it directly.
+	 */
+	if (taskc->orphaned || READ_ONCE(shed_at)) {
+		cpu = prev_cpu;
+		if (!bpf_cpumask_test_cpu(cpu, p->cpus_ptr))
+			cpu = bpf_cpumask_any_distribute(p->cpus_ptr);
+		scx_bpf_put_idle_cpumask(idle_smtmask);
+		return cpu < nr_cpu_ids ? cpu : prev_cpu;
+	}
+
 	if (p->nr_cpus_allowed == 1) {
 		cpu = prev_cpu;
 		if (kthreads_local && (p->flags & PF_KTHREAD)) {
@@ -1155,6 +1276,40 @@ void BPF_STRUCT_OPS(rusty_enqueue, struct task_struct *p __arg_trusted, u64 enq_
 	if (!(taskc = lookup_task_ctx_mask(p, &p_cpumask)) || !p_cpumask)
 		return;
 
+	/*
+	 * @p can't run in any domain, so none of the domain DSQs would ever be
+	 * consumed by a CPU it can run on. Dispatch it directly to one of its
+	 * CPUs instead.
+	 */
+	if (taskc->orphaned) {
+		cpu = scx_bpf_task_cpu(p);
+		if (!bpf_cpumask_test_cpu(cpu, p->cpus_ptr))
+			cpu = bpf_cpumask_any_distribute(p->cpus_ptr);
+		if (cpu < nr_cpu_ids) {
+			stat_add(RUSTY_STAT_ORPHAN_DISPATCH, 1);
+			scx_bpf_dsq_insert(p, SCX_DSQ_LOCAL_ON | cpu, slice_ns, enq_flags);
+			scx_bpf_kick_cpu(cpu, SCX_KICK_IDLE);
+			return;
+		}
+	}
+
+	/*
+	 * We're shedding load. Queue @p FIFO on its CPU and skip all the
+	 * domain bookkeeping until the queue delay recovers.
+	 */
+	if (READ_ONCE(shed_at)) {
+		cpu = scx_bpf_task_cpu(p);
+		if (!bpf_cpumask_test_cpu(cpu, p->cpus_ptr))
+			cpu = bpf_cpumask_any_distribute(p->cpus_ptr);
+		if (cpu < nr_cpu_ids) {
+			stat_add(RUSTY_STAT_SHED_DISPATCH, 1);
+			taskc->enq_at = scx_bpf_now();
+			scx_bpf_dsq_insert(p, SCX_DSQ_LOCAL_ON | cpu, slice_ns, enq_flags);
+			scx_bpf_kick_cpu(cpu, SCX_KICK_IDLE);
+			return;
+		}
+	}
+
 	domc = task_domain(taskc);
 	if (!domc)
 		return;
@@ -1194,6 +1349,7 @@ void BPF_STRUCT_OPS(rusty_enqueue, struct task_struct *p __arg_trusted, u64 enq_
 	}
 
 dom_queue:
+	taskc->enq_at = scx_bpf_now();
 	if (fifo_sched)
 		scx_bpf_dsq_insert(p, taskc->target_dom, slice_ns, enq_flags);
 	else
@@ -1338,6 +1494,46 @@ static void task_set_preferred_mempolicy_dom_mask(struct task_struct *p,
 #endif
 
 
+/*
+ * Deadline server for tasks which would otherwise starve, e.g. heavily niced
+ * tasks or tasks affinitized to busy CPUs. A task which has been waiting on
+ * its domain's DSQ for longer than a second minus @min_service_ns is run
+ * ahead of its turn for @min_service_ns, so that it receives at least that
+ * much CPU time per second.
+ */
+static bool dispatch_starving(s32 cpu, u32 dom_id)
+{
+	struct task_struct *p;
+	struct task_ctx *taskc;
+	u64 now = scx_bpf_now(), last;
+
+	if (dom_id >= MAX_DOMS)
+		return false;
+
+	last = READ_ONCE(dl_server_at[dom_id]);
+	if (now - last < DL_SERVER_INTV_NS ||
+	    __sync_val_compare_and_swap(&dl_server_at[dom_id], last, now) != last)
+		return false;
+
+	bpf_for_each(scx_dsq, p, dom_id, 0) {
+		if (!(taskc = try_lookup_task_ctx(p)))
+			continue;
+
+		if (now - taskc->enq_at < NSEC_PER_SEC - min_service_ns ||
+		    !bpf_cpumask_test_cpu(cpu, p->cpus_ptr))
+			continue;
+
+		__COMPAT_scx_bpf_dsq_move_set_slice(BPF_FOR_EACH_ITER, min_service_ns);
+		if (__COMPAT_scx_bpf_dsq_move(BPF_FOR_EACH_ITER, p, SCX_DSQ_LOCAL, 0)) {
+			stat_add(RUSTY_STAT_DL_SERVER, 1);
+			stat_add(RUSTY_STAT_DL_SERVER_NS, min_service_ns);
+			return true;
+		}
+	}
+
+	return false;
+}
+
 void BPF_STRUCT_OPS(rusty_dispatch, s32 cpu, struct task_struct *prev)
 {
 	u32 curr_dom = cpu_to_dom_id(cpu), dom;
@@ -1354,12 +1550,15 @@ void BPF_STRUCT_OPS(rusty_dispatch, s32 cpu, struct task_struct *prev)
 	if (unlikely(is_offline_cpu(cpu)))
 		return;
 
+	if (min_service_ns && dispatch_starving(cpu, curr_dom))
+		return;
+
 	if (scx_bpf_dsq_move_to_local(curr_dom)) {
 		stat_add(RUSTY_STAT_DSQ_DISPATCH, 1);
 		return;
 	}
 
-	if (!greedy_threshold)
+	if (single_dom || !greedy_threshold || READ_ONCE(shed_at))
 		return;
 
 	pcpuc = lookup_pcpu_ctx(cpu);
@@ -1429,8 +1628,14 @@ void BPF_STRUCT_OPS(rusty_runnable, struct task_struct *p, u64 enq_flags)
 
 	wakee_ctx->is_kworker = p->flags & PF_WQ_WORKER;
 
-	task_load_adj(wakee_ctx, now, true);
-	dom_dcycle_adj(wakee_ctx->domc, wakee_ctx->weight, now, true);
+	/*
+	 * With a single domain there's nothing to balance, skip the load
+	 * tracking which is only consumed by the userspace load balancer.
+	 */
+	if (!single_dom) {
+		task_load_adj(wakee_ctx, now, true);
+		dom_dcycle_adj(wakee_ctx->domc, wakee_ctx->weight, now, true);
+	}
 
 	if (fifo_sched)
 		return;
@@ -1446,6 +1651,50 @@ void BPF_STRUCT_OPS(rusty_runnable, struct task_struct *p, u64 enq_flags)
 	waker_ctx->last_woke_at = now;
 }
 
+/*
+ * Track the average time tasks spend queued and enter or leave load shedding
+ * mode. Called when @p starts running after being queued at @taskc->enq_at.
+ */
+static void update_shed_mode(struct task_ctx *taskc, u64 now)
+{
+	u64 avg, over_at, last;
+
+	if (!taskc->enq_at)
+		return;
+
+	avg = calc_avg(queue_delay_avg, now - taskc->enq_at);
+	WRITE_ONCE(queue_delay_avg, avg);
+	taskc->enq_at = 0;
+
+	last = READ_ONCE(shed_at);
+	if (!last) {
+		if (avg < shed_delay_ns) {
+			WRITE_ONCE(shed_over_at, 0);
+			return;
+		}
+
+		over_at = READ_ONCE(shed_over_at);
+		if (!over_at) {
+			__sync_val_compare_and_swap(&shed_over_at, 0, now);
+			return;
+		}
+
+		if (now - over_at >= shed_window_ns &&
+		    __sync_val_compare_and_swap(&shed_at, 0, now) == 0)
+			stat_add(RUSTY_STAT_SHED, 1);
+		return;
+	}
+
+	/* stay for at least a window to avoid flapping */
+	if (avg >= shed_delay_ns / 2 || now - last < shed_window_ns)
+		return;
+
+	if (__sync_val_compare_and_swap(&shed_at, last, 0) == last) {
+		WRITE_ONCE(shed_over_at, 0);
+		stat_add(RUSTY_STAT_SHED_NS, now - last);
+	}
+}
+
 static void running_update_vtime(struct task_struct *p,
 				 struct task_ctx *taskc,
 				 dom_ptr domc)
@@ -1483,7 +1732,7 @@ void BPF_STRUCT_OPS(rusty_running, struct task_struct *p)
 	 * strict. We just need to be right most of the time.
 	 */
 	dap_gen = domc->active_tasks.genn;
-	if (taskc->dom_active_tasks_gen != dap_gen) {
+	if (!single_dom && taskc->dom_active_tasks_gen != dap_gen) {
 		u64 idx = __sync_fetch_and_add(&domc->active_tasks.write_idx, 1) %
 			MAX_DOM_ACTIVE_TPTRS;
 
@@ -1500,6 +1749,12 @@ void BPF_STRUCT_OPS(rusty_running, struct task_struct *p)
 		taskc->dom_active_tasks_gen = dap_gen;
 	}
 
+	if (shed_delay_ns)
+		update_shed_mode(taskc, scx_bpf_now());
+
+	if (taskc->in_coloc)
+		coloc_account(taskc);
+
 	if (fifo_sched)
 		return;
 
@@ -1552,8 +1807,10 @@ void BPF_STRUCT_OPS(rusty_quiescent, struct task_struct *p, u64 deq_flags)
 	if (!(domc = task_domain(taskc)))
 		return;
 
-	task_load_adj(taskc, now, false);
-	dom_dcycle_adj(domc, taskc->weight, now, false);
+	if (!single_dom) {
+		task_load_adj(taskc, now, false);
+		dom_dcycle_adj(domc, taskc->weight, now, false);
+	}
 
 	if (fifo_sched)
 		return;
@@ -59,6 +59,19 @@ struct task_ctx {
 	/* select_cpu() telling enqueue() to queue directly on the DSQ */
 	bool dispatch_local;
 
+	/* No domain intersects the task's cpumask, e.g. after CPU hotplug */
+	bool orphaned;
+
+	/* When the task was last queued on a DSQ, 0 once it started running */
+	u64 enq_at;
+
+	/* When the task's domain last moved to a different NUMA node */
+	u64 node_at;
+
+	/* Member of colocation group @coloc_grp, see coloc_place() */
+	bool in_coloc;
+	u32 coloc_grp;
+
 	/* For visibility from userspace, may become stale after multithreaded exec */
 	u32 pid;
 
@@ -0,0 +1,389 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::str::FromStr;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::bail;
+use anyhow::Result;
+use libbpf_rs::MapCore as _;
+use log::debug;
+use log::info;
+use log::warn;
+use scx_utils::ProcCache;
+
+use crate::bpf_intf;
+use crate::stats::ColocGroupStats;
+use crate::BpfSkel;
+
+pub const MAX_COLOC_GROUPS: usize = bpf_intf::consts_MAX_COLOC_GROUPS as usize;
+const MAX_COLOC_TASKS: usize = bpf_intf::consts_MAX_COLOC_TASKS as usize;
+
+/// How long the comm and cgroup of a task are cached across scans.
+const PROC_CACHE_TTL: Duration = Duration::from_secs(10);
+
+/// Name of the built-in group of --audio-affinity.
+const AUDIO_GROUP: &str = "audio";
+
+/// Audio servers whose threads are all considered audio threads. These are
+/// comm prefixes, so they also cover e.g. pipewire-pulse and jackdbus.
+const AUDIO_SERVERS: &[&str] = &["pipewire", "wireplumber", "jackd", "pulseaudio"];
+
+/// Cgroups of the audio servers, e.g. pipewire.service in the user session.
+const AUDIO_CGROUPS: &[&str] = &["pipewire", "jack"];
+
+/// PipeWire names the realtime threads of its clients data-loop.N.
+const AUDIO_CLIENT_THREAD: &str = "data-loop";
+
+/// Only move a group to another domain if its load is below this fraction of
+/// the current group domain's load.
+const COLOC_DOM_SWITCH_RATIO: f64 = 0.75;
+
+#[derive(Clone, Debug)]
+pub enum ColocMatch {
+    /// Processes whose comm starts with the prefix.
+    Comm(String),
+    /// Processes whose cgroup path contains the string.
+    Cgroup(String),
+    /// Threads whose comm starts with the prefix, in any process.
+    Thread(String),
+}
+
+/// A colocation group as specified on the command line, e.g.
+/// "game=comm:wine,comm:pipewire,cgroup:steam".
+#[derive(Clone, Debug)]
+pub struct ColocGroupSpec {
+    pub name: String,
+    pub matches: Vec<ColocMatch>,
+}
+
+impl FromStr for ColocGroupSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((name, matches)) = s.split_once('=') else {
+            bail!(
+                "invalid colocation group {:?}, expected NAME=MATCH[,MATCH...]",
+                s
+            );
+        };
+        if name.is_empty() {
+            bail!("colocation group {:?} has no name", s);
+        }
+
+        let mut spec = Self {
+            name: name.to_string(),
+            matches: vec![],
+        };
+        for mt in matches.split(',') {
+            spec.matches.push(match mt.split_once(':') {
+                Some(("comm", pat)) if !pat.is_empty() => ColocMatch::Comm(pat.to_string()),
+                Some(("cgroup", pat)) if !pat.is_empty() => ColocMatch::Cgroup(pat.to_string()),
+                Some(("thread", pat)) if !pat.is_empty() => ColocMatch::Thread(pat.to_string()),
+                _ => bail!(
+                    "invalid match {:?} in colocation group {:?}, expected comm:PREFIX, cgroup:PATTERN or thread:PREFIX",
+                    mt,
+                    name
+                ),
+            });
+        }
+        Ok(spec)
+    }
+}
+
+impl ColocGroupSpec {
+    /// The group of --audio-affinity: all the threads of the audio servers
+    /// (PipeWire, JACK, PulseAudio) and the data loop threads of PipeWire
+    /// clients.
+    pub fn audio() -> Self {
+        let mut matches: Vec<ColocMatch> = AUDIO_SERVERS
+            .iter()
+            .map(|comm| ColocMatch::Comm(comm.to_string()))
+            .collect();
+        matches.extend(
+            AUDIO_CGROUPS
+                .iter()
+                .map(|cgroup| ColocMatch::Cgroup(cgroup.to_string())),
+        );
+        matches.push(ColocMatch::Thread(AUDIO_CLIENT_THREAD.to_string()));
+        Self {
+            name: AUDIO_GROUP.to_string(),
+            matches,
+        }
+    }
+
+    fn matches_proc(&self, comm: &str, cgroup: &str) -> bool {
+        self.matches.iter().any(|mt| match mt {
+            ColocMatch::Comm(prefix) => comm.starts_with(prefix.as_str()),
+            ColocMatch::Cgroup(pat) => cgroup.contains(pat.as_str()),
+            ColocMatch::Thread(_) => false,
+        })
+    }
+
+    fn matches_thread(&self, comm: &str) -> bool {
+        self.matches.iter().any(|mt| match mt {
+            ColocMatch::Thread(prefix) => comm.starts_with(prefix.as_str()),
+            _ => false,
+        })
+    }
+
+    fn has_thread_matches(&self) -> bool {
+        self.matches
+            .iter()
+            .any(|mt| matches!(mt, ColocMatch::Thread(_)))
+    }
+}
+
+struct ColocGroup {
+    spec: ColocGroupSpec,
+    damping: Duration,
+    nr_tasks: usize,
+    dom: Option<usize>,
+    dom_at: Instant,
+    nr_dom_switches: u64,
+    nr_runs: u64,
+    nr_home_runs: u64,
+    residency: f64,
+}
+
+/// Keeps the threads of each colocation group together on one domain so
+/// that processes which work closely together, e.g. a game, its wine server
+/// and the audio server, share caches. Groups are placed on the least
+/// loaded domain, preferring domains which don't host another group yet,
+/// and exempt from load balancing. A group moves at most once per its
+/// damping period and only when another domain is significantly less loaded.
+pub struct Colocation {
+    groups: Vec<ColocGroup>,
+    tids: HashMap<u32, u32>,
+    procs: ProcCache,
+}
+
+impl Colocation {
+    /// Create the colocation groups from their specs and damping periods.
+    /// A task belongs to the first group it matches.
+    pub fn new(specs: Vec<(ColocGroupSpec, Duration)>) -> Result<Self> {
+        if specs.len() > MAX_COLOC_GROUPS {
+            bail!(
+                "Too many colocation groups ({}), at most {} are supported",
+                specs.len(),
+                MAX_COLOC_GROUPS
+            );
+        }
+        let mut names = HashSet::new();
+        for (spec, _) in specs.iter() {
+            if !names.insert(spec.name.as_str()) {
+                bail!("Duplicate colocation group {:?}", &spec.name);
+            }
+        }
+
+        let now = Instant::now();
+        Ok(Self {
+            groups: specs
+                .into_iter()
+                .map(|(spec, damping)| ColocGroup {
+                    spec,
+                    damping,
+                    nr_tasks: 0,
+                    dom: None,
+                    dom_at: now,
+                    nr_dom_switches: 0,
+                    nr_runs: 0,
+                    nr_home_runs: 0,
+                    residency: 0.0,
+                })
+                .collect(),
+            tids: HashMap::new(),
+            procs: ProcCache::new(PROC_CACHE_TTL),
+        })
+    }
+
+    pub fn nr_groups(&self) -> usize {
+        self.groups.len()
+    }
+
+    pub fn stats(&self) -> BTreeMap<String, ColocGroupStats> {
+        self.groups
+            .iter()
+            .map(|group| {
+                (
+                    group.spec.name.clone(),
+                    ColocGroupStats {
+                        nr_tasks: group.nr_tasks as u64,
+                        dom: group.dom.map_or(-1, |dom| dom as i64),
+                        nr_dom_switches: group.nr_dom_switches,
+                        residency: group.residency,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Map the threads of the matching processes, and the matching threads
+    /// of the other processes, to their groups.
+    fn scan(&mut self) -> HashMap<u32, u32> {
+        let ids = |path: &str| -> Vec<u32> {
+            fs::read_dir(path)
+                .map(|dir| {
+                    dir.filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        let match_threads = self
+            .groups
+            .iter()
+            .any(|group| group.spec.has_thread_matches());
+        let mut tids = HashMap::new();
+        let mut seen = HashSet::new();
+        for pid in ids("/proc") {
+            seen.insert(pid);
+            let Ok(info) = self.procs.get(pid as i32) else {
+                continue;
+            };
+            let gid = self
+                .groups
+                .iter()
+                .position(|group| group.spec.matches_proc(&info.comm, &info.cgroup));
+            if gid.is_none() && !match_threads {
+                continue;
+            }
+
+            for tid in ids(&format!("/proc/{}/task", pid)) {
+                seen.insert(tid);
+                let gid = gid.or_else(|| {
+                    let info = self.procs.get(tid as i32).ok()?;
+                    self.groups
+                        .iter()
+                        .position(|group| group.spec.matches_thread(&info.comm))
+                });
+                if let Some(gid) = gid {
+                    tids.insert(tid, gid as u32);
+                }
+            }
+        }
+
+        // Forget the tasks which exited.
+        self.procs.retain(|pid| seen.contains(&(pid as u32)));
+        tids
+    }
+
+    fn update_tids(&mut self, skel: &mut BpfSkel) -> Result<()> {
+        let mut tids = self.scan();
+        if tids.len() > MAX_COLOC_TASKS {
+            warn!(
+                "Too many colocated threads ({}), only tracking {}",
+                tids.len(),
+                MAX_COLOC_TASKS
+            );
+            tids = tids.into_iter().take(MAX_COLOC_TASKS).collect();
+        }
+
+        for tid in self.tids.keys().filter(|tid| !tids.contains_key(tid)) {
+            // The entry may be gone already if the map update below failed.
+            let _ = skel.maps.coloc_pids.delete(&tid.to_ne_bytes());
+        }
+        for (tid, gid) in tids.iter() {
+            if self.tids.get(tid) != Some(gid) {
+                skel.maps.coloc_pids.update(
+                    &tid.to_ne_bytes(),
+                    &gid.to_ne_bytes(),
+                    libbpf_rs::MapFlags::ANY,
+                )?;
+            }
+        }
+
+        for (gid, group) in self.groups.iter_mut().enumerate() {
+            let nr_tasks = tids.values().filter(|&&g| g as usize == gid).count();
+            if nr_tasks != group.nr_tasks {
+                debug!(
+                    "Tracking {} threads in colocation group {:?}",
+                    nr_tasks, &group.spec.name
+                );
+            }
+            group.nr_tasks = nr_tasks;
+        }
+        self.tids = tids;
+        Ok(())
+    }
+
+    /// Fraction of the runs of each group's threads which were on the group
+    /// domain since the last step.
+    fn update_residency(&mut self, skel: &BpfSkel) {
+        let bss_data = skel.maps.bss_data.as_ref().unwrap();
+        for (gid, group) in self.groups.iter_mut().enumerate() {
+            let nr_runs = bss_data.coloc_nr_runs[gid];
+            let nr_home_runs = bss_data.coloc_nr_home_runs[gid];
+            let (runs, home_runs) = (
+                nr_runs.saturating_sub(group.nr_runs),
+                nr_home_runs.saturating_sub(group.nr_home_runs),
+            );
+            if runs > 0 {
+                group.residency = home_runs as f64 / runs as f64 * 100.0;
+            }
+            group.nr_runs = nr_runs;
+            group.nr_home_runs = nr_home_runs;
+        }
+    }
+
+    /// Pick the domain of each group from the domain loads of the last load
+    /// balancing round and refresh the group members.
+    pub fn step(
+        &mut self,
+        skel: &mut BpfSkel,
+        dom_loads: &BTreeMap<usize, f64>,
+        now: Instant,
+    ) -> Result<()> {
+        self.update_residency(skel);
+
+        // Assign the domains before telling BPF about the members so that
+        // no member is placed on a group domain which isn't set yet.
+        let mut taken: Vec<usize> = vec![];
+        for gid in 0..self.groups.len() {
+            let group = &self.groups[gid];
+            let all_taken = dom_loads.keys().all(|id| taken.contains(id));
+            let Some((&best, &best_load)) = dom_loads
+                .iter()
+                .filter(|(id, _)| all_taken || !taken.contains(id))
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            else {
+                continue;
+            };
+
+            let switch = match group.dom.and_then(|dom| dom_loads.get(&dom)) {
+                None => true,
+                Some(&cur_load) => {
+                    now.duration_since(group.dom_at) >= group.damping
+                        && best_load < cur_load * COLOC_DOM_SWITCH_RATIO
+                }
+            };
+            if !switch || group.dom == Some(best) {
+                if let Some(dom) = group.dom {
+                    taken.push(dom);
+                }
+                continue;
+            }
+
+            let group = &mut self.groups[gid];
+            if let Some(dom) = group.dom {
+                info!(
+                    "Moving colocation group {:?} from domain {} to {}",
+                    &group.spec.name, dom, best
+                );
+                group.nr_dom_switches += 1;
+            }
+            group.dom = Some(best);
+            group.dom_at = now;
+            taken.push(best);
+            skel.maps.bss_data.as_mut().unwrap().coloc_doms[gid] = best as u32;
+        }
+
+        self.update_tids(skel)
+    }
+}
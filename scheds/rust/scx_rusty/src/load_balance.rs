@@ -322,6 +322,7 @@ struct TaskInfo {
     preferred_dom_mask: u64,
     migrated: Cell<bool>,
     is_kworker: bool,
+    in_coloc: bool,
 }
 
 impl LoadOrdered for TaskInfo {
@@ -461,6 +462,9 @@ pub struct LoadBalancer<'a, 'b> {
 
     lb_apply_weight: bool,
     balance_load: bool,
+
+    // (pid, from node, to node) of the tasks moved across NUMA nodes.
+    xnode_moves: Vec<(u32, usize, usize)>,
 }
 
 // Verify that the number of buckets is a factor of the maximum weight to
@@ -489,6 +493,8 @@ impl<'a, 'b> LoadBalancer<'a, 'b> {
             lb_apply_weight,
             balance_load,
 
+            xnode_moves: vec![],
+
             dom_group,
         }
     }
@@ -513,6 +519,12 @@ impl<'a, 'b> LoadBalancer<'a, 'b> {
             .collect()
     }
 
+    /// Return the (pid, from node, to node) of the tasks moved across NUMA
+    /// nodes by the last load balancing round.
+    pub fn take_xnode_moves(&mut self) -> Vec<(u32, usize, usize)> {
+        std::mem::take(&mut self.xnode_moves)
+    }
+
     fn create_domain_hierarchy(&mut self) -> Result<()> {
         let ledger = self.calculate_load_avgs()?;
 
@@ -678,6 +690,7 @@ impl<'a, 'b> LoadBalancer<'a, 'b> {
                 preferred_dom_mask: taskc.preferred_dom_mask,
                 migrated: Cell::new(false),
                 is_kworker: unsafe { taskc.is_kworker.assume_init() },
+                in_coloc: unsafe { taskc.in_coloc.assume_init() },
             });
         }
 
@@ -722,6 +735,7 @@ impl<'a, 'b> LoadBalancer<'a, 'b> {
             .filter(|task| {
                 task.dom_mask & (1 << pull_dom_id) != 0
                     && !(self.skip_kworkers && task.is_kworker)
+                    && !task.in_coloc
                     && !task.migrated.get()
             })
             .collect();
@@ -770,7 +784,16 @@ impl<'a, 'b> LoadBalancer<'a, 'b> {
         task.migrated.set(true);
         std::mem::swap(&mut push_dom.tasks, &mut SortedVec::from_unsorted(tasks));
 
-        push_dom.transfer_load(load, unsafe { &mut *taskc_p }, pull_dom);
+        let taskc = unsafe { &mut *taskc_p };
+        let push_node = self.dom_group.dom_numa_id(&push_dom.id);
+        let pull_node = self.dom_group.dom_numa_id(&pull_dom.id);
+        if let (Some(from), Some(to)) = (push_node, pull_node) {
+            if from != to {
+                self.xnode_moves.push((taskc.pid, from, to));
+            }
+        }
+
+        push_dom.transfer_load(load, taskc, pull_dom);
         Ok(Some(load))
     }
 
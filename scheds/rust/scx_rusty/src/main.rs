@@ -12,15 +12,27 @@ use domain::DomainGroup;
 pub mod tuner;
 use tuner::Tuner;
 
+mod tunables;
+use tunables::Tunables;
+
 pub mod load_balance;
 use load_balance::LoadBalancer;
 
+mod mem_follow;
+use mem_follow::MemFollower;
+
+mod coloc;
+use coloc::ColocGroupSpec;
+use coloc::Colocation;
+
 mod stats;
 use std::collections::BTreeMap;
 use std::mem::MaybeUninit;
+use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::time::Duration;
 use std::time::Instant;
 use std::time::SystemTime;
@@ -42,9 +54,11 @@ use crossbeam::channel::RecvTimeoutError;
 use libbpf_rs::MapCore as _;
 use libbpf_rs::OpenObject;
 use log::info;
+use log::warn;
 use scx_stats::prelude::*;
 use scx_utils::build_id;
 use scx_utils::compat;
+use scx_utils::control::ControlServerHandle;
 use scx_utils::init_libbpf_logging;
 use scx_utils::libbpf_clap_opts::LibbpfOpts;
 use scx_utils::scx_enums;
@@ -54,6 +68,7 @@ use scx_utils::scx_ops_open;
 use scx_utils::uei_exited;
 use scx_utils::uei_report;
 use scx_utils::Cpumask;
+use scx_utils::SchedThreadArgs;
 use scx_utils::Topology;
 use scx_utils::UserExitInfo;
 use scx_utils::NR_CPU_IDS;
@@ -168,6 +183,29 @@ struct Opts {
     #[clap(short = 'f', long, action = clap::ArgAction::SetTrue)]
     fifo_sched: bool,
 
+    /// Minimum CPU time in microseconds guaranteed to every runnable task
+    /// each second. A task which has been waiting in its domain's queue for
+    /// longer than a second minus this is run ahead of its turn for this
+    /// long. This keeps heavily niced and heavily affinitized tasks from
+    /// starving when the system is saturated. 0 disables.
+    #[clap(long, default_value = "0")]
+    min_service_us_per_s: u64,
+
+    /// Enter load shedding mode when the average time tasks wait in the
+    /// queues stays above this many milliseconds for --shed-window-ms. While
+    /// shedding, tasks are queued FIFO on their CPUs, skipping domain
+    /// placement and greedy execution, until the average delay drops below
+    /// half of this. This keeps pathological loads from tripping the kernel
+    /// watchdog. 0 disables.
+    #[clap(long, default_value = "0")]
+    shed_delay_ms: u64,
+
+    /// How long in milliseconds the queue delay must stay above
+    /// --shed-delay-ms before load shedding starts. Also the minimum time
+    /// spent shedding.
+    #[clap(long, default_value = "1000")]
+    shed_window_ms: u64,
+
     /// Idle CPUs with utilization lower than this will get remote tasks
     /// directly pushed onto them. 0 disables, 100 always enables.
     #[clap(short = 'D', long, default_value = "90.0")]
@@ -198,6 +236,47 @@ struct Opts {
     #[clap(long, action = clap::ArgAction::SetTrue)]
     mempolicy_affinity: bool,
 
+    /// Make the memory of a task follow it when the load balancer moved it
+    /// to another NUMA node and it stayed there for this many milliseconds.
+    /// Pages are migrated only for single-threaded processes, the moves of
+    /// other tasks are logged as advice. 0 disables.
+    #[clap(long, default_value = "0")]
+    numa_mem_follow_ms: u64,
+
+    /// Don't migrate any pages for --numa-mem-follow-ms, only log the advice.
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    numa_mem_follow_advise: bool,
+
+    /// Keep the threads of the audio servers (PipeWire, JACK, PulseAudio)
+    /// and the data loop threads of PipeWire clients together on the least
+    /// loaded domain and exempt them from load balancing. This reduces
+    /// xruns caused by audio threads being moved around under load. The
+    /// audio threads form the built-in colocation group "audio", which
+    /// takes precedence over the --coloc-group's.
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    audio_affinity: bool,
+
+    /// Minimum time in milliseconds between moves of the audio threads to
+    /// another domain with --audio-affinity.
+    #[clap(long, default_value = "5000")]
+    audio_damping_ms: u64,
+
+    /// Keep the threads of processes which work closely together, e.g. a
+    /// game, its wine server and the audio server, on the same domain and
+    /// let the load balancer move them only as a whole. Specified as
+    /// NAME=MATCH[,MATCH...] where MATCH is comm:PREFIX to match processes
+    /// by their comm, cgroup:PATTERN to match them by their cgroup path or
+    /// thread:PREFIX to match single threads by their comm, e.g.
+    /// "game=comm:wine,comm:pipewire,cgroup:steam". Can be specified
+    /// multiple times. A task belongs to the first group it matches.
+    #[clap(long = "coloc-group")]
+    coloc_groups: Vec<ColocGroupSpec>,
+
+    /// Minimum time in milliseconds between moves of a colocation group to
+    /// another domain.
+    #[clap(long, default_value = "5000")]
+    coloc_damping_ms: u64,
+
     /// Enable stats monitoring with the specified interval.
     #[clap(long)]
     stats: Option<f64>,
@@ -224,12 +303,29 @@ struct Opts {
     #[clap(long)]
     help_stats: bool,
 
+    /// Change greedy stealing tunables of the running scheduler through its
+    /// control socket and exit. Takes KEY=VAL pairs where KEY is one of
+    /// greedy_threshold, greedy_threshold_x_numa, direct_greedy_under and
+    /// kick_greedy_under, e.g. --tune greedy_threshold=4. Without any pair,
+    /// the current values are printed.
+    #[clap(long, num_args = 0..)]
+    tune: Option<Vec<String>>,
+
+    /// Load the greedy stealing tunables from this file on startup, if it
+    /// exists, and save them to it whenever they're changed with --tune.
+    /// Values in the file override the command line options.
+    #[clap(long)]
+    tunables_file: Option<PathBuf>,
+
     /// Tunable for prioritizing CPU performance by configuring the CPU frequency governor.
     /// Valid values are [0, 1024]. Higher values prioritize performance, lower values
     /// prioritize energy efficiency. When in doubt, use 0 or 1024.
     #[clap(long, default_value = "0")]
     perf: u32,
 
+    #[clap(flatten, next_help_heading = "Scheduler Thread Options")]
+    sched_thread: SchedThreadArgs,
+
     #[clap(flatten, next_help_heading = "Libbpf Options")]
     pub libbpf: LibbpfOpts,
 }
@@ -272,6 +368,8 @@ struct StatsCtx {
     cpu_total: u64,
     bpf_stats: Vec<u64>,
     time_used: Duration,
+    nr_mem_follow: u64,
+    nr_mem_advice: u64,
 }
 
 impl StatsCtx {
@@ -305,10 +403,17 @@ impl StatsCtx {
             cpu_total: 0,
             bpf_stats: vec![0u64; bpf_intf::stat_idx_RUSTY_NR_STATS as usize],
             time_used: Duration::default(),
+            nr_mem_follow: 0,
+            nr_mem_advice: 0,
         }
     }
 
-    fn new(skel: &BpfSkel, proc_reader: &procfs::ProcReader, time_used: Duration) -> Result<Self> {
+    fn new(
+        skel: &BpfSkel,
+        proc_reader: &procfs::ProcReader,
+        time_used: Duration,
+        mem_follower: &MemFollower,
+    ) -> Result<Self> {
         let (cpu_busy, cpu_total) = read_cpu_busy_and_total(proc_reader)?;
 
         Ok(Self {
@@ -316,6 +421,8 @@ impl StatsCtx {
             cpu_total,
             bpf_stats: Self::read_bpf_stats(skel)?,
             time_used,
+            nr_mem_follow: mem_follower.nr_follow,
+            nr_mem_advice: mem_follower.nr_advice,
         })
     }
 
@@ -330,6 +437,8 @@ impl StatsCtx {
                 .map(|(lhs, rhs)| sub_or_zero(&lhs, &rhs))
                 .collect(),
             time_used: self.time_used - rhs.time_used,
+            nr_mem_follow: sub_or_zero(&self.nr_mem_follow, &rhs.nr_mem_follow),
+            nr_mem_advice: sub_or_zero(&self.nr_mem_advice, &rhs.nr_mem_advice),
         }
     }
 }
@@ -342,6 +451,7 @@ struct Scheduler<'a> {
     tune_interval: Duration,
     balance_load: bool,
     balanced_kworkers: bool,
+    fast_path: bool,
 
     dom_group: Arc<DomainGroup>,
 
@@ -350,9 +460,16 @@ struct Scheduler<'a> {
     lb_at: SystemTime,
     lb_stats: BTreeMap<usize, NodeStats>,
     time_used: Duration,
+    mem_follower: MemFollower,
+    coloc: Option<Colocation>,
 
     tuner: Tuner,
+    tunables: Arc<Mutex<Tunables>>,
+    applied_tunables: Tunables,
+    tunables_file: Option<PathBuf>,
+    shedding: bool,
     stats_server: StatsServer<StatsCtx, (StatsCtx, ClusterStats)>,
+    _control_server: ControlServerHandle,
 }
 
 impl<'a> Scheduler<'a> {
@@ -387,6 +504,13 @@ impl<'a> Scheduler<'a> {
             );
         }
 
+        if opts.min_service_us_per_s >= 1_000_000 {
+            bail!(
+                "--min-service-us-per-s ({}) must be less than a second",
+                opts.min_service_us_per_s
+            );
+        }
+
         skel.maps.bss_data.as_mut().unwrap().slice_ns = scx_enums.SCX_SLICE_DFL;
 
         let rodata = skel.maps.rodata_data.as_mut().unwrap();
@@ -394,6 +518,31 @@ impl<'a> Scheduler<'a> {
         rodata.nr_doms = domains.nr_doms() as u32;
         rodata.nr_cpu_ids = *NR_CPU_IDS as u32;
 
+        // With a single domain, there's nothing to balance. Skip the domain
+        // load tracking in BPF and the userspace load balancer.
+        let fast_path = domains.nr_doms() == 1;
+        rodata.single_dom = fast_path;
+        if fast_path {
+            info!("Single domain detected, fast path active");
+        }
+
+        let mut coloc_groups = vec![];
+        if opts.audio_affinity {
+            coloc_groups.push((
+                ColocGroupSpec::audio(),
+                Duration::from_millis(opts.audio_damping_ms),
+            ));
+        }
+        coloc_groups.extend(
+            opts.coloc_groups
+                .iter()
+                .map(|spec| (spec.clone(), Duration::from_millis(opts.coloc_damping_ms))),
+        );
+        let coloc = match coloc_groups.is_empty() || fast_path {
+            true => None,
+            false => Some(Colocation::new(coloc_groups)?),
+        };
+
         // Any CPU with dom > MAX_DOMS is considered offline by default. There
         // are a few places in the BPF code where we skip over offlined CPUs
         // (e.g. when initializing or refreshing tune params), and elsewhere the
@@ -442,17 +591,37 @@ impl<'a> Scheduler<'a> {
         rodata.load_half_life = (opts.load_half_life * 1000000000.0) as u32;
         rodata.kthreads_local = opts.kthreads_local;
         rodata.fifo_sched = opts.fifo_sched;
-        rodata.greedy_threshold = opts.greedy_threshold;
-        rodata.greedy_threshold_x_numa = opts.greedy_threshold_x_numa;
         rodata.direct_greedy_numa = opts.direct_greedy_numa;
         rodata.mempolicy_affinity = opts.mempolicy_affinity;
         rodata.debug = opts.verbose as u32;
         rodata.rusty_perf_mode = opts.perf;
+        rodata.min_service_ns = opts.min_service_us_per_s * 1000;
+        rodata.nr_coloc_groups = coloc.as_ref().map_or(0, |coloc| coloc.nr_groups() as u32);
+        rodata.shed_delay_ns = opts.shed_delay_ms * 1_000_000;
+        rodata.shed_window_ns = opts.shed_window_ms * 1_000_000;
+
+        let mut tunables = Tunables {
+            greedy_threshold: opts.greedy_threshold,
+            greedy_threshold_x_numa: opts.greedy_threshold_x_numa,
+            direct_greedy_under: opts.direct_greedy_under,
+            kick_greedy_under: opts.kick_greedy_under,
+        };
+        if let Some(path) = opts.tunables_file.as_ref().filter(|path| path.exists()) {
+            tunables = Tunables::load(path)?;
+            info!("Loaded tunables from {}: {:?}", path.display(), &tunables);
+        }
 
         // Attach.
         let mut skel = scx_ops_load!(skel, rusty, uei)?;
+
+        let bss_data = skel.maps.bss_data.as_mut().unwrap();
+        bss_data.greedy_threshold = tunables.greedy_threshold;
+        bss_data.greedy_threshold_x_numa = tunables.greedy_threshold_x_numa;
+
         let struct_ops = Some(scx_ops_attach!(skel, rusty)?);
+        let shared_tunables = Arc::new(Mutex::new(tunables.clone()));
         let stats_server = StatsServer::new(stats::server_data()).launch()?;
+        let control_server = Tunables::control_server(shared_tunables.clone()).launch()?;
 
         for (id, dom) in domains.doms().iter() {
             let mut ctx = dom.ctx.lock().unwrap();
@@ -473,6 +642,7 @@ impl<'a> Scheduler<'a> {
             tune_interval: Duration::from_secs_f64(opts.tune_interval),
             balance_load: !opts.no_load_balance,
             balanced_kworkers: opts.balanced_kworkers,
+            fast_path,
 
             dom_group: domains.clone(),
             proc_reader,
@@ -480,15 +650,25 @@ impl<'a> Scheduler<'a> {
             lb_at: SystemTime::now(),
             lb_stats: BTreeMap::new(),
             time_used: Duration::default(),
+            mem_follower: MemFollower::new(
+                Duration::from_millis(opts.numa_mem_follow_ms),
+                opts.numa_mem_follow_advise,
+            ),
+            coloc,
 
             tuner: Tuner::new(
                 domains,
-                opts.direct_greedy_under,
-                opts.kick_greedy_under,
+                tunables.direct_greedy_under,
+                tunables.kick_greedy_under,
                 opts.slice_us_underutil * 1000,
                 opts.slice_us_overutil * 1000,
             )?,
+            tunables: shared_tunables,
+            applied_tunables: tunables,
+            tunables_file: opts.tunables_file.clone(),
+            shedding: false,
             stats_server,
+            _control_server: control_server,
         })
     }
 
@@ -504,8 +684,12 @@ impl<'a> Scheduler<'a> {
             + stat(bpf_intf::stat_idx_RUSTY_STAT_DIRECT_GREEDY_FAR)
             + stat(bpf_intf::stat_idx_RUSTY_STAT_DSQ_DISPATCH)
             + stat(bpf_intf::stat_idx_RUSTY_STAT_GREEDY_LOCAL)
-            + stat(bpf_intf::stat_idx_RUSTY_STAT_GREEDY_XNUMA);
+            + stat(bpf_intf::stat_idx_RUSTY_STAT_GREEDY_XNUMA)
+            + stat(bpf_intf::stat_idx_RUSTY_STAT_DL_SERVER)
+            + stat(bpf_intf::stat_idx_RUSTY_STAT_ORPHAN_DISPATCH)
+            + stat(bpf_intf::stat_idx_RUSTY_STAT_SHED_DISPATCH);
         let stat_pct = |idx| stat(idx) as f64 / total as f64 * 100.0;
+        let bss_data = self.skel.maps.bss_data.as_ref().unwrap();
 
         let cpu_busy = if sc.cpu_total != 0 {
             (sc.cpu_busy as f64 / sc.cpu_total as f64) * 100.0
@@ -536,6 +720,7 @@ impl<'a> Scheduler<'a> {
 
             task_get_err: sc.bpf_stats[bpf_intf::stat_idx_RUSTY_STAT_TASK_GET_ERR as usize],
             time_used: sc.time_used.as_secs_f64(),
+            fast_path: self.fast_path as u64,
 
             sync_prev_idle: stat_pct(bpf_intf::stat_idx_RUSTY_STAT_SYNC_PREV_IDLE),
             wake_sync: stat_pct(bpf_intf::stat_idx_RUSTY_STAT_WAKE_SYNC),
@@ -548,6 +733,31 @@ impl<'a> Scheduler<'a> {
             dsq_dispatch: stat_pct(bpf_intf::stat_idx_RUSTY_STAT_DSQ_DISPATCH),
             greedy_local: stat_pct(bpf_intf::stat_idx_RUSTY_STAT_GREEDY_LOCAL),
             greedy_xnuma: stat_pct(bpf_intf::stat_idx_RUSTY_STAT_GREEDY_XNUMA),
+            dl_server: stat_pct(bpf_intf::stat_idx_RUSTY_STAT_DL_SERVER),
+            dl_server_us: stat(bpf_intf::stat_idx_RUSTY_STAT_DL_SERVER_NS) / 1000,
+            orphan: stat_pct(bpf_intf::stat_idx_RUSTY_STAT_ORPHAN_DISPATCH),
+            nr_orphaned: stat(bpf_intf::stat_idx_RUSTY_STAT_ORPHANED),
+            nr_xnode_migrations: stat(bpf_intf::stat_idx_RUSTY_STAT_XNODE_MIGRATION),
+            xnode_residency_ms: match stat(bpf_intf::stat_idx_RUSTY_STAT_XNODE_MIGRATION) {
+                0 => 0.0,
+                nr => {
+                    stat(bpf_intf::stat_idx_RUSTY_STAT_XNODE_RESIDENCY_NS) as f64
+                        / nr as f64
+                        / 1_000_000.0
+                }
+            },
+            shedding: self.shedding as u64,
+            nr_shed: stat(bpf_intf::stat_idx_RUSTY_STAT_SHED),
+            shed_ms: stat(bpf_intf::stat_idx_RUSTY_STAT_SHED_NS) / 1_000_000,
+            shed: stat_pct(bpf_intf::stat_idx_RUSTY_STAT_SHED_DISPATCH),
+            queue_delay_us: bss_data.queue_delay_avg / 1000,
+            nr_mem_follow: sc.nr_mem_follow,
+            nr_mem_advice: sc.nr_mem_advice,
+            nr_coloc_place: stat(bpf_intf::stat_idx_RUSTY_STAT_COLOC_PLACE),
+            coloc: self
+                .coloc
+                .as_ref()
+                .map_or_else(BTreeMap::new, |coloc| coloc.stats()),
             kick_greedy: stat_pct(bpf_intf::stat_idx_RUSTY_STAT_KICK_GREEDY),
             repatriate: stat_pct(bpf_intf::stat_idx_RUSTY_STAT_REPATRIATE),
             dl_clamp: stat_pct(bpf_intf::stat_idx_RUSTY_STAT_DL_CLAMP),
@@ -573,9 +783,67 @@ impl<'a> Scheduler<'a> {
 
         self.lb_at = SystemTime::now();
         self.lb_stats = lb.get_stats();
+        self.mem_follower
+            .record(lb.take_xnode_moves(), Instant::now());
         Ok(())
     }
 
+    fn coloc_step(&mut self, now: Instant) -> Result<()> {
+        let Some(coloc) = self.coloc.as_mut() else {
+            return Ok(());
+        };
+
+        let dom_loads: BTreeMap<usize, f64> = self
+            .lb_stats
+            .values()
+            .flat_map(|node| node.doms.iter().map(|(id, dom)| (*id, dom.load)))
+            .collect();
+        coloc.step(&mut self.skel, &dom_loads, now)
+    }
+
+    fn update_tunables(&mut self) {
+        let tunables = self.tunables.lock().unwrap().clone();
+        if tunables == self.applied_tunables {
+            return;
+        }
+
+        let bss_data = self.skel.maps.bss_data.as_mut().unwrap();
+        bss_data.greedy_threshold = tunables.greedy_threshold;
+        bss_data.greedy_threshold_x_numa = tunables.greedy_threshold_x_numa;
+        self.tuner
+            .set_greedy_under(tunables.direct_greedy_under, tunables.kick_greedy_under);
+        info!("Tunables updated: {:?}", &tunables);
+
+        if let Some(path) = self.tunables_file.as_ref() {
+            if let Err(e) = tunables.save(path) {
+                warn!("{:?}", e);
+            }
+        }
+        self.applied_tunables = tunables;
+    }
+
+    fn check_shedding(&mut self) {
+        let bss_data = self.skel.maps.bss_data.as_ref().unwrap();
+        let shedding = bss_data.shed_at != 0;
+        if shedding == self.shedding {
+            return;
+        }
+
+        let delay_ms = bss_data.queue_delay_avg as f64 / 1_000_000.0;
+        if shedding {
+            warn!(
+                "Queue delay {:.1}ms over threshold, shedding load",
+                delay_ms
+            );
+        } else {
+            info!(
+                "Queue delay {:.1}ms recovered, resuming normal operation",
+                delay_ms
+            );
+        }
+        self.shedding = shedding;
+    }
+
     fn run(&mut self, shutdown: Arc<AtomicBool>) -> Result<UserExitInfo> {
         let (res_ch, req_ch) = self.stats_server.channels();
         let now = Instant::now();
@@ -587,8 +855,11 @@ impl<'a> Scheduler<'a> {
         while !shutdown.load(Ordering::Relaxed) && !uei_exited!(&self.skel, uei) {
             let now = Instant::now();
 
+            self.update_tunables();
+
             if now >= next_tune_at {
                 self.tuner.step(&mut self.skel)?;
+                self.check_shedding();
                 next_tune_at += self.tune_interval;
                 if next_tune_at < now {
                     next_tune_at = now + self.tune_interval;
@@ -596,7 +867,11 @@ impl<'a> Scheduler<'a> {
             }
 
             if now >= next_sched_at {
-                self.lb_step()?;
+                if !self.fast_path {
+                    self.lb_step()?;
+                    self.mem_follower.step(now);
+                    self.coloc_step(now)?;
+                }
                 next_sched_at += self.sched_interval;
                 if next_sched_at < now {
                     next_sched_at = now + self.sched_interval;
@@ -607,7 +882,12 @@ impl<'a> Scheduler<'a> {
 
             match req_ch.recv_deadline(next_sched_at.min(next_tune_at)) {
                 Ok(prev_sc) => {
-                    let cur_sc = StatsCtx::new(&self.skel, &self.proc_reader, self.time_used)?;
+                    let cur_sc = StatsCtx::new(
+                        &self.skel,
+                        &self.proc_reader,
+                        self.time_used,
+                        &self.mem_follower,
+                    )?;
                     let delta_sc = cur_sc.delta(&prev_sc);
                     let cstats = self.cluster_stats(&delta_sc, self.lb_stats.clone());
                     res_ch.send((cur_sc, cstats))?;
@@ -636,10 +916,7 @@ fn main() -> Result<()> {
     let opts = Opts::parse();
 
     if opts.version {
-        println!(
-            "scx_rusty: {}",
-            build_id::full_version(env!("CARGO_PKG_VERSION"))
-        );
+        println!("{:#}", scx_utils::version_info!());
         return Ok(());
     }
 
@@ -648,6 +925,10 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
+    if let Some(kvs) = opts.tune.as_ref() {
+        return tunables::tune(kvs);
+    }
+
     let llv = match opts.verbose {
         0 => simplelog::LevelFilter::Info,
         1 => simplelog::LevelFilter::Debug,
@@ -685,6 +966,8 @@ fn main() -> Result<()> {
         }
     }
 
+    let _sched_thread = opts.sched_thread.apply()?;
+
     let mut open_object = MaybeUninit::uninit();
     loop {
         let mut sched = Scheduler::init(&opts, &mut open_object)?;
@@ -0,0 +1,157 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+use std::collections::HashMap;
+use std::fs;
+use std::time::Duration;
+use std::time::Instant;
+
+use log::debug;
+use log::info;
+use log::warn;
+
+/// Minimum interval between the summaries of the NUMA advice.
+const ADVICE_SUMMARY_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Pending {
+    from: usize,
+    to: usize,
+    at: Instant,
+}
+
+/// Makes the memory of the tasks which the load balancer moved to another
+/// NUMA node follow them, once they've stayed on the new node for @delay.
+///
+/// Pages are migrated with migrate_pages(2) only for single-threaded
+/// processes, as the other threads of a process may still be running on the
+/// old node. For the rest, and for all tasks in advise-only mode, the move
+/// is logged at debug level as advice for e.g. numactl or an external
+/// memory manager, with a periodic summary at info level.
+pub struct MemFollower {
+    delay: Duration,
+    advise_only: bool,
+    pending: HashMap<u32, Pending>,
+    summary_at: Option<Instant>,
+    nr_advice_summarized: u64,
+    pub nr_follow: u64,
+    pub nr_advice: u64,
+}
+
+impl MemFollower {
+    /// A zero @delay disables following.
+    pub fn new(delay: Duration, advise_only: bool) -> Self {
+        Self {
+            delay,
+            advise_only,
+            pending: HashMap::new(),
+            summary_at: None,
+            nr_advice_summarized: 0,
+            nr_follow: 0,
+            nr_advice: 0,
+        }
+    }
+
+    /// Record the (pid, from node, to node) cross-node moves of a load
+    /// balancing round. A task moved again restarts its residency timer and
+    /// a task moved back to where it came from is dropped.
+    pub fn record(&mut self, moves: Vec<(u32, usize, usize)>, now: Instant) {
+        if self.delay.is_zero() {
+            return;
+        }
+
+        for (pid, from, to) in moves {
+            let from = self.pending.get(&pid).map_or(from, |p| p.from);
+            if from == to {
+                self.pending.remove(&pid);
+            } else {
+                self.pending.insert(pid, Pending { from, to, at: now });
+            }
+        }
+    }
+
+    /// Act on the tasks which have stayed on their new node for long enough.
+    pub fn step(&mut self, now: Instant) {
+        let delay = self.delay;
+        let ready: Vec<(u32, usize, usize)> = self
+            .pending
+            .iter()
+            .filter(|(_, p)| now.duration_since(p.at) >= delay)
+            .map(|(pid, p)| (*pid, p.from, p.to))
+            .collect();
+
+        for (pid, from, to) in ready {
+            self.pending.remove(&pid);
+            self.follow(pid, from, to);
+        }
+
+        let nr_new = self.nr_advice - self.nr_advice_summarized;
+        let due = match self.summary_at {
+            Some(at) => now.duration_since(at) >= ADVICE_SUMMARY_INTERVAL,
+            None => true,
+        };
+        if nr_new > 0 && due {
+            info!(
+                "NUMA advice issued for {} tasks (total {}), see debug log for details",
+                nr_new, self.nr_advice
+            );
+            self.nr_advice_summarized = self.nr_advice;
+            self.summary_at = Some(now);
+        }
+    }
+
+    fn follow(&mut self, pid: u32, from: usize, to: usize) {
+        // The task may have exited in the meantime.
+        let Some((tgid, nr_threads)) = read_tgid_and_threads(pid) else {
+            return;
+        };
+
+        if self.advise_only || tgid != pid || nr_threads > 1 || from >= 64 || to >= 64 {
+            debug!(
+                "NUMA advice: pid={} tgid={} threads={} memory node {} -> {}",
+                pid, tgid, nr_threads, from, to
+            );
+            self.nr_advice += 1;
+            return;
+        }
+
+        let old_nodes: u64 = 1 << from;
+        let new_nodes: u64 = 1 << to;
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_migrate_pages,
+                pid as libc::pid_t,
+                u64::BITS as libc::c_ulong + 1,
+                &old_nodes as *const u64,
+                &new_nodes as *const u64,
+            )
+        };
+        if ret < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::ESRCH) {
+                warn!(
+                    "Failed to migrate pages of pid {} to node {}: {}",
+                    pid, to, err
+                );
+            }
+            return;
+        }
+
+        debug!(
+            "Migrated memory of pid {} from node {} to {}",
+            pid, from, to
+        );
+        self.nr_follow += 1;
+    }
+}
+
+fn read_tgid_and_threads(pid: u32) -> Option<(u32, u64)> {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let field = |name: &str| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(name))
+            .and_then(|val| val.trim().parse::<u64>().ok())
+    };
+    Some((field("Tgid:")? as u32, field("Threads:")?))
+}
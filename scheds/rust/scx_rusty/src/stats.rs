@@ -98,6 +98,31 @@ impl NodeStats {
     }
 }
 
+#[stat_doc]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Stats)]
+#[stat(_om_prefix = "c_", _om_label = "coloc_group")]
+pub struct ColocGroupStats {
+    #[stat(desc = "# of threads in the group")]
+    pub nr_tasks: u64,
+    #[stat(desc = "domain the group is kept on, -1 if none")]
+    pub dom: i64,
+    #[stat(desc = "# of times the group domain changed")]
+    pub nr_dom_switches: u64,
+    #[stat(desc = "% of runs of the group's threads on the group domain")]
+    pub residency: f64,
+}
+
+impl ColocGroupStats {
+    pub fn format<W: Write>(&self, w: &mut W, name: &str) -> Result<()> {
+        writeln!(
+            w,
+            "coloc[{}] tasks={} dom={} dom_switch={} residency={:5.2}",
+            name, self.nr_tasks, self.dom, self.nr_dom_switches, self.residency,
+        )?;
+        Ok(())
+    }
+}
+
 #[stat_doc]
 #[derive(Clone, Debug, Default, Serialize, Deserialize, Stats)]
 #[stat(top)]
@@ -122,6 +147,8 @@ pub struct ClusterStats {
     pub task_get_err: u64,
     #[stat(desc = "time spent running scheduler userspace")]
     pub time_used: f64,
+    #[stat(desc = "1 if the single domain fast path is active")]
+    pub fast_path: u64,
 
     #[stat(desc = "% WAKE_SYNC directly dispatched to idle previous CPU")]
     pub sync_prev_idle: f64,
@@ -145,6 +172,34 @@ pub struct ClusterStats {
     pub greedy_local: f64,
     #[stat(desc = "% scheduled from foreign node")]
     pub greedy_xnuma: f64,
+    #[stat(desc = "% starving tasks run by the deadline server")]
+    pub dl_server: f64,
+    #[stat(desc = "CPU time in usecs granted by the deadline server")]
+    pub dl_server_us: u64,
+    #[stat(desc = "% directly dispatched as no domain intersects the task's cpumask")]
+    pub orphan: f64,
+    #[stat(desc = "# of tasks found with no domain intersecting their cpumask")]
+    pub nr_orphaned: u64,
+    #[stat(desc = "# of task domain changes across NUMA nodes")]
+    pub nr_xnode_migrations: u64,
+    #[stat(desc = "avg msecs tasks stayed on a node before moving to another")]
+    pub xnode_residency_ms: f64,
+    #[stat(desc = "1 if shedding load due to excessive queue delay")]
+    pub shedding: u64,
+    #[stat(desc = "# of times load shedding started")]
+    pub nr_shed: u64,
+    #[stat(desc = "msecs spent shedding load, accounted when shedding ends")]
+    pub shed_ms: u64,
+    #[stat(desc = "% queued FIFO on the task's CPU while shedding load")]
+    pub shed: f64,
+    #[stat(desc = "average queue delay in usecs")]
+    pub queue_delay_us: u64,
+    #[stat(desc = "# of tasks whose memory was migrated to follow them")]
+    pub nr_mem_follow: u64,
+    #[stat(desc = "# of cross-node moves logged as memory migration advice")]
+    pub nr_mem_advice: u64,
+    #[stat(desc = "# of threads moved to their colocation group's domain")]
+    pub nr_coloc_place: u64,
     #[stat(desc = "% foreign domain CPU kicked on enqueue")]
     pub kick_greedy: f64,
     #[stat(desc = "% repatriated to local domain on enqueue")]
@@ -159,6 +214,9 @@ pub struct ClusterStats {
     #[stat(_om_skip)]
     pub kick_greedy_cpus: Vec<u64>,
 
+    #[stat(desc = "per-colocation group statistics")]
+    pub coloc: BTreeMap<String, ColocGroupStats>,
+
     #[stat(desc = "per-node statistics")]
     pub nodes: BTreeMap<usize, NodeStats>,
 }
@@ -174,6 +232,9 @@ impl ClusterStats {
             self.task_get_err,
             self.time_used * 1000.0,
         )?;
+        if self.fast_path != 0 {
+            writeln!(w, "fast path active")?;
+        }
         writeln!(
             w,
             "tot={:7} sync_prev_idle={:5.2} wsync={:5.2}",
@@ -199,13 +260,36 @@ impl ClusterStats {
 
         writeln!(
             w,
-            "kick_greedy={:5.2} rep={:5.2}",
-            self.kick_greedy, self.repatriate
+            "kick_greedy={:5.2} rep={:5.2} orphan={:5.2}/{}",
+            self.kick_greedy, self.repatriate, self.orphan, self.nr_orphaned
+        )?;
+        writeln!(
+            w,
+            "xnode_mig={} residency={:.1}ms mem_follow={} mem_advice={}",
+            self.nr_xnode_migrations,
+            self.xnode_residency_ms,
+            self.nr_mem_follow,
+            self.nr_mem_advice,
         )?;
         writeln!(
             w,
-            "dl_clamp={:5.2} dl_preset={:5.2}",
-            self.dl_clamp, self.dl_preset,
+            "qdelay={}us shed={:5.2} nr_shed={} shed_time={}ms{}",
+            self.queue_delay_us,
+            self.shed,
+            self.nr_shed,
+            self.shed_ms,
+            if self.shedding != 0 { " SHEDDING" } else { "" },
+        )?;
+        if !self.coloc.is_empty() {
+            writeln!(w, "coloc place={}", self.nr_coloc_place)?;
+            for (name, group) in self.coloc.iter() {
+                group.format(w, name)?;
+            }
+        }
+        writeln!(
+            w,
+            "dl_clamp={:5.2} dl_preset={:5.2} dl_server={:5.2}/{}us",
+            self.dl_clamp, self.dl_preset, self.dl_server, self.dl_server_us,
         )?;
 
         writeln!(w, "slice={}us", self.slice_us)?;
@@ -253,6 +337,7 @@ pub fn server_data() -> StatsServerData<StatsCtx, (StatsCtx, ClusterStats)> {
     StatsServerData::new()
         .add_meta(DomainStats::meta())
         .add_meta(NodeStats::meta())
+        .add_meta(ColocGroupStats::meta())
         .add_meta(ClusterStats::meta())
         .add_ops("top", StatsOps { open, close: None })
 }
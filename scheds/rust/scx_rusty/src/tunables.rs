@@ -0,0 +1,134 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use scx_stats::prelude::*;
+use scx_utils::control::ControlClient;
+use scx_utils::control::ControlServer;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+
+pub const CONTROL_PATH: &str = "/var/run/scx/scx_rusty/control";
+
+const TUNABLES: &[(&str, &str)] = &[
+    (
+        "greedy_threshold",
+        "Number of queued tasks before a domain can be stolen from",
+    ),
+    (
+        "greedy_threshold_x_numa",
+        "Same as greedy_threshold but across NUMA nodes, 0 disables",
+    ),
+    (
+        "direct_greedy_under",
+        "Util % under which a CPU can steal directly from other domains",
+    ),
+    (
+        "kick_greedy_under",
+        "Util % under which a CPU is kicked when other domains are overloaded",
+    ),
+];
+
+/// Greedy stealing knobs which can be changed while the scheduler is
+/// running through the control socket. See the corresponding command line
+/// options for their meaning.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Tunables {
+    pub greedy_threshold: u32,
+    pub greedy_threshold_x_numa: u32,
+    pub direct_greedy_under: f64,
+    pub kick_greedy_under: f64,
+}
+
+impl Tunables {
+    fn get(&self, key: &str) -> Value {
+        match key {
+            "greedy_threshold" => self.greedy_threshold.into(),
+            "greedy_threshold_x_numa" => self.greedy_threshold_x_numa.into(),
+            "direct_greedy_under" => self.direct_greedy_under.into(),
+            "kick_greedy_under" => self.kick_greedy_under.into(),
+            _ => Value::Null,
+        }
+    }
+
+    /// Update the knob @key to @val. Nothing is changed if @val is invalid.
+    fn set(&mut self, key: &str, val: &Value) -> Result<()> {
+        let parse_u32 = || -> Result<u32> {
+            match val.as_u64().map(u32::try_from) {
+                Some(Ok(v)) => Ok(v),
+                _ => bail!("{} must be a 32bit unsigned integer", key),
+            }
+        };
+        let parse_pct = || -> Result<f64> {
+            match val.as_f64() {
+                Some(pct) if (0.0..=100.0).contains(&pct) => Ok(pct),
+                _ => bail!("{} must be in [0, 100]", key),
+            }
+        };
+
+        match key {
+            "greedy_threshold" => self.greedy_threshold = parse_u32()?,
+            "greedy_threshold_x_numa" => self.greedy_threshold_x_numa = parse_u32()?,
+            "direct_greedy_under" => self.direct_greedy_under = parse_pct()?,
+            "kick_greedy_under" => self.kick_greedy_under = parse_pct()?,
+            _ => bail!("unknown tunable {:?}", key),
+        }
+        Ok(())
+    }
+
+    /// Serve @tunables on the control socket. The scheduler picks up the
+    /// changes on its next scheduling interval.
+    pub fn control_server(tunables: Arc<Mutex<Tunables>>) -> ControlServer {
+        let mut server = ControlServer::new(CONTROL_PATH);
+        for (key, desc) in TUNABLES.iter() {
+            let (get, set) = (tunables.clone(), tunables.clone());
+            server = server.tunable(
+                key,
+                desc,
+                move || get.lock().unwrap().get(key),
+                move |val| set.lock().unwrap().set(key, &val),
+            );
+        }
+        server
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let buf = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&buf).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// Handle --tune: apply the KEY=VAL pairs in @kvs to the running scheduler
+/// and print the resulting values.
+pub fn tune(kvs: &[String]) -> Result<()> {
+    let mut client = ControlClient::connect(CONTROL_PATH)?;
+
+    for kv in kvs.iter() {
+        let Some((key, val)) = kv.split_once('=') else {
+            bail!("invalid tunable {:?}, expected KEY=VAL", kv);
+        };
+        let val = serde_json::from_str(val.trim())
+            .with_context(|| format!("invalid value for {}", key.trim()))?;
+        client.set(key.trim(), val)?;
+    }
+
+    for tunable in client.list()? {
+        println!("{}={}", tunable.name, tunable.value);
+    }
+    Ok(())
+}
@@ -108,6 +108,13 @@ impl Tuner {
         })
     }
 
+    /// Update the utilization thresholds, in percent, below which direct and
+    /// kick greedy are enabled. Takes effect on the next step().
+    pub fn set_greedy_under(&mut self, direct_greedy_under: f64, kick_greedy_under: f64) {
+        self.direct_greedy_under = direct_greedy_under / 100.0;
+        self.kick_greedy_under = kick_greedy_under / 100.0;
+    }
+
     /// Apply a step in the Tuner by:
     ///
     /// 1. Recording CPU stats from procfs